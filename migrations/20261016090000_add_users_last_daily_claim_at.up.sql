@@ -0,0 +1,2 @@
+-- Add up migration script here
+ALTER TABLE users ADD COLUMN last_daily_claim_at timestamp with time zone NULL;
@@ -0,0 +1,2 @@
+-- Add down migration script here
+ALTER TABLE battle_statuses DROP CONSTRAINT battle_statuses_user_id_key;
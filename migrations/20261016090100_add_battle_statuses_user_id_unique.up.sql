@@ -0,0 +1,6 @@
+-- Add up migration script here
+DELETE FROM battle_statuses a
+USING battle_statuses b
+WHERE a.user_id = b.user_id
+AND (a.updated_at, a.id) < (b.updated_at, b.id);
+ALTER TABLE battle_statuses ADD CONSTRAINT battle_statuses_user_id_key UNIQUE (user_id);
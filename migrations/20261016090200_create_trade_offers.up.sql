@@ -0,0 +1,14 @@
+-- Add up migration script here
+CREATE TABLE IF NOT EXISTS trade_offers (
+	id varchar(255) NOT NULL PRIMARY KEY,
+	from_user_id varchar(255) NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+	to_user_id varchar(255) NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+	offered_mnstr_id varchar(255) NOT NULL REFERENCES mnstrs(id) ON DELETE CASCADE,
+	requested_mnstr_id varchar(255) NULL REFERENCES mnstrs(id) ON DELETE CASCADE,
+	status varchar(255) NOT NULL,
+	created_at timestamp with time zone DEFAULT CURRENT_TIMESTAMP NOT NULL,
+	updated_at timestamp with time zone DEFAULT CURRENT_TIMESTAMP NOT NULL,
+	archived_at timestamp with time zone NULL
+);
+CREATE INDEX IF NOT EXISTS idx_trade_offers_from_user_id ON trade_offers USING btree (from_user_id);
+CREATE INDEX IF NOT EXISTS idx_trade_offers_to_user_id ON trade_offers USING btree (to_user_id);
@@ -0,0 +1,3 @@
+-- Add down migration script here
+ALTER TABLE battle_statuses
+DROP COLUMN ready;
@@ -0,0 +1,3 @@
+-- Add up migration script here
+ALTER TABLE battle_statuses
+ADD COLUMN ready boolean NOT NULL DEFAULT FALSE;
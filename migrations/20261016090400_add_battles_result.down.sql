@@ -0,0 +1,6 @@
+-- Add down migration script here
+ALTER TABLE battles DROP COLUMN loser_coins_awarded;
+ALTER TABLE battles DROP COLUMN loser_xp_awarded;
+ALTER TABLE battles DROP COLUMN winner_coins_awarded;
+ALTER TABLE battles DROP COLUMN winner_xp_awarded;
+ALTER TABLE battles DROP COLUMN outcome;
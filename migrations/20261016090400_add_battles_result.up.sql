@@ -0,0 +1,6 @@
+-- Add up migration script here
+ALTER TABLE battles ADD COLUMN outcome varchar(255) NULL;
+ALTER TABLE battles ADD COLUMN winner_xp_awarded int4 NULL;
+ALTER TABLE battles ADD COLUMN winner_coins_awarded int4 NULL;
+ALTER TABLE battles ADD COLUMN loser_xp_awarded int4 NULL;
+ALTER TABLE battles ADD COLUMN loser_coins_awarded int4 NULL;
@@ -0,0 +1,2 @@
+-- Add up migration script here
+ALTER TABLE users ADD COLUMN primary_mnstr_id varchar(255) NULL;
@@ -0,0 +1,2 @@
+-- Add up migration script here
+ALTER TABLE battles ADD COLUMN seed int8 DEFAULT 0 NOT NULL;
@@ -0,0 +1,9 @@
+-- Add up migration script here
+CREATE TABLE IF NOT EXISTS blocks (
+	id varchar(255) NOT NULL PRIMARY KEY,
+	blocker_id varchar(255) NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+	blocked_id varchar(255) NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+	created_at timestamp with time zone DEFAULT CURRENT_TIMESTAMP NOT NULL,
+	CONSTRAINT blocks_blocker_id_blocked_id_key UNIQUE (blocker_id, blocked_id)
+);
+CREATE INDEX IF NOT EXISTS idx_blocks_blocked_id ON blocks USING btree (blocked_id);
@@ -0,0 +1,11 @@
+-- Add up migration script here
+CREATE TABLE IF NOT EXISTS reports (
+	id varchar(255) NOT NULL PRIMARY KEY,
+	reporter_id varchar(255) NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+	reported_id varchar(255) NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+	reason text NOT NULL,
+	created_at timestamp with time zone DEFAULT CURRENT_TIMESTAMP NOT NULL,
+	updated_at timestamp with time zone DEFAULT CURRENT_TIMESTAMP NOT NULL,
+	archived_at timestamp with time zone NULL
+);
+CREATE INDEX IF NOT EXISTS idx_reports_reported_id ON reports USING btree (reported_id);
@@ -0,0 +1,2 @@
+-- Add up migration script here
+ALTER TABLE mnstrs ADD COLUMN stat_points int4 DEFAULT 0 NOT NULL;
@@ -0,0 +1,2 @@
+-- Add up migration script here
+ALTER TABLE users ADD COLUMN guest_claim_token_hash text NULL;
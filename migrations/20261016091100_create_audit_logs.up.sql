@@ -0,0 +1,11 @@
+-- Add up migration script here
+CREATE TABLE IF NOT EXISTS audit_logs (
+	id varchar(255) NOT NULL PRIMARY KEY,
+	user_id varchar(255) NOT NULL,
+	action varchar(255) NOT NULL,
+	metadata text NULL,
+	created_at timestamp with time zone DEFAULT CURRENT_TIMESTAMP NOT NULL,
+	updated_at timestamp with time zone DEFAULT CURRENT_TIMESTAMP NOT NULL
+);
+CREATE INDEX IF NOT EXISTS idx_audit_logs_user_id ON audit_logs USING btree (user_id);
+CREATE INDEX IF NOT EXISTS idx_audit_logs_created_at ON audit_logs USING btree (created_at);
@@ -0,0 +1,3 @@
+-- Add down migration script here
+ALTER TABLE users DROP COLUMN sms_opt_in;
+ALTER TABLE users DROP COLUMN email_opt_in;
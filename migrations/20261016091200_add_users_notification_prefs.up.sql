@@ -0,0 +1,3 @@
+-- Add up migration script here
+ALTER TABLE users ADD COLUMN email_opt_in boolean DEFAULT true NOT NULL;
+ALTER TABLE users ADD COLUMN sms_opt_in boolean DEFAULT true NOT NULL;
@@ -0,0 +1,2 @@
+-- Add down migration script here
+DROP INDEX IF EXISTS idx_mnstrs_user_id_mnstr_qr_code;
@@ -0,0 +1,16 @@
+-- Add up migration script here
+-- Archive the extra copies of a QR code a user collected twice, keeping the
+-- one they've levelled furthest, so the unique index can be built
+UPDATE mnstrs SET archived_at = CURRENT_TIMESTAMP, updated_at = CURRENT_TIMESTAMP
+WHERE id IN (
+	SELECT id FROM (
+		SELECT id, ROW_NUMBER() OVER (
+			PARTITION BY user_id, mnstr_qr_code
+			ORDER BY current_level DESC, current_experience DESC, created_at ASC, id ASC
+		) AS copy
+		FROM mnstrs
+		WHERE archived_at IS NULL AND mnstr_qr_code <> ''
+	) copies
+	WHERE copy > 1
+);
+CREATE UNIQUE INDEX IF NOT EXISTS idx_mnstrs_user_id_mnstr_qr_code ON mnstrs USING btree (user_id, mnstr_qr_code) WHERE archived_at IS NULL AND mnstr_qr_code <> '';
@@ -0,0 +1,4 @@
+-- Add down migration script here
+DROP INDEX IF EXISTS idx_users_normalized_email;
+ALTER TABLE users DROP CONSTRAINT IF EXISTS users_phone_normalized;
+ALTER TABLE users DROP CONSTRAINT IF EXISTS users_email_normalized;
@@ -0,0 +1,79 @@
+-- Add up migration script here
+-- Bring contact details stored before they were normalized in line with
+-- `normalize_email` and `normalize_phone`, so legacy accounts can be saved
+-- again and `User@Example.COM` can't sit beside `user@example.com`.
+-- Details that can't be normalized are cleared, and when two accounts end up
+-- with the same one the live, verified, oldest account keeps it.
+CREATE TEMPORARY TABLE normalized_contacts ON COMMIT DROP AS
+SELECT id,
+	CASE WHEN LOWER(BTRIM(email)) ~ '^[^@\s]+@[^@\s.][^@\s]*\.[^@\s]*[^@\s.]$'
+		THEN LOWER(BTRIM(email))
+	END AS email,
+	CASE WHEN phone_digits ~ '^[0-9]+$' THEN
+		CASE
+			WHEN BTRIM(phone) LIKE '+%' THEN '+' || phone_digits
+			WHEN LENGTH(phone_digits) = 10 THEN '+1' || phone_digits
+			WHEN LENGTH(phone_digits) = 11 AND phone_digits LIKE '1%' THEN '+' || phone_digits
+		END
+	END AS phone,
+	archived_at,
+	email_verified,
+	phone_verified,
+	created_at
+FROM (
+	SELECT *, REGEXP_REPLACE(BTRIM(phone), '^\+|[ .()-]', '', 'g') AS phone_digits
+	FROM users
+) contacts;
+
+UPDATE normalized_contacts SET phone = NULL WHERE phone !~ '^\+[1-9][0-9]{7,14}$';
+
+UPDATE normalized_contacts SET email = NULL
+WHERE id IN (
+	SELECT id FROM (
+		SELECT id, ROW_NUMBER() OVER (
+			PARTITION BY email
+			ORDER BY archived_at IS NULL DESC, email_verified DESC NULLS LAST, created_at ASC, id ASC
+		) AS copy
+		FROM normalized_contacts
+		WHERE email IS NOT NULL
+	) copies
+	WHERE copy > 1
+);
+UPDATE normalized_contacts SET phone = NULL
+WHERE id IN (
+	SELECT id FROM (
+		SELECT id, ROW_NUMBER() OVER (
+			PARTITION BY phone
+			ORDER BY archived_at IS NULL DESC, phone_verified DESC NULLS LAST, created_at ASC, id ASC
+		) AS copy
+		FROM normalized_contacts
+		WHERE phone IS NOT NULL
+	) copies
+	WHERE copy > 1
+);
+
+-- Clear first so a normalized value never collides with a copy that hasn't
+-- been cleared yet
+UPDATE users SET
+	email = NULL,
+	email_verified = FALSE,
+	updated_at = CURRENT_TIMESTAMP
+FROM normalized_contacts
+WHERE users.id = normalized_contacts.id AND users.email IS NOT NULL AND normalized_contacts.email IS NULL;
+UPDATE users SET
+	phone = NULL,
+	phone_verified = FALSE,
+	updated_at = CURRENT_TIMESTAMP
+FROM normalized_contacts
+WHERE users.id = normalized_contacts.id AND users.phone IS NOT NULL AND normalized_contacts.phone IS NULL;
+UPDATE users SET
+	email = normalized_contacts.email,
+	phone = normalized_contacts.phone,
+	updated_at = CURRENT_TIMESTAMP
+FROM normalized_contacts
+WHERE users.id = normalized_contacts.id
+	AND (users.email IS DISTINCT FROM normalized_contacts.email OR users.phone IS DISTINCT FROM normalized_contacts.phone);
+
+ALTER TABLE users ADD CONSTRAINT users_email_normalized CHECK (email = LOWER(BTRIM(email)));
+ALTER TABLE users ADD CONSTRAINT users_phone_normalized CHECK (phone ~ '^\+[1-9][0-9]{7,14}$');
+CREATE UNIQUE INDEX IF NOT EXISTS idx_users_normalized_email ON users USING btree (LOWER(BTRIM(email)));
@@ -0,0 +1,2 @@
+-- Add up migration script here
+ALTER TABLE users ADD COLUMN last_practice_reward_at timestamp with time zone NULL;
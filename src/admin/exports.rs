@@ -0,0 +1,212 @@
+//! Admin exports streamed as NDJSON.
+//!
+//! Rows are read with keyset pagination on `id`, `EXPORT_BATCH_SIZE` at a time,
+//! and written out as they arrive so an export never holds the whole table.
+
+use futures::{
+    Stream, StreamExt,
+    stream::{self, BoxStream},
+};
+use rocket::{
+    http::{ContentType, Status},
+    request::FromParam,
+    response::stream::TextStream,
+};
+use serde::Serialize;
+
+use crate::{
+    models::{transaction::Transaction, user::User},
+    utils::{admin::is_admin, token::RawToken},
+    websocket::helpers::verify_session_token,
+};
+
+/// Rows fetched per query while exporting.
+pub const EXPORT_BATCH_SIZE: i64 = 500;
+
+/// User fields left out of exports.
+const REDACTED_USER_FIELDS: &[&str] = &[
+    "password_hash",
+    "email_verification_code",
+    "phone_verification_code",
+    "wallet",
+    "mnstrs",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExportResource {
+    Users,
+    Transactions,
+}
+
+impl<'r> FromParam<'r> for ExportResource {
+    type Error = &'r str;
+
+    fn from_param(param: &'r str) -> Result<Self, Self::Error> {
+        match param {
+            "users" => Ok(ExportResource::Users),
+            "transactions" => Ok(ExportResource::Transactions),
+            _ => Err(param),
+        }
+    }
+}
+
+#[get("/export/<resource>")]
+pub async fn export(
+    resource: ExportResource,
+    token: RawToken,
+) -> Result<(ContentType, TextStream<BoxStream<'static, String>>), Status> {
+    let session = match verify_session_token(token).await {
+        Ok(session) => session,
+        Err(_) => return Err(Status::Unauthorized),
+    };
+    if !is_admin(&session.user_id) {
+        return Err(Status::Forbidden);
+    }
+
+    let lines = match resource {
+        ExportResource::Users => ndjson_lines(
+            |after, limit| User::find_page_by(vec![], after, limit),
+            |user: &User| user.id.clone(),
+            REDACTED_USER_FIELDS,
+            EXPORT_BATCH_SIZE,
+        )
+        .boxed(),
+        ExportResource::Transactions => ndjson_lines(
+            |after, limit| Transaction::find_page_by(vec![], after, limit),
+            |transaction: &Transaction| transaction.id.clone(),
+            &[],
+            EXPORT_BATCH_SIZE,
+        )
+        .boxed(),
+    };
+    Ok((
+        ContentType::new("application", "x-ndjson"),
+        TextStream(lines),
+    ))
+}
+
+/// Pages through rows with `fetch(after, limit)` and yields one NDJSON line per
+/// row. The next page is only fetched once the previous one has been consumed.
+///
+/// A failed fetch ends the stream early; the error is logged since the response
+/// headers have already been sent by then.
+pub fn ndjson_lines<T, F, Fut>(
+    fetch: F,
+    key: fn(&T) -> String,
+    redacted: &'static [&'static str],
+    batch_size: i64,
+) -> impl Stream<Item = String>
+where
+    T: Serialize,
+    F: FnMut(Option<String>, i64) -> Fut,
+    Fut: Future<Output = Result<Vec<T>, anyhow::Error>>,
+{
+    stream::unfold(
+        (fetch, None::<String>, false),
+        move |(mut fetch, after, done)| async move {
+            if done {
+                return None;
+            }
+            let rows = match fetch(after, batch_size).await {
+                Ok(rows) => rows,
+                Err(e) => {
+                    println!("[ndjson_lines] Failed to fetch rows: {:?}", e);
+                    return None;
+                }
+            };
+            if rows.is_empty() {
+                return None;
+            }
+            let done = (rows.len() as i64) < batch_size;
+            let after = rows.last().map(key);
+            let lines = rows
+                .iter()
+                .filter_map(|row| ndjson_line(row, redacted))
+                .collect::<Vec<String>>();
+            Some((stream::iter(lines), (fetch, after, done)))
+        },
+    )
+    .flatten()
+}
+
+fn ndjson_line<T: Serialize>(row: &T, redacted: &[&str]) -> Option<String> {
+    let mut value = match serde_json::to_value(row) {
+        Ok(value) => value,
+        Err(e) => {
+            println!("[ndjson_line] Failed to serialize row: {:?}", e);
+            return None;
+        }
+    };
+    if let Some(fields) = value.as_object_mut() {
+        for field in redacted {
+            fields.remove(*field);
+        }
+    }
+    Some(format!("{}\n", value))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    };
+
+    use serde_json::json;
+
+    use super::*;
+
+    #[derive(Serialize)]
+    struct Row {
+        id: String,
+        secret: String,
+    }
+
+    #[tokio::test]
+    async fn test_export_streams_one_line_per_row_in_batches() {
+        let seeded = (0..25)
+            .map(|i| format!("{:03}", i))
+            .collect::<Vec<String>>();
+        let fetches = Arc::new(AtomicUsize::new(0));
+        let largest_batch = Arc::new(AtomicUsize::new(0));
+
+        let fetch = {
+            let fetches = fetches.clone();
+            let largest_batch = largest_batch.clone();
+            move |after: Option<String>, limit: i64| {
+                let rows = seeded
+                    .iter()
+                    .filter(|id| after.as_ref().map_or(true, |after| *id > after))
+                    .take(limit as usize)
+                    .map(|id| Row {
+                        id: id.clone(),
+                        secret: "hunter2".to_string(),
+                    })
+                    .collect::<Vec<Row>>();
+                fetches.fetch_add(1, Ordering::SeqCst);
+                largest_batch.fetch_max(rows.len(), Ordering::SeqCst);
+                async move { Ok(rows) }
+            }
+        };
+        let mut lines = Box::pin(ndjson_lines(
+            fetch,
+            |row: &Row| row.id.clone(),
+            &["secret"],
+            10,
+        ));
+
+        // Only the first batch is read before the first line goes out
+        let first = lines.next().await.unwrap();
+        assert_eq!(fetches.load(Ordering::SeqCst), 1);
+        assert_eq!(first, format!("{}\n", json!({ "id": "000" })));
+
+        let rest = lines.collect::<Vec<String>>().await;
+        assert_eq!(rest.len() + 1, 25);
+        assert!(
+            rest.iter()
+                .all(|line| line.ends_with('\n') && !line.contains("secret"))
+        );
+        assert_eq!(largest_batch.load(Ordering::SeqCst), 10);
+        assert_eq!(fetches.load(Ordering::SeqCst), 3);
+    }
+}
@@ -0,0 +1,7 @@
+use rocket::Route;
+
+pub mod exports;
+
+pub fn routes() -> Vec<Route> {
+    routes![exports::export]
+}
@@ -0,0 +1,178 @@
+//! Damage spread and critical hits for attacks that land.
+//!
+//! A hit's base damage is spread by up to `DAMAGE_VARIANCE_PERCENT` either way,
+//! then a crit multiplies it by `CRIT_MULTIPLIER`. The crit chance starts at
+//! `CRIT_CHANCE_PERCENT` and grows with the stat driving the attack: speed for
+//! physical attacks, intelligence for magic. Every roll comes from the turn's
+//! `BattleRng`, so replays land the same crits. The settings are read once.
+
+use std::sync::OnceLock;
+
+use crate::battle::helpers::{BattleRng, stat_modifier};
+
+pub const DEFAULT_CRIT_CHANCE_PERCENT: i32 = 5;
+pub const DEFAULT_CRIT_MULTIPLIER: f64 = 1.5;
+pub const DEFAULT_DAMAGE_VARIANCE_PERCENT: i32 = 10;
+
+/// Crit chance added per point the crit stat adds to a roll.
+pub const CRIT_CHANCE_PER_MODIFIER_PERCENT: i32 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DamageRules {
+    pub crit_chance_percent: i32,
+    pub crit_multiplier: f64,
+    pub variance_percent: i32,
+}
+
+impl Default for DamageRules {
+    fn default() -> Self {
+        Self {
+            crit_chance_percent: DEFAULT_CRIT_CHANCE_PERCENT,
+            crit_multiplier: DEFAULT_CRIT_MULTIPLIER,
+            variance_percent: DEFAULT_DAMAGE_VARIANCE_PERCENT,
+        }
+    }
+}
+
+/// What a landed attack dealt, before it is capped at the defender's health.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Damage {
+    pub amount: i32,
+    pub crit: bool,
+}
+
+impl DamageRules {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        Self {
+            crit_chance_percent: parse_percent(
+                std::env::var("CRIT_CHANCE_PERCENT").ok(),
+                defaults.crit_chance_percent,
+            ),
+            crit_multiplier: match std::env::var("CRIT_MULTIPLIER")
+                .ok()
+                .and_then(|value| value.trim().parse::<f64>().ok())
+            {
+                Some(multiplier) if multiplier.is_finite() && multiplier >= 1.0 => multiplier,
+                _ => defaults.crit_multiplier,
+            },
+            variance_percent: parse_percent(
+                std::env::var("DAMAGE_VARIANCE_PERCENT").ok(),
+                defaults.variance_percent,
+            ),
+        }
+    }
+
+    /// The rules in effect, read from the environment on first use.
+    pub fn current() -> Self {
+        static CURRENT: OnceLock<DamageRules> = OnceLock::new();
+        *CURRENT.get_or_init(DamageRules::from_env)
+    }
+
+    /// The chance out of 100 that a hit backed by `crit_stat` crits.
+    pub fn crit_chance(&self, crit_stat: i32) -> i32 {
+        (self.crit_chance_percent + stat_modifier(crit_stat) * CRIT_CHANCE_PER_MODIFIER_PERCENT)
+            .clamp(0, 100)
+    }
+
+    /// Spreads `base` damage and rolls for a crit. A hit always deals at least
+    /// one point.
+    pub fn roll(&self, base: i32, crit_stat: i32, rng: &mut BattleRng) -> Damage {
+        let spread = base.max(0) * self.variance_percent / 100;
+        let mut amount = base;
+        if spread > 0 {
+            amount += rng.roll_dice(spread * 2 + 1) - spread - 1;
+        }
+
+        let crit = rng.roll_dice(100) <= self.crit_chance(crit_stat);
+        if crit {
+            amount = (amount as f64 * self.crit_multiplier).round() as i32;
+        }
+
+        Damage {
+            amount: amount.max(1),
+            crit,
+        }
+    }
+}
+
+/// Falls back to `default` for a missing or unparsable value; clamped to 0-100.
+fn parse_percent(value: Option<String>, default: i32) -> i32 {
+    match value.and_then(|value| value.trim().parse::<i32>().ok()) {
+        Some(percent) => percent.clamp(0, 100),
+        None => default,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::battle::helpers::MAX_STAT_MODIFIER;
+
+    const SEED: i64 = 7_301_044_221;
+
+    #[test]
+    fn test_crit_multiplies_damage() {
+        let rules = DamageRules {
+            crit_chance_percent: 100,
+            crit_multiplier: 2.0,
+            variance_percent: 0,
+        };
+        let damage = rules.roll(6, 0, &mut BattleRng::for_turn(SEED, 1));
+        assert_eq!(
+            damage,
+            Damage {
+                amount: 12,
+                crit: true
+            }
+        );
+
+        // The same seed and turn crit the same way on replay
+        assert_eq!(rules.roll(6, 0, &mut BattleRng::for_turn(SEED, 1)), damage);
+    }
+
+    #[test]
+    fn test_non_crit_hit_stays_within_variance() {
+        let rules = DamageRules {
+            crit_chance_percent: 0,
+            crit_multiplier: 2.0,
+            variance_percent: 10,
+        };
+        for turn in 1..=50 {
+            let damage = rules.roll(20, 0, &mut BattleRng::for_turn(SEED, turn));
+            assert!(!damage.crit);
+            assert!((18..=22).contains(&damage.amount));
+            assert_eq!(
+                rules.roll(20, 0, &mut BattleRng::for_turn(SEED, turn)),
+                damage
+            );
+        }
+
+        let flat = DamageRules {
+            variance_percent: 0,
+            ..rules
+        };
+        assert_eq!(
+            flat.roll(3, 0, &mut BattleRng::for_turn(SEED, 1)),
+            Damage {
+                amount: 3,
+                crit: false
+            }
+        );
+    }
+
+    #[test]
+    fn test_crit_chance_grows_with_stat() {
+        let rules = DamageRules::default();
+        assert_eq!(rules.crit_chance(0), DEFAULT_CRIT_CHANCE_PERCENT);
+        assert_eq!(
+            rules.crit_chance(1_000),
+            DEFAULT_CRIT_CHANCE_PERCENT + MAX_STAT_MODIFIER * CRIT_CHANCE_PER_MODIFIER_PERCENT
+        );
+
+        assert_eq!(parse_percent(None, 10), 10);
+        assert_eq!(parse_percent(Some(" 25 ".to_string()), 10), 25);
+        assert_eq!(parse_percent(Some("lots".to_string()), 10), 10);
+        assert_eq!(parse_percent(Some("250".to_string()), 10), 100);
+    }
+}
@@ -1,8 +1,8 @@
 use crate::models::mnstr::Mnstr;
-use crate::battle::helpers::roll_dice;
+use crate::battle::helpers::BattleRng;
 
-pub fn rest(defender: &mut Mnstr) -> i32 {
-    let mut defense = roll_dice(20)as i32;
+pub fn rest(defender: &mut Mnstr, rng: &mut BattleRng) -> i32 {
+    let mut defense = rng.roll_dice(20)as i32;
     if (defense + defender.current_defense) >= defender.max_defense {
         defense = defender.max_defense;
     }
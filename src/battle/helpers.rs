@@ -1,6 +1,91 @@
 use rand::prelude::*;
 
-pub fn roll_dice(number: i32) -> i32 {
-    let mut rng = rand::rng();
-    rng.random_range(1..(number + 1)) as i32
-}
\ No newline at end of file
+/// Dice for one turn of a battle, derived from the battle's seed and the turn
+/// index so a replay with the stored seed rolls exactly the same numbers.
+///
+/// This is SplitMix64 rather than a `rand` generator: its output is fixed by
+/// the algorithm, so stored seeds keep replaying the same after upgrades.
+#[derive(Debug, Clone)]
+pub struct BattleRng {
+    state: u64,
+}
+
+impl BattleRng {
+    pub fn for_turn(seed: i64, turn: i64) -> Self {
+        let mut rng = Self {
+            state: (seed as u64) ^ (turn as u64).wrapping_mul(0xD1B5_4A32_D192_ED03),
+        };
+        // Mix once so neighbouring turns don't start from neighbouring states
+        rng.state = rng.next_u64();
+        rng
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Rolls a die with `number` sides, from 1 to `number`.
+    pub fn roll_dice(&mut self, number: i32) -> i32 {
+        let sides = number.max(1) as u64;
+        (self.next_u64() % sides) as i32 + 1
+    }
+
+    pub fn coin_flip(&mut self) -> bool {
+        self.next_u64() & 1 == 0
+    }
+}
+
+/// Stat points per point a stat adds to a roll.
+pub const STAT_MODIFIER_DIVISOR: i32 = 20;
+
+/// The most any one stat adds to a d20 roll, so a high-level stat can't
+/// decide a roll on its own.
+pub const MAX_STAT_MODIFIER: i32 = 5;
+
+/// What a stat adds to a roll: one per `STAT_MODIFIER_DIVISOR` points, from 0
+/// for a depleted (or negative) stat up to `MAX_STAT_MODIFIER`.
+pub fn stat_modifier(stat: i32) -> i32 {
+    (stat.max(0) / STAT_MODIFIER_DIVISOR).min(MAX_STAT_MODIFIER)
+}
+
+pub fn new_battle_seed() -> i64 {
+    rand::rng().random_range(i64::MIN..i64::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_battle_rng_is_reproducible() {
+        let rolls = |seed, turn| {
+            let mut rng = BattleRng::for_turn(seed, turn);
+            (0..8).map(|_| rng.roll_dice(20)).collect::<Vec<_>>()
+        };
+        assert_eq!(rolls(42, 3), rolls(42, 3));
+        assert_ne!(rolls(42, 3), rolls(42, 4));
+        assert_ne!(rolls(42, 3), rolls(43, 3));
+        assert!(rolls(42, 3).iter().all(|roll| (1..=20).contains(roll)));
+    }
+
+    #[test]
+    fn test_stat_modifier_is_clamped() {
+        assert_eq!(stat_modifier(i32::MIN), 0);
+        assert_eq!(stat_modifier(-40), 0);
+        assert_eq!(stat_modifier(-1), 0);
+        assert_eq!(stat_modifier(0), 0);
+        assert_eq!(stat_modifier(STAT_MODIFIER_DIVISOR - 1), 0);
+        assert_eq!(stat_modifier(STAT_MODIFIER_DIVISOR), 1);
+        assert_eq!(stat_modifier(59), 2);
+        assert_eq!(
+            stat_modifier(STAT_MODIFIER_DIVISOR * MAX_STAT_MODIFIER),
+            MAX_STAT_MODIFIER
+        );
+        assert_eq!(stat_modifier(1_000), MAX_STAT_MODIFIER);
+        assert_eq!(stat_modifier(i32::MAX), MAX_STAT_MODIFIER);
+    }
+}
@@ -1,22 +1,23 @@
 use crate::models::mnstr::Mnstr;
-use crate::battle::helpers::roll_dice;
+use crate::battle::damage::{Damage, DamageRules};
+use crate::battle::helpers::{BattleRng, stat_modifier};
 
-pub fn attack(attacker: &mut Mnstr, defender: &mut Mnstr) -> (bool, i32) {
-    let attacker_roll = roll_dice(20) + (attacker.current_magic / 20) as i32;
-    let defender_roll = roll_dice(20) + (defender.current_magic / 20) as i32;
+pub fn attack(attacker: &mut Mnstr, defender: &mut Mnstr, rng: &mut BattleRng) -> (bool, Damage) {
+    let attacker_roll = rng.roll_dice(20) + stat_modifier(attacker.current_magic);
+    let defender_roll = rng.roll_dice(20) + stat_modifier(defender.current_magic);
 
     let mut hit = false;
-    let mut damage = 0;
+    let mut damage = Damage::default();
     let difference = attacker_roll - defender_roll;
 
     if difference > 0 {
         hit = true;
-        if difference > defender.current_health {
-            damage = defender.current_health;
+        damage = DamageRules::current().roll(difference, attacker.current_intelligence, rng);
+        if damage.amount > defender.current_health {
+            damage.amount = defender.current_health;
             defender.current_health = 0;
         } else {
-            damage = difference;
-            defender.current_health -= damage;
+            defender.current_health -= damage.amount;
         }
 
         let new_magic = attacker.current_magic - 1;
@@ -1,4 +1,6 @@
 pub mod physical;
 pub mod defend;
 pub mod magic;
-pub mod helpers;
\ No newline at end of file
+pub mod helpers;
+pub mod damage;
+pub mod turn_order;
\ No newline at end of file
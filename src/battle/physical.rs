@@ -1,26 +1,27 @@
 use crate::models::mnstr::Mnstr;
-use crate::battle::helpers::roll_dice;
+use crate::battle::damage::{Damage, DamageRules};
+use crate::battle::helpers::{BattleRng, stat_modifier};
 
-pub fn attack(attacker: &mut Mnstr, defender: &mut Mnstr) -> (bool, i32) {
-    let attacker_roll = roll_dice(20)
-        + (attacker.current_speed / 20) as i32
-        + (attacker.current_attack / 20) as i32;
-    let defender_roll = roll_dice(20)
-        + (defender.current_intelligence / 20) as i32
-        + (defender.current_defense / 20) as i32;
+pub fn attack(attacker: &mut Mnstr, defender: &mut Mnstr, rng: &mut BattleRng) -> (bool, Damage) {
+    let attacker_roll = rng.roll_dice(20)
+        + stat_modifier(attacker.current_speed)
+        + stat_modifier(attacker.current_attack);
+    let defender_roll = rng.roll_dice(20)
+        + stat_modifier(defender.current_intelligence)
+        + stat_modifier(defender.current_defense);
 
     let mut hit = false;
-    let mut damage = 0;
+    let mut damage = Damage::default();
     let difference = attacker_roll - defender_roll;
 
     if difference > 0 {
         hit = true;
-        if difference > defender.current_health {
-            damage = defender.current_health;
+        damage = DamageRules::current().roll(difference, attacker.current_speed, rng);
+        if damage.amount > defender.current_health {
+            damage.amount = defender.current_health;
             defender.current_health = 0;
         } else {
-            damage = difference;
-            defender.current_health -= damage;
+            defender.current_health -= damage.amount;
         }
     }
 
@@ -45,4 +46,32 @@ pub fn attack(attacker: &mut Mnstr, defender: &mut Mnstr) -> (bool, i32) {
     }
 
     (hit, damage)
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn play(seed: i64) -> Vec<(bool, Damage)> {
+        let mut challenger = Mnstr::new("challenger".to_string(), None, None, "a".to_string());
+        let mut opponent = Mnstr::new("opponent".to_string(), None, None, "b".to_string());
+        (1..=10)
+            .map(|turn| {
+                let mut rng = BattleRng::for_turn(seed, turn);
+                if turn % 2 == 1 {
+                    attack(&mut challenger, &mut opponent, &mut rng)
+                } else {
+                    attack(&mut opponent, &mut challenger, &mut rng)
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_replaying_with_stored_seed_reproduces_outcomes() {
+        let seed = 7_301_044_221;
+        let played = play(seed);
+        assert_eq!(play(seed), played);
+        assert!(played.iter().all(|(hit, damage)| *hit || *damage == Damage::default()));
+    }
+}
@@ -0,0 +1,153 @@
+use crate::models::mnstr::Mnstr;
+
+/// How the first turn of a battle is decided, set with `TURN_ORDER_RULE`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TurnOrderRule {
+    CoinFlip,
+    /// The faster mnstr goes first; equal speeds fall back to a coin flip.
+    SpeedBased,
+}
+
+impl From<String> for TurnOrderRule {
+    fn from(value: String) -> Self {
+        match value.as_str() {
+            "speed" => TurnOrderRule::SpeedBased,
+            _ => TurnOrderRule::CoinFlip,
+        }
+    }
+}
+
+pub fn turn_order_rule() -> TurnOrderRule {
+    match std::env::var("TURN_ORDER_RULE") {
+        Ok(rule) => rule.into(),
+        Err(_) => TurnOrderRule::CoinFlip,
+    }
+}
+
+/// Picks who takes the first turn. `coin_flip` returns `true` when the
+/// challenger wins the flip, so callers can pass a fixed RNG in tests.
+pub fn first_turn_user_id(
+    rule: TurnOrderRule,
+    challenger_id: &str,
+    challenger_mnstr: Option<&Mnstr>,
+    opponent_id: &str,
+    opponent_mnstr: Option<&Mnstr>,
+    coin_flip: impl FnOnce() -> bool,
+) -> String {
+    if let (TurnOrderRule::SpeedBased, Some(challenger_mnstr), Some(opponent_mnstr)) =
+        (rule, challenger_mnstr, opponent_mnstr)
+    {
+        if challenger_mnstr.current_speed > opponent_mnstr.current_speed {
+            return challenger_id.to_string();
+        }
+        if opponent_mnstr.current_speed > challenger_mnstr.current_speed {
+            return opponent_id.to_string();
+        }
+    }
+
+    if coin_flip() {
+        challenger_id.to_string()
+    } else {
+        opponent_id.to_string()
+    }
+}
+
+/// Whose turn it is after `turns_played` turns. Every attack, defend or magic
+/// action writes one battle log and hands the turn to the other player.
+pub fn turn_user_id_after(
+    first_turn_user_id: &str,
+    challenger_id: &str,
+    opponent_id: &str,
+    turns_played: usize,
+) -> String {
+    let second_turn_user_id = match first_turn_user_id == challenger_id {
+        true => opponent_id,
+        false => challenger_id,
+    };
+    match turns_played % 2 {
+        0 => first_turn_user_id.to_string(),
+        _ => second_turn_user_id.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mnstr_with_speed(speed: i32) -> Mnstr {
+        let mut mnstr = Mnstr::new("user".to_string(), None, None, "qr".to_string());
+        mnstr.current_speed = speed;
+        mnstr
+    }
+
+    #[test]
+    fn test_speed_based_turn_order() {
+        let fast = mnstr_with_speed(30);
+        let slow = mnstr_with_speed(10);
+
+        for coin in [true, false] {
+            assert_eq!(
+                first_turn_user_id(
+                    TurnOrderRule::SpeedBased,
+                    "challenger",
+                    Some(&slow),
+                    "opponent",
+                    Some(&fast),
+                    || coin,
+                ),
+                "opponent"
+            );
+            assert_eq!(
+                first_turn_user_id(
+                    TurnOrderRule::SpeedBased,
+                    "challenger",
+                    Some(&fast),
+                    "opponent",
+                    Some(&slow),
+                    || coin,
+                ),
+                "challenger"
+            );
+        }
+
+        // Ties and the coin flip rule defer to the RNG
+        assert_eq!(
+            first_turn_user_id(
+                TurnOrderRule::SpeedBased,
+                "challenger",
+                Some(&fast),
+                "opponent",
+                Some(&fast),
+                || false,
+            ),
+            "opponent"
+        );
+        assert_eq!(
+            first_turn_user_id(
+                TurnOrderRule::CoinFlip,
+                "challenger",
+                Some(&slow),
+                "opponent",
+                Some(&fast),
+                || true,
+            ),
+            "challenger"
+        );
+    }
+
+    #[test]
+    fn test_turns_alternate_from_the_first() {
+        assert_eq!(
+            turn_user_id_after("opponent", "challenger", "opponent", 0),
+            "opponent"
+        );
+        assert_eq!(
+            turn_user_id_after("opponent", "challenger", "opponent", 1),
+            "challenger"
+        );
+        assert_eq!(
+            turn_user_id_after("challenger", "challenger", "opponent", 4),
+            "challenger"
+        );
+    }
+}
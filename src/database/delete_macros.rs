@@ -52,7 +52,7 @@
 #[macro_export]
 macro_rules! delete_resource_where_fields {
     ($resource:ty, $params:expr) => {{
-        use crate::database::connection::get_connection;
+        use crate::database::connection::fetch_one;
         use crate::database::traits::DatabaseResource;
         use crate::database::values::DatabaseValue;
         use crate::utils::strings::camel_to_snake_case;
@@ -67,7 +67,6 @@ macro_rules! delete_resource_where_fields {
                 2,
                 false,
             );
-            let pool = get_connection().await;
 
             let params: Vec<(&str, DatabaseValue)> = $params.clone();
 
@@ -86,7 +85,7 @@ macro_rules! delete_resource_where_fields {
             }
 
             for (i, field) in fields.iter().enumerate() {
-                query.push_str(&format!("{} = ${}", field, i + 1));
+                query.push_str(&values[i].condition(field, i + 1));
                 if i < fields.len() - 1 {
                     query.push_str(" AND ");
                 }
@@ -102,14 +101,14 @@ macro_rules! delete_resource_where_fields {
                 query = query.bind(archived_at);
             }
 
-            match query.fetch_one(&pool).await {
+            match fetch_one(&resource_name, "delete_resource_where_fields", query).await {
                 Ok(row) => Ok(<$resource as DatabaseResource>::from_row(&row)?),
                 Err(e) => Err(anyhow::Error::msg(e.to_string())),
             }
         }
     }};
     ($resource:ty, $params:expr, $permanent:expr) => {{
-        use crate::database::connection::get_connection;
+        use crate::database::connection::fetch_one;
         use crate::database::traits::DatabaseResource;
         use crate::database::values::DatabaseValue;
         use crate::utils::strings::camel_to_snake_case;
@@ -124,7 +123,6 @@ macro_rules! delete_resource_where_fields {
                 2,
                 false,
             );
-            let pool = get_connection().await;
 
             let permanent: bool = $permanent;
             let params: Vec<(&str, DatabaseValue)> = $params.clone();
@@ -148,7 +146,7 @@ macro_rules! delete_resource_where_fields {
             }
 
             for (i, field) in fields.iter().enumerate() {
-                query.push_str(&format!("{} = ${}", field, i + 1));
+                query.push_str(&values[i].condition(field, i + 1));
                 if i < fields.len() - 1 {
                     query.push_str(" AND ");
                 }
@@ -164,7 +162,7 @@ macro_rules! delete_resource_where_fields {
                 query = query.bind(archived_at);
             }
 
-            match query.fetch_one(&pool).await {
+            match fetch_one(&resource_name, "delete_resource_where_fields", query).await {
                 Ok(row) => Ok(<$resource as DatabaseResource>::from_row(&row)?),
                 Err(e) => Err(anyhow::Error::msg(e.to_string())),
             }
@@ -8,8 +8,7 @@
 ///
 /// This macro generates an INSERT query and automatically handles common database fields:
 /// - Generates UUID if `has_id()` returns true
-/// - Sets `created_at` timestamp if `is_creatable()` returns true
-/// - Sets `updated_at` timestamp if `is_updatable()` returns true
+/// - Leaves `created_at` and `updated_at` to the database defaults unless they are passed in
 /// - Sets `expires_at` timestamp (30 days from now) if `is_expirable()` returns true
 ///
 /// # Arguments
@@ -34,9 +33,11 @@
 macro_rules! insert_resource {
     ($resource:ty, $params:expr) => {{
         use crate::database::{
-            connection::get_connection, traits::DatabaseResource, values::DatabaseValue,
+            connection::fetch_one,
+            traits::{DatabaseResource, validate_columns},
+            values::DatabaseValue,
         };
-        use crate::utils::strings::camel_to_snake_case;
+        use crate::utils::{redact::redact_debug, strings::camel_to_snake_case};
         use pluralizer::pluralize;
         use time::{Duration, OffsetDateTime};
         use uuid::Uuid;
@@ -48,8 +49,6 @@ macro_rules! insert_resource {
             }
 
             let id = Uuid::new_v4().to_string();
-            let created_at = OffsetDateTime::now_utc();
-            let updated_at = created_at.clone();
             let expires_at = (OffsetDateTime::now_utc() + Duration::days(30));
 
             let resource_name = pluralize(
@@ -57,7 +56,6 @@ macro_rules! insert_resource {
                 2,
                 false,
             );
-            let pool = get_connection().await;
 
             let mut params: Vec<(String, DatabaseValue)> = Vec::new();
             for (field, value) in input_params.into_iter() {
@@ -72,58 +70,19 @@ macro_rules! insert_resource {
                 }
             }
 
-            if <$resource as DatabaseResource>::is_creatable() {
-                if let Some(idx) = params
-                    .iter()
-                    .position(|(field, _)| field.contains("created_at"))
-                {
-                    params[idx] = (
-                        "created_at".to_string(),
-                        DatabaseValue::DateTime(created_at.clone().to_string()),
-                    );
-                } else {
-                    params.push((
-                        "created_at".to_string(),
-                        DatabaseValue::DateTime(created_at.clone().to_string()),
-                    ));
-                }
-            }
-
-            if <$resource as DatabaseResource>::is_updatable() {
-                if let Some(idx) = params
-                    .iter()
-                    .position(|(field, _)| field.contains("updated_at"))
-                {
-                    params[idx] = (
-                        "updated_at".to_string(),
-                        DatabaseValue::DateTime(updated_at.clone().to_string()),
-                    );
-                } else {
-                    params.push((
-                        "updated_at".to_string(),
-                        DatabaseValue::DateTime(updated_at.clone().to_string()),
-                    ));
-                }
-            }
-
             if <$resource as DatabaseResource>::is_expirable() {
                 if let Some(idx) = params
                     .iter()
                     .position(|(field, _)| field.contains("expires_at"))
                 {
-                    params[idx] = (
-                        "expires_at".to_string(),
-                        DatabaseValue::DateTime(expires_at.clone().to_string()),
-                    );
+                    params[idx] = ("expires_at".to_string(), expires_at.into());
                 } else {
-                    params.push((
-                        "expires_at".to_string(),
-                        DatabaseValue::DateTime(expires_at.clone().to_string()),
-                    ));
+                    params.push(("expires_at".to_string(), expires_at.into()));
                 }
             }
 
             let fields: Vec<String> = params.iter().map(|(field, _)| field.clone()).collect();
+            validate_columns::<$resource>(&resource_name, &fields)?;
             let values: Vec<DatabaseValue> =
                 params.iter().map(|(_, value)| (*value).clone()).collect();
 
@@ -149,7 +108,7 @@ macro_rules! insert_resource {
                         query.push_str(&format!("Cast(${} AS TEXT)", i + 1));
                     }
                     DatabaseValue::DateTime(_) => {
-                        query.push_str(&format!("CAST(${} AS TIMESTAMP WITHOUT TIME ZONE)", i + 1));
+                        query.push_str(&format!("CAST(${} AS TIMESTAMP WITH TIME ZONE)", i + 1));
                     }
                     DatabaseValue::Int(_) | DatabaseValue::Int32(_) => {
                         query.push_str(&format!("CAST(${} AS INTEGER)", i + 1));
@@ -175,10 +134,10 @@ macro_rules! insert_resource {
                 query = query.bind(value);
             }
 
-            match query.fetch_one(&pool).await {
+            match fetch_one(&resource_name, "insert_resource", query).await {
                 Ok(row) => Ok(<$resource as DatabaseResource>::from_row(&row)?),
                 Err(e) => {
-                    println!("Error fetching row: {:?}", e);
+                    println!("Error fetching row: {}", redact_debug(&e));
                     Err(anyhow::Error::msg(e.to_string()))
                 }
             }
@@ -190,7 +149,7 @@ macro_rules! insert_resource {
 macro_rules! insert_resource_batch {
     ($resource:ty, $resources:expr) => {{
         use crate::database::{
-            connection::get_connection, traits::DatabaseResource, values::DatabaseValue,
+            connection::fetch_all, traits::DatabaseResource, values::DatabaseValue,
         };
         use crate::utils::strings::camel_to_snake_case;
         use pluralizer::pluralize;
@@ -198,7 +157,6 @@ macro_rules! insert_resource_batch {
         use uuid::Uuid;
 
         async {
-            let pool = get_connection().await;
             let resources: Vec<Vec<(&str, DatabaseValue)>> = $resources.clone();
             let resource_name = pluralize(
                 camel_to_snake_case(stringify!($resource).to_string()).as_str(),
@@ -206,8 +164,6 @@ macro_rules! insert_resource_batch {
                 false,
             );
 
-            let created_at = OffsetDateTime::now_utc();
-            let updated_at = created_at.clone();
             let expires_at = (OffsetDateTime::now_utc() + Duration::days(30));
 
             if resources.is_empty() {
@@ -228,22 +184,6 @@ macro_rules! insert_resource_batch {
                 }
             }
 
-            if <$resource as DatabaseResource>::is_creatable() {
-                if let Some(idx) = fields.iter().position(|field| field == &"created_at") {
-                    fields[idx] = "created_at";
-                } else {
-                    fields.push("created_at");
-                }
-            }
-
-            if <$resource as DatabaseResource>::is_updatable() {
-                if let Some(idx) = fields.iter().position(|field| field == &"updated_at") {
-                    fields[idx] = "updated_at";
-                } else {
-                    fields.push("updated_at");
-                }
-            }
-
             if <$resource as DatabaseResource>::is_expirable() {
                 if let Some(idx) = fields.iter().position(|field| field == &"expires_at") {
                     fields[idx] = "expires_at";
@@ -281,54 +221,14 @@ macro_rules! insert_resource_batch {
                     }
                 }
 
-                if <$resource as DatabaseResource>::is_creatable() {
-                    if let Some(idx) = input_params
-                        .iter()
-                        .position(|(field, _)| field == &"created_at")
-                    {
-                        input_params[idx] = (
-                            "created_at",
-                            DatabaseValue::DateTime(created_at.clone().to_string()),
-                        );
-                    } else {
-                        input_params.push((
-                            "created_at",
-                            DatabaseValue::DateTime(created_at.clone().to_string()),
-                        ));
-                    }
-                }
-
-                if <$resource as DatabaseResource>::is_updatable() {
-                    if let Some(idx) = input_params
-                        .iter()
-                        .position(|(field, _)| field == &"updated_at")
-                    {
-                        input_params[idx] = (
-                            "updated_at",
-                            DatabaseValue::DateTime(updated_at.clone().to_string()),
-                        );
-                    } else {
-                        input_params.push((
-                            "updated_at",
-                            DatabaseValue::DateTime(updated_at.clone().to_string()),
-                        ));
-                    }
-                }
-
                 if <$resource as DatabaseResource>::is_expirable() {
                     if let Some(idx) = input_params
                         .iter()
                         .position(|(field, _)| field == &"expires_at")
                     {
-                        input_params[idx] = (
-                            "expires_at",
-                            DatabaseValue::DateTime(expires_at.clone().to_string()),
-                        );
+                        input_params[idx] = ("expires_at", expires_at.into());
                     } else {
-                        input_params.push((
-                            "expires_at",
-                            DatabaseValue::DateTime(expires_at.clone().to_string()),
-                        ));
+                        input_params.push(("expires_at", expires_at.into()));
                     }
                 }
 
@@ -353,10 +253,8 @@ macro_rules! insert_resource_batch {
                             value_query.push_str(&format!("${}", idx));
                         }
                         DatabaseValue::DateTime(_) => {
-                            value_query.push_str(&format!(
-                                "CAST(${} AS TIMESTAMP WITHOUT TIME ZONE)",
-                                idx
-                            ));
+                            value_query
+                                .push_str(&format!("CAST(${} AS TIMESTAMP WITH TIME ZONE)", idx));
                         }
                         DatabaseValue::Int(_) | DatabaseValue::Int32(_) => {
                             value_query.push_str(&format!("CAST(${} AS INTEGER)", idx));
@@ -389,7 +287,7 @@ macro_rules! insert_resource_batch {
                 query = query.bind(value);
             }
 
-            match query.fetch_all(&pool).await {
+            match fetch_all(&resource_name, "insert_resource_batch", query).await {
                 Ok(rows) => Ok(rows
                     .into_iter()
                     .map(|row| <$resource as DatabaseResource>::from_row(&row))
@@ -68,7 +68,7 @@
 macro_rules! join_all_resources_where_fields_on {
     ($resource:ty, $join_resource:ty, $params:expr) => {{
         use crate::database::{
-            connection::get_connection, traits::DatabaseResource, values::DatabaseValue,
+            connection::fetch_all, traits::DatabaseResource, values::DatabaseValue,
         };
         use crate::utils::strings::camel_to_snake_case;
         use pluralizer::pluralize;
@@ -82,8 +82,6 @@ macro_rules! join_all_resources_where_fields_on {
             let join_resource_table_name = pluralize(&join_resource_name, 2, false);
             let join_resource_join_name = format!("{}_id", join_resource_name);
 
-            let pool = get_connection().await;
-
             let params: Vec<(&str, DatabaseValue)> = $params.clone();
             let fields = params
                 .iter()
@@ -115,7 +113,7 @@ macro_rules! join_all_resources_where_fields_on {
                 query = query.bind(value);
             }
 
-            match query.fetch_all(&pool).await {
+            match fetch_all(&resource_name, "join_all_resources_where_fields_on", query).await {
                 Ok(rows) => Ok(rows
                     .iter()
                     .map(|row| <$resource as DatabaseResource>::from_row(row).unwrap())
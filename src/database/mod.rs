@@ -23,6 +23,7 @@
 //! - `upsert_macros.rs` - Macros for upserting resources
 //! - `delete_macros.rs` - Macros for deleting resources (soft/hard delete)
 //! - `join_macros.rs` - Macros for complex queries with table joins
+//! - `schema.rs` - Startup check that every resource's table exists
 //!
 //! ## Quick Start
 //!
@@ -102,6 +103,7 @@ pub mod delete_macros;
 pub mod insert_macros;
 pub mod join_macros;
 pub mod query_macros;
+pub mod schema;
 pub mod traits;
 pub mod update_macros;
 pub mod upsert_macros;
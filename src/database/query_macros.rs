@@ -3,6 +3,11 @@
 //! This module provides macros for finding and retrieving resources from the database.
 //! All macros work with any struct that implements the `DatabaseResource` trait.
 
+use crate::database::{
+    traits::{ArchivedFilter, where_clause},
+    values::DatabaseValue,
+};
+
 /// Finds all resources matching the specified field conditions.
 ///
 /// # Arguments
@@ -49,7 +54,11 @@ macro_rules! find_all_resources_where_fields {
     ($resource:ty, $params:expr, $order_by:expr, None) => {{ find_all_resources_where_fields!($resource, $params, $order_by, Option::<String>::None) }};
     ($resource:ty, $params:expr, $order_by:expr, $order_direction:expr) => {{
         use crate::database::{
-            connection::get_connection, traits::DatabaseResource, values::DatabaseValue,
+            connection::fetch_all,
+            traits::{
+                ArchivedFilter, DatabaseResource, order_by_clause, validate_columns, where_clause,
+            },
+            values::DatabaseValue,
         };
         use crate::utils::strings::camel_to_snake_case;
         use pluralizer::pluralize;
@@ -60,47 +69,316 @@ macro_rules! find_all_resources_where_fields {
                 2,
                 false,
             );
-            let pool = get_connection().await;
 
             let params: Vec<(&str, DatabaseValue)> = $params.clone();
             let fields = params
                 .iter()
                 .map(|field| field.0.to_string())
                 .collect::<Vec<String>>();
+            validate_columns::<$resource>(&resource_name, &fields)?;
             let values = params
                 .iter()
                 .map(|field| field.1.clone())
                 .collect::<Vec<DatabaseValue>>();
 
             let mut query = format!("SELECT * FROM {}", resource_name);
-            if fields.len() > 0 {
-                query.push_str(" WHERE ");
+            query.push_str(&where_clause(ArchivedFilter::Any, &fields, &values));
+
+            query.push_str(&order_by_clause::<$resource>(
+                $order_by.map(|order_by| order_by.to_string()),
+                $order_direction.map(|order_direction| order_direction.to_string()),
+            ));
+
+            let mut query = sqlx::query(sqlx::AssertSqlSafe(query));
+            for value in values.iter() {
+                query = query.bind(value);
             }
-            for (i, field) in fields.iter().enumerate() {
-                query.push_str(&format!("{} = ${}", field, i + 1));
-                if i < fields.len() - 1 {
-                    query.push_str(" AND ");
-                }
+
+            match fetch_all(&resource_name, "find_all_resources_where_fields", query).await {
+                Ok(rows) => Ok(rows
+                    .into_iter()
+                    .map(|row| <$resource as DatabaseResource>::from_row(&row))
+                    .collect::<Result<Vec<$resource>, _>>()?),
+                Err(e) => Err(anyhow::Error::msg(e.to_string())),
             }
+        }
+    }};
+}
 
-            let order_by = match $order_by {
-                Some(order_by) => order_by.to_string(),
-                None => "updated_at".to_string(),
-            };
+/// Finds all resources matching the specified field conditions, sorted by one column.
+///
+/// Unlike passing an order to `find_all_resources_where_fields!`, the column is
+/// checked with `order_column` and the direction is an `OrderDirection`, so neither
+/// can carry SQL. Rows that tie on the column are ordered by `id`.
+///
+/// # Arguments
+/// * `$resource` - The resource type (must implement DatabaseResource)
+/// * `$params` - Vector of `(&str, DatabaseValue)` tuples for field conditions
+/// * `$order_by` - The column to sort by
+/// * `$direction` - `OrderDirection` to sort in
+///
+/// # Returns
+/// `Result<Vec<Resource>, Error>` - Vector of matching resources or database error
+///
+/// # Example
+/// ```rust
+/// let params = vec![("wallet_id", "123".into())];
+/// let history = find_all_resources_where_fields_ordered!(
+///     Transaction,
+///     params,
+///     "created_at",
+///     OrderDirection::Desc
+/// )
+/// .await?;
+/// ```
+#[macro_export]
+macro_rules! find_all_resources_where_fields_ordered {
+    ($resource:ty, $params:expr, $order_by:expr, $direction:expr) => {{
+        use crate::database::traits::order_column;
+        use crate::utils::strings::camel_to_snake_case;
+        use pluralizer::pluralize;
 
-            let order_direction = match $order_direction {
-                Some(order_direction) => order_direction.to_string(),
-                None => "ASC".to_string(),
-            };
+        async {
+            let resource_name = pluralize(
+                camel_to_snake_case(stringify!($resource).to_string()).as_str(),
+                2,
+                false,
+            );
+            let order_by = order_column::<$resource>(&resource_name, &$order_by.to_string())?;
+            let direction: crate::database::traits::OrderDirection = $direction;
+            $crate::find_all_resources_where_fields!(
+                $resource,
+                $params,
+                Some(order_by),
+                Some(direction)
+            )
+            .await
+        }
+    }};
+}
 
-            query.push_str(&format!(" ORDER BY {} {}", order_by, order_direction));
+/// Finds one page of resources matching the specified field conditions.
+///
+/// Rows come in `DatabaseResource::default_order_by()` order with `id` breaking
+/// ties, so consecutive pages neither repeat nor skip rows. The limit and offset
+/// are bound as `BIGINT` parameters rather than written into the SQL.
+///
+/// # Arguments
+/// * `$resource` - The resource type (must implement DatabaseResource)
+/// * `$params` - Vector of `(&str, DatabaseValue)` tuples for field conditions
+/// * `$limit` - Maximum number of resources to return, as `i64`
+/// * `$offset` - Number of matching resources to skip, as `i64`
+///
+/// # Returns
+/// `Result<Vec<Resource>, Error>` - Vector of matching resources or database error
+///
+/// # Example
+/// ```rust
+/// let params = vec![("user_id", "123".into())];
+/// let second_page = find_all_resources_where_fields_paginated!(Mnstr, params, 25, 25).await?;
+/// ```
+#[macro_export]
+macro_rules! find_all_resources_where_fields_paginated {
+    ($resource:ty, $params:expr, $limit:expr, $offset:expr) => {{
+        use crate::database::{
+            connection::fetch_all,
+            traits::{
+                ArchivedFilter, DatabaseResource, order_by_clause, validate_columns, where_clause,
+            },
+            values::DatabaseValue,
+        };
+        use crate::utils::strings::camel_to_snake_case;
+        use pluralizer::pluralize;
+
+        async {
+            let resource_name = pluralize(
+                camel_to_snake_case(stringify!($resource).to_string()).as_str(),
+                2,
+                false,
+            );
+
+            let params: Vec<(&str, DatabaseValue)> = $params.clone();
+            let fields = params
+                .iter()
+                .map(|field| field.0.to_string())
+                .collect::<Vec<String>>();
+            validate_columns::<$resource>(&resource_name, &fields)?;
+            let values = params
+                .iter()
+                .map(|field| field.1.clone())
+                .collect::<Vec<DatabaseValue>>();
+            let limit: i64 = $limit;
+            let offset: i64 = $offset;
+
+            let mut query = format!("SELECT * FROM {}", resource_name);
+            query.push_str(&where_clause(ArchivedFilter::Any, &fields, &values));
+            query.push_str(&order_by_clause::<$resource>(None, None));
+            query.push_str(&format!(
+                " LIMIT ${}::BIGINT OFFSET ${}::BIGINT",
+                values.len() + 1,
+                values.len() + 2
+            ));
 
             let mut query = sqlx::query(sqlx::AssertSqlSafe(query));
             for value in values.iter() {
                 query = query.bind(value);
             }
+            query = query.bind(limit).bind(offset);
+
+            match fetch_all(
+                &resource_name,
+                "find_all_resources_where_fields_paginated",
+                query,
+            )
+            .await
+            {
+                Ok(rows) => Ok(rows
+                    .into_iter()
+                    .map(|row| <$resource as DatabaseResource>::from_row(&row))
+                    .collect::<Result<Vec<$resource>, _>>()?),
+                Err(e) => Err(anyhow::Error::msg(e.to_string())),
+            }
+        }
+    }};
+}
 
-            match query.fetch_all(&pool).await {
+/// Finds all resources matching any of the specified field conditions.
+///
+/// The conditions are joined with `OR` instead of `AND`, for lookups where a
+/// user may identify themselves by one of several fields. Use
+/// `find_all_resources_where_fields!` when every condition must hold. With no
+/// params nothing is returned.
+///
+/// # Arguments
+/// * `$resource` - The resource type (must implement DatabaseResource)
+/// * `$params` - Vector of `(&str, DatabaseValue)` tuples for field conditions
+///
+/// # Returns
+/// `Result<Vec<Resource>, Error>` - Vector of matching resources or database error
+///
+/// # Example
+/// ```rust
+/// let params = vec![
+///     ("email", "player@mnstr.app".into()),
+///     ("phone", "+15555550123".into())
+/// ];
+/// let users = find_all_resources_where_any_fields!(User, params).await?;
+/// ```
+#[macro_export]
+macro_rules! find_all_resources_where_any_fields {
+    ($resource:ty, $params:expr) => {{
+        use crate::database::{
+            connection::fetch_all,
+            traits::{
+                ArchivedFilter, DatabaseResource, order_by_clause, validate_columns,
+                where_any_clause,
+            },
+            values::DatabaseValue,
+        };
+        use crate::utils::strings::camel_to_snake_case;
+        use pluralizer::pluralize;
+
+        async {
+            let resource_name = pluralize(
+                camel_to_snake_case(stringify!($resource).to_string()).as_str(),
+                2,
+                false,
+            );
+
+            let params: Vec<(&str, DatabaseValue)> = $params.clone();
+            let fields = params
+                .iter()
+                .map(|field| field.0.to_string())
+                .collect::<Vec<String>>();
+            validate_columns::<$resource>(&resource_name, &fields)?;
+            let values = params
+                .iter()
+                .map(|field| field.1.clone())
+                .collect::<Vec<DatabaseValue>>();
+
+            let mut query = format!("SELECT * FROM {}", resource_name);
+            query.push_str(&where_any_clause(ArchivedFilter::Any, &fields, &values));
+            query.push_str(&order_by_clause::<$resource>(None, None));
+
+            let mut query = sqlx::query(sqlx::AssertSqlSafe(query));
+            for value in values.iter() {
+                query = query.bind(value);
+            }
+
+            match fetch_all(&resource_name, "find_all_resources_where_any_fields", query).await {
+                Ok(rows) => Ok(rows
+                    .into_iter()
+                    .map(|row| <$resource as DatabaseResource>::from_row(&row))
+                    .collect::<Result<Vec<$resource>, _>>()?),
+                Err(e) => Err(anyhow::Error::msg(e.to_string())),
+            }
+        }
+    }};
+}
+
+/// Finds all resources matching every one of the specified comparisons.
+///
+/// Each condition compares a field with an `Operator`, so unlike the
+/// `where_fields` macros it can express ranges such as expired sessions or a
+/// minimum level. Values are bound in order, like the other macros.
+///
+/// # Arguments
+/// * `$resource` - The resource type (must implement DatabaseResource)
+/// * `$conditions` - Vector of `(&str, Operator, DatabaseValue)` tuples
+///
+/// # Returns
+/// `Result<Vec<Resource>, Error>` - Vector of matching resources or database error
+///
+/// # Example
+/// ```rust
+/// let conditions = vec![("expires_at", Operator::Lt, OffsetDateTime::now_utc().into())];
+/// let expired = find_all_resources_where_compare!(Session, conditions).await?;
+/// ```
+#[macro_export]
+macro_rules! find_all_resources_where_compare {
+    ($resource:ty, $conditions:expr) => {{
+        use crate::database::{
+            connection::fetch_all,
+            traits::{
+                DatabaseResource, Operator, compare_clause, order_by_clause, validate_columns,
+            },
+            values::DatabaseValue,
+        };
+        use crate::utils::strings::camel_to_snake_case;
+        use pluralizer::pluralize;
+
+        async {
+            let resource_name = pluralize(
+                camel_to_snake_case(stringify!($resource).to_string()).as_str(),
+                2,
+                false,
+            );
+
+            let conditions: Vec<(&str, Operator, DatabaseValue)> = $conditions.clone();
+            let fields = conditions
+                .iter()
+                .map(|condition| condition.0.to_string())
+                .collect::<Vec<String>>();
+            validate_columns::<$resource>(&resource_name, &fields)?;
+            let operators = conditions
+                .iter()
+                .map(|condition| condition.1)
+                .collect::<Vec<Operator>>();
+            let values = conditions
+                .iter()
+                .map(|condition| condition.2.clone())
+                .collect::<Vec<DatabaseValue>>();
+
+            let mut query = format!("SELECT * FROM {}", resource_name);
+            query.push_str(&compare_clause(&fields, &operators, &values));
+            query.push_str(&order_by_clause::<$resource>(None, None));
+
+            let mut query = sqlx::query(sqlx::AssertSqlSafe(query));
+            for value in values.iter() {
+                query = query.bind(value);
+            }
+
+            match fetch_all(&resource_name, "find_all_resources_where_compare", query).await {
                 Ok(rows) => Ok(rows
                     .into_iter()
                     .map(|row| <$resource as DatabaseResource>::from_row(&row))
@@ -163,7 +441,11 @@ macro_rules! find_all_unarchived_resources_where_fields {
     }};
     ($resource:ty, $params:expr, $order_by:expr, $order_direction:expr) => {{
         use crate::database::{
-            connection::get_connection, traits::DatabaseResource, values::DatabaseValue,
+            connection::fetch_all,
+            traits::{
+                ArchivedFilter, DatabaseResource, order_by_clause, validate_columns, where_clause,
+            },
+            values::DatabaseValue,
         };
         use crate::utils::strings::camel_to_snake_case;
         use pluralizer::pluralize;
@@ -174,48 +456,41 @@ macro_rules! find_all_unarchived_resources_where_fields {
                 2,
                 false,
             );
-            let pool = get_connection().await;
 
             let params: Vec<(&str, DatabaseValue)> = $params.clone();
             let fields = params
                 .iter()
                 .map(|field| field.0.to_string())
                 .collect::<Vec<String>>();
-            let values = params.iter().map(|field| &field.1).collect::<Vec<_>>();
+            validate_columns::<$resource>(&resource_name, &fields)?;
+            let values = params
+                .iter()
+                .map(|field| field.1.clone())
+                .collect::<Vec<DatabaseValue>>();
 
-            let mut query = format!("SELECT * FROM {} WHERE archived_at IS NULL", resource_name);
-            if fields.len() > 0 {
-                query.push_str(" AND ");
-            }
-            for (i, field) in fields.iter().enumerate() {
-                query.push_str(&format!("{} = ${}", field, i + 1));
-                if i < fields.len() - 1 {
-                    query.push_str(" AND ");
-                }
-            }
+            let mut query = format!("SELECT * FROM {}", resource_name);
+            query.push_str(&where_clause(ArchivedFilter::Unarchived, &fields, &values));
+            query.push_str(&order_by_clause::<$resource>(
+                $order_by.map(|order_by| order_by.to_string()),
+                $order_direction.map(|order_direction| order_direction.to_string()),
+            ));
 
             let mut query = sqlx::query(sqlx::AssertSqlSafe(query));
-            for (_, value) in values.iter().enumerate() {
+            for value in values.iter() {
                 query = query.bind(value);
             }
 
-            let order_by = match $order_by {
-                Some(order_by) => order_by.to_string(),
-                None => "updated_at".to_string(),
-            };
-
-            let order_direction = match $order_direction {
-                Some(order_direction) => order_direction.to_string(),
-                None => "ASC".to_string(),
-            };
-
-            query.push_str(&format!(" ORDER BY {} {}", order_by, order_direction));
-
-            match query.fetch_all(&pool).await {
-                Ok(rows) => rows
+            match fetch_all(
+                &resource_name,
+                "find_all_unarchived_resources_where_fields",
+                query,
+            )
+            .await
+            {
+                Ok(rows) => Ok(rows
                     .into_iter()
                     .map(|row| <$resource as DatabaseResource>::from_row(&row))
-                    .collect::<Result<Vec<$resource>, _>>(),
+                    .collect::<Result<Vec<$resource>, _>>()?),
                 Err(e) => Err(anyhow::Error::msg(e.to_string())),
             }
         }
@@ -274,7 +549,11 @@ macro_rules! find_all_archived_resources_where_fields {
     }};
     ($resource:ty, $params:expr, $order_by:expr, $order_direction:expr) => {{
         use crate::database::{
-            connection::get_connection, traits::DatabaseResource, values::DatabaseValue,
+            connection::fetch_all,
+            traits::{
+                ArchivedFilter, DatabaseResource, order_by_clause, validate_columns, where_clause,
+            },
+            values::DatabaseValue,
         };
         use crate::utils::strings::camel_to_snake_case;
         use pluralizer::pluralize;
@@ -285,23 +564,120 @@ macro_rules! find_all_archived_resources_where_fields {
                 2,
                 false,
             );
-            let pool = get_connection().await;
 
             let params: Vec<(&str, DatabaseValue)> = $params.clone();
             let fields = params
                 .iter()
                 .map(|field| field.0.to_string())
                 .collect::<Vec<String>>();
-            let values = params.iter().map(|field| &field.1).collect::<Vec<_>>();
-            let mut query = format!(
-                "SELECT * FROM {} WHERE archived_at IS NOT NULL",
-                resource_name
+            validate_columns::<$resource>(&resource_name, &fields)?;
+            let values = params
+                .iter()
+                .map(|field| field.1.clone())
+                .collect::<Vec<DatabaseValue>>();
+
+            let mut query = format!("SELECT * FROM {}", resource_name);
+            query.push_str(&where_clause(ArchivedFilter::Archived, &fields, &values));
+            query.push_str(&order_by_clause::<$resource>(
+                $order_by.map(|order_by| order_by.to_string()),
+                $order_direction.map(|order_direction| order_direction.to_string()),
+            ));
+
+            let mut query = sqlx::query(sqlx::AssertSqlSafe(query));
+            for value in values.iter() {
+                query = query.bind(value);
+            }
+
+            match fetch_all(
+                &resource_name,
+                "find_all_archived_resources_where_fields",
+                query,
+            )
+            .await
+            {
+                Ok(rows) => Ok(rows
+                    .into_iter()
+                    .map(|row| <$resource as DatabaseResource>::from_row(&row))
+                    .collect::<Result<Vec<$resource>, _>>()?),
+                Err(e) => Err(anyhow::Error::msg(e.to_string())),
+            }
+        }
+    }};
+}
+
+/// Finds a single resource matching the specified field conditions.
+///
+/// This macro generates a SELECT query with WHERE clauses and LIMIT 1 to return
+/// exactly one resource. If multiple resources match, only the first one is returned.
+///
+/// # Arguments
+/// * `$resource` - The resource type (must implement DatabaseResource)
+/// * `$params` - Vector of `(&str, DatabaseValue)` tuples for field conditions
+///
+/// # Returns
+/// `Result<Resource, Error>` - Single matching resource or database error
+///
+/// # Example
+/// ```rust
+/// let params = vec![("email", "user@example.com".into())];
+/// let user = find_one_resource_where_fields!(User, params).await?;
+/// ```
+#[macro_export]
+macro_rules! find_one_resource_where_fields {
+    ($resource:ty, $params:expr) => {{
+        find_one_resource_where_fields!(
+            $resource,
+            $params,
+            Option::<String>::None,
+            Option::<String>::None
+        )
+    }};
+    ($resource:ty, $params:expr, None, None) => {{
+        find_one_resource_where_fields!(
+            $resource,
+            $params,
+            Option::<String>::None,
+            Option::<String>::None
+        )
+    }};
+    ($resource:ty, $params:expr, None, $order_direction:expr) => {{
+        find_one_resource_where_fields!(
+            $resource,
+            $params,
+            Option::<String>::None,
+            $order_direction
+        )
+    }};
+    ($resource:ty, $params:expr, $order_by:expr, None) => {{ find_one_resource_where_fields!($resource, $params, $order_by, Option::<String>::None) }};
+    ($resource:ty, $params:expr, $order_by:expr, $order_direction:expr) => {{
+        use crate::database::{
+            connection::fetch_one,
+            traits::{DatabaseResource, validate_columns},
+            values::DatabaseValue,
+        };
+        use crate::utils::strings::camel_to_snake_case;
+        use pluralizer::pluralize;
+
+        async {
+            let resource_name = pluralize(
+                camel_to_snake_case(stringify!($resource).to_string()).as_str(),
+                2,
+                false,
             );
+
+            let params: Vec<(&str, DatabaseValue)> = $params.clone();
+            let fields = params
+                .iter()
+                .map(|field| field.0.to_string())
+                .collect::<Vec<String>>();
+            validate_columns::<$resource>(&resource_name, &fields)?;
+            let values = params.iter().map(|field| &field.1).collect::<Vec<_>>();
+            let mut query = format!("SELECT * FROM {}", resource_name);
             if fields.len() > 0 {
-                query.push_str(" AND ");
+                query.push_str(" WHERE ");
             }
             for (i, field) in fields.iter().enumerate() {
-                query.push_str(&format!("{} = ${}", field, i + 1));
+                query.push_str(&values[i].condition(field, i + 1));
                 if i < fields.len() - 1 {
                     query.push_str(" AND ");
                 }
@@ -319,69 +695,192 @@ macro_rules! find_all_archived_resources_where_fields {
 
             query.push_str(&format!(" ORDER BY {} {}", order_by, order_direction));
 
+            query.push_str(" LIMIT 1");
+
+            let mut query = sqlx::query(sqlx::AssertSqlSafe(query));
+            for (_, value) in values.iter().enumerate() {
+                query = query.bind(value);
+            }
+
+            match fetch_one(&resource_name, "find_one_resource_where_fields", query).await {
+                Ok(row) => Ok(<$resource as DatabaseResource>::from_row(&row)?),
+                Err(e) => Err(anyhow::Error::msg(e.to_string())),
+            }
+        }
+    }};
+}
+
+/// Finds a single resource matching the specified field conditions, if one exists.
+///
+/// Unlike `find_one_resource_where_fields!`, a missing row is not an error: it
+/// resolves to `Ok(None)`, while connection and query failures still return `Err`.
+///
+/// # Arguments
+/// * `$resource` - The resource type (must implement DatabaseResource)
+/// * `$params` - Vector of `(&str, DatabaseValue)` tuples for field conditions
+///
+/// # Returns
+/// `Result<Option<Resource>, Error>` - The matching resource, `None`, or database error
+///
+/// # Example
+/// ```rust
+/// let params = vec![("user_id", "123".into())];
+/// if let Some(wallet) = find_optional_resource_where_fields!(Wallet, params).await? {
+///     // ...
+/// }
+/// ```
+#[macro_export]
+macro_rules! find_optional_resource_where_fields {
+    ($resource:ty, $params:expr) => {{
+        use crate::database::{
+            connection::fetch_one,
+            query_macros::optional_result,
+            traits::{DatabaseResource, validate_columns},
+            values::DatabaseValue,
+        };
+        use crate::utils::strings::camel_to_snake_case;
+        use pluralizer::pluralize;
+
+        async {
+            let resource_name = pluralize(
+                camel_to_snake_case(stringify!($resource).to_string()).as_str(),
+                2,
+                false,
+            );
+
+            let params: Vec<(&str, DatabaseValue)> = $params.clone();
+            let fields = params
+                .iter()
+                .map(|field| field.0.to_string())
+                .collect::<Vec<String>>();
+            validate_columns::<$resource>(&resource_name, &fields)?;
+            let values = params.iter().map(|field| &field.1).collect::<Vec<_>>();
+            let mut query = format!("SELECT * FROM {}", resource_name);
+            if fields.len() > 0 {
+                query.push_str(" WHERE ");
+            }
+            for (i, field) in fields.iter().enumerate() {
+                query.push_str(&values[i].condition(field, i + 1));
+                if i < fields.len() - 1 {
+                    query.push_str(" AND ");
+                }
+            }
+            query.push_str(" ORDER BY updated_at ASC LIMIT 1");
+
+            let mut query = sqlx::query(sqlx::AssertSqlSafe(query));
+            for value in values.iter() {
+                query = query.bind(value);
+            }
+
+            match optional_result(
+                fetch_one(&resource_name, "find_optional_resource_where_fields", query).await,
+            )? {
+                Some(row) => Ok(Some(<$resource as DatabaseResource>::from_row(&row)?)),
+                None => Ok::<_, anyhow::Error>(None),
+            }
+        }
+    }};
+}
+
+/// Counts the resources matching the specified field conditions without fetching them.
+///
+/// # Arguments
+/// * `$resource` - The resource type (must implement DatabaseResource)
+/// * `$params` - Vector of `(&str, DatabaseValue)` tuples for field conditions
+///
+/// # Returns
+/// `Result<i64, Error>` - Number of matching resources or database error
+///
+/// # Example
+/// ```rust
+/// let params = vec![("status", BattleStatusState::InQueue.to_string().into())];
+/// let queued = count_resources_where_fields!(BattleStatus, params).await?;
+/// ```
+#[macro_export]
+macro_rules! count_resources_where_fields {
+    ($resource:ty, $params:expr) => {{
+        use crate::database::{
+            connection::fetch_one, query_macros::count_query, traits::validate_columns,
+            values::DatabaseValue,
+        };
+        use crate::utils::strings::camel_to_snake_case;
+        use pluralizer::pluralize;
+        use sqlx::Row;
+
+        async {
+            let resource_name = pluralize(
+                camel_to_snake_case(stringify!($resource).to_string()).as_str(),
+                2,
+                false,
+            );
+
+            let params: Vec<(&str, DatabaseValue)> = $params.clone();
+            let fields = params
+                .iter()
+                .map(|field| field.0.to_string())
+                .collect::<Vec<String>>();
+            validate_columns::<$resource>(&resource_name, &fields)?;
+            let values = params
+                .iter()
+                .map(|field| field.1.clone())
+                .collect::<Vec<DatabaseValue>>();
+
+            let query = count_query(&resource_name, &fields, &values);
             let mut query = sqlx::query(sqlx::AssertSqlSafe(query));
-            for (_, value) in values.iter().enumerate() {
+            for value in values.iter() {
                 query = query.bind(value);
             }
 
-            match query.fetch_all(&pool).await {
-                Ok(rows) => rows
-                    .into_iter()
-                    .map(|row| <$resource as DatabaseResource>::from_row(&row))
-                    .collect::<Result<Vec<$resource>, _>>(),
+            match fetch_one(&resource_name, "count_resources_where_fields", query).await {
+                Ok(row) => Ok(row.try_get::<i64, _>("count")?),
                 Err(e) => Err(anyhow::Error::msg(e.to_string())),
             }
         }
     }};
 }
 
-/// Finds a single resource matching the specified field conditions.
+/// Builds the `SELECT COUNT(*)` for `count_resources_where_fields!`, with the
+/// same conditions as the `find_all*` macros and no `WHERE` when there are none.
+pub fn count_query(resource_name: &str, fields: &[String], values: &[DatabaseValue]) -> String {
+    format!(
+        "SELECT COUNT(*) AS count FROM {}{}",
+        resource_name,
+        where_clause(ArchivedFilter::Any, fields, values)
+    )
+}
+
+/// Finds the unarchived resources matching the specified field conditions and
+/// locks their rows until the surrounding `transaction` ends.
 ///
-/// This macro generates a SELECT query with WHERE clauses and LIMIT 1 to return
-/// exactly one resource. If multiple resources match, only the first one is returned.
+/// Rows are locked in `id` order, so transactions locking the same rows wait
+/// for each other instead of deadlocking. Outside a `transaction` the lock is
+/// released as soon as the query returns.
 ///
 /// # Arguments
 /// * `$resource` - The resource type (must implement DatabaseResource)
 /// * `$params` - Vector of `(&str, DatabaseValue)` tuples for field conditions
 ///
 /// # Returns
-/// `Result<Resource, Error>` - Single matching resource or database error
+/// `Result<Vec<Resource>, Error>` - The locked resources or database error
 ///
 /// # Example
 /// ```rust
-/// let params = vec![("email", "user@example.com".into())];
-/// let user = find_one_resource_where_fields!(User, params).await?;
+/// transaction(async {
+///     let params = vec![("id", user_id.into())];
+///     let users = lock_resources_where_fields!(User, params).await?;
+///     // No one else can update or archive the user until this returns
+///     Ok(users)
+/// })
+/// .await?;
 /// ```
 #[macro_export]
-macro_rules! find_one_resource_where_fields {
+macro_rules! lock_resources_where_fields {
     ($resource:ty, $params:expr) => {{
-        find_one_resource_where_fields!(
-            $resource,
-            $params,
-            Option::<String>::None,
-            Option::<String>::None
-        )
-    }};
-    ($resource:ty, $params:expr, None, None) => {{
-        find_one_resource_where_fields!(
-            $resource,
-            $params,
-            Option::<String>::None,
-            Option::<String>::None
-        )
-    }};
-    ($resource:ty, $params:expr, None, $order_direction:expr) => {{
-        find_one_resource_where_fields!(
-            $resource,
-            $params,
-            Option::<String>::None,
-            $order_direction
-        )
-    }};
-    ($resource:ty, $params:expr, $order_by:expr, None) => {{ find_one_resource_where_fields!($resource, $params, $order_by, Option::<String>::None) }};
-    ($resource:ty, $params:expr, $order_by:expr, $order_direction:expr) => {{
         use crate::database::{
-            connection::get_connection, traits::DatabaseResource, values::DatabaseValue,
+            connection::fetch_all,
+            query_macros::lock_query,
+            traits::{DatabaseResource, validate_columns},
+            values::DatabaseValue,
         };
         use crate::utils::strings::camel_to_snake_case;
         use pluralizer::pluralize;
@@ -392,52 +891,55 @@ macro_rules! find_one_resource_where_fields {
                 2,
                 false,
             );
-            let pool = get_connection().await;
 
             let params: Vec<(&str, DatabaseValue)> = $params.clone();
             let fields = params
                 .iter()
                 .map(|field| field.0.to_string())
                 .collect::<Vec<String>>();
-            let values = params.iter().map(|field| &field.1).collect::<Vec<_>>();
-            let mut query = format!("SELECT * FROM {}", resource_name);
-            if fields.len() > 0 {
-                query.push_str(" WHERE ");
-            }
-            for (i, field) in fields.iter().enumerate() {
-                query.push_str(&format!("{} = ${}", field, i + 1));
-                if i < fields.len() - 1 {
-                    query.push_str(" AND ");
-                }
-            }
-
-            let order_by = match $order_by {
-                Some(order_by) => order_by.to_string(),
-                None => "updated_at".to_string(),
-            };
-
-            let order_direction = match $order_direction {
-                Some(order_direction) => order_direction.to_string(),
-                None => "ASC".to_string(),
-            };
-
-            query.push_str(&format!(" ORDER BY {} {}", order_by, order_direction));
-
-            query.push_str(" LIMIT 1");
+            validate_columns::<$resource>(&resource_name, &fields)?;
+            let values = params
+                .iter()
+                .map(|field| field.1.clone())
+                .collect::<Vec<DatabaseValue>>();
 
+            let query = lock_query(&resource_name, &fields, &values);
             let mut query = sqlx::query(sqlx::AssertSqlSafe(query));
-            for (_, value) in values.iter().enumerate() {
+            for value in values.iter() {
                 query = query.bind(value);
             }
 
-            match query.fetch_one(&pool).await {
-                Ok(row) => Ok(<$resource as DatabaseResource>::from_row(&row)?),
+            match fetch_all(&resource_name, "lock_resources_where_fields", query).await {
+                Ok(rows) => Ok(rows
+                    .into_iter()
+                    .map(|row| <$resource as DatabaseResource>::from_row(&row))
+                    .collect::<Result<Vec<$resource>, _>>()?),
                 Err(e) => Err(anyhow::Error::msg(e.to_string())),
             }
         }
     }};
 }
 
+/// Builds the `SELECT ... FOR UPDATE` for `lock_resources_where_fields!`.
+pub fn lock_query(resource_name: &str, fields: &[String], values: &[DatabaseValue]) -> String {
+    format!(
+        "SELECT * FROM {}{} ORDER BY id FOR UPDATE",
+        resource_name,
+        where_clause(ArchivedFilter::Unarchived, fields, values)
+    )
+}
+
+/// Converts a single-row query result into an optional one.
+///
+/// `RowNotFound` becomes `Ok(None)`; every other error is propagated.
+pub fn optional_result<T>(result: Result<T, sqlx::Error>) -> Result<Option<T>, anyhow::Error> {
+    match result {
+        Ok(value) => Ok(Some(value)),
+        Err(sqlx::Error::RowNotFound) => Ok(None),
+        Err(e) => Err(anyhow::Error::msg(e.to_string())),
+    }
+}
+
 /// Finds a single unarchived resource matching the specified field conditions.
 ///
 /// This macro generates a SELECT query that returns exactly one unarchived resource
@@ -491,7 +993,7 @@ macro_rules! find_one_unarchived_resource_where_fields {
     }};
     ($resource:ty, $params:expr, $order_by:expr, $order_direction:expr) => {{
         use crate::database::{
-            connection::get_connection, traits::DatabaseResource, values::DatabaseValue,
+            connection::fetch_one, traits::DatabaseResource, values::DatabaseValue,
         };
         use crate::utils::strings::camel_to_snake_case;
         use pluralizer::pluralize;
@@ -502,7 +1004,6 @@ macro_rules! find_one_unarchived_resource_where_fields {
                 2,
                 false,
             );
-            let pool = get_connection().await;
 
             let params: Vec<(&str, DatabaseValue)> = $params.clone();
             let fields = params
@@ -515,7 +1016,7 @@ macro_rules! find_one_unarchived_resource_where_fields {
                 query.push_str(" AND ");
             }
             for (i, field) in fields.iter().enumerate() {
-                query.push_str(&format!("{} = ${}", field, i + 1));
+                query.push_str(&values[i].condition(field, i + 1));
                 if i < fields.len() - 1 {
                     query.push_str(" AND ");
                 }
@@ -540,7 +1041,13 @@ macro_rules! find_one_unarchived_resource_where_fields {
                 query = query.bind(value);
             }
 
-            match query.fetch_one(&pool).await {
+            match fetch_one(
+                &resource_name,
+                "find_one_unarchived_resource_where_fields",
+                query,
+            )
+            .await
+            {
                 Ok(row) => Ok(<$resource as DatabaseResource>::from_row(&row)?),
                 Err(e) => Err(e),
             }
@@ -568,8 +1075,42 @@ macro_rules! find_one_unarchived_resource_where_fields {
 #[macro_export]
 macro_rules! find_one_archived_resource_where_fields {
     ($resource:ty, $params:expr) => {{
+        find_one_archived_resource_where_fields!(
+            $resource,
+            $params,
+            Option::<String>::None,
+            Option::<String>::None
+        )
+    }};
+    ($resource:ty, $params:expr, None, None) => {{
+        find_one_archived_resource_where_fields!(
+            $resource,
+            $params,
+            Option::<String>::None,
+            Option::<String>::None
+        )
+    }};
+    ($resource:ty, $params:expr, None, $order_direction:expr) => {{
+        find_one_archived_resource_where_fields!(
+            $resource,
+            $params,
+            Option::<String>::None,
+            $order_direction
+        )
+    }};
+    ($resource:ty, $params:expr, $order_by:expr, None) => {{
+        find_one_archived_resource_where_fields!(
+            $resource,
+            $params,
+            $order_by,
+            Option::<String>::None
+        )
+    }};
+    ($resource:ty, $params:expr, $order_by:expr, $order_direction:expr) => {{
         use crate::database::{
-            connection::get_connection, traits::DatabaseResource, values::DatabaseValue,
+            connection::fetch_one,
+            traits::{ArchivedFilter, DatabaseResource, validate_columns, where_clause},
+            values::DatabaseValue,
         };
         use crate::utils::strings::camel_to_snake_case;
         use pluralizer::pluralize;
@@ -580,28 +1121,20 @@ macro_rules! find_one_archived_resource_where_fields {
                 2,
                 false,
             );
-            let pool = get_connection().await;
-
-            let mut query = format!(
-                "SELECT * FROM {} WHERE archived_at IS NOT NULL",
-                resource_name
-            );
-            if fields.len() > 0 {
-                query.push_str(" AND ");
-            }
 
             let params: Vec<(&str, DatabaseValue)> = $params.clone();
             let fields = params
                 .iter()
                 .map(|field| field.0.to_string())
                 .collect::<Vec<String>>();
+            validate_columns::<$resource>(&resource_name, &fields)?;
+            let values = params
+                .iter()
+                .map(|field| field.1.clone())
+                .collect::<Vec<DatabaseValue>>();
 
-            for (i, field) in fields.iter().enumerate() {
-                query.push_str(&format!("{} = ${}", field, i + 1));
-                if i < fields.len() - 1 {
-                    query.push_str(" AND ");
-                }
-            }
+            let mut query = format!("SELECT * FROM {}", resource_name);
+            query.push_str(&where_clause(ArchivedFilter::Archived, &fields, &values));
 
             let order_by = match $order_by {
                 Some(order_by) => order_by.to_string(),
@@ -617,12 +1150,18 @@ macro_rules! find_one_archived_resource_where_fields {
 
             query.push_str(" LIMIT 1");
 
-            let mut query = sqlx::query(&query);
-            for (_, value) in params.iter().enumerate() {
-                query = query.bind(value.1.clone());
+            let mut query = sqlx::query(sqlx::AssertSqlSafe(query));
+            for value in values.iter() {
+                query = query.bind(value);
             }
 
-            match query.fetch_one(&pool).await {
+            match fetch_one(
+                &resource_name,
+                "find_one_archived_resource_where_fields",
+                query,
+            )
+            .await
+            {
                 Ok(row) => Ok(<$resource as DatabaseResource>::from_row(&row)?),
                 Err(e) => Err(anyhow::Error::msg(e.to_string())),
             }
@@ -693,7 +1232,7 @@ macro_rules! find_all_resources_where_fields_like {
         )
     }};
     ($resource:ty, $params:expr, $search_term:expr, $order_by:expr, $order_direction:expr) => {{
-        use crate::database::{connection::get_connection, traits::DatabaseResource};
+        use crate::database::{connection::fetch_all, traits::DatabaseResource};
         use crate::utils::strings::camel_to_snake_case;
         use pluralizer::pluralize;
 
@@ -703,7 +1242,6 @@ macro_rules! find_all_resources_where_fields_like {
                 2,
                 false,
             );
-            let pool = get_connection().await;
 
             let params: Vec<&str> = $params.clone();
 
@@ -735,7 +1273,13 @@ macro_rules! find_all_resources_where_fields_like {
                 query = query.bind(format!("%{}%", $search_term));
             }
 
-            match query.fetch_all(&pool).await {
+            match fetch_all(
+                &resource_name,
+                "find_all_resources_where_fields_like",
+                query,
+            )
+            .await
+            {
                 Ok(rows) => Ok(rows
                     .into_iter()
                     .map(|row| <$resource as DatabaseResource>::from_row(&row))
@@ -746,15 +1290,18 @@ macro_rules! find_all_resources_where_fields_like {
     }};
 }
 
-/// Finds all resources matching the specified field conditions with IN operator.
+/// Finds all resources whose field has one of the given values, in one query.
 ///
-/// This macro generates a SELECT query that returns all resources where the specified field
-/// is in the list of values.
+/// Each value gets its own placeholder, `WHERE field IN ($1, $2, ...)`. An empty
+/// list returns an empty `Vec` without querying the database. Results are ordered
+/// like `find_all_resources_where_fields!`, by `created_at` unless an order is given.
 ///
 /// # Arguments
 /// * `$resource` - The resource type (must implement DatabaseResource)
 /// * `$field` - The field to check for IN condition
-/// * `$values` - Vector of values to check for IN condition
+/// * `$values` - Vector of values to check for IN condition, anything convertible into `DatabaseValue`
+/// * `$order_by` - Optional field to order by
+/// * `$order_direction` - Optional order direction (ASC or DESC)
 ///
 /// # Returns
 /// `Result<Vec<Resource>, Error>` - Vector of matching resources or database error
@@ -803,46 +1350,218 @@ macro_rules! find_all_resources_where_fields_in {
         )
     }};
     ($resource:ty, $field:expr, $values:expr, $order_by:expr, $order_direction:expr) => {{
-        use crate::database::{connection::get_connection, traits::DatabaseResource};
+        use crate::database::{
+            connection::fetch_all,
+            traits::{DatabaseResource, in_clause, order_by_clause, validate_columns},
+            values::DatabaseValue,
+        };
         use crate::utils::strings::camel_to_snake_case;
         use pluralizer::pluralize;
 
         async {
+            let values = $values
+                .iter()
+                .cloned()
+                .map(DatabaseValue::from)
+                .collect::<Vec<DatabaseValue>>();
+            if values.is_empty() {
+                return Ok(Vec::<$resource>::new());
+            }
+
             let resource_name = pluralize(
                 camel_to_snake_case(stringify!($resource).to_string()).as_str(),
                 2,
                 false,
             );
-            let pool = get_connection().await;
+            let field = $field.to_string();
+            validate_columns::<$resource>(&resource_name, &[field.clone()])?;
 
             let mut query = format!("SELECT * FROM {}", resource_name);
-            query.push_str(&format!(" WHERE {} IN (", $field));
-            for (i, _) in $values.iter().enumerate() {
-                query.push_str(&format!("${}", i + 1));
-                if i < $values.len() - 1 {
-                    query.push_str(", ");
-                }
+            query.push_str(&in_clause(&field, &values));
+            query.push_str(&order_by_clause::<$resource>(
+                $order_by.map(|order_by| order_by.to_string()),
+                $order_direction.map(|order_direction| order_direction.to_string()),
+            ));
+
+            let mut query = sqlx::query(sqlx::AssertSqlSafe(query));
+            for value in values.iter() {
+                query = query.bind(value);
             }
-            query.push_str(")");
 
-            let order_by = match $order_by {
-                Some(order_by) => order_by.to_string(),
-                None => "updated_at".to_string(),
-            };
+            match fetch_all(&resource_name, "find_all_resources_where_fields_in", query).await {
+                Ok(rows) => Ok(rows
+                    .into_iter()
+                    .map(|row| <$resource as DatabaseResource>::from_row(&row))
+                    .collect::<Result<Vec<$resource>, _>>()?),
+                Err(e) => Err(anyhow::Error::msg(e.to_string())),
+            }
+        }
+    }};
+}
 
-            let order_direction = match $order_direction {
-                Some(order_direction) => order_direction.to_string(),
-                None => "ASC".to_string(),
-            };
+/// Finds a page of resources ordered by a column, starting after a keyset cursor.
+///
+/// This macro generates a SELECT query of the form
+/// `WHERE ... AND order_col > $after ORDER BY order_col ASC LIMIT n`. Unlike
+/// OFFSET pagination the cost doesn't grow with the page number, and rows inserted
+/// while paging don't shift later pages. `$order_col` should be unique (e.g. `id`)
+/// so no rows are skipped or repeated between pages.
+///
+/// # Arguments
+/// * `$resource` - The resource type (must implement DatabaseResource)
+/// * `$params` - Vector of `(&str, DatabaseValue)` tuples for field conditions
+/// * `$order_col` - The column to order and page by
+/// * `$after` - `Option<DatabaseValue>` of the last seen `$order_col` value, `None` for the first page
+/// * `$limit` - Maximum number of resources to return
+///
+/// # Returns
+/// `Result<Vec<Resource>, Error>` - Vector of matching resources or database error
+///
+/// # Example
+/// ```rust
+/// let params = vec![("user_id", "123".into())];
+/// let page = find_page_after!(Mnstr, params, "id", Some("abc".into()), 25).await?;
+/// ```
+#[macro_export]
+macro_rules! find_page_after {
+    ($resource:ty, $params:expr, $order_col:expr, $after:expr, $limit:expr) => {{
+        use crate::database::{
+            connection::fetch_all,
+            traits::{
+                ArchivedFilter, DatabaseResource, order_column, validate_columns, where_clause,
+            },
+            values::DatabaseValue,
+        };
+        use crate::utils::strings::camel_to_snake_case;
+        use pluralizer::pluralize;
 
-            query.push_str(&format!(" ORDER BY {} {}", order_by, order_direction));
+        async {
+            let resource_name = pluralize(
+                camel_to_snake_case(stringify!($resource).to_string()).as_str(),
+                2,
+                false,
+            );
+
+            let params: Vec<(&str, DatabaseValue)> = $params.clone();
+            let fields = params
+                .iter()
+                .map(|field| field.0.to_string())
+                .collect::<Vec<String>>();
+            validate_columns::<$resource>(&resource_name, &fields)?;
+            let mut values = params
+                .iter()
+                .map(|field| field.1.clone())
+                .collect::<Vec<DatabaseValue>>();
+            let order_col = order_column::<$resource>(&resource_name, $order_col)?;
+
+            let mut query = format!("SELECT * FROM {}", resource_name);
+            query.push_str(&where_clause(ArchivedFilter::Any, &fields, &values));
+            let after: Option<DatabaseValue> = $after;
+            if let Some(after) = after {
+                query.push_str(match values.is_empty() {
+                    true => " WHERE ",
+                    false => " AND ",
+                });
+                query.push_str(&format!(
+                    "{} > {}",
+                    order_col,
+                    after.placeholder(values.len() + 1)
+                ));
+                values.push(after);
+            }
+
+            let limit: i64 = $limit;
+            let limit = DatabaseValue::from(limit);
+            query.push_str(&format!(
+                " ORDER BY {} ASC LIMIT {}",
+                order_col,
+                limit.placeholder(values.len() + 1)
+            ));
+            values.push(limit);
+
+            let mut query = sqlx::query(sqlx::AssertSqlSafe(query));
+            for value in values.iter() {
+                query = query.bind(value);
+            }
+
+            match fetch_all(&resource_name, "find_page_after", query).await {
+                Ok(rows) => Ok(rows
+                    .into_iter()
+                    .map(|row| <$resource as DatabaseResource>::from_row(&row))
+                    .collect::<Result<Vec<$resource>, _>>()?),
+                Err(e) => Err(anyhow::Error::msg(e.to_string())),
+            }
+        }
+    }};
+}
+
+/// Finds one offset page of resources matching the specified field conditions.
+///
+/// # Arguments
+/// * `$resource` - The resource type (must implement DatabaseResource)
+/// * `$params` - Vector of `(&str, DatabaseValue)` tuples for field conditions
+/// * `$archived` - `ArchivedFilter` for which rows to keep by `archived_at`
+/// * `$page` - `&PageRequest` from `apply_pagination`, giving the order, limit and offset
+///
+/// # Returns
+/// `Result<Vec<Resource>, Error>` - Vector of matching resources or database error
+///
+/// # Example
+/// ```rust
+/// let page = apply_pagination(input, &LEADERBOARD_ORDERING)?;
+/// let users = find_page_ordered!(User, vec![], ArchivedFilter::Unarchived, &page).await?;
+/// ```
+#[macro_export]
+macro_rules! find_page_ordered {
+    ($resource:ty, $params:expr, $archived:expr, $page:expr) => {{
+        use crate::database::{
+            connection::fetch_all,
+            traits::{DatabaseResource, validate_columns, where_clause},
+            values::DatabaseValue,
+        };
+        use crate::utils::strings::camel_to_snake_case;
+        use pluralizer::pluralize;
+
+        async {
+            let resource_name = pluralize(
+                camel_to_snake_case(stringify!($resource).to_string()).as_str(),
+                2,
+                false,
+            );
+
+            let params: Vec<(&str, DatabaseValue)> = $params.clone();
+            let fields = params
+                .iter()
+                .map(|field| field.0.to_string())
+                .collect::<Vec<String>>();
+            validate_columns::<$resource>(&resource_name, &fields)?;
+            let mut values = params
+                .iter()
+                .map(|field| field.1.clone())
+                .collect::<Vec<DatabaseValue>>();
+
+            let page = $page;
+            validate_columns::<$resource>(&resource_name, &[page.order_column.to_string()])?;
+
+            let mut query = format!("SELECT * FROM {}", resource_name);
+            query.push_str(&where_clause($archived, &fields, &values));
+            query.push_str(&page.order_clause());
+            let limit = DatabaseValue::from(page.limit);
+            let offset = DatabaseValue::from(page.offset);
+            query.push_str(&format!(
+                " LIMIT {} OFFSET {}",
+                limit.placeholder(values.len() + 1),
+                offset.placeholder(values.len() + 2)
+            ));
+            values.push(limit);
+            values.push(offset);
 
             let mut query = sqlx::query(sqlx::AssertSqlSafe(query));
-            for value in $values.iter() {
+            for value in values.iter() {
                 query = query.bind(value);
             }
 
-            match query.fetch_all(&pool).await {
+            match fetch_all(&resource_name, "find_page_ordered", query).await {
                 Ok(rows) => Ok(rows
                     .into_iter()
                     .map(|row| <$resource as DatabaseResource>::from_row(&row))
@@ -852,3 +1571,120 @@ macro_rules! find_all_resources_where_fields_in {
         }
     }};
 }
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::{
+        database::connection::rolled_back,
+        delete_resource_where_fields,
+        models::{mnstr::Mnstr, user::User},
+    };
+
+    #[test]
+    fn test_optional_result() {
+        assert_eq!(optional_result(Ok(1)).unwrap(), Some(1));
+        assert_eq!(
+            optional_result::<i32>(Err(sqlx::Error::RowNotFound)).unwrap(),
+            None
+        );
+        assert!(optional_result::<i32>(Err(sqlx::Error::PoolTimedOut)).is_err());
+    }
+
+    #[test]
+    fn test_find_one_archived_resource_expands() {
+        // Building the futures is enough to type-check every arm; nothing runs
+        // until they are awaited
+        let params: Vec<(&str, DatabaseValue)> = vec![("user_id", "user".into())];
+        let _ = find_one_archived_resource_where_fields!(Mnstr, params);
+        let _ = find_one_archived_resource_where_fields!(Mnstr, params, None, None);
+        let _ = find_one_archived_resource_where_fields!(
+            Mnstr,
+            params,
+            Some("created_at"),
+            Some("DESC")
+        );
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a migrated database at DATABASE_URL"]
+    async fn test_find_one_archived_resource_finds_only_archived_rows() {
+        rolled_back(async {
+            let name = Uuid::new_v4().to_string();
+            let mut user = User::new(
+                Some(format!("{}@example.com", name)),
+                None,
+                "password".to_string(),
+                name,
+            );
+            if let Some(error) = user.create().await {
+                return Err(error);
+            }
+            let mut archived = Mnstr::new(user.id.clone(), None, None, "archived".to_string());
+            let mut kept = Mnstr::new(user.id.clone(), None, None, "kept".to_string());
+            for mnstr in [&mut archived, &mut kept] {
+                if let Some(error) = mnstr.create().await {
+                    return Err(error);
+                }
+            }
+            delete_resource_where_fields!(Mnstr, vec![("id", archived.id.clone().into())]).await?;
+
+            let params: Vec<(&str, DatabaseValue)> = vec![("user_id", user.id.clone().into())];
+            let found = find_one_archived_resource_where_fields!(Mnstr, params.clone()).await?;
+            assert_eq!(found.id, archived.id);
+            assert!(found.archived_at.is_some());
+            let found = find_one_archived_resource_where_fields!(
+                Mnstr,
+                params,
+                Some("created_at"),
+                Some("DESC")
+            )
+            .await?;
+            assert_eq!(found.id, archived.id);
+
+            let params: Vec<(&str, DatabaseValue)> = vec![("id", kept.id.clone().into())];
+            assert!(
+                find_one_archived_resource_where_fields!(Mnstr, params)
+                    .await
+                    .is_err()
+            );
+            Ok(())
+        })
+        .await
+        .unwrap();
+    }
+
+    #[test]
+    fn test_count_query() {
+        assert_eq!(
+            count_query("battle_statuses", &[], &[]),
+            "SELECT COUNT(*) AS count FROM battle_statuses"
+        );
+        assert_eq!(
+            count_query(
+                "battle_statuses",
+                &["status".to_string()],
+                &[DatabaseValue::String("inQueue".to_string())]
+            ),
+            "SELECT COUNT(*) AS count FROM battle_statuses WHERE status = $1"
+        );
+    }
+
+    #[test]
+    fn test_lock_query() {
+        assert_eq!(
+            lock_query("users", &["id".to_string()], &["user".into()]),
+            "SELECT * FROM users WHERE archived_at IS NULL AND id = $1 ORDER BY id FOR UPDATE"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_find_page_after_rejects_unknown_order_column() {
+        // The column is checked before any query is sent, so no database is needed
+        let params: Vec<(&str, DatabaseValue)> = vec![];
+        let page = find_page_after!(Mnstr, params, "id; DROP TABLE mnstrs", None, 10).await;
+        assert!(page.is_err());
+    }
+}
@@ -0,0 +1,166 @@
+//! Startup Schema Validation
+//!
+//! The macros derive every table name from the resource's type name
+//! (camelCase to snake_case + pluralization), so a name that pluralizes
+//! unexpectedly only fails once a query runs against it. When `VALIDATE_SCHEMA`
+//! is set, startup probes each resource's table and refuses to boot if any is
+//! missing.
+
+use sqlx::{PgPool, postgres::PgRow};
+
+use crate::{
+    database::traits::DatabaseResource,
+    models::{
+        audit_log::AuditLog, battle::Battle, battle_log::BattleLog, battle_status::BattleStatus,
+        block::Block, effect::Effect, item::Item, item_effect::ItemEffect, mnstr::Mnstr,
+        mnstr_user_item::MnstrUserItem, report::Report, session::Session, trade_offer::TradeOffer,
+        transaction::Transaction, user::User, user_item::UserItem, wallet::Wallet,
+    },
+    utils::strings::camel_to_snake_case,
+};
+
+/// Lists resource type names, checking at compile time that each one is a
+/// `DatabaseResource`.
+macro_rules! resource_names {
+    ($($resource:ident),* $(,)?) => {
+        vec![$({
+            let _: fn(&PgRow) -> Result<$resource, sqlx::Error> =
+                <$resource as DatabaseResource>::from_row;
+            stringify!($resource)
+        }),*]
+    };
+}
+
+/// Every resource the macros query, by type name.
+pub fn registered_resources() -> Vec<&'static str> {
+    resource_names![
+        AuditLog,
+        Battle,
+        BattleLog,
+        BattleStatus,
+        Block,
+        Effect,
+        Item,
+        ItemEffect,
+        Mnstr,
+        MnstrUserItem,
+        Report,
+        Session,
+        TradeOffer,
+        Transaction,
+        User,
+        UserItem,
+        Wallet,
+    ]
+}
+
+/// The table the macros use for a resource type name.
+///
+/// # Example
+///
+/// ```rust
+/// use crate::database::schema::table_name;
+///
+/// assert_eq!(table_name("BattleLog"), "battle_logs");
+/// ```
+pub fn table_name(resource: &str) -> String {
+    pluralizer::pluralize(camel_to_snake_case(resource.to_string()).as_str(), 2, false)
+}
+
+/// Whether `VALIDATE_SCHEMA` asks for the startup check.
+pub fn validate_schema_enabled() -> bool {
+    match std::env::var("VALIDATE_SCHEMA") {
+        Ok(value) => matches!(value.trim().to_lowercase().as_str(), "1" | "true" | "yes"),
+        Err(_) => false,
+    }
+}
+
+/// Probes each resource's table with `table_exists`.
+///
+/// # Returns
+///
+/// `Vec<(&str, String)>` - The resources whose table is missing, with the table name
+pub async fn missing_tables<F, Fut>(
+    resources: &[&'static str],
+    mut table_exists: F,
+) -> Vec<(&'static str, String)>
+where
+    F: FnMut(String) -> Fut,
+    Fut: Future<Output = bool>,
+{
+    let mut missing = Vec::new();
+    for resource in resources {
+        let table = table_name(resource);
+        if !table_exists(table.clone()).await {
+            missing.push((*resource, table));
+        }
+    }
+    missing
+}
+
+/// Runs `SELECT 1 FROM <table> LIMIT 0` for every registered resource.
+///
+/// # Returns
+///
+/// `Result<(), anyhow::Error>` - An error listing every missing table
+pub async fn validate_schema(pool: &PgPool) -> Result<(), anyhow::Error> {
+    let missing = missing_tables(&registered_resources(), |table| {
+        let pool = pool.clone();
+        async move {
+            let query = format!("SELECT 1 FROM {} LIMIT 0", table);
+            sqlx::query(sqlx::AssertSqlSafe(query))
+                .execute(&pool)
+                .await
+                .is_ok()
+        }
+    })
+    .await;
+    if missing.is_empty() {
+        return Ok(());
+    }
+
+    for (resource, table) in missing.iter() {
+        println!(
+            "[validate_schema] Table `{}` for {} does not exist",
+            table, resource
+        );
+    }
+    Err(anyhow::anyhow!(
+        "Missing tables: {}",
+        missing
+            .iter()
+            .map(|(_, table)| table.as_str())
+            .collect::<Vec<&str>>()
+            .join(", ")
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_table_name() {
+        assert_eq!(table_name("Mnstr"), "mnstrs");
+        assert_eq!(table_name("BattleLog"), "battle_logs");
+        assert_eq!(table_name("MnstrUserItem"), "mnstr_user_items");
+        assert_eq!(table_name("AuditLog"), "audit_logs");
+    }
+
+    #[tokio::test]
+    async fn test_missing_table_is_reported() {
+        let existing = registered_resources()
+            .into_iter()
+            .filter(|resource| *resource != "BattleLog")
+            .map(table_name)
+            .collect::<Vec<String>>();
+
+        let missing = missing_tables(&registered_resources(), |table| {
+            let exists = existing.contains(&table);
+            async move { exists }
+        })
+        .await;
+
+        assert_eq!(missing, vec![("BattleLog", "battle_logs".to_string())]);
+    }
+}
@@ -11,6 +11,8 @@
 
 use sqlx::{Error, postgres::PgRow};
 
+use crate::database::values::DatabaseValue;
+
 /// Trait that must be implemented by any struct used with database macros.
 ///
 /// This trait provides metadata about how a resource should behave in database operations.
@@ -26,6 +28,11 @@ use sqlx::{Error, postgres::PgRow};
 /// - `is_expirable()` - Whether resource has expires_at timestamps
 /// - `is_verifiable()` - Whether resource supports verification
 ///
+/// # Provided Methods
+///
+/// - `columns()` - Table columns used to validate param field names in debug builds
+/// - `default_order_by()` - Column `find_all*` macros sort by when none is given
+///
 /// # Example Implementation
 ///
 /// ```rust
@@ -141,4 +148,447 @@ pub trait DatabaseResource {
     /// `bool` - Whether the resource supports verification
     #[allow(unused)]
     fn is_verifiable() -> bool;
+
+    /// The columns of the resource's table.
+    ///
+    /// In debug builds the macros check every param field name against this list
+    /// before building SQL, so a typo fails with the offending name instead of a
+    /// Postgres error. The default empty list skips the check.
+    ///
+    /// # Returns
+    ///
+    /// `&'static [&'static str]` - The table's column names
+    fn columns() -> &'static [&'static str] {
+        &[]
+    }
+
+    /// The column the `find_all*` macros order by when the caller passes none.
+    ///
+    /// Ties are broken by `id`, so repeated calls return rows in the same order.
+    ///
+    /// # Returns
+    ///
+    /// `&'static str` - The default sort column, `created_at` unless overridden
+    fn default_order_by() -> &'static str {
+        "created_at"
+    }
+}
+
+/// Builds the `ORDER BY` clause for the `find_all*` macros.
+///
+/// # Arguments
+///
+/// * `order_by` - The caller's sort column, or `None` for `T::default_order_by()`
+/// * `order_direction` - The caller's direction, or `None` for `ASC`
+///
+/// # Returns
+///
+/// `String` - The clause, with a leading space and an `id` tie-breaker
+pub fn order_by_clause<T: DatabaseResource>(
+    order_by: Option<String>,
+    order_direction: Option<String>,
+) -> String {
+    let order_by = order_by.unwrap_or_else(|| T::default_order_by().to_string());
+    let order_direction = order_direction.unwrap_or_else(|| "ASC".to_string());
+    if order_by == "id" {
+        return format!(" ORDER BY id {}", order_direction);
+    }
+    format!(" ORDER BY {} {}, id ASC", order_by, order_direction)
+}
+
+/// Sort direction for `find_all_resources_where_fields_ordered!`.
+///
+/// Taking the direction as an enum keeps caller strings out of the SQL.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OrderDirection {
+    Asc,
+    Desc,
+}
+
+impl std::fmt::Display for OrderDirection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OrderDirection::Asc => write!(f, "ASC"),
+            OrderDirection::Desc => write!(f, "DESC"),
+        }
+    }
+}
+
+/// Checks a sort column before it is written into an `ORDER BY` clause.
+///
+/// Unlike `validate_columns`, this runs in every build. Resources that list
+/// `T::columns()` only accept one of them; the rest accept a plain lowercase
+/// identifier.
+///
+/// # Arguments
+///
+/// * `resource_name` - The table name, used in the error message
+/// * `column` - The caller's sort column
+///
+/// # Returns
+///
+/// `Result<String, anyhow::Error>` - The column, or an error naming it
+pub fn order_column<T: DatabaseResource>(
+    resource_name: &str,
+    column: &str,
+) -> Result<String, anyhow::Error> {
+    let columns = T::columns();
+    let valid = match columns.is_empty() {
+        true => {
+            column.starts_with(|c: char| c.is_ascii_lowercase() || c == '_')
+                && column
+                    .chars()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+        }
+        false => columns.contains(&column),
+    };
+    match valid {
+        true => Ok(column.to_string()),
+        false => Err(anyhow::anyhow!(
+            "Invalid order column `{}` on {}",
+            column,
+            resource_name
+        )),
+    }
+}
+
+/// Which rows the `find_all*` macros return, by `archived_at`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchivedFilter {
+    Any,
+    Unarchived,
+    Archived,
+}
+
+/// Builds the `WHERE` clause for the `find_all*` macros.
+///
+/// # Arguments
+///
+/// * `archived` - Which rows to keep by `archived_at`
+/// * `fields` - The param field names, each compared for equality or `IS NULL`
+/// * `values` - The param values, used for their placeholders
+///
+/// # Returns
+///
+/// `String` - The clause with a leading space, or an empty string with no conditions
+pub fn where_clause(
+    archived: ArchivedFilter,
+    fields: &[String],
+    values: &[DatabaseValue],
+) -> String {
+    let mut conditions = match archived {
+        ArchivedFilter::Any => vec![],
+        ArchivedFilter::Unarchived => vec!["archived_at IS NULL".to_string()],
+        ArchivedFilter::Archived => vec!["archived_at IS NOT NULL".to_string()],
+    };
+    for (i, field) in fields.iter().enumerate() {
+        conditions.push(values[i].condition(field, i + 1));
+    }
+    if conditions.is_empty() {
+        return String::new();
+    }
+    format!(" WHERE {}", conditions.join(" AND "))
+}
+
+/// Builds the `WHERE` clause for `find_all_resources_where_any_fields!`.
+///
+/// The param conditions are joined with `OR` and wrapped in parentheses, so the
+/// `archived_at` condition still applies to every row:
+/// `archived_at IS NULL AND (email = $1 OR phone = $2)`. With no params nothing
+/// matches, rather than every row.
+///
+/// # Arguments
+///
+/// * `archived` - Which rows to keep by `archived_at`
+/// * `fields` - The param field names, each compared for equality or `IS NULL`
+/// * `values` - The param values, used for their placeholders
+///
+/// # Returns
+///
+/// `String` - The clause with a leading space
+pub fn where_any_clause(
+    archived: ArchivedFilter,
+    fields: &[String],
+    values: &[DatabaseValue],
+) -> String {
+    let mut conditions = match archived {
+        ArchivedFilter::Any => vec![],
+        ArchivedFilter::Unarchived => vec!["archived_at IS NULL".to_string()],
+        ArchivedFilter::Archived => vec!["archived_at IS NOT NULL".to_string()],
+    };
+    let any = fields
+        .iter()
+        .enumerate()
+        .map(|(i, field)| values[i].condition(field, i + 1))
+        .collect::<Vec<String>>();
+    if any.is_empty() {
+        conditions.push("FALSE".to_string());
+    } else {
+        conditions.push(format!("({})", any.join(" OR ")));
+    }
+    format!(" WHERE {}", conditions.join(" AND "))
+}
+
+/// Comparison for one condition of `find_all_resources_where_compare!`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operator {
+    Eq,
+    Ne,
+    Gt,
+    Lt,
+    Gte,
+    Lte,
+}
+
+impl std::fmt::Display for Operator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Operator::Eq => write!(f, "="),
+            Operator::Ne => write!(f, "<>"),
+            Operator::Gt => write!(f, ">"),
+            Operator::Lt => write!(f, "<"),
+            Operator::Gte => write!(f, ">="),
+            Operator::Lte => write!(f, "<="),
+        }
+    }
+}
+
+/// Builds the `WHERE` clause for `find_all_resources_where_compare!`.
+///
+/// Conditions are joined with `AND`. `Eq` and `Ne` against `DatabaseValue::None`
+/// become `IS NULL` and `IS NOT NULL` checks; the ordering operators never
+/// match a NULL.
+///
+/// # Arguments
+///
+/// * `fields` - The condition field names
+/// * `operators` - The operator for each field
+/// * `values` - The condition values, used for their placeholders
+///
+/// # Returns
+///
+/// `String` - The clause with a leading space, or an empty string with no conditions
+pub fn compare_clause(
+    fields: &[String],
+    operators: &[Operator],
+    values: &[DatabaseValue],
+) -> String {
+    let conditions = fields
+        .iter()
+        .enumerate()
+        .map(|(i, field)| match (operators[i], &values[i]) {
+            (Operator::Eq, _) => values[i].condition(field, i + 1),
+            (Operator::Ne, DatabaseValue::None) => {
+                format!("({} IS NOT NULL AND ${} IS NULL)", field, i + 1)
+            }
+            (operator, value) => format!("{} {} {}", field, operator, value.placeholder(i + 1)),
+        })
+        .collect::<Vec<String>>();
+    if conditions.is_empty() {
+        return String::new();
+    }
+    format!(" WHERE {}", conditions.join(" AND "))
+}
+
+/// Builds the `WHERE` clause for `find_all_resources_where_fields_in!`.
+///
+/// # Arguments
+///
+/// * `field` - The field name to match
+/// * `values` - The values the field may have, used for their placeholders
+///
+/// # Returns
+///
+/// `String` - The clause with a leading space, or `WHERE FALSE` with no values
+pub fn in_clause(field: &str, values: &[DatabaseValue]) -> String {
+    if values.is_empty() {
+        return " WHERE FALSE".to_string();
+    }
+    let placeholders = values
+        .iter()
+        .enumerate()
+        .map(|(i, value)| value.placeholder(i + 1))
+        .collect::<Vec<String>>();
+    format!(" WHERE {} IN ({})", field, placeholders.join(", "))
+}
+
+/// Checks param field names against `T::columns()` in debug builds.
+///
+/// # Arguments
+///
+/// * `resource_name` - The table name, used in the error message
+/// * `fields` - The param field names about to be used in a query
+///
+/// # Returns
+///
+/// `Result<(), anyhow::Error>` - An error naming the first unknown field
+pub fn validate_columns<T: DatabaseResource>(
+    resource_name: &str,
+    fields: &[String],
+) -> Result<(), anyhow::Error> {
+    let columns = T::columns();
+    if !cfg!(debug_assertions) || columns.is_empty() {
+        return Ok(());
+    }
+    match fields
+        .iter()
+        .find(|field| !columns.contains(&field.as_str()))
+    {
+        Some(field) => Err(anyhow::anyhow!(
+            "Unknown column `{}` on {}",
+            field,
+            resource_name
+        )),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{
+        mnstr::{Mnstr, MnstrOrderBy},
+        transaction::Transaction,
+        user::User,
+    };
+
+    #[test]
+    fn test_order_by_clause() {
+        // The tie-breaker keeps rows created in the same instant in a fixed order
+        assert_eq!(
+            order_by_clause::<Transaction>(None, None),
+            " ORDER BY created_at ASC, id ASC"
+        );
+        assert_eq!(
+            order_by_clause::<Mnstr>(
+                Some(MnstrOrderBy::Level.to_string()),
+                Some("DESC".to_string())
+            ),
+            " ORDER BY current_level DESC, id ASC"
+        );
+        assert_eq!(
+            order_by_clause::<Transaction>(Some("id".to_string()), None),
+            " ORDER BY id ASC"
+        );
+    }
+
+    #[test]
+    fn test_order_column_rejects_unsafe_columns() {
+        assert_eq!(
+            order_column::<Transaction>("transactions", "created_at").unwrap(),
+            "created_at"
+        );
+        assert!(
+            order_column::<Transaction>("transactions", "created_at; DROP TABLE users").is_err()
+        );
+        assert!(order_column::<Transaction>("transactions", "\"id\"").is_err());
+        assert!(order_column::<Transaction>("transactions", "").is_err());
+
+        // Resources that list their columns only accept those
+        assert!(order_column::<User>("users", "display_name").is_ok());
+        assert!(order_column::<User>("users", "not_a_column").is_err());
+
+        assert_eq!(
+            order_by_clause::<Transaction>(
+                Some("created_at".to_string()),
+                Some(OrderDirection::Desc.to_string())
+            ),
+            " ORDER BY created_at DESC, id ASC"
+        );
+    }
+
+    #[test]
+    fn test_where_clause_filters_archived_rows() {
+        let fields = vec!["user_id".to_string()];
+        let values = vec![DatabaseValue::String("user".to_string())];
+
+        assert_eq!(
+            where_clause(ArchivedFilter::Any, &fields, &values),
+            " WHERE user_id = $1"
+        );
+        // Released mnstrs are left out of battles and collections...
+        assert_eq!(
+            where_clause(ArchivedFilter::Unarchived, &fields, &values),
+            " WHERE archived_at IS NULL AND user_id = $1"
+        );
+        // ...but can still be found among the archived ones
+        assert_eq!(
+            where_clause(ArchivedFilter::Archived, &fields, &values),
+            " WHERE archived_at IS NOT NULL AND user_id = $1"
+        );
+        assert_eq!(where_clause(ArchivedFilter::Any, &[], &[]), "");
+        assert_eq!(
+            where_clause(ArchivedFilter::Unarchived, &[], &[]),
+            " WHERE archived_at IS NULL"
+        );
+    }
+
+    #[test]
+    fn test_where_any_clause_matches_either_field() {
+        let fields = vec!["email".to_string(), "phone".to_string()];
+        let values = vec![
+            DatabaseValue::String("player@mnstr.app".to_string()),
+            DatabaseValue::String("+15555550123".to_string()),
+        ];
+
+        assert_eq!(
+            where_any_clause(ArchivedFilter::Any, &fields, &values),
+            " WHERE (email = $1 OR phone = $2)"
+        );
+        // The OR group doesn't let archived rows through
+        assert_eq!(
+            where_any_clause(ArchivedFilter::Unarchived, &fields, &values),
+            " WHERE archived_at IS NULL AND (email = $1 OR phone = $2)"
+        );
+        assert_eq!(
+            where_any_clause(ArchivedFilter::Any, &[], &[]),
+            " WHERE FALSE"
+        );
+    }
+
+    #[test]
+    fn test_compare_clause_writes_each_operator() {
+        let fields = vec![
+            "expires_at".to_string(),
+            "current_level".to_string(),
+            "archived_at".to_string(),
+        ];
+        let operators = vec![Operator::Lt, Operator::Gte, Operator::Eq];
+        let values = vec![
+            DatabaseValue::DateTime("2026-10-16T00:00:00Z".to_string()),
+            DatabaseValue::Int32(5),
+            DatabaseValue::None,
+        ];
+
+        assert_eq!(
+            compare_clause(&fields, &operators, &values),
+            " WHERE expires_at < CAST($1 AS TIMESTAMP WITH TIME ZONE) \
+             AND current_level >= CAST($2 AS INTEGER) \
+             AND (archived_at IS NULL AND $3 IS NULL)"
+        );
+        assert_eq!(
+            compare_clause(
+                &["name".to_string(), "archived_at".to_string()],
+                &[Operator::Ne, Operator::Ne],
+                &["Flame".into(), DatabaseValue::None],
+            ),
+            " WHERE name <> $1 AND (archived_at IS NOT NULL AND $2 IS NULL)"
+        );
+        assert_eq!(compare_clause(&[], &[], &[]), "");
+    }
+
+    #[test]
+    fn test_in_clause_binds_each_value() {
+        let values = vec![
+            DatabaseValue::String("challenger".to_string()),
+            DatabaseValue::String("opponent".to_string()),
+        ];
+
+        assert_eq!(in_clause("user_id", &values), " WHERE user_id IN ($1, $2)");
+        assert_eq!(
+            in_clause("current_level", &[DatabaseValue::Int32(5)]),
+            " WHERE current_level IN (CAST($1 AS INTEGER))"
+        );
+        assert_eq!(in_clause("user_id", &[]), " WHERE FALSE");
+    }
 }
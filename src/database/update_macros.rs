@@ -7,7 +7,8 @@
 /// Updates an existing resource in the database by ID.
 ///
 /// This macro generates an UPDATE query and automatically handles common database fields:
-/// - Sets `updated_at` timestamp if `is_updatable()` returns true
+/// - Sets `updated_at` to the database's `CURRENT_TIMESTAMP` if `is_updatable()` returns true,
+///   unless an `updated_at` value is passed in
 /// - Sets `expires_at` timestamp (30 days from now) if `is_expirable()` returns true
 /// - Fetches and returns the updated resource after successful update
 /// - Supports updating multiple fields in a single operation
@@ -33,7 +34,10 @@
 macro_rules! update_resource {
     ($resource:ty, $id:expr, $params:expr) => {{
         use crate::database::{
-            connection::get_connection, traits::DatabaseResource, values::DatabaseValue,
+            connection::fetch_one,
+            traits::{DatabaseResource, validate_columns},
+            update_macros::update_query,
+            values::DatabaseValue,
         };
         use crate::utils::strings::camel_to_snake_case;
         use pluralizer::pluralize;
@@ -41,7 +45,6 @@ macro_rules! update_resource {
 
         async {
             let id = $id.to_string();
-            let updated_at = OffsetDateTime::now_utc();
             let expires_at = (OffsetDateTime::now_utc() + Duration::days(30));
 
             let resource_name = pluralize(
@@ -49,7 +52,6 @@ macro_rules! update_resource {
                 2,
                 false,
             );
-            let pool = get_connection().await;
 
             let mut params: Vec<(&str, DatabaseValue)> = Vec::new();
 
@@ -60,16 +62,8 @@ macro_rules! update_resource {
                 }
             }
 
-            if <$resource as DatabaseResource>::is_updatable() {
-                if let Some(idx) = params
-                    .iter()
-                    .position(|(field, _)| field.contains("updated_at"))
-                {
-                    params[idx] = ("updated_at", updated_at.into());
-                } else {
-                    params.push(("updated_at", updated_at.into()));
-                }
-            }
+            let set_updated_at = <$resource as DatabaseResource>::is_updatable()
+                && !params.iter().any(|(field, _)| *field == "updated_at");
 
             if <$resource as DatabaseResource>::is_expirable() {
                 if let Some(idx) = params
@@ -86,60 +80,18 @@ macro_rules! update_resource {
                 .iter()
                 .map(|(field, _)| field.to_string())
                 .collect::<Vec<String>>();
+            validate_columns::<$resource>(&resource_name, &fields)?;
             let values: Vec<&DatabaseValue> = params.iter().map(|(_, value)| value).collect();
 
-            let mut query = format!("UPDATE {} SET ", resource_name);
-
-            for (i, field) in fields.iter().enumerate() {
-                let value = values[i];
-                match value {
-                    DatabaseValue::None => {
-                        query.push_str(&format!("{} = NULL", field));
-                    }
-                    DatabaseValue::Str(_) | DatabaseValue::String(_) | DatabaseValue::Text(_) => {
-                        query.push_str(&format!("{} = ${}", field, i + 1));
-                    }
-                    DatabaseValue::DateTime(_) => {
-                        query.push_str(&format!(
-                            "{} = CAST(${} AS TIMESTAMP WITH TIME ZONE)",
-                            field,
-                            i + 1
-                        ));
-                    }
-                    DatabaseValue::Int(_) => {
-                        query.push_str(&format!("{} = CAST(${} AS INTEGER)", field, i + 1));
-                    }
-                    DatabaseValue::Int32(_) => {
-                        query.push_str(&format!("{} = CAST(${} AS INTEGER)", field, i + 1));
-                    }
-                    DatabaseValue::Int64(_) => {
-                        query.push_str(&format!("{} = CAST(${} AS BIGINT)", field, i + 1));
-                    }
-                    DatabaseValue::Float(_) => {
-                        query.push_str(&format!("{} = CAST(${} AS FLOAT)", field, i + 1));
-                    }
-                    DatabaseValue::Boolean(_) => {
-                        query.push_str(&format!("{} = CAST(${} AS BOOLEAN)", field, i + 1));
-                    }
-                }
-                if i < fields.len() - 1 {
-                    query.push_str(", ");
-                }
-            }
-
-            query.push_str(&format!(" WHERE id = ${}", fields.len() + 1));
-            query.push_str(&format!(" RETURNING *"));
+            let (query, binds) = update_query(&resource_name, &fields, &values, set_updated_at);
 
             let mut query = sqlx::query(sqlx::AssertSqlSafe(query));
-            for (_, value) in values.iter().enumerate() {
-                match value {
-                    DatabaseValue::None => query = query.bind(Option::<String>::None),
-                    _ => query = query.bind(value),
-                }
+            for value in binds {
+                query = query.bind(value);
             }
             query = query.bind(&id);
 
-            match query.fetch_one(&pool).await {
+            match fetch_one(&resource_name, "update_resource", query).await {
                 Ok(row) => Ok(<$resource as DatabaseResource>::from_row(&row)?),
                 Err(e) => Err(anyhow::Error::msg(e.to_string())),
             }
@@ -147,6 +99,88 @@ macro_rules! update_resource {
     }};
 }
 
+/// Partially updates an existing resource in the database by ID.
+///
+/// Each param is `(&str, Option<DatabaseValue>)` so that three states can be expressed:
+/// - `Some(value)` - write the value
+/// - `Some(DatabaseValue::None)` - set the column to NULL
+/// - `None` - leave the column unchanged
+///
+/// # Arguments
+/// * `$resource` - The resource type (must implement DatabaseResource)
+/// * `$id` - The unique identifier of the resource to update
+/// * `$params` - Vector of `(&str, Option<DatabaseValue>)` tuples for field updates
+///
+/// # Returns
+/// `Result<Resource, Error>` - The updated resource or database error
+///
+/// # Example
+/// ```rust
+/// // Clear the phone number without touching the email
+/// let params = vec![
+///     ("phone", Some(DatabaseValue::None)),
+///     ("email", None),
+/// ];
+/// let updated_user = update_resource_fields!(User, "user-123", params).await?;
+/// ```
+#[macro_export]
+macro_rules! update_resource_fields {
+    ($resource:ty, $id:expr, $params:expr) => {{
+        let params = $crate::database::update_macros::present_params($params);
+        $crate::update_resource!($resource, $id, params)
+    }};
+}
+
+/// Keeps the present entries of a partial update, dropping absent (`None`) ones.
+pub fn present_params<'a>(
+    params: Vec<(&'a str, Option<crate::database::values::DatabaseValue>)>,
+) -> Vec<(&'a str, crate::database::values::DatabaseValue)> {
+    params
+        .into_iter()
+        .filter_map(|(field, value)| value.map(|value| (field, value)))
+        .collect()
+}
+
+/// Builds the `UPDATE` for `update_resource!`.
+///
+/// `DatabaseValue::None` is written as `NULL` rather than bound, so only the
+/// other values take placeholders, numbered in order, and the id is bound last.
+///
+/// # Returns
+///
+/// `(String, Vec<&DatabaseValue>)` - The query and the values to bind before the id
+pub fn update_query<'a>(
+    resource_name: &str,
+    fields: &[String],
+    values: &[&'a crate::database::values::DatabaseValue],
+    set_updated_at: bool,
+) -> (String, Vec<&'a crate::database::values::DatabaseValue>) {
+    use crate::database::values::DatabaseValue;
+
+    let mut assignments = Vec::new();
+    let mut binds = Vec::new();
+    for (field, value) in fields.iter().zip(values.iter().copied()) {
+        match value {
+            DatabaseValue::None => assignments.push(format!("{} = NULL", field)),
+            _ => {
+                binds.push(value);
+                assignments.push(format!("{} = {}", field, value.placeholder(binds.len())));
+            }
+        }
+    }
+    if set_updated_at {
+        assignments.push("updated_at = CURRENT_TIMESTAMP".to_string());
+    }
+
+    let query = format!(
+        "UPDATE {} SET {} WHERE id = ${} RETURNING *",
+        resource_name,
+        assignments.join(", "),
+        binds.len() + 1
+    );
+    (query, binds)
+}
+
 /// Updates a batch of resources in the database by ID.
 ///
 /// This macro generates an UPDATE query and automatically handles common database fields:
@@ -175,21 +209,19 @@ macro_rules! update_resource {
 macro_rules! update_resource_batch {
     ($resource:ty, $resources:expr) => {{
         use crate::database::{
-            connection::get_connection, traits::DatabaseResource, values::DatabaseValue,
+            connection::fetch_all, traits::DatabaseResource, values::DatabaseValue,
         };
         use crate::utils::strings::camel_to_snake_case;
         use pluralizer::pluralize;
         use time::{Duration, OffsetDateTime};
 
         async {
-            let pool = get_connection().await;
             let resources: Vec<Vec<(&str, DatabaseValue)>> = $resources.clone();
             let resource_name = pluralize(
                 camel_to_snake_case(stringify!($resource).to_string()).as_str(),
                 2,
                 false,
             );
-            let updated_at = OffsetDateTime::now_utc();
             let expires_at = (OffsetDateTime::now_utc() + Duration::days(30));
 
             if resources.is_empty() {
@@ -204,13 +236,8 @@ macro_rules! update_resource_batch {
                 .map(|(field, _)| field.to_string())
                 .collect::<Vec<String>>();
 
-            if <$resource as DatabaseResource>::is_updatable() {
-                if let Some(_) = fields.iter().position(|field| field == "updated_at") {
-                    fields.push("updated_at".to_string());
-                } else {
-                    fields.push("updated_at".to_string());
-                }
-            }
+            let set_updated_at = <$resource as DatabaseResource>::is_updatable()
+                && !fields.iter().any(|field| field == "updated_at");
 
             if <$resource as DatabaseResource>::is_expirable() {
                 if let Some(_) = fields.iter().position(|field| field == "expires_at") {
@@ -228,6 +255,9 @@ macro_rules! update_resource_batch {
                     query.push_str(", ");
                 }
             }
+            if set_updated_at {
+                query.push_str(", updated_at = CURRENT_TIMESTAMP");
+            }
 
             query.push_str(" FROM (VALUES ");
 
@@ -246,17 +276,6 @@ macro_rules! update_resource_batch {
                 };
                 resource_ids.push(id.unwrap().1.to_string());
 
-                if <$resource as DatabaseResource>::is_updatable() {
-                    if let Some(idx) = input_params
-                        .iter()
-                        .position(|(field, _)| field == &"updated_at")
-                    {
-                        input_params[idx] = ("updated_at", updated_at.into());
-                    } else {
-                        input_params.push(("updated_at", updated_at.into()));
-                    }
-                }
-
                 if <$resource as DatabaseResource>::is_expirable() {
                     if let Some(idx) = input_params
                         .iter()
@@ -327,7 +346,7 @@ macro_rules! update_resource_batch {
                 query = query.bind(value);
             }
 
-            match query.fetch_all(&pool).await {
+            match fetch_all(&resource_name, "update_resource_batch", query).await {
                 Ok(rows) => Ok(rows
                     .into_iter()
                     .map(|row| <$resource as DatabaseResource>::from_row(&row))
@@ -337,3 +356,67 @@ macro_rules! update_resource_batch {
         }
     }};
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::values::DatabaseValue;
+
+    fn phone_param(params: &[(&str, DatabaseValue)]) -> Option<DatabaseValue> {
+        params
+            .iter()
+            .find(|(field, _)| *field == "phone")
+            .map(|(_, value)| value.clone())
+    }
+
+    #[test]
+    fn test_present_params() {
+        let set = present_params(vec![
+            ("phone", Some("+15551234567".into())),
+            ("email", None),
+        ]);
+        assert!(matches!(
+            phone_param(&set),
+            Some(DatabaseValue::String(phone)) if phone == "+15551234567"
+        ));
+        assert_eq!(set.len(), 1);
+
+        let set_null = present_params(vec![("phone", Some(DatabaseValue::None)), ("email", None)]);
+        assert!(matches!(phone_param(&set_null), Some(DatabaseValue::None)));
+        assert_eq!(set_null.len(), 1);
+
+        let absent = present_params(vec![("phone", None), ("email", None)]);
+        assert!(phone_param(&absent).is_none());
+        assert!(absent.is_empty());
+    }
+
+    #[test]
+    fn test_update_query_skips_null_values() {
+        let fields = vec![
+            "email_verification_code".to_string(),
+            "display_name".to_string(),
+            "experience_level".to_string(),
+        ];
+        let display_name = DatabaseValue::String("Jane".to_string());
+        let experience_level = DatabaseValue::Int32(3);
+        let values = vec![&DatabaseValue::None, &display_name, &experience_level];
+
+        let (query, binds) = update_query("users", &fields, &values, true);
+        assert_eq!(
+            query,
+            "UPDATE users SET email_verification_code = NULL, display_name = $1, \
+             experience_level = CAST($2 AS INTEGER), updated_at = CURRENT_TIMESTAMP \
+             WHERE id = $3 RETURNING *"
+        );
+        assert_eq!(binds.len(), 2);
+        assert!(matches!(binds[0], DatabaseValue::String(name) if name == "Jane"));
+        assert!(matches!(binds[1], DatabaseValue::Int32(3)));
+
+        let (query, binds) = update_query("users", &fields[..1], &values[..1], false);
+        assert_eq!(
+            query,
+            "UPDATE users SET email_verification_code = NULL WHERE id = $1 RETURNING *"
+        );
+        assert!(binds.is_empty());
+    }
+}
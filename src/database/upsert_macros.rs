@@ -1,8 +1,23 @@
+//! Upsert Macros for Database Operations
+//!
+//! This module provides macros that insert resources or, when a row with the
+//! same `id` already exists, update it in place. Like the insert macros they
+//! leave `created_at` and `updated_at` to the database.
+
+/// Inserts a resource, or updates the existing row with the same `id`.
+///
+/// - Generates a UUID if `has_id()` returns true and no `id` is passed in
+/// - Leaves `created_at` to the database default, and sets `updated_at` to the
+///   database's `CURRENT_TIMESTAMP` on conflict unless it is passed in
+/// - Sets `expires_at` timestamp (30 days from now) if `is_expirable()` returns true
+///
+/// # Returns
+/// `Result<Resource, Error>` - The inserted or updated resource
 #[macro_export]
 macro_rules! upsert_resource {
     ($resource:ty, $params:expr) => {{
         use crate::database::{
-            connection::get_connection, traits::DatabaseResource, values::DatabaseValue,
+            connection::fetch_one, traits::DatabaseResource, values::DatabaseValue,
         };
         use crate::utils::strings::camel_to_snake_case;
         use pluralizer::pluralize;
@@ -15,8 +30,6 @@ macro_rules! upsert_resource {
                 return Err(anyhow::Error::msg("No params provided"));
             }
 
-            let pool = get_connection().await;
-
             let resource_name = pluralize(
                 camel_to_snake_case(stringify!($resource).to_string()).as_str(),
                 2,
@@ -28,31 +41,14 @@ macro_rules! upsert_resource {
                 params.push((field.to_string(), value.clone()))
             }
 
-            if <$resource as DatabaseResource>::has_id() {
+            if <$resource as DatabaseResource>::has_id()
+                && !params.iter().any(|(field, _)| field == "id")
+            {
                 params.push(("id".to_string(), Uuid::new_v4().to_string().into()));
             }
 
-            if <$resource as DatabaseResource>::is_creatable() {
-                if let Some(idx) = params
-                    .iter()
-                    .position(|(field, _)| field.contains("created_at"))
-                {
-                    params[idx] = ("created_at".to_string(), OffsetDateTime::now_utc().into());
-                } else {
-                    params.push(("created_at".to_string(), OffsetDateTime::now_utc().into()));
-                }
-            }
-
-            if <$resource as DatabaseResource>::is_updatable() {
-                if let Some(idx) = params
-                    .iter()
-                    .position(|(field, _)| field.contains("updated_at"))
-                {
-                    params[idx] = ("updated_at".to_string(), OffsetDateTime::now_utc().into());
-                } else {
-                    params.push(("updated_at".to_string(), OffsetDateTime::now_utc().into()));
-                }
-            }
+            let set_updated_at = <$resource as DatabaseResource>::is_updatable()
+                && !params.iter().any(|(field, _)| field == "updated_at");
 
             if <$resource as DatabaseResource>::is_expirable() {
                 if let Some(idx) = params
@@ -97,7 +93,7 @@ macro_rules! upsert_resource {
                         query.push_str(&format!("Cast(${} AS TEXT)", i + 1));
                     }
                     DatabaseValue::DateTime(_) => {
-                        query.push_str(&format!("CAST(${} AS TIMESTAMP WITHOUT TIME ZONE)", i + 1));
+                        query.push_str(&format!("CAST(${} AS TIMESTAMP WITH TIME ZONE)", i + 1));
                     }
                     DatabaseValue::Int(_) => {
                         query.push_str(&format!("CAST(${} AS INTEGER)", i + 1));
@@ -120,21 +116,27 @@ macro_rules! upsert_resource {
                 }
             }
             query.push_str(") ON CONFLICT (id) DO UPDATE SET ");
-            for (i, field) in fields.iter().enumerate() {
-                query.push_str(format!("{} = EXCLUDED.{}", field, field).as_str());
-                if i < fields.len() - 1 {
+            let updates = fields
+                .iter()
+                .filter(|field| field.as_str() != "id")
+                .map(|field| format!("{} = EXCLUDED.{}", field, field))
+                .collect::<Vec<String>>();
+            query.push_str(&updates.join(", "));
+            if set_updated_at {
+                if !updates.is_empty() {
                     query.push_str(", ");
                 }
+                query.push_str("updated_at = CURRENT_TIMESTAMP");
             }
             query.push_str(" RETURNING *");
-            let mut query = sqlx::query(&query);
+            let mut query = sqlx::query(sqlx::AssertSqlSafe(query));
             for (_, value) in values.iter().enumerate() {
                 match value {
                     DatabaseValue::None => query = query.bind(Option::<String>::None),
                     _ => query = query.bind(value),
                 }
             }
-            match query.fetch_one(&pool).await {
+            match fetch_one(&resource_name, "upsert_resource", query).await {
                 Ok(row) => Ok(<$resource as DatabaseResource>::from_row(&row)?),
                 Err(e) => Err(e.into()),
             }
@@ -142,11 +144,19 @@ macro_rules! upsert_resource {
     }};
 }
 
+/// Inserts several resources in one statement, updating any rows whose `id`
+/// already exists.
+///
+/// Every resource must pass the same fields. Ids, `expires_at` and
+/// `updated_at` are handled the same way as in [`upsert_resource!`].
+///
+/// # Returns
+/// `Result<Vec<Resource>, Error>` - The inserted or updated resources
 #[macro_export]
 macro_rules! upsert_resource_batch {
     ($resource:ty, $resources:expr) => {{
         use crate::database::{
-            connection::get_connection, traits::DatabaseResource, values::DatabaseValue,
+            connection::fetch_all, traits::DatabaseResource, values::DatabaseValue,
         };
         use crate::utils::strings::camel_to_snake_case;
         use pluralizer::pluralize;
@@ -154,7 +164,6 @@ macro_rules! upsert_resource_batch {
         use uuid::Uuid;
 
         async {
-            let pool = get_connection().await;
             let resources: Vec<Vec<(&str, DatabaseValue)>> = $resources.clone();
             let resource_name = pluralize(
                 camel_to_snake_case(stringify!($resource).to_string()).as_str(),
@@ -162,175 +171,107 @@ macro_rules! upsert_resource_batch {
                 false,
             );
 
-            let created_at = OffsetDateTime::now_utc();
-            let updated_at = created_at.clone();
             let expires_at = (OffsetDateTime::now_utc() + Duration::days(30));
 
             if resources.is_empty() {
                 return Ok(Vec::<$resource>::new());
             }
 
-            let mut fields = resources[0]
-                .clone()
+            let mut fields: Vec<&str> = resources[0]
                 .iter()
-                .map(|(field, _)| field.to_string())
-                .collect::<Vec<String>>();
-
-            if <$resource as DatabaseResource>::has_id() {
-                if let Some(idx) = fields.iter().position(|field| field == "id") {
-                    fields[idx] = "id".to_string();
-                } else {
-                    fields.push("id".to_string());
-                }
-            }
-
-            if <$resource as DatabaseResource>::is_creatable() {
-                if let Some(idx) = fields.iter().position(|field| field == "created_at") {
-                    fields[idx] = "created_at".to_string();
-                } else {
-                    fields.push("created_at".to_string());
-                }
-            }
+                .map(|(field, _)| *field)
+                .collect::<Vec<&str>>();
 
-            if <$resource as DatabaseResource>::is_updatable() {
-                if let Some(idx) = fields.iter().position(|field| field == "updated_at") {
-                    fields[idx] = "updated_at".to_string();
-                } else {
-                    fields.push("updated_at".to_string());
-                }
+            if <$resource as DatabaseResource>::has_id() && !fields.contains(&"id") {
+                fields.push("id");
             }
 
-            if <$resource as DatabaseResource>::is_expirable() {
-                if let Some(_) = fields.iter().position(|field| field == "expires_at") {
-                    fields.push("expires_at".to_string());
-                } else {
-                    fields.push("expires_at".to_string());
-                }
+            if <$resource as DatabaseResource>::is_expirable() && !fields.contains(&"expires_at") {
+                fields.push("expires_at");
             }
 
-            let mut query = format!("INSERT INTO {} (", resource_name);
-
-            for (i, field) in fields.iter().enumerate() {
-                query.push_str(field);
-                if i < fields.len() - 1 {
-                    query.push_str(", ");
-                }
-            }
+            let set_updated_at =
+                <$resource as DatabaseResource>::is_updatable() && !fields.contains(&"updated_at");
 
-            query.push_str(") VALUES (");
+            let mut query = format!(
+                "INSERT INTO {} ({}) VALUES ",
+                resource_name,
+                fields.join(", ")
+            );
 
             let mut values: Vec<DatabaseValue> = Vec::new();
 
-            for (idx, resource) in resources.iter().enumerate() {
+            for (i, resource) in resources.iter().enumerate() {
                 let mut input_params: Vec<(&str, DatabaseValue)> = resource.clone();
                 if input_params.is_empty() {
                     return Err(anyhow::Error::msg("Params are empty"));
                 }
 
-                let id = Uuid::new_v4().to_string();
-
-                if <$resource as DatabaseResource>::has_id() {
-                    if let None = input_params.iter().position(|(field, _)| field == &"id") {
-                        input_params[idx] = ("id", id.clone().into());
-                    } else {
-                        input_params.push(("id", id.clone().into()));
-                    }
-                }
-
-                if <$resource as DatabaseResource>::is_creatable() {
-                    if let None = input_params
-                        .iter()
-                        .position(|(field, _)| field == &"created_at")
-                    {
-                        input_params[idx] = ("created_at", created_at.clone().into());
-                    } else {
-                        input_params.push(("created_at", created_at.clone().into()));
-                    }
-                }
-
-                if <$resource as DatabaseResource>::is_updatable() {
-                    if let None = input_params
-                        .iter()
-                        .position(|(field, _)| field == &"updated_at")
-                    {
-                        input_params[idx] = ("updated_at", updated_at.clone().into());
-                    } else {
-                        input_params.push(("updated_at", updated_at.clone().into()));
-                    }
+                if <$resource as DatabaseResource>::has_id()
+                    && !input_params.iter().any(|(field, _)| field == &"id")
+                {
+                    input_params.push(("id", Uuid::new_v4().to_string().into()));
                 }
 
                 if <$resource as DatabaseResource>::is_expirable() {
-                    if let None = input_params
+                    if let Some(idx) = input_params
                         .iter()
                         .position(|(field, _)| field == &"expires_at")
                     {
-                        input_params[idx] = ("expires_at", expires_at.clone().into());
+                        input_params[idx] = ("expires_at", expires_at.into());
                     } else {
-                        input_params.push(("expires_at", expires_at.clone().into()));
+                        input_params.push(("expires_at", expires_at.into()));
                     }
                 }
 
-                let mut idxs: Vec<usize> = Vec::new();
-                for (_, value) in input_params {
-                    values.push(value.clone());
-                    idxs.push(values.len() - 1);
+                if input_params.len() != fields.len() {
+                    return Err(anyhow::Error::msg(
+                        "Every resource must pass the same fields",
+                    ));
                 }
 
-                let mut value_query = String::from("(");
-
-                for (j, _) in fields.iter().enumerate() {
-                    let idx = idxs[j];
-                    let value = resource[idx].1.clone();
-                    match value {
-                        DatabaseValue::None => {
-                            value_query.push_str("NULL");
-                        }
-                        DatabaseValue::Str(_)
-                        | DatabaseValue::String(_)
-                        | DatabaseValue::Text(_) => {
-                            value_query.push_str(&format!("${}", idx));
-                        }
-                        DatabaseValue::DateTime(_) => {
-                            value_query.push_str(&format!(
-                                "CAST(${} AS TIMESTAMP WITHOUT TIME ZONE)",
-                                idx
+                let mut placeholders: Vec<String> = Vec::new();
+                for field in fields.iter() {
+                    let value = match input_params.iter().find(|(name, _)| name == field) {
+                        Some((_, value)) => value.clone(),
+                        None => {
+                            return Err(anyhow::Error::msg(
+                                "Every resource must pass the same fields",
                             ));
                         }
-                        DatabaseValue::Int(_) | DatabaseValue::Int32(_) => {
-                            value_query.push_str(&format!("CAST(${} AS INTEGER)", idx));
-                        }
-                        DatabaseValue::Int64(_) => {
-                            value_query.push_str(&format!("CAST(${} AS BIGINT)", idx));
-                        }
-                        DatabaseValue::Float(_) => {
-                            value_query.push_str(&format!("CAST(${} AS FLOAT)", idx));
-                        }
-                        DatabaseValue::Boolean(_) => {
-                            value_query.push_str(&format!("CAST(${} AS BOOLEAN)", idx));
+                    };
+                    match value {
+                        DatabaseValue::None => placeholders.push("NULL".to_string()),
+                        _ => {
+                            values.push(value);
+                            placeholders.push(values[values.len() - 1].placeholder(values.len()));
                         }
                     }
-                    value_query.push_str(")");
-                    if idx < resources.len() - 1 {
-                        value_query.push_str(", ");
-                    }
                 }
-                query.push_str(&value_query);
-            }
 
-            query.push_str(") ON CONFLICT (id) DO UPDATE SET ");
-            for (i, field) in fields.iter().enumerate() {
-                query.push_str(format!("{} = EXCLUDED.{}", field, field).as_str());
-                if i < fields.len() - 1 {
+                query.push_str(&format!("({})", placeholders.join(", ")));
+                if i < resources.len() - 1 {
                     query.push_str(", ");
                 }
             }
+
+            query.push_str(" ON CONFLICT (id) DO UPDATE SET ");
+            let mut updates = fields
+                .iter()
+                .filter(|field| **field != "id")
+                .map(|field| format!("{} = EXCLUDED.{}", field, field))
+                .collect::<Vec<String>>();
+            if set_updated_at {
+                updates.push("updated_at = CURRENT_TIMESTAMP".to_string());
+            }
+            query.push_str(&updates.join(", "));
             query.push_str(" RETURNING *");
 
-            let mut query = sqlx::query(&query);
-            for (_, value) in values.iter().enumerate() {
+            let mut query = sqlx::query(sqlx::AssertSqlSafe(query));
+            for value in values.iter() {
                 query = query.bind(value);
             }
-            match query.fetch_all(&pool).await {
+            match fetch_all(&resource_name, "upsert_resource_batch", query).await {
                 Ok(rows) => Ok(rows
                     .into_iter()
                     .map(|row| <$resource as DatabaseResource>::from_row(&row))
@@ -340,3 +281,95 @@ macro_rules! upsert_resource_batch {
         }
     }};
 }
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use crate::{
+        database::{
+            connection::{execute, rolled_back},
+            values::DatabaseValue,
+        },
+        models::user::User,
+    };
+
+    fn user_params(id: &str, display_name: &str) -> Vec<(&'static str, DatabaseValue)> {
+        vec![
+            ("id", id.to_string().into()),
+            ("display_name", display_name.to_string().into()),
+            ("email", format!("{}@example.com", id).into()),
+            ("password_hash", "hash".into()),
+        ]
+    }
+
+    async fn backdate_updated_at(id: &str) -> Result<(), anyhow::Error> {
+        let query = sqlx::query(
+            "UPDATE users SET updated_at = updated_at - INTERVAL '1 day' WHERE id = $1",
+        )
+        .bind(id);
+        execute("users", "backdate", query).await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a migrated database at DATABASE_URL"]
+    async fn test_upsert_resource_updates_the_existing_row() {
+        rolled_back(async {
+            let id = Uuid::new_v4().to_string();
+            let inserted = upsert_resource!(User, user_params(&id, "before")).await?;
+            assert_eq!(inserted.id, id);
+            backdate_updated_at(&id).await?;
+
+            let updated = upsert_resource!(User, user_params(&id, "after")).await?;
+            assert_eq!(updated.id, id);
+            assert_eq!(updated.display_name, "after");
+            assert_eq!(updated.created_at, inserted.created_at);
+            // The database refreshes updated_at when the row already exists
+            assert_eq!(updated.updated_at, inserted.updated_at);
+            Ok(())
+        })
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a migrated database at DATABASE_URL"]
+    async fn test_upsert_resource_batch_inserts_and_updates() {
+        rolled_back(async {
+            let ids = vec![Uuid::new_v4().to_string(), Uuid::new_v4().to_string()];
+            let inserted = upsert_resource_batch!(
+                User,
+                vec![
+                    user_params(&ids[0], "first"),
+                    user_params(&ids[1], "second")
+                ]
+            )
+            .await?;
+            assert_eq!(inserted.len(), 2);
+            backdate_updated_at(&ids[0]).await?;
+
+            let new_id = Uuid::new_v4().to_string();
+            let upserted = upsert_resource_batch!(
+                User,
+                vec![
+                    user_params(&ids[0], "renamed"),
+                    user_params(&new_id, "third")
+                ]
+            )
+            .await?;
+            assert_eq!(upserted.len(), 2);
+            let renamed = upserted.iter().find(|user| user.id == ids[0]).unwrap();
+            assert_eq!(renamed.display_name, "renamed");
+            assert_eq!(renamed.created_at, inserted[0].created_at);
+            assert_eq!(renamed.updated_at, inserted[0].updated_at);
+            assert!(upserted.iter().any(|user| user.id == new_id));
+
+            let empty: Vec<Vec<(&str, DatabaseValue)>> = vec![];
+            assert!(upsert_resource_batch!(User, empty).await?.is_empty());
+            Ok(())
+        })
+        .await
+        .unwrap();
+    }
+}
@@ -15,7 +15,9 @@ use sqlx::postgres::PgArgumentBuffer;
 use sqlx::{Encode, Postgres, Type, encode::IsNull, error::BoxDynError};
 use std::fmt::{self, Display};
 use std::iter::FromIterator;
-use time::OffsetDateTime;
+use time::{OffsetDateTime, UtcOffset};
+
+use crate::utils::time::format_rfc3339;
 
 /// Represents a type-safe database value with proper SQL encoding.
 ///
@@ -90,6 +92,45 @@ pub enum DatabaseValue {
     DateTime(String),
 }
 
+/// Writes a timestamp as RFC 3339 in UTC, which Postgres reads the same way
+/// whatever the connection's time zone.
+fn utc_string(dt: &OffsetDateTime) -> String {
+    format_rfc3339(dt).unwrap_or_else(|_| dt.to_offset(UtcOffset::UTC).to_string())
+}
+
+impl DatabaseValue {
+    /// The bind placeholder for this value at `index`, cast to the column type.
+    ///
+    /// Every variant is bound as text, so numeric, boolean and timestamp values
+    /// need the cast to compare against or be written to non-text columns.
+    pub fn placeholder(&self, index: usize) -> String {
+        match self {
+            DatabaseValue::None
+            | DatabaseValue::Str(_)
+            | DatabaseValue::String(_)
+            | DatabaseValue::Text(_) => format!("${}", index),
+            DatabaseValue::DateTime(_) => format!("CAST(${} AS TIMESTAMP WITH TIME ZONE)", index),
+            DatabaseValue::Int(_) | DatabaseValue::Int32(_) => {
+                format!("CAST(${} AS INTEGER)", index)
+            }
+            DatabaseValue::Int64(_) => format!("CAST(${} AS BIGINT)", index),
+            DatabaseValue::Float(_) => format!("CAST(${} AS FLOAT)", index),
+            DatabaseValue::Boolean(_) => format!("CAST(${} AS BOOLEAN)", index),
+        }
+    }
+
+    /// The `WHERE` condition matching `field` against this value at `index`.
+    ///
+    /// `= NULL` never matches, so `None` becomes an `IS NULL` check. Its value
+    /// is still bound, which keeps the placeholders after it at their index.
+    pub fn condition(&self, field: &str, index: usize) -> String {
+        match self {
+            DatabaseValue::None => format!("({} IS NULL AND ${} IS NULL)", field, index),
+            _ => format!("{} = {}", field, self.placeholder(index)),
+        }
+    }
+}
+
 impl Display for DatabaseValue {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{:?}", self)
@@ -104,7 +145,7 @@ impl<'q> Encode<'q, Postgres> for DatabaseValue {
             DatabaseValue::String(s) => Encode::<Postgres>::encode_by_ref(s, buf),
             DatabaseValue::Text(s) => Encode::<Postgres>::encode_by_ref(s, buf),
             DatabaseValue::Int(i) => Encode::<Postgres>::encode_by_ref(i, buf),
-            DatabaseValue::Int32(i) => Encode::<Postgres>::encode_by_ref(i, buf),
+            DatabaseValue::Int32(i) => Encode::<Postgres>::encode_by_ref(&i.to_string(), buf),
             DatabaseValue::Int64(i) => Encode::<Postgres>::encode_by_ref(i, buf),
             DatabaseValue::Float(f) => Encode::<Postgres>::encode_by_ref(f, buf),
             DatabaseValue::Boolean(b) => Encode::<Postgres>::encode_by_ref(b, buf),
@@ -152,7 +193,7 @@ impl FromIterator<bool> for DatabaseValue {
 
 impl FromIterator<OffsetDateTime> for DatabaseValue {
     fn from_iter<I: IntoIterator<Item = OffsetDateTime>>(iter: I) -> Self {
-        DatabaseValue::DateTime(iter.into_iter().map(|dt| dt.to_string()).collect())
+        DatabaseValue::DateTime(iter.into_iter().map(|dt| utc_string(&dt)).collect())
     }
 }
 
@@ -200,7 +241,7 @@ impl From<bool> for DatabaseValue {
 
 impl From<OffsetDateTime> for DatabaseValue {
     fn from(dt: OffsetDateTime) -> Self {
-        DatabaseValue::DateTime(dt.to_string())
+        DatabaseValue::DateTime(utc_string(&dt))
     }
 }
 
@@ -222,12 +263,26 @@ impl From<f64> for DatabaseValue {
     }
 }
 
+/// `None` is SQL `NULL`, never an empty or zero value, so a missing email is
+/// stored as `NULL` and found with `IS NULL`.
 impl<T: Into<DatabaseValue>> From<Option<T>> for DatabaseValue {
     fn from(option: Option<T>) -> Self {
         option.map(|v| v.into()).unwrap_or(DatabaseValue::None)
     }
 }
 
+/// Converts a GraphQL nullable input into a partial update entry: an omitted
+/// argument leaves the column unchanged and an explicit `null` clears it.
+pub fn nullable_param<T: Into<DatabaseValue>>(
+    value: juniper::Nullable<T>,
+) -> Option<DatabaseValue> {
+    match value {
+        juniper::Nullable::ImplicitNull => None,
+        juniper::Nullable::ExplicitNull => Some(DatabaseValue::None),
+        juniper::Nullable::Some(value) => Some(value.into()),
+    }
+}
+
 impl From<DatabaseValue> for String {
     fn from(value: DatabaseValue) -> Self {
         match value {
@@ -236,3 +291,82 @@ impl From<DatabaseValue> for String {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_timestamps_are_written_in_utc() {
+        let eastern = UtcOffset::from_hms(-5, 0, 0).unwrap();
+        let value: DatabaseValue = OffsetDateTime::from_unix_timestamp(1_735_805_045)
+            .unwrap()
+            .to_offset(eastern)
+            .into();
+        assert!(matches!(
+            value,
+            DatabaseValue::DateTime(ref dt) if dt == "2025-01-02T08:04:05Z"
+        ));
+    }
+
+    #[test]
+    fn test_integer_filter_is_cast() {
+        let value: DatabaseValue = 5i32.into();
+        assert_eq!(
+            format!("experience_level = {}", value.placeholder(1)),
+            "experience_level = CAST($1 AS INTEGER)"
+        );
+        assert_eq!(
+            DatabaseValue::Int32(5).placeholder(2),
+            "CAST($2 AS INTEGER)"
+        );
+        assert_eq!(
+            DatabaseValue::from(5i64).placeholder(1),
+            "CAST($1 AS BIGINT)"
+        );
+        assert_eq!(
+            DatabaseValue::from(true).placeholder(1),
+            "CAST($1 AS BOOLEAN)"
+        );
+        assert_eq!(DatabaseValue::from("user").placeholder(3), "$3");
+    }
+
+    #[test]
+    fn test_none_is_stored_and_queried_as_null() {
+        let cases: Vec<(DatabaseValue, DatabaseValue)> = vec![
+            (Option::<String>::None.into(), Some("a".to_string()).into()),
+            (Option::<&str>::None.into(), Some("a").into()),
+            (Option::<bool>::None.into(), Some(true).into()),
+            (Option::<i32>::None.into(), Some(1i32).into()),
+            (Option::<i64>::None.into(), Some(1i64).into()),
+            (Option::<f64>::None.into(), Some(1.5f64).into()),
+            (
+                Option::<OffsetDateTime>::None.into(),
+                Some(OffsetDateTime::UNIX_EPOCH).into(),
+            ),
+        ];
+        for (none, some) in cases {
+            assert!(matches!(none, DatabaseValue::None));
+            assert!(!matches!(some, DatabaseValue::None));
+        }
+        assert!(matches!(
+            DatabaseValue::from(Some(7i32)),
+            DatabaseValue::Int(ref i) if i == "7"
+        ));
+
+        // Bound as a real NULL rather than an empty string
+        let mut buf = PgArgumentBuffer::default();
+        let is_null = DatabaseValue::from(Option::<String>::None)
+            .encode_by_ref(&mut buf)
+            .unwrap();
+        assert!(matches!(is_null, IsNull::Yes));
+
+        let email: DatabaseValue = Option::<String>::None.into();
+        assert_eq!(
+            email.condition("email", 2),
+            "(email IS NULL AND $2 IS NULL)"
+        );
+        let email: DatabaseValue = Some("user@example.com".to_string()).into();
+        assert_eq!(email.condition("email", 2), "email = $2");
+    }
+}
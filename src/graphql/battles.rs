@@ -0,0 +1,744 @@
+use juniper::{FieldError, GraphQLObject};
+use serde::Serialize;
+
+use crate::{
+    battle::{
+        helpers::BattleRng,
+        turn_order::{TurnOrderRule, first_turn_user_id, turn_order_rule, turn_user_id_after},
+    },
+    graphql::{
+        Ctx,
+        pagination::{page_limit, page_offset},
+    },
+    models::{
+        battle::{Battle, BattleResult, RecentOpponent, SpectatableBattle},
+        battle_log::{BattleLog, BattleReplayEntry},
+        battle_status::{BattleStatus, BattleStatusState},
+        block::Block,
+        mnstr::Mnstr,
+        user::User,
+    },
+    utils::validation::validate_id,
+    websocket::battle_queue::models::{BattleLogData, BattleQueueGameData},
+};
+
+/// Battle logs sent along with the game state.
+pub const RECENT_BATTLE_LOGS: usize = 10;
+
+/// Archived battles looked through for recent opponents.
+pub const RECENT_OPPONENT_BATTLES: i64 = 200;
+
+#[derive(Debug, Clone, GraphQLObject)]
+pub struct CurrentBattleStatus {
+    pub in_battle: bool,
+    pub battle_id: Option<String>,
+    /// The last settled battle, including ones that ended after the user left.
+    pub last_result: Option<BattleResult>,
+}
+
+/// One side of a matchup preview. Only the level and power score of the mnstr
+/// are shown; its individual stats stay hidden until the battle starts.
+#[derive(Debug, Clone, Serialize, GraphQLObject)]
+#[serde(rename_all = "camelCase")]
+pub struct MatchupSide {
+    pub user_id: String,
+    pub display_name: String,
+    pub mnstr_id: Option<String>,
+    pub mnstr_name: Option<String>,
+    pub level: Option<i32>,
+    pub power_score: Option<i32>,
+}
+
+impl MatchupSide {
+    pub fn new(user: &User, mnstr: Option<&Mnstr>) -> Self {
+        Self {
+            user_id: user.id.clone(),
+            display_name: user.display_name.clone(),
+            mnstr_id: mnstr.map(|mnstr| mnstr.id.clone()),
+            mnstr_name: mnstr.map(|mnstr| mnstr.mnstr_name.clone()),
+            level: mnstr.map(|mnstr| mnstr.current_level),
+            power_score: mnstr.map(|mnstr| mnstr.power_score),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, GraphQLObject)]
+#[serde(rename_all = "camelCase")]
+pub struct MatchupPreview {
+    pub player: MatchupSide,
+    pub challenger: MatchupSide,
+}
+
+/// Everything a client needs to pick a battle back up after reconnecting.
+#[derive(Debug, Clone, GraphQLObject)]
+pub struct BattleState {
+    pub game_data: BattleQueueGameData,
+    /// The last `RECENT_BATTLE_LOGS` turns, oldest first.
+    pub recent_logs: Vec<BattleReplayEntry>,
+}
+
+pub struct BattleQueryType;
+
+#[juniper::graphql_object]
+impl BattleQueryType {
+    async fn current_status(ctx: &Ctx) -> Result<CurrentBattleStatus, FieldError> {
+        current_status(ctx).await
+    }
+
+    async fn battle_replay(
+        ctx: &Ctx,
+        battle_id: String,
+    ) -> Result<Vec<BattleReplayEntry>, FieldError> {
+        battle_replay(ctx, battle_id).await
+    }
+
+    async fn battle_state(ctx: &Ctx, battle_id: String) -> Result<BattleState, FieldError> {
+        battle_state(ctx, battle_id).await
+    }
+
+    async fn matchup_preview(
+        ctx: &Ctx,
+        challenger_id: String,
+        challenger_mnstr_id: Option<String>,
+        mnstr_id: Option<String>,
+    ) -> Result<MatchupPreview, FieldError> {
+        matchup_preview(ctx, challenger_id, challenger_mnstr_id, mnstr_id).await
+    }
+
+    async fn spectatable_battles(
+        ctx: &Ctx,
+        limit: Option<i32>,
+        offset: Option<i32>,
+    ) -> Result<Vec<SpectatableBattle>, FieldError> {
+        spectatable_battles(ctx, limit, offset).await
+    }
+
+    async fn recent_opponents(
+        ctx: &Ctx,
+        limit: Option<i32>,
+    ) -> Result<Vec<RecentOpponent>, FieldError> {
+        recent_opponents(ctx, limit).await
+    }
+
+    /// How many players are waiting in the battle queue.
+    async fn queue_size(ctx: &Ctx) -> Result<i32, FieldError> {
+        queue_size(ctx).await
+    }
+}
+
+pub async fn current_status(ctx: &Ctx) -> Result<CurrentBattleStatus, FieldError> {
+    if let None = ctx.session {
+        return Err(FieldError::from("Invalid session"));
+    }
+    let session = ctx.session.as_ref().unwrap().clone();
+
+    let battle_status =
+        match BattleStatus::find_optional_by(vec![("user_id", session.user_id.clone().into())])
+            .await
+        {
+            Ok(battle_status) => battle_status,
+            Err(e) => {
+                println!("[current_status] Failed to get battle status: {:?}", e);
+                return Err(FieldError::from("Failed to get battle status"));
+            }
+        };
+
+    let last_result = match Battle::find_last_result(session.user_id.clone()).await {
+        Ok(last_result) => last_result,
+        Err(e) => {
+            println!("[current_status] Failed to get last battle result: {:?}", e);
+            return Err(FieldError::from("Failed to get last battle result"));
+        }
+    };
+
+    Ok(current_battle_status(battle_status, last_result))
+}
+
+pub async fn queue_size(ctx: &Ctx) -> Result<i32, FieldError> {
+    if let None = ctx.session {
+        return Err(FieldError::from("Invalid session"));
+    }
+
+    match BattleStatus::count_in_queue().await {
+        Ok(count) => Ok(count.min(i32::MAX as i64) as i32),
+        Err(e) => {
+            println!("[queue_size] Failed to count queued players: {:?}", e);
+            Err(FieldError::from("Failed to get queue size"))
+        }
+    }
+}
+
+pub async fn battle_replay(
+    ctx: &Ctx,
+    battle_id: String,
+) -> Result<Vec<BattleReplayEntry>, FieldError> {
+    let battle = match Battle::find_one_with_archived(battle_id.clone()).await {
+        Ok(battle) => battle,
+        Err(e) => {
+            println!("[battle_replay] Failed to get battle: {:?}", e);
+            return Err(FieldError::from("Battle not found"));
+        }
+    };
+
+    let user_id = ctx.session.as_ref().map(|session| session.user_id.as_str());
+    if !battle.can_view_replay(user_id) {
+        return Err(FieldError::from("Not authorized"));
+    }
+
+    match BattleLog::find_replay(battle.id.clone()).await {
+        Ok(entries) => Ok(entries),
+        Err(e) => {
+            println!("[battle_replay] Failed to get battle logs: {:?}", e);
+            Err(FieldError::from("Failed to get battle replay"))
+        }
+    }
+}
+
+/// The current state of one of the user's battles, read from the database
+/// rather than from anything the client cached.
+pub async fn battle_state(ctx: &Ctx, battle_id: String) -> Result<BattleState, FieldError> {
+    if let None = ctx.session {
+        return Err(FieldError::from("Invalid session"));
+    }
+    let session = ctx.session.as_ref().unwrap().clone();
+    if let Err(e) = validate_id(&battle_id) {
+        return Err(FieldError::from(e.to_string()));
+    }
+
+    let battle = match Battle::find_one(battle_id).await {
+        Ok(battle) => battle,
+        Err(e) => {
+            println!("[battle_state] Failed to get battle: {:?}", e);
+            return Err(FieldError::from("Battle not found"));
+        }
+    };
+    if !battle.is_participant(&session.user_id) {
+        return Err(FieldError::from("Not authorized"));
+    }
+
+    let mut mnstrs = Vec::new();
+    for mnstr_id in [
+        battle.challenger_mnstr_id.clone(),
+        battle.opponent_mnstr_id.clone(),
+    ] {
+        let mnstr = match mnstr_id {
+            Some(mnstr_id) => match Mnstr::find_one(mnstr_id, false).await {
+                Ok(mnstr) => Some(mnstr),
+                Err(e) => {
+                    println!("[battle_state] Failed to get mnstr: {:?}", e);
+                    return Err(FieldError::from("Mnstr not found"));
+                }
+            },
+            None => None,
+        };
+        mnstrs.push(mnstr);
+    }
+    let opponent_mnstr = mnstrs.pop().unwrap();
+    let challenger_mnstr = mnstrs.pop().unwrap();
+
+    let logs = match BattleLog::find_replay(battle.id.clone()).await {
+        Ok(logs) => logs,
+        Err(e) => {
+            println!("[battle_state] Failed to get battle logs: {:?}", e);
+            return Err(FieldError::from("Failed to get battle logs"));
+        }
+    };
+
+    Ok(build_battle_state(
+        &battle,
+        challenger_mnstr,
+        opponent_mnstr,
+        logs,
+        turn_order_rule(),
+    ))
+}
+
+/// Rebuilds the game data the battle's messages carry. The first turn is
+/// decided the same way as when the mnstrs were chosen, and each logged turn
+/// hands it to the other player since.
+fn build_battle_state(
+    battle: &Battle,
+    challenger_mnstr: Option<Mnstr>,
+    opponent_mnstr: Option<Mnstr>,
+    logs: Vec<BattleReplayEntry>,
+    rule: TurnOrderRule,
+) -> BattleState {
+    let turn_user_id = match (&challenger_mnstr, &opponent_mnstr) {
+        (Some(challenger_mnstr), Some(opponent_mnstr)) if !battle.is_settled() => {
+            let mut rng = BattleRng::for_turn(battle.seed, 0);
+            let first_turn_user_id = first_turn_user_id(
+                rule,
+                &battle.challenger_id,
+                Some(challenger_mnstr),
+                &battle.opponent_id,
+                Some(opponent_mnstr),
+                || rng.coin_flip(),
+            );
+            Some(turn_user_id_after(
+                &first_turn_user_id,
+                &battle.challenger_id,
+                &battle.opponent_id,
+                logs.len(),
+            ))
+        }
+        _ => None,
+    };
+    let battle_log_data = logs.last().map(|entry| BattleLogData {
+        missed: entry.missed,
+        hit: entry.hit,
+        damage: entry.damage,
+        defense: entry.defense,
+        crit: entry.crit,
+    });
+
+    let game_data = BattleQueueGameData {
+        battle_id: Some(battle.id.clone()),
+        challenger_mnstr,
+        challenger_mnstrs: None,
+        opponent_mnstr,
+        opponent_mnstrs: None,
+        mnstr: None,
+        winner_id: battle.winner_id.clone(),
+        winner_xp_awarded: battle.winner_xp_awarded,
+        winner_coins_awarded: battle.winner_coins_awarded,
+        loser_xp_awarded: battle.loser_xp_awarded,
+        loser_coins_awarded: battle.loser_coins_awarded,
+        turn_user_id,
+        battle_log_data,
+    };
+    let skipped = logs.len().saturating_sub(RECENT_BATTLE_LOGS);
+    let recent_logs = logs
+        .into_iter()
+        .skip(skipped)
+        .collect::<Vec<BattleReplayEntry>>();
+    BattleState {
+        game_data,
+        recent_logs,
+    }
+}
+
+/// Running battles the user can watch, excluding their own.
+pub async fn spectatable_battles(
+    ctx: &Ctx,
+    limit: Option<i32>,
+    offset: Option<i32>,
+) -> Result<Vec<SpectatableBattle>, FieldError> {
+    if let None = ctx.session {
+        return Err(FieldError::from("Invalid session"));
+    }
+    let session = ctx.session.as_ref().unwrap().clone();
+
+    match Battle::find_spectatable(session.user_id, page_limit(limit), page_offset(offset)).await {
+        Ok(battles) => Ok(battles),
+        Err(e) => {
+            println!("[spectatable_battles] Failed to get battles: {:?}", e);
+            Err(FieldError::from("Failed to get battles"))
+        }
+    }
+}
+
+/// The distinct players the user fought most recently, for rematches, with
+/// whether they are online and what they are doing in the battle queue.
+/// Players blocked either way are left out.
+pub async fn recent_opponents(
+    ctx: &Ctx,
+    limit: Option<i32>,
+) -> Result<Vec<RecentOpponent>, FieldError> {
+    if let None = ctx.session {
+        return Err(FieldError::from("Invalid session"));
+    }
+    let session = ctx.session.as_ref().unwrap().clone();
+
+    let battles = match Battle::find_recent_archived(
+        session.user_id.clone(),
+        RECENT_OPPONENT_BATTLES,
+    )
+    .await
+    {
+        Ok(battles) => battles,
+        Err(e) => {
+            println!("[recent_opponents] Failed to get battles: {:?}", e);
+            return Err(FieldError::from("Failed to get battles"));
+        }
+    };
+    let blocked_user_ids = match Block::blocked_user_ids(session.user_id.clone()).await {
+        Ok(blocked_user_ids) => blocked_user_ids,
+        Err(e) => {
+            println!("[recent_opponents] Failed to get blocks: {:?}", e);
+            return Err(FieldError::from("Failed to get blocks"));
+        }
+    };
+    let battles = battles
+        .into_iter()
+        .filter(|battle| !blocked_user_ids.iter().any(|id| battle.is_participant(id)))
+        .collect::<Vec<Battle>>();
+    let opponents =
+        Battle::recent_opponents(&battles, &session.user_id, page_limit(limit) as usize);
+
+    let user_ids = opponents
+        .iter()
+        .map(|opponent| opponent.user_id.clone())
+        .collect::<Vec<String>>();
+    let battle_statuses = match BattleStatus::find_all_by_user_ids(user_ids).await {
+        Ok(battle_statuses) => battle_statuses,
+        Err(e) => {
+            println!("[recent_opponents] Failed to get battle statuses: {:?}", e);
+            return Err(FieldError::from("Failed to get battle statuses"));
+        }
+    };
+    Ok(opponents
+        .into_iter()
+        .map(|opponent| {
+            let battle_status = battle_statuses
+                .iter()
+                .find(|battle_status| battle_status.user_id == opponent.user_id);
+            opponent.with_status(battle_status)
+        })
+        .collect())
+}
+
+/// What a challenged player sees before accepting: the challenger's chosen
+/// mnstr, or their primary one, against the player's own.
+pub async fn matchup_preview(
+    ctx: &Ctx,
+    challenger_id: String,
+    challenger_mnstr_id: Option<String>,
+    mnstr_id: Option<String>,
+) -> Result<MatchupPreview, FieldError> {
+    if let None = ctx.session {
+        return Err(FieldError::from("Invalid session"));
+    }
+    let session = ctx.session.as_ref().unwrap().clone();
+    for id in [
+        Some(&challenger_id),
+        challenger_mnstr_id.as_ref(),
+        mnstr_id.as_ref(),
+    ]
+    .into_iter()
+    .flatten()
+    {
+        if let Err(e) = validate_id(id) {
+            return Err(FieldError::from(e.to_string()));
+        }
+    }
+    if challenger_id == session.user_id {
+        return Err(FieldError::from("Cannot preview a matchup with yourself"));
+    }
+
+    match Block::is_blocked_between(session.user_id.clone(), challenger_id.clone()).await {
+        Ok(false) => (),
+        Ok(true) => return Err(FieldError::from("Player is not available")),
+        Err(e) => {
+            println!("[matchup_preview] Failed to get blocks: {:?}", e);
+            return Err(FieldError::from("Player is not available"));
+        }
+    };
+
+    let params = vec![("user_id", challenger_id.clone().into())];
+    match BattleStatus::find_optional_by(params).await {
+        Ok(Some(status)) if status.has_challenged(&session.user_id) => (),
+        Ok(_) => return Err(FieldError::from("No challenge from this player")),
+        Err(e) => {
+            println!("[matchup_preview] Failed to get battle status: {:?}", e);
+            return Err(FieldError::from("Failed to get battle status"));
+        }
+    };
+
+    let mut sides = Vec::new();
+    for (user_id, mnstr_id) in [
+        (session.user_id.clone(), mnstr_id),
+        (challenger_id, challenger_mnstr_id),
+    ] {
+        let user = match ctx.find_user(user_id).await {
+            Ok(user) => user,
+            Err(e) => {
+                println!("[matchup_preview] Failed to get user: {:?}", e);
+                return Err(FieldError::from("User not found"));
+            }
+        };
+        let mnstr = matchup_mnstr(&user, mnstr_id).await?;
+        sides.push(MatchupSide::new(&user, mnstr.as_ref()));
+    }
+    let challenger = sides.pop().unwrap();
+    let player = sides.pop().unwrap();
+    Ok(MatchupPreview { player, challenger })
+}
+
+/// The mnstr `user` picked, which has to be theirs, or else their primary one.
+async fn matchup_mnstr(user: &User, mnstr_id: Option<String>) -> Result<Option<Mnstr>, FieldError> {
+    let mnstr_id = match mnstr_id {
+        Some(mnstr_id) => mnstr_id,
+        None => {
+            return match user.primary_mnstr().await {
+                Ok(mnstr) => Ok(mnstr),
+                Err(e) => {
+                    println!("[matchup_mnstr] Failed to get primary mnstr: {:?}", e);
+                    Err(FieldError::from("Failed to get mnstr"))
+                }
+            };
+        }
+    };
+    match Mnstr::find_one(mnstr_id, false).await {
+        Ok(mnstr) if user.owns_mnstr(&mnstr) => Ok(Some(mnstr)),
+        Ok(_) => Err(FieldError::from("Mnstr not found")),
+        Err(e) => {
+            println!("[matchup_mnstr] Failed to get mnstr: {:?}", e);
+            Err(FieldError::from("Mnstr not found"))
+        }
+    }
+}
+
+fn current_battle_status(
+    battle_status: Option<BattleStatus>,
+    last_result: Option<BattleResult>,
+) -> CurrentBattleStatus {
+    let battle_id = battle_status
+        .filter(|status| matches!(status.status, BattleStatusState::InBattle))
+        .and_then(|status| status.battle_id);
+    CurrentBattleStatus {
+        in_battle: battle_id.is_some(),
+        battle_id,
+        last_result,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::OffsetDateTime;
+
+    #[test]
+    fn test_escaped_battle_shows_in_current_status() {
+        // What `handle_game_ended` leaves behind after the challenger escapes
+        let mut battle = Battle::new(
+            "challenger".to_string(),
+            "Challenger".to_string(),
+            "opponent".to_string(),
+            "Opponent".to_string(),
+        );
+        battle.id = "battle".to_string();
+        battle.winner_id = battle.forfeit_winner_id("challenger");
+        battle.outcome = Some("escaped".to_string());
+        battle.winner_xp_awarded = Some(40);
+        battle.winner_coins_awarded = Some(12);
+        battle.loser_xp_awarded = Some(20);
+        battle.loser_coins_awarded = Some(5);
+        assert!(battle.result_for("challenger").is_none());
+        battle.archived_at = Some(OffsetDateTime::now_utc());
+
+        // The escaping player's battle status was removed when they left
+        let status = current_battle_status(None, battle.result_for("challenger"));
+        assert!(!status.in_battle);
+        let result = status.last_result.unwrap();
+        assert_eq!(result.battle_id, "battle");
+        assert_eq!(result.opponent_id, "opponent");
+        assert!(!result.won);
+        assert_eq!(result.outcome.as_deref(), Some("escaped"));
+        assert_eq!(result.xp_awarded, Some(20));
+        assert_eq!(result.coins_awarded, Some(5));
+
+        let result = battle.result_for("opponent").unwrap();
+        assert!(result.won);
+        assert_eq!(result.xp_awarded, Some(40));
+        assert!(battle.result_for("intruder").is_none());
+    }
+
+    #[test]
+    fn test_battle_replay_is_public_once_settled() {
+        let mut battle = Battle::new(
+            "challenger".to_string(),
+            "Challenger".to_string(),
+            "opponent".to_string(),
+            "Opponent".to_string(),
+        );
+        assert!(battle.can_view_replay(Some("challenger")));
+        assert!(battle.can_view_replay(Some("opponent")));
+        assert!(!battle.can_view_replay(Some("intruder")));
+        assert!(!battle.can_view_replay(None));
+
+        battle.winner_id = Some("opponent".to_string());
+        battle.archived_at = Some(OffsetDateTime::now_utc());
+        assert!(battle.can_view_replay(Some("intruder")));
+        assert!(battle.can_view_replay(None));
+    }
+
+    #[test]
+    fn test_matchup_preview_hides_individual_stats() {
+        let mut player = User::new(None, None, "password".to_string(), "Player".to_string());
+        player.id = "player".to_string();
+        let mut challenger =
+            User::new(None, None, "password".to_string(), "Challenger".to_string());
+        challenger.id = "challenger".to_string();
+
+        let mut mnstr = Mnstr::new(
+            "challenger".to_string(),
+            Some("Bitey".to_string()),
+            None,
+            "qr".to_string(),
+        );
+        mnstr.id = "mnstr".to_string();
+        mnstr.current_level = 4;
+        mnstr.max_attack = 30;
+        mnstr.update_power_score();
+
+        let preview = MatchupPreview {
+            player: MatchupSide::new(&player, None),
+            challenger: MatchupSide::new(&challenger, Some(&mnstr)),
+        };
+        assert_eq!(preview.player.user_id, "player");
+        assert!(preview.player.power_score.is_none());
+        assert_eq!(preview.challenger.level, Some(4));
+        assert_eq!(preview.challenger.power_score, Some(mnstr.power_score()));
+
+        let value = serde_json::to_value(&preview).unwrap();
+        for side in ["player", "challenger"] {
+            let fields = value[side].as_object().unwrap();
+            for hidden in [
+                "currentHealth",
+                "maxHealth",
+                "currentAttack",
+                "maxAttack",
+                "currentDefense",
+                "maxDefense",
+                "currentSpeed",
+                "maxSpeed",
+                "currentIntelligence",
+                "maxIntelligence",
+                "currentMagic",
+                "maxMagic",
+                "statPoints",
+            ] {
+                assert!(!fields.contains_key(hidden), "{} exposes {}", side, hidden);
+            }
+        }
+        assert_eq!(value["challenger"]["mnstrName"], "Bitey");
+    }
+
+    #[test]
+    fn test_battle_state_reflects_stored_stats_and_turn() {
+        let mut battle = Battle::new(
+            "challenger".to_string(),
+            "Challenger".to_string(),
+            "opponent".to_string(),
+            "Opponent".to_string(),
+        );
+        battle.id = "battle".to_string();
+        let stored = |id: &str, user_id: &str, speed: i32, health: i32| {
+            let mut mnstr = Mnstr::new(user_id.to_string(), None, None, format!("qr-{}", id));
+            mnstr.id = id.to_string();
+            mnstr.current_speed = speed;
+            mnstr.current_health = health;
+            mnstr
+        };
+        let log = |i: i32, user_id: &str| BattleReplayEntry {
+            id: format!("log-{}", i),
+            user_id: user_id.to_string(),
+            mnstr_id: format!("{}-mnstr", user_id),
+            action: "hit".to_string(),
+            missed: Some(false),
+            hit: Some(true),
+            damage: Some(i),
+            defense: None,
+            crit: None,
+            created_at: None,
+        };
+
+        // The faster challenger opened, and three turns have been played since
+        let logs = (1..=3)
+            .map(|i| log(i, if i % 2 == 1 { "challenger" } else { "opponent" }))
+            .collect::<Vec<BattleReplayEntry>>();
+        let state = build_battle_state(
+            &battle,
+            Some(stored("challenger-mnstr", "challenger", 30, 41)),
+            Some(stored("opponent-mnstr", "opponent", 10, 17)),
+            logs,
+            TurnOrderRule::SpeedBased,
+        );
+        let game_data = &state.game_data;
+        assert_eq!(game_data.battle_id.as_deref(), Some("battle"));
+        assert_eq!(game_data.turn_user_id.as_deref(), Some("opponent"));
+        assert_eq!(
+            game_data.challenger_mnstr.as_ref().unwrap().current_health,
+            41
+        );
+        assert_eq!(
+            game_data.opponent_mnstr.as_ref().unwrap().current_health,
+            17
+        );
+        assert_eq!(game_data.battle_log_data.as_ref().unwrap().damage, Some(3));
+        assert!(game_data.winner_id.is_none());
+        assert_eq!(state.recent_logs.len(), 3);
+
+        // Only the latest turns are sent, and a settled battle has no turn
+        let logs = (1..=25).map(|i| log(i, "challenger")).collect::<Vec<_>>();
+        battle.winner_id = Some("challenger".to_string());
+        let state = build_battle_state(
+            &battle,
+            Some(stored("challenger-mnstr", "challenger", 30, 41)),
+            Some(stored("opponent-mnstr", "opponent", 10, 0)),
+            logs,
+            TurnOrderRule::SpeedBased,
+        );
+        assert!(state.game_data.turn_user_id.is_none());
+        assert_eq!(state.game_data.winner_id.as_deref(), Some("challenger"));
+        assert_eq!(state.recent_logs.len(), RECENT_BATTLE_LOGS);
+        assert_eq!(state.recent_logs[0].id, "log-16");
+    }
+
+    #[test]
+    fn test_recent_opponents_are_distinct_and_newest_first() {
+        let epoch = OffsetDateTime::from_unix_timestamp(1_760_000_000).unwrap();
+        let battle = |id: &str, challenger: &str, opponent: &str, minutes: Option<i64>| {
+            let mut battle = Battle::new(
+                challenger.to_string(),
+                format!("{} name", challenger),
+                opponent.to_string(),
+                format!("{} name", opponent),
+            );
+            battle.id = id.to_string();
+            battle.archived_at = minutes.map(|minutes| epoch + time::Duration::minutes(minutes));
+            battle
+        };
+        let battles = vec![
+            battle("b1", "player", "ann", Some(1)),
+            battle("b2", "bob", "player", Some(5)),
+            battle("b3", "player", "ann", Some(9)),
+            battle("b4", "cat", "player", Some(3)),
+            // Still running, so not a recent opponent yet
+            battle("b5", "player", "dan", None),
+            // Someone else's battle
+            battle("b6", "eve", "bob", Some(20)),
+        ];
+
+        let opponents = Battle::recent_opponents(&battles, "player", 10);
+        let ids = opponents
+            .iter()
+            .map(|opponent| opponent.user_id.as_str())
+            .collect::<Vec<&str>>();
+        assert_eq!(ids, vec!["ann", "bob", "cat"]);
+        assert_eq!(opponents[0].display_name, "ann name");
+        assert_eq!(
+            opponents[0].last_battled_at,
+            epoch + time::Duration::minutes(9)
+        );
+        assert_eq!(opponents[1].display_name, "bob name");
+
+        let opponents = Battle::recent_opponents(&battles, "player", 2);
+        assert_eq!(opponents.len(), 2);
+
+        let queued = BattleStatus::new(
+            "bob".to_string(),
+            "bob name".to_string(),
+            None,
+            None,
+            None,
+            BattleStatusState::InQueue,
+        );
+        let bob = opponents[1].clone().with_status(Some(&queued));
+        assert!(bob.online);
+        assert_eq!(bob.status.as_deref(), Some("inQueue"));
+        let ann = opponents[0].clone().with_status(None);
+        assert!(!ann.online);
+        assert!(ann.status.is_none());
+    }
+}
@@ -0,0 +1,81 @@
+use juniper::FieldError;
+
+use crate::{
+    graphql::Ctx,
+    models::{block::Block, report::Report},
+};
+
+pub struct BlockMutationType;
+
+#[juniper::graphql_object]
+impl BlockMutationType {
+    async fn block(ctx: &Ctx, user_id: String) -> Result<Block, FieldError> {
+        block(ctx, user_id).await
+    }
+
+    async fn unblock(ctx: &Ctx, user_id: String) -> Result<bool, FieldError> {
+        unblock(ctx, user_id).await
+    }
+
+    async fn report(ctx: &Ctx, user_id: String, reason: String) -> Result<Report, FieldError> {
+        report(ctx, user_id, reason).await
+    }
+}
+
+pub async fn block(ctx: &Ctx, user_id: String) -> Result<Block, FieldError> {
+    if let None = ctx.session {
+        return Err(FieldError::from("Invalid session"));
+    }
+    let session = ctx.session.as_ref().unwrap().clone();
+
+    if let Err(e) = ctx.find_user(user_id.clone()).await {
+        println!("[block] Failed to get user: {:?}", e);
+        return Err(FieldError::from("User not found"));
+    }
+
+    let mut block = Block::new(session.user_id.clone(), user_id);
+    if let Some(error) = block.create().await {
+        println!("[block] Failed to block user: {:?}", error);
+        return Err(FieldError::from("Failed to block user"));
+    }
+
+    Ok(block)
+}
+
+pub async fn unblock(ctx: &Ctx, user_id: String) -> Result<bool, FieldError> {
+    if let None = ctx.session {
+        return Err(FieldError::from("Invalid session"));
+    }
+    let session = ctx.session.as_ref().unwrap().clone();
+
+    let mut block = Block::new(session.user_id.clone(), user_id);
+    if let Some(error) = block.delete().await {
+        println!("[unblock] Failed to unblock user: {:?}", error);
+        return Err(FieldError::from("User is not blocked"));
+    }
+
+    Ok(true)
+}
+
+pub async fn report(ctx: &Ctx, user_id: String, reason: String) -> Result<Report, FieldError> {
+    if let None = ctx.session {
+        return Err(FieldError::from("Invalid session"));
+    }
+    let session = ctx.session.as_ref().unwrap().clone();
+
+    if let Err(e) = ctx.find_user(user_id.clone()).await {
+        println!("[report] Failed to get user: {:?}", e);
+        return Err(FieldError::from("User not found"));
+    }
+
+    let mut report = Report::new(session.user_id.clone(), user_id, reason);
+    if let Some(error) = report.validate() {
+        return Err(FieldError::from(error.to_string()));
+    }
+    if let Some(error) = report.create().await {
+        println!("[report] Failed to report user: {:?}", error);
+        return Err(FieldError::from("Failed to report user"));
+    }
+
+    Ok(report)
+}
@@ -0,0 +1,88 @@
+//! Per-request memoization of loads by id.
+//!
+//! Resolving one GraphQL request can load the same row more than once, such as
+//! the session's user in two fields. Each `Ctx` holds its own caches, so an
+//! entry never outlives the request. Mutations invalidate what they write so
+//! fields resolved after them read it fresh.
+
+use std::{collections::HashMap, sync::Mutex};
+
+pub struct RequestCache<T> {
+    entries: Mutex<HashMap<String, T>>,
+}
+
+impl<T> Default for RequestCache<T> {
+    fn default() -> Self {
+        Self {
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl<T: Clone> RequestCache<T> {
+    /// The cached value for `id`, otherwise whatever `load(id)` returns. Only
+    /// successful loads are kept.
+    pub async fn get_or_load<F, Fut>(&self, id: String, load: F) -> Result<T, anyhow::Error>
+    where
+        F: FnOnce(String) -> Fut,
+        Fut: Future<Output = Result<T, anyhow::Error>>,
+    {
+        if let Some(value) = self.entries.lock().unwrap().get(&id) {
+            return Ok(value.clone());
+        }
+        let value = load(id.clone()).await?;
+        self.entries.lock().unwrap().insert(id, value.clone());
+        Ok(value)
+    }
+
+    pub fn invalidate(&self, id: &str) {
+        self.entries.lock().unwrap().remove(id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+    use crate::models::user::User;
+
+    #[tokio::test]
+    async fn test_same_user_is_loaded_once_per_request() {
+        let users: RequestCache<User> = RequestCache::default();
+        let queries = AtomicUsize::new(0);
+        let load = |id: String| {
+            queries.fetch_add(1, Ordering::SeqCst);
+            async move {
+                let mut user = User::new(None, None, "password".to_string(), "Player".to_string());
+                user.id = id;
+                Ok(user)
+            }
+        };
+
+        let first = users.get_or_load("user".to_string(), load).await.unwrap();
+        let second = users.get_or_load("user".to_string(), load).await.unwrap();
+        assert_eq!(queries.load(Ordering::SeqCst), 1);
+        assert_eq!(first.id, second.id);
+
+        users.get_or_load("other".to_string(), load).await.unwrap();
+        assert_eq!(queries.load(Ordering::SeqCst), 2);
+
+        // A write in the request sends the next read back to the database
+        users.invalidate("user");
+        users.get_or_load("user".to_string(), load).await.unwrap();
+        assert_eq!(queries.load(Ordering::SeqCst), 3);
+
+        let failed = users
+            .get_or_load("missing".to_string(), |_| async {
+                Err(anyhow::anyhow!("User not found"))
+            })
+            .await;
+        assert!(failed.is_err());
+        users
+            .get_or_load("missing".to_string(), load)
+            .await
+            .unwrap();
+        assert_eq!(queries.load(Ordering::SeqCst), 4);
+    }
+}
@@ -0,0 +1,78 @@
+use juniper::{GraphQLEnum, GraphQLObject};
+
+use crate::models::{
+    generated::{level_xp, mnstr_xp},
+    xp,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, GraphQLEnum)]
+pub enum LevelCurveKind {
+    User,
+    Mnstr,
+}
+
+#[derive(Debug, Clone, GraphQLObject)]
+pub struct LevelCurve {
+    /// XP thresholds for user levels, indexed by level.
+    pub user: Vec<i32>,
+    /// XP needed to reach each mnstr level, indexed by level.
+    pub mnstr: Vec<i32>,
+}
+
+pub struct LevelQueryType;
+
+#[juniper::graphql_object]
+impl LevelQueryType {
+    async fn level_curve() -> LevelCurve {
+        level_curve()
+    }
+
+    async fn xp_to_next_level(level: i32, kind: Option<LevelCurveKind>) -> i32 {
+        xp_to_next_level(level, kind.unwrap_or(LevelCurveKind::User))
+    }
+}
+
+pub fn level_curve() -> LevelCurve {
+    LevelCurve {
+        user: level_xp::XP_FOR_LEVEL.to_vec(),
+        mnstr: mnstr_xp::XP_FOR_LEVEL.to_vec(),
+    }
+}
+
+/// Mirrors `update_experience_to_next_level` on users and mnstrs: levels past
+/// the end of the curve use its last entry.
+pub fn xp_to_next_level(level: i32, kind: LevelCurveKind) -> i32 {
+    let curve: &[i32] = match kind {
+        LevelCurveKind::User => &level_xp::XP_FOR_LEVEL,
+        LevelCurveKind::Mnstr => &mnstr_xp::XP_FOR_LEVEL,
+    };
+    xp::xp_to_next_level(curve, level)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_level_curve_matches_generated_tables() {
+        let curve = level_curve();
+        assert_eq!(curve.user, level_xp::XP_FOR_LEVEL.to_vec());
+        assert_eq!(curve.mnstr, mnstr_xp::XP_FOR_LEVEL.to_vec());
+    }
+
+    #[test]
+    fn test_xp_to_next_level() {
+        assert_eq!(
+            xp_to_next_level(0, LevelCurveKind::User),
+            level_xp::XP_FOR_LEVEL[1]
+        );
+        assert_eq!(
+            xp_to_next_level(3, LevelCurveKind::Mnstr),
+            mnstr_xp::XP_FOR_LEVEL[4]
+        );
+        assert_eq!(
+            xp_to_next_level(1000, LevelCurveKind::Mnstr),
+            mnstr_xp::XP_FOR_LEVEL[mnstr_xp::XP_FOR_LEVEL.len() - 1]
+        );
+    }
+}
@@ -2,7 +2,7 @@ use juniper::{FieldError, GraphQLInputObject};
 use serde::{Deserialize, Serialize};
 use time::OffsetDateTime;
 
-use crate::{database::values::DatabaseValue, graphql::Ctx, models::{mnstr::{DEFAULT_STAT_VALUE, Mnstr}, session::Session}, utils::sessions::{get_user_from_token}};
+use crate::{database::values::DatabaseValue, graphql::Ctx, models::{mnstr::{COLLECTION_FULL_ERROR, CollectionFullError, Mnstr, MnstrStat, NOT_ENOUGH_COINS_ERROR}, session::Session}, utils::{sessions::get_user_from_token, validation::{validate_id, validate_qr_code}}};
 
 #[derive(Debug, Serialize, Deserialize, GraphQLInputObject, Clone)]
 pub struct BatchMnstrInput {
@@ -19,22 +19,21 @@ pub struct MnstrInput {
     pub created_at: Option<OffsetDateTime>,
     pub updated_at: Option<OffsetDateTime>,
     pub archived_at: Option<OffsetDateTime>,
-    pub current_level: Option<i32>,
-    pub current_experience: Option<i32>,
-    pub current_health: Option<i32>,
-    pub max_health: Option<i32>,
-    pub current_attack: Option<i32>,
-    pub max_attack: Option<i32>,
-    pub current_defense: Option<i32>,
-    pub max_defense: Option<i32>,
-    pub current_speed: Option<i32>,
-    pub max_speed: Option<i32>,
-    pub current_intelligence: Option<i32>,
-    pub max_intelligence: Option<i32>,
-    pub current_magic: Option<i32>,
-    pub max_magic: Option<i32>,
-    pub experience_to_next_level: Option<i32>,
 }
+
+impl MnstrInput {
+    /// Checks the client-provided id and QR code before they reach the database.
+    pub fn validate(&self) -> Result<(), anyhow::Error> {
+        if let Some(id) = &self.id {
+            validate_id(id)?;
+        }
+        if let Some(mnstr_qr_code) = &self.mnstr_qr_code {
+            validate_qr_code(mnstr_qr_code)?;
+        }
+        Ok(())
+    }
+}
+
 pub struct MnstrMutationType;
 
 #[juniper::graphql_object]
@@ -48,38 +47,8 @@ impl MnstrMutationType {
         mnstr_name: Option<String>,
         mnstr_description: Option<String>,
         mnstr_qr_code: Option<String>,
-        current_health: Option<i32>,
-        max_health: Option<i32>,
-        current_attack: Option<i32>,
-        max_attack: Option<i32>,
-        current_defense: Option<i32>,
-        max_defense: Option<i32>,
-        current_speed: Option<i32>,
-        max_speed: Option<i32>,
-        current_intelligence: Option<i32>,
-        max_intelligence: Option<i32>,
-        current_magic: Option<i32>,
-        max_magic: Option<i32>,
     ) -> Result<Mnstr, FieldError> {
-        create(
-            ctx,
-            mnstr_name,
-            mnstr_description,
-            mnstr_qr_code,
-            current_health,
-            max_health,
-            current_attack,
-            max_attack,
-            current_defense,
-            max_defense,
-            current_speed,
-            max_speed,
-            current_intelligence,
-            max_intelligence,
-            current_magic,
-            max_magic,
-        )
-        .await
+        create(ctx, mnstr_name, mnstr_description, mnstr_qr_code).await
     }
 
     async fn create_batch(ctx: &Ctx, mnstrs: BatchMnstrInput) -> Result<Vec<Mnstr>, FieldError> {
@@ -92,50 +61,35 @@ impl MnstrMutationType {
         mnstr_name: Option<String>,
         mnstr_description: Option<String>,
         mnstr_qr_code: Option<String>,
-        current_health: Option<i32>,
-        max_health: Option<i32>,
-        current_attack: Option<i32>,
-        max_attack: Option<i32>,
-        current_defense: Option<i32>,
-        max_defense: Option<i32>,
-        current_speed: Option<i32>,
-        max_speed: Option<i32>,
-        current_intelligence: Option<i32>,
-        max_intelligence: Option<i32>,
-        current_magic: Option<i32>,
-        max_magic: Option<i32>,
     ) -> Result<Mnstr, FieldError> {
-        update(
-            ctx,
-            id,
-            mnstr_name,
-            mnstr_description,
-            mnstr_qr_code,
-            current_health,
-            max_health,
-            current_attack,
-            max_attack,
-            current_defense,
-            max_defense,
-            current_speed,
-            max_speed,
-            current_intelligence,
-            max_intelligence,
-            current_magic,
-            max_magic,
-        )
-        .await
+        update(ctx, id, mnstr_name, mnstr_description, mnstr_qr_code).await
     }
 
     async fn update_batch(ctx: &Ctx, mnstrs: BatchMnstrInput) -> Result<Vec<Mnstr>, FieldError> {
         update_batch(ctx, mnstrs.mnstrs).await
     }
+
+    async fn allocate_stat(
+        ctx: &Ctx,
+        mnstr_id: String,
+        stat: MnstrStat,
+        points: i32,
+    ) -> Result<Mnstr, FieldError> {
+        allocate_stat(ctx, mnstr_id, stat, points).await
+    }
+
+    async fn restore_all(ctx: &Ctx) -> Result<Vec<Mnstr>, FieldError> {
+        restore_all(ctx).await
+    }
 }
 
 pub async fn collect(ctx: &Ctx, mnstr_qr_code: String) -> Result<Mnstr, FieldError> {
     if let None = ctx.session {
         return Err(FieldError::from("Invalid session"));
     }
+    if let Err(e) = validate_qr_code(&mnstr_qr_code) {
+        return Err(FieldError::from(e.to_string()));
+    }
     let session = ctx.session.as_ref().unwrap().clone();
     let user = match get_user_from_token::<Session>(session.session_token.clone()).await {
         Ok(user) => user,
@@ -154,6 +108,9 @@ pub async fn collect(ctx: &Ctx, mnstr_qr_code: String) -> Result<Mnstr, FieldErr
 
     if let Some(error) = mnstr.create().await {
         println!("[collect] Failed to create mnstr: {:?}", error);
+        if error.downcast_ref::<CollectionFullError>().is_some() {
+            return Err(FieldError::from(COLLECTION_FULL_ERROR));
+        }
         return Err(FieldError::from("Failed to create mnstr"));
     }
 
@@ -165,22 +122,15 @@ pub async fn create(
     mnstr_name: Option<String>,
     mnstr_description: Option<String>,
     mnstr_qr_code: Option<String>,
-    current_health: Option<i32>,
-    max_health: Option<i32>,
-    current_attack: Option<i32>,
-    max_attack: Option<i32>,
-    current_defense: Option<i32>,
-    max_defense: Option<i32>,
-    current_speed: Option<i32>,
-    max_speed: Option<i32>,
-    current_intelligence: Option<i32>,
-    max_intelligence: Option<i32>,
-    current_magic: Option<i32>,
-    max_magic: Option<i32>,
 ) -> Result<Mnstr, FieldError> {
     if let None = ctx.session {
         return Err(FieldError::from("Invalid session"));
     }
+    if let Some(mnstr_qr_code) = &mnstr_qr_code {
+        if let Err(e) = validate_qr_code(mnstr_qr_code) {
+            return Err(FieldError::from(e.to_string()));
+        }
+    }
     let session = ctx.session.as_ref().unwrap().clone();
     let user = match get_user_from_token::<Session>(session.session_token.clone()).await {
         Ok(user) => user,
@@ -197,21 +147,11 @@ pub async fn create(
         mnstr_qr_code.unwrap_or(String::new()),
     );
 
-    mnstr.current_health = current_health.unwrap_or(DEFAULT_STAT_VALUE);
-    mnstr.max_health = max_health.unwrap_or(DEFAULT_STAT_VALUE);
-    mnstr.current_attack = current_attack.unwrap_or(DEFAULT_STAT_VALUE);
-    mnstr.max_attack = max_attack.unwrap_or(DEFAULT_STAT_VALUE);
-    mnstr.current_defense = current_defense.unwrap_or(DEFAULT_STAT_VALUE);
-    mnstr.max_defense = max_defense.unwrap_or(DEFAULT_STAT_VALUE);
-    mnstr.current_speed = current_speed.unwrap_or(DEFAULT_STAT_VALUE);
-    mnstr.max_speed = max_speed.unwrap_or(DEFAULT_STAT_VALUE);
-    mnstr.current_intelligence = current_intelligence.unwrap_or(DEFAULT_STAT_VALUE);
-    mnstr.max_intelligence = max_intelligence.unwrap_or(DEFAULT_STAT_VALUE);
-    mnstr.current_magic = current_magic.unwrap_or(DEFAULT_STAT_VALUE);
-    mnstr.max_magic = max_magic.unwrap_or(DEFAULT_STAT_VALUE);
-
     if let Some(error) = mnstr.create().await {
         println!("[create] Failed to create mnstr: {:?}", error);
+        if error.downcast_ref::<CollectionFullError>().is_some() {
+            return Err(FieldError::from(COLLECTION_FULL_ERROR));
+        }
         return Err(FieldError::from("Failed to create mnstr"));
     }
 
@@ -222,6 +162,12 @@ pub async fn create_batch(ctx: &Ctx, mnstrs: Vec<MnstrInput>) -> Result<Vec<Mnst
     if let None = ctx.session {
         return Err(FieldError::from("Invalid session"));
     }
+    if let Some(e) = mnstrs
+        .iter()
+        .find_map(|mnstr_input| mnstr_input.validate().err())
+    {
+        return Err(FieldError::from(e.to_string()));
+    }
     let session = ctx.session.as_ref().unwrap().clone();
     let user = match get_user_from_token::<Session>(session.session_token.clone()).await {
         Ok(user) => user,
@@ -249,33 +195,6 @@ pub async fn create_batch(ctx: &Ctx, mnstrs: Vec<MnstrInput>) -> Result<Vec<Mnst
                 "mnstr_qr_code",
                 mnstr_input.mnstr_qr_code.as_ref().map(|s| s.into()),
             ));
-            mnstr_params.push((
-                "current_health",
-                mnstr_input.current_health.map_or(Some(DEFAULT_STAT_VALUE.into()), |i| Some(i.into())),
-            ));
-            mnstr_params.push(("max_health", mnstr_input.max_health.map_or(Some(DEFAULT_STAT_VALUE.into()), |i| Some(i.into()))));
-            mnstr_params.push((
-                "current_attack",
-                mnstr_input.current_attack.map_or(Some(DEFAULT_STAT_VALUE.into()), |i| Some(i.into())),
-            ));
-            mnstr_params.push(("max_attack", mnstr_input.max_attack.map_or(Some(DEFAULT_STAT_VALUE.into()), |i| Some(i.into()))));
-            mnstr_params.push((
-                "current_defense",
-                mnstr_input.current_defense.map_or(Some(DEFAULT_STAT_VALUE.into()), |i| Some(i.into())),
-            ));
-            mnstr_params.push(("max_defense", mnstr_input.max_defense.map_or(Some(DEFAULT_STAT_VALUE.into()), |i| Some(i.into()))));
-            mnstr_params.push(("current_speed", mnstr_input.current_speed.map_or(Some(DEFAULT_STAT_VALUE.into()), |i| Some(i.into()))));
-            mnstr_params.push(("max_speed", mnstr_input.max_speed.map_or(Some(DEFAULT_STAT_VALUE.into()), |i| Some(i.into()))));
-            mnstr_params.push((
-                "current_intelligence",
-                mnstr_input.current_intelligence.map_or(Some(DEFAULT_STAT_VALUE.into()), |i| Some(i.into())),
-            ));
-            mnstr_params.push((
-                "max_intelligence",
-                mnstr_input.max_intelligence.map_or(Some(DEFAULT_STAT_VALUE.into()), |i| Some(i.into())),
-            ));
-            mnstr_params.push(("current_magic", mnstr_input.current_magic.map_or(Some(DEFAULT_STAT_VALUE.into()), |i| Some(i.into()))));
-            mnstr_params.push(("max_magic", mnstr_input.max_magic.map_or(Some(DEFAULT_STAT_VALUE.into()), |i| Some(i.into()))));
             mnstr_params
         })
         .collect::<Vec<Vec<(&str, Option<DatabaseValue>)>>>();
@@ -295,22 +214,18 @@ pub async fn update(
     mnstr_name: Option<String>,
     mnstr_description: Option<String>,
     mnstr_qr_code: Option<String>,
-    current_health: Option<i32>,
-    max_health: Option<i32>,
-    current_attack: Option<i32>,
-    max_attack: Option<i32>,
-    current_defense: Option<i32>,
-    max_defense: Option<i32>,
-    current_speed: Option<i32>,
-    max_speed: Option<i32>,
-    current_intelligence: Option<i32>,
-    max_intelligence: Option<i32>,
-    current_magic: Option<i32>,
-    max_magic: Option<i32>,
 ) -> Result<Mnstr, FieldError> {
     if let None = ctx.session {
         return Err(FieldError::from("Invalid session"));
     }
+    if let Err(e) = validate_id(&id) {
+        return Err(FieldError::from(e.to_string()));
+    }
+    if let Some(mnstr_qr_code) = &mnstr_qr_code {
+        if let Err(e) = validate_qr_code(mnstr_qr_code) {
+            return Err(FieldError::from(e.to_string()));
+        }
+    }
 
     let mut mnstr = match Mnstr::find_one(id, false).await {
         Ok(mnstr) => mnstr,
@@ -323,18 +238,6 @@ pub async fn update(
     mnstr.mnstr_name = mnstr_name.unwrap_or(mnstr.mnstr_name);
     mnstr.mnstr_description = mnstr_description.unwrap_or(mnstr.mnstr_description);
     mnstr.mnstr_qr_code = mnstr_qr_code.unwrap_or(mnstr.mnstr_qr_code);
-    mnstr.current_health = current_health.unwrap_or(mnstr.current_health);
-    mnstr.max_health = max_health.unwrap_or(mnstr.max_health);
-    mnstr.current_attack = current_attack.unwrap_or(mnstr.current_attack);
-    mnstr.max_attack = max_attack.unwrap_or(mnstr.max_attack);
-    mnstr.current_defense = current_defense.unwrap_or(mnstr.current_defense);
-    mnstr.max_defense = max_defense.unwrap_or(mnstr.max_defense);
-    mnstr.current_speed = current_speed.unwrap_or(mnstr.current_speed);
-    mnstr.max_speed = max_speed.unwrap_or(mnstr.max_speed);
-    mnstr.current_intelligence = current_intelligence.unwrap_or(mnstr.current_intelligence);
-    mnstr.max_intelligence = max_intelligence.unwrap_or(mnstr.max_intelligence);
-    mnstr.current_magic = current_magic.unwrap_or(mnstr.current_magic);
-    mnstr.max_magic = max_magic.unwrap_or(mnstr.max_magic);
 
     if let Some(error) = mnstr.update().await {
         println!("[update] Failed to update mnstr: {:?}", error);
@@ -351,6 +254,12 @@ pub async fn update_batch(
     if let None = ctx.session {
         return Err(FieldError::from("Invalid session"));
     }
+    if let Some(e) = mnstr_inputs
+        .iter()
+        .find_map(|mnstr_input| mnstr_input.validate().err())
+    {
+        return Err(FieldError::from(e.to_string()));
+    }
     let session = ctx.session.as_ref().unwrap().clone();
     let user = match get_user_from_token::<Session>(session.session_token.clone()).await {
         Ok(user) => user,
@@ -379,38 +288,6 @@ pub async fn update_batch(
                 "mnstr_qr_code",
                 mnstr_input.mnstr_qr_code.as_ref().map(|s| s.into()),
             ));
-            mnstr_params.push(("current_level", mnstr_input.current_level.map_or(Some(0.into()), |i| Some(i.into()))));
-            mnstr_params.push((
-                "current_experience",
-                mnstr_input.current_experience.map_or(Some(0.into()), |i| Some(i.into())),
-            ));
-            mnstr_params.push((
-                "current_health",
-                mnstr_input.current_health.map_or(Some(DEFAULT_STAT_VALUE.into()), |i| Some(i.into())),
-            ));
-            mnstr_params.push(("max_health", mnstr_input.max_health.map_or(Some(DEFAULT_STAT_VALUE.into()), |i| Some(i.into()))));
-            mnstr_params.push((
-                "current_attack",
-                mnstr_input.current_attack.map_or(Some(DEFAULT_STAT_VALUE.into()), |i| Some(i.into())),
-            ));
-            mnstr_params.push(("max_attack", mnstr_input.max_attack.map_or(Some(DEFAULT_STAT_VALUE.into()), |i| Some(i.into()))));
-            mnstr_params.push((
-                "current_defense",
-                mnstr_input.current_defense.map_or(Some(DEFAULT_STAT_VALUE.into()), |i| Some(i.into())),
-            ));
-            mnstr_params.push(("max_defense", mnstr_input.max_defense.map_or(Some(DEFAULT_STAT_VALUE.into()), |i| Some(i.into()))));
-            mnstr_params.push(("current_speed", mnstr_input.current_speed.map_or(Some(DEFAULT_STAT_VALUE.into()), |i| Some(i.into()))));
-            mnstr_params.push(("max_speed", mnstr_input.max_speed.map_or(Some(DEFAULT_STAT_VALUE.into()), |i| Some(i.into()))));
-            mnstr_params.push((
-                "current_intelligence",
-                mnstr_input.current_intelligence.map_or(Some(DEFAULT_STAT_VALUE.into()), |i| Some(i.into())),
-            ));
-            mnstr_params.push((
-                "max_intelligence",
-                mnstr_input.max_intelligence.map_or(Some(DEFAULT_STAT_VALUE.into()), |i| Some(i.into())),
-            ));
-            mnstr_params.push(("current_magic", mnstr_input.current_magic.map_or(Some(DEFAULT_STAT_VALUE.into()), |i| Some(i.into()))));
-            mnstr_params.push(("max_magic", mnstr_input.max_magic.map_or(Some(DEFAULT_STAT_VALUE.into()), |i| Some(i.into()))));
             mnstr_params
         })
         .collect::<Vec<Vec<(&str, Option<DatabaseValue>)>>>();
@@ -425,3 +302,55 @@ pub async fn update_batch(
 
     Ok(mnstrs)
 }
+
+/// Spends points from the mnstr's stat point pool instead of taking stat
+/// values from the client.
+pub async fn allocate_stat(
+    ctx: &Ctx,
+    mnstr_id: String,
+    stat: MnstrStat,
+    points: i32,
+) -> Result<Mnstr, FieldError> {
+    if let None = ctx.session {
+        return Err(FieldError::from("Invalid session"));
+    }
+    if let Err(e) = validate_id(&mnstr_id) {
+        return Err(FieldError::from(e.to_string()));
+    }
+    let session = ctx.session.as_ref().unwrap();
+
+    let mut mnstr = match Mnstr::find_one(mnstr_id, false).await {
+        Ok(mnstr) => mnstr,
+        Err(e) => {
+            println!("[allocate_stat] Failed to find mnstr: {:?}", e);
+            return Err(FieldError::from("Mnstr not found"));
+        }
+    };
+
+    if let Some(error) = mnstr.allocate_stat(&session.user_id, stat, points).await {
+        println!("[allocate_stat] Failed to allocate stat: {:?}", error);
+        return Err(FieldError::from(error.to_string()));
+    }
+
+    Ok(mnstr)
+}
+
+/// Restores every one of the user's mnstrs not in a running battle, charging
+/// the bulk restore cost once.
+pub async fn restore_all(ctx: &Ctx) -> Result<Vec<Mnstr>, FieldError> {
+    if let None = ctx.session {
+        return Err(FieldError::from("Invalid session"));
+    }
+    let session = ctx.session.as_ref().unwrap();
+
+    match Mnstr::restore_all(session.user_id.clone()).await {
+        Ok(mnstrs) => Ok(mnstrs),
+        Err(e) => {
+            println!("[restore_all] Failed to restore mnstrs: {:?}", e);
+            if e.to_string() == NOT_ENOUGH_COINS_ERROR {
+                return Err(FieldError::from(NOT_ENOUGH_COINS_ERROR));
+            }
+            Err(FieldError::from("Failed to restore mnstrs"))
+        }
+    }
+}
@@ -1,31 +1,109 @@
-use juniper::FieldError;
+use juniper::{FieldError, GraphQLObject};
+use time::OffsetDateTime;
 
-use crate::{graphql::Ctx, models::mnstr::{Mnstr, MnstrOrderBy, MnstrOrderDirection}};
+use crate::{
+    graphql::{
+        Ctx,
+        pagination::{
+            PageInput, PageOrdering, Paginated, SortDirection, apply_pagination, decode_cursor,
+            page_limit, page_offset,
+        },
+    },
+    models::{
+        battle::{Battle, MnstrRecord},
+        mnstr::{
+            MAX_MNSTR_IDS, Mnstr, MnstrOrderBy, MnstrOrderDirection, TOO_MANY_MNSTR_IDS_ERROR,
+        },
+    },
+    utils::validation::{validate_id, validate_qr_code},
+};
 
 pub type MnstrOrderByInput = MnstrOrderBy;
 pub type MnstrOrderDirectionInput = MnstrOrderDirection;
 
+const MNSTR_ORDERING: PageOrdering = PageOrdering {
+    columns: &[
+        ("updatedAt", "updated_at"),
+        ("createdAt", "created_at"),
+        ("name", "mnstr_name"),
+        ("level", "current_level"),
+        ("experience", "current_experience"),
+        ("health", "max_health"),
+        ("attack", "max_attack"),
+        ("defense", "max_defense"),
+        ("speed", "max_speed"),
+        ("intelligence", "max_intelligence"),
+        ("magic", "max_magic"),
+    ],
+    direction: SortDirection::Desc,
+};
+
+#[derive(GraphQLObject)]
+pub struct MnstrPage {
+    pub items: Vec<Mnstr>,
+    pub next_cursor: Option<String>,
+}
+
+impl From<Paginated<Mnstr>> for MnstrPage {
+    fn from(page: Paginated<Mnstr>) -> Self {
+        Self {
+            items: page.items,
+            next_cursor: page.next_cursor,
+        }
+    }
+}
+
 pub struct MnstrQueryType;
 
 #[juniper::graphql_object]
 impl MnstrQueryType {
+    /// The whole collection, or one page of it when `page` is given. A page
+    /// takes its order from `page` rather than `orderBy`/`orderDirection`.
+    /// Without `page`, `first` and `offset` page through the collection oldest
+    /// first.
     async fn list(
         ctx: &Ctx,
         order_by: Option<MnstrOrderByInput>,
         order_direction: Option<MnstrOrderDirectionInput>,
+        page: Option<PageInput>,
+        first: Option<i32>,
+        offset: Option<i32>,
     ) -> Result<Vec<Mnstr>, FieldError> {
-        list(ctx, order_by, order_direction).await
+        list(ctx, order_by, order_direction, page, first, offset).await
     }
 
     async fn qr_code(ctx: &Ctx, mnstr_qr_code: String) -> Result<Option<Mnstr>, FieldError> {
         by_qr_code(ctx, mnstr_qr_code).await
     }
+
+    async fn by_ids(ctx: &Ctx, ids: Vec<String>) -> Result<Vec<Mnstr>, FieldError> {
+        by_ids(ctx, ids).await
+    }
+
+    async fn battle_ready(ctx: &Ctx) -> Result<Vec<Mnstr>, FieldError> {
+        battle_ready(ctx).await
+    }
+
+    async fn page(
+        ctx: &Ctx,
+        after: Option<String>,
+        limit: Option<i32>,
+    ) -> Result<MnstrPage, FieldError> {
+        page(ctx, after, limit).await
+    }
+
+    async fn record(ctx: &Ctx, mnstr_id: String) -> Result<MnstrRecord, FieldError> {
+        record(ctx, mnstr_id).await
+    }
 }
 
 async fn list(
     ctx: &Ctx,
     order_by: Option<MnstrOrderByInput>,
     order_direction: Option<MnstrOrderDirectionInput>,
+    page: Option<PageInput>,
+    first: Option<i32>,
+    offset: Option<i32>,
 ) -> Result<Vec<Mnstr>, FieldError> {
     if let None = ctx.session {
         return Err(FieldError::from("Invalid session"));
@@ -34,6 +112,30 @@ async fn list(
 
     let params = vec![("user_id", session.user_id.clone().into())];
 
+    if let Some(page) = page {
+        let page = match apply_pagination(Some(page), &MNSTR_ORDERING) {
+            Ok(page) => page,
+            Err(e) => return Err(FieldError::from(e.to_string())),
+        };
+        return match Mnstr::find_ordered_page_by(params, &page).await {
+            Ok(mnstrs) => at_rest(session.user_id.clone(), mnstrs).await,
+            Err(e) => {
+                println!("[mnstrs] Failed to get mnstrs page: {:?}", e);
+                Err(FieldError::from("Failed to get mnstrs"))
+            }
+        };
+    }
+
+    if first.is_some() || offset.is_some() {
+        return match Mnstr::find_offset_page_by(params, page_limit(first), page_offset(offset)).await {
+            Ok(mnstrs) => at_rest(session.user_id.clone(), mnstrs).await,
+            Err(e) => {
+                println!("[mnstrs] Failed to get mnstrs page: {:?}", e);
+                Err(FieldError::from("Failed to get mnstrs"))
+            }
+        };
+    }
+
     println!(
         "[mnstrs] Order by: {:?}",
         order_by.clone().unwrap().to_string()
@@ -43,8 +145,8 @@ async fn list(
         order_direction.clone().unwrap().to_string()
     );
 
-    match Mnstr::find_all_by(params, false, order_by, order_direction).await {
-        Ok(mnstrs) => Ok(mnstrs),
+    match Mnstr::find_all_unarchived_by(params, false, order_by, order_direction).await {
+        Ok(mnstrs) => at_rest(session.user_id.clone(), mnstrs).await,
         Err(e) => {
             println!("[mnstrs] Failed to get mnstrs: {:?}", e);
             return Ok(vec![]);
@@ -52,10 +154,26 @@ async fn list(
     }
 }
 
+/// Shows the collection's mnstrs that are mid-battle at rest, flagged as in
+/// battle, rather than with the damage they've taken so far.
+async fn at_rest(user_id: String, mnstrs: Vec<Mnstr>) -> Result<Vec<Mnstr>, FieldError> {
+    let now = OffsetDateTime::now_utc();
+    match Battle::find_mnstr_engagements(user_id, now).await {
+        Ok(engagements) => Ok(Mnstr::at_rest(mnstrs, &engagements)),
+        Err(e) => {
+            println!("[at_rest] Failed to get engagements: {:?}", e);
+            Err(FieldError::from("Failed to get mnstrs"))
+        }
+    }
+}
+
 async fn by_qr_code(ctx: &Ctx, mnstr_qr_code: String) -> Result<Option<Mnstr>, FieldError> {
     if let None = ctx.session {
         return Err(FieldError::from("Invalid session"));
     }
+    if let Err(e) = validate_qr_code(&mnstr_qr_code) {
+        return Err(FieldError::from(e.to_string()));
+    }
     let session = ctx.session.as_ref().unwrap().clone();
 
     let params = vec![
@@ -71,3 +189,88 @@ async fn by_qr_code(ctx: &Ctx, mnstr_qr_code: String) -> Result<Option<Mnstr>, F
         }
     }
 }
+
+async fn by_ids(ctx: &Ctx, ids: Vec<String>) -> Result<Vec<Mnstr>, FieldError> {
+    if let None = ctx.session {
+        return Err(FieldError::from("Invalid session"));
+    }
+    if ids.len() > MAX_MNSTR_IDS {
+        return Err(FieldError::from(TOO_MANY_MNSTR_IDS_ERROR));
+    }
+    if let Err(e) = ids.iter().try_for_each(|id| validate_id(id)) {
+        return Err(FieldError::from(e.to_string()));
+    }
+    let session = ctx.session.as_ref().unwrap().clone();
+
+    match Mnstr::find_all_by_ids(session.user_id.clone(), ids, false).await {
+        Ok(mnstrs) => Ok(mnstrs),
+        Err(e) => {
+            println!("[by_ids] Failed to get mnstrs: {:?}", e);
+            return Err(FieldError::from("Failed to get mnstrs"));
+        }
+    }
+}
+
+async fn battle_ready(ctx: &Ctx) -> Result<Vec<Mnstr>, FieldError> {
+    if let None = ctx.session {
+        return Err(FieldError::from("Invalid session"));
+    }
+    let session = ctx.session.as_ref().unwrap().clone();
+
+    match Mnstr::find_battle_ready(session.user_id.clone()).await {
+        Ok(mnstrs) => Ok(mnstrs),
+        Err(e) => {
+            println!("[battle_ready] Failed to get mnstrs: {:?}", e);
+            return Err(FieldError::from("Failed to get mnstrs"));
+        }
+    }
+}
+
+async fn page(
+    ctx: &Ctx,
+    after: Option<String>,
+    limit: Option<i32>,
+) -> Result<MnstrPage, FieldError> {
+    if let None = ctx.session {
+        return Err(FieldError::from("Invalid session"));
+    }
+    let session = ctx.session.as_ref().unwrap().clone();
+
+    let after = match after.map(|cursor| decode_cursor(&cursor)).transpose() {
+        Ok(after) => after,
+        Err(e) => {
+            println!("[page] Invalid cursor: {:?}", e);
+            return Err(FieldError::from("Invalid cursor"));
+        }
+    };
+    let limit = page_limit(limit);
+
+    let params = vec![("user_id", session.user_id.clone().into())];
+    match Mnstr::find_page_by(params, after, limit + 1).await {
+        Ok(mnstrs) => {
+            let mnstrs = at_rest(session.user_id.clone(), mnstrs).await?;
+            Ok(Paginated::from_rows(mnstrs, limit, |mnstr| mnstr.id.clone()).into())
+        }
+        Err(e) => {
+            println!("[page] Failed to get mnstrs: {:?}", e);
+            return Err(FieldError::from("Failed to get mnstrs"));
+        }
+    }
+}
+
+async fn record(ctx: &Ctx, mnstr_id: String) -> Result<MnstrRecord, FieldError> {
+    if let None = ctx.session {
+        return Err(FieldError::from("Invalid session"));
+    }
+    if let Err(e) = validate_id(&mnstr_id) {
+        return Err(FieldError::from(e.to_string()));
+    }
+
+    match Battle::find_mnstr_record(mnstr_id).await {
+        Ok(record) => Ok(record),
+        Err(e) => {
+            println!("[record] Failed to get mnstr record: {:?}", e);
+            return Err(FieldError::from("Failed to get mnstr record"));
+        }
+    }
+}
@@ -1,28 +1,69 @@
-use futures::stream;
-use juniper::{Context, FieldError, RootNode, graphql_object, graphql_subscription};
+use std::panic::AssertUnwindSafe;
+
+use futures::{FutureExt, stream};
+use juniper::{Context, FieldError, RootNode, graphql_object, graphql_subscription, graphql_value};
 use juniper_rocket::{GraphQLRequest, GraphQLResponse};
-use rocket::{Route, get, post, response::content::RawHtml};
+use rocket::{Route, State, get, http::Status, post, response::content::RawHtml};
 
 use crate::{
     graphql::{
+        battles::BattleQueryType,
+        blocks::BlockMutationType,
+        cache::RequestCache,
+        levels::LevelQueryType,
         mnstrs::{mutations::MnstrMutationType, queries::MnstrQueryType},
         sessions::{SessionMutationType, SessionQueryType},
+        trades::mutations::TradeMutationType,
         users::{mutations::UserMutationType, queries::UserQueryType},
     },
-    models::session::Session,
-    utils::{sessions::validate_session, token::RawToken},
+    models::{session::Session, user::User},
+    state::AppState,
+    utils::{
+        redact::redact,
+        sessions::{SessionError, authenticate},
+        token::RawToken,
+    },
 };
 
+pub mod battles;
+pub mod blocks;
+pub mod cache;
+pub mod levels;
 pub mod mnstrs;
+pub mod pagination;
 pub mod sessions;
+pub mod trades;
 pub mod users;
 
+/// The message of the error returned when resolving a request panicked.
+pub const INTERNAL_ERROR_MESSAGE: &str = "Internal server error";
+
 pub fn routes() -> Vec<Route> {
     routes![graphiql, graphql]
 }
 
 pub struct Ctx {
     pub session: Option<Session>,
+    pub state: AppState,
+    pub users: RequestCache<User>,
+}
+
+impl Ctx {
+    pub fn new(state: AppState, session: Option<Session>) -> Self {
+        Self {
+            session,
+            state,
+            users: RequestCache::default(),
+        }
+    }
+
+    /// Loads a user at most once per request. Callers that write the user
+    /// invalidate it in `users`.
+    pub async fn find_user(&self, id: String) -> Result<User, anyhow::Error> {
+        self.users
+            .get_or_load(id, |id| User::find_one(id, false))
+            .await
+    }
 }
 
 impl Context for Ctx {}
@@ -42,6 +83,14 @@ impl Query {
     pub async fn mnstrs() -> MnstrQueryType {
         MnstrQueryType
     }
+
+    pub async fn levels() -> LevelQueryType {
+        LevelQueryType
+    }
+
+    pub async fn battles() -> BattleQueryType {
+        BattleQueryType
+    }
 }
 
 pub struct Mutation;
@@ -59,6 +108,14 @@ impl Mutation {
     pub async fn mnstrs() -> MnstrMutationType {
         MnstrMutationType
     }
+
+    pub async fn trades() -> TradeMutationType {
+        TradeMutationType
+    }
+
+    pub async fn blocks() -> BlockMutationType {
+        BlockMutationType
+    }
 }
 
 pub struct Subscription;
@@ -78,17 +135,20 @@ pub fn graphiql() -> RawHtml<String> {
 }
 
 #[post("/", data = "<request>")]
-pub async fn graphql(request: GraphQLRequest, token: RawToken) -> GraphQLResponse {
-    let mut ctx = Ctx { session: None };
+pub async fn graphql(
+    request: GraphQLRequest,
+    token: RawToken,
+    state: &State<AppState>,
+) -> GraphQLResponse {
+    catch_internal_errors(execute(request, token, state.inner().clone())).await
+}
+
+async fn execute(request: GraphQLRequest, token: RawToken, state: AppState) -> GraphQLResponse {
+    let mut ctx = Ctx::new(state, None);
     if !token.value.is_empty() {
-        let session = match verify_session_token(token).await {
+        let session = match authenticate::<Session>(token.value).await {
             Ok(session) => session,
-            Err(_) => {
-                return GraphQLResponse::error(FieldError::new(
-                    "Invalid session",
-                    juniper::Value::Null,
-                ));
-            }
+            Err(error) => return GraphQLResponse::error(session_field_error(error)),
         };
         ctx.session = Some(session);
     }
@@ -97,13 +157,86 @@ pub async fn graphql(request: GraphQLRequest, token: RawToken) -> GraphQLRespons
     request.execute(&schema, &ctx).await
 }
 
-async fn verify_session_token(token: RawToken) -> Result<Session, FieldError> {
-    let mut session = match Session::find_one_by_token(token.value).await {
-        Ok(session) => session,
-        Err(e) => return Err(e.into()),
-    };
-    if validate_session(&mut session).await.is_some() {
-        return Err(FieldError::from("Invalid session"));
+/// Runs a request, answering a panic anywhere in it, from the session lookup
+/// to a resolver, with a 500 carrying a generic GraphQL error body instead of
+/// Rocket's bare one.
+/// What panicked is logged but never sent to the client.
+async fn catch_internal_errors<F>(response: F) -> GraphQLResponse
+where
+    F: Future<Output = GraphQLResponse>,
+{
+    match AssertUnwindSafe(response).catch_unwind().await {
+        Ok(response) => response,
+        Err(panic) => {
+            let message = panic
+                .downcast_ref::<&str>()
+                .map(|message| message.to_string())
+                .or_else(|| panic.downcast_ref::<String>().cloned())
+                .unwrap_or_default();
+            println!("[graphql] Request panicked: {}", redact(&message));
+            let body = GraphQLResponse::error(FieldError::new(
+                INTERNAL_ERROR_MESSAGE,
+                graphql_value!({ "code": "INTERNAL_SERVER_ERROR" }),
+            ));
+            GraphQLResponse(Status::InternalServerError, body.1)
+        }
+    }
+}
+
+/// A failed session check as a GraphQL error, with its code in `extensions`.
+pub fn session_field_error(error: SessionError) -> FieldError {
+    FieldError::new(error.message(), graphql_value!({ "code": error.code() }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_panicking_request_returns_graphql_error() {
+        let response = catch_internal_errors(async {
+            let sessions: Vec<Session> = Vec::new();
+            GraphQLResponse::error(FieldError::from(sessions[0].id.clone()))
+        })
+        .await;
+
+        assert_eq!(response.0, Status::InternalServerError);
+        let body = serde_json::from_str::<serde_json::Value>(&response.1).unwrap();
+        let error = &body["errors"][0];
+        assert_eq!(error["message"], INTERNAL_ERROR_MESSAGE);
+        assert_eq!(error["extensions"]["code"], "INTERNAL_SERVER_ERROR");
+        assert!(!response.1.contains("index out of bounds"));
+    }
+
+    #[tokio::test]
+    async fn test_request_that_resolves_is_unchanged() {
+        let response = catch_internal_errors(async {
+            GraphQLResponse::error(FieldError::from("Invalid session"))
+        })
+        .await;
+
+        let body = serde_json::from_str::<serde_json::Value>(&response.1).unwrap();
+        assert_eq!(body["errors"][0]["message"], "Invalid session");
+    }
+    #[test]
+    fn test_session_errors_carry_their_code() {
+        let body = |error: SessionError| {
+            let response = GraphQLResponse::error(session_field_error(error));
+            serde_json::from_str::<serde_json::Value>(&response.1).unwrap()
+        };
+
+        let expired = body(SessionError::Expired);
+        assert_eq!(expired["errors"][0]["message"], "Session expired");
+        assert_eq!(
+            expired["errors"][0]["extensions"]["code"],
+            "SESSION_EXPIRED"
+        );
+
+        let unauthenticated = body(SessionError::Unauthenticated);
+        assert_eq!(unauthenticated["errors"][0]["message"], "Invalid session");
+        assert_eq!(
+            unauthenticated["errors"][0]["extensions"]["code"],
+            "UNAUTHENTICATED"
+        );
     }
-    Ok(session)
 }
@@ -0,0 +1,264 @@
+use std::fmt::Write;
+
+use juniper::{GraphQLEnum, GraphQLInputObject};
+
+pub const DEFAULT_PAGE_SIZE: i32 = 25;
+/// The most rows any page returns, whatever limit was asked for.
+pub const MAX_PAGE_SIZE: i32 = 100;
+
+/// A page of results along with the opaque cursor for the page after it.
+#[derive(Debug, Clone)]
+pub struct Paginated<T> {
+    pub items: Vec<T>,
+    pub next_cursor: Option<String>,
+}
+
+impl<T> Paginated<T> {
+    /// Builds a page from rows fetched with `limit + 1`; the extra row only
+    /// signals that another page exists and is dropped.
+    pub fn from_rows(mut rows: Vec<T>, limit: i64, cursor_value: impl Fn(&T) -> String) -> Self {
+        let mut next_cursor = None;
+        if rows.len() as i64 > limit {
+            rows.truncate(limit as usize);
+            next_cursor = rows.last().map(|row| encode_cursor(&cursor_value(row)));
+        }
+        Self {
+            items: rows,
+            next_cursor,
+        }
+    }
+}
+
+pub fn page_limit(limit: Option<i32>) -> i64 {
+    limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE) as i64
+}
+
+/// Rows to skip for offset pagination; negative offsets start at the first row.
+pub fn page_offset(offset: Option<i32>) -> i64 {
+    offset.unwrap_or(0).max(0) as i64
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, GraphQLEnum)]
+pub enum SortDirection {
+    Asc,
+    Desc,
+}
+
+impl SortDirection {
+    pub fn sql(&self) -> &'static str {
+        match self {
+            SortDirection::Asc => "ASC",
+            SortDirection::Desc => "DESC",
+        }
+    }
+}
+
+/// Offset paging arguments shared by list resolvers.
+#[derive(Debug, Clone, Default, GraphQLInputObject)]
+pub struct PageInput {
+    pub limit: Option<i32>,
+    pub offset: Option<i32>,
+    pub order_by: Option<String>,
+    pub direction: Option<SortDirection>,
+}
+
+/// The orderings a resolver accepts as `(orderBy, column)` pairs, the first
+/// being the default, and the direction used when none is given.
+#[derive(Debug, Clone, Copy)]
+pub struct PageOrdering {
+    pub columns: &'static [(&'static str, &'static str)],
+    pub direction: SortDirection,
+}
+
+/// A page checked by `apply_pagination`, ready for `find_page_ordered!`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PageRequest {
+    pub limit: i64,
+    pub offset: i64,
+    pub order_column: &'static str,
+    pub direction: SortDirection,
+}
+
+impl PageRequest {
+    /// The `ORDER BY` clause with a leading space. `id` breaks ties so rows
+    /// don't shift between pages.
+    pub fn order_clause(&self) -> String {
+        format!(
+            " ORDER BY {} {}, id ASC",
+            self.order_column,
+            self.direction.sql()
+        )
+    }
+}
+
+/// Caps the page at `MAX_PAGE_SIZE` and resolves `order_by` against the
+/// orderings the resolver allows, so only known columns reach the query.
+///
+/// # Returns
+///
+/// Returns an error for an `order_by` that isn't one of `ordering.columns`.
+pub fn apply_pagination(
+    page: Option<PageInput>,
+    ordering: &PageOrdering,
+) -> Result<PageRequest, anyhow::Error> {
+    let page = page.unwrap_or_default();
+    let order_column = match page.order_by {
+        None => ordering.columns[0].1,
+        Some(order_by) => match ordering.columns.iter().find(|(name, _)| *name == order_by) {
+            Some((_, column)) => *column,
+            None => {
+                let names = ordering
+                    .columns
+                    .iter()
+                    .map(|(name, _)| *name)
+                    .collect::<Vec<&str>>();
+                return Err(anyhow::anyhow!(
+                    "Unknown orderBy `{}`, expected one of: {}",
+                    order_by,
+                    names.join(", ")
+                ));
+            }
+        },
+    };
+    Ok(PageRequest {
+        limit: page_limit(page.limit),
+        offset: page_offset(page.offset),
+        order_column,
+        direction: page.direction.unwrap_or(ordering.direction),
+    })
+}
+
+pub fn encode_cursor(value: &str) -> String {
+    value.as_bytes().iter().fold(
+        String::with_capacity(value.len() * 2),
+        |mut cursor, byte| {
+            write!(&mut cursor, "{byte:02x}").expect("writing to a String cannot fail");
+            cursor
+        },
+    )
+}
+
+pub fn decode_cursor(cursor: &str) -> Result<String, anyhow::Error> {
+    if cursor.len() % 2 != 0 {
+        return Err(anyhow::anyhow!("Invalid cursor"));
+    }
+    let bytes = (0..cursor.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&cursor[i..i + 2], 16))
+        .collect::<Result<Vec<u8>, _>>()
+        .map_err(|_| anyhow::anyhow!("Invalid cursor"))?;
+    String::from_utf8(bytes).map_err(|_| anyhow::anyhow!("Invalid cursor"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cursor_round_trip() {
+        let cursor = encode_cursor("0b6f0c4e-1f2a-4d6e-9a51-2f7f0b0c1d2e");
+        assert_eq!(
+            decode_cursor(&cursor).unwrap(),
+            "0b6f0c4e-1f2a-4d6e-9a51-2f7f0b0c1d2e"
+        );
+        assert!(decode_cursor("zz").is_err());
+        assert!(decode_cursor("abc").is_err());
+    }
+
+    #[test]
+    fn test_page_offset() {
+        assert_eq!(page_offset(None), 0);
+        assert_eq!(page_offset(Some(40)), 40);
+        assert_eq!(page_offset(Some(-5)), 0);
+    }
+
+    const ORDERING: PageOrdering = PageOrdering {
+        columns: &[("level", "experience_level"), ("name", "display_name")],
+        direction: SortDirection::Desc,
+    };
+
+    #[test]
+    fn test_over_large_limit_is_capped() {
+        let page = apply_pagination(
+            Some(PageInput {
+                limit: Some(10_000),
+                offset: Some(-3),
+                ..Default::default()
+            }),
+            &ORDERING,
+        )
+        .unwrap();
+        assert_eq!(page.limit, MAX_PAGE_SIZE as i64);
+        assert_eq!(page.offset, 0);
+
+        let page = apply_pagination(None, &ORDERING).unwrap();
+        assert_eq!(page.limit, DEFAULT_PAGE_SIZE as i64);
+        assert_eq!(
+            page.order_clause(),
+            " ORDER BY experience_level DESC, id ASC"
+        );
+    }
+
+    #[test]
+    fn test_ordering_inputs_are_validated() {
+        let page = apply_pagination(
+            Some(PageInput {
+                order_by: Some("name".to_string()),
+                direction: Some(SortDirection::Asc),
+                ..Default::default()
+            }),
+            &ORDERING,
+        )
+        .unwrap();
+        assert_eq!(page.order_column, "display_name");
+        assert_eq!(page.order_clause(), " ORDER BY display_name ASC, id ASC");
+
+        // Columns are only reachable through their orderBy name
+        for order_by in ["display_name", "password_hash", "level; DROP TABLE users"] {
+            let error = apply_pagination(
+                Some(PageInput {
+                    order_by: Some(order_by.to_string()),
+                    ..Default::default()
+                }),
+                &ORDERING,
+            )
+            .unwrap_err();
+            assert!(error.to_string().contains("expected one of: level, name"));
+        }
+    }
+
+    #[test]
+    fn test_iterating_by_cursor_visits_every_row_once() {
+        let mut rows = (0..53).map(|i| format!("{:03}", i)).collect::<Vec<_>>();
+        let limit = page_limit(Some(10));
+
+        let mut visited: Vec<String> = Vec::new();
+        let mut cursor: Option<String> = None;
+        loop {
+            let after = cursor.as_ref().map(|c| decode_cursor(c).unwrap());
+            let page_rows = rows
+                .iter()
+                .filter(|row| {
+                    after
+                        .as_ref()
+                        .map_or(true, |after| row.as_str() > after.as_str())
+                })
+                .take(limit as usize + 1)
+                .cloned()
+                .collect::<Vec<_>>();
+            let page = Paginated::from_rows(page_rows, limit, |row| row.clone());
+            visited.extend(page.items);
+
+            // Rows inserted behind the cursor mid-iteration don't shift later pages
+            rows.insert(0, format!("-{:03}", visited.len()));
+
+            match page.next_cursor {
+                Some(next_cursor) => cursor = Some(next_cursor),
+                None => break,
+            }
+        }
+
+        let expected = (0..53).map(|i| format!("{:03}", i)).collect::<Vec<_>>();
+        assert_eq!(visited, expected);
+    }
+}
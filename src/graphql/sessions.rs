@@ -5,16 +5,20 @@ use crate::{
     delete_resource_where_fields, find_one_unarchived_resource_where_fields,
     graphql::Ctx,
     insert_resource,
-    models::{session::Session, user::User},
-    utils::{passwords::hash_password, sessions::validate_session},
+    models::{audit_log::AuditLog, session::Session, user::User},
+    utils::{contact::normalize_email, passwords::hash_password, sessions::validate_session},
 };
 
 pub struct SessionMutationType;
 
 #[juniper::graphql_object]
 impl SessionMutationType {
-    async fn login(email: String, password: String) -> Result<Session, FieldError> {
-        create_session(email, password).await
+    async fn login(
+        email: String,
+        password: String,
+        device_id: Option<String>,
+    ) -> Result<Session, FieldError> {
+        create_session(email, password, device_id).await
     }
 
     async fn logout(ctx: &Ctx) -> Result<bool, FieldError> {
@@ -22,7 +26,17 @@ impl SessionMutationType {
     }
 }
 
-pub async fn create_session(email: String, password: String) -> Result<Session, FieldError> {
+/// `device_id` is whatever stable id the client keeps for its install, used to
+/// audit logins from devices the user hasn't logged in from before.
+pub async fn create_session(
+    email: String,
+    password: String,
+    device_id: Option<String>,
+) -> Result<Session, FieldError> {
+    let email = match normalize_email(&email) {
+        Ok(email) => email,
+        Err(_) => return Err(FieldError::from("Invalid email or password")),
+    };
     let password_hash = hash_password(&password);
     let params = vec![
         ("email", email.into()),
@@ -37,11 +51,16 @@ pub async fn create_session(email: String, password: String) -> Result<Session,
         }
     };
 
+    if !user.is_active() {
+        return Err(FieldError::from("Email address has not been verified"));
+    }
+
     let mut session = Session::new(user.id.clone());
     if let Some(error) = session.create().await {
         println!("Failed to create session: {:?}", error);
         return Err(FieldError::from("Failed to create session"));
     };
+    AuditLog::record_login(user.id.clone(), device_id).await;
 
     Ok(session)
 }
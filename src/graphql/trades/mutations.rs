@@ -0,0 +1,101 @@
+use juniper::FieldError;
+
+use crate::{
+    graphql::Ctx,
+    models::{
+        mnstr::{COLLECTION_FULL_ERROR, CollectionFullError},
+        trade_offer::TradeOffer,
+    },
+};
+
+pub struct TradeMutationType;
+
+#[juniper::graphql_object]
+impl TradeMutationType {
+    async fn create_offer(
+        ctx: &Ctx,
+        to_user_id: String,
+        offered_mnstr_id: String,
+        requested_mnstr_id: Option<String>,
+    ) -> Result<TradeOffer, FieldError> {
+        create_offer(ctx, to_user_id, offered_mnstr_id, requested_mnstr_id).await
+    }
+
+    async fn accept_offer(ctx: &Ctx, id: String) -> Result<TradeOffer, FieldError> {
+        accept_offer(ctx, id).await
+    }
+
+    async fn decline_offer(ctx: &Ctx, id: String) -> Result<TradeOffer, FieldError> {
+        decline_offer(ctx, id).await
+    }
+}
+
+pub async fn create_offer(
+    ctx: &Ctx,
+    to_user_id: String,
+    offered_mnstr_id: String,
+    requested_mnstr_id: Option<String>,
+) -> Result<TradeOffer, FieldError> {
+    if let None = ctx.session {
+        return Err(FieldError::from("Invalid session"));
+    }
+    let session = ctx.session.as_ref().unwrap().clone();
+
+    let mut trade_offer = TradeOffer::new(
+        session.user_id.clone(),
+        to_user_id,
+        offered_mnstr_id,
+        requested_mnstr_id,
+    );
+    if let Some(error) = trade_offer.create().await {
+        println!("[create_offer] Failed to create trade offer: {:?}", error);
+        return Err(FieldError::from("Failed to create trade offer"));
+    }
+
+    Ok(trade_offer)
+}
+
+pub async fn accept_offer(ctx: &Ctx, id: String) -> Result<TradeOffer, FieldError> {
+    if let None = ctx.session {
+        return Err(FieldError::from("Invalid session"));
+    }
+    let session = ctx.session.as_ref().unwrap().clone();
+
+    let mut trade_offer = match TradeOffer::find_one(id).await {
+        Ok(trade_offer) => trade_offer,
+        Err(e) => {
+            println!("[accept_offer] Failed to get trade offer: {:?}", e);
+            return Err(FieldError::from("Failed to get trade offer"));
+        }
+    };
+    if let Some(error) = trade_offer.accept(&session.user_id).await {
+        println!("[accept_offer] Failed to accept trade offer: {:?}", error);
+        if error.downcast_ref::<CollectionFullError>().is_some() {
+            return Err(FieldError::from(COLLECTION_FULL_ERROR));
+        }
+        return Err(FieldError::from("Failed to accept trade offer"));
+    }
+
+    Ok(trade_offer)
+}
+
+pub async fn decline_offer(ctx: &Ctx, id: String) -> Result<TradeOffer, FieldError> {
+    if let None = ctx.session {
+        return Err(FieldError::from("Invalid session"));
+    }
+    let session = ctx.session.as_ref().unwrap().clone();
+
+    let mut trade_offer = match TradeOffer::find_one(id).await {
+        Ok(trade_offer) => trade_offer,
+        Err(e) => {
+            println!("[decline_offer] Failed to get trade offer: {:?}", e);
+            return Err(FieldError::from("Failed to get trade offer"));
+        }
+    };
+    if let Some(error) = trade_offer.decline(&session.user_id).await {
+        println!("[decline_offer] Failed to decline trade offer: {:?}", error);
+        return Err(FieldError::from("Failed to decline trade offer"));
+    }
+
+    Ok(trade_offer)
+}
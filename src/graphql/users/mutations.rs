@@ -1,22 +1,59 @@
-use juniper::FieldError;
+use juniper::{FieldError, GraphQLObject, Nullable};
+use time::OffsetDateTime;
 
 use crate::{
-    graphql::{Ctx, users::utils::send_email_verification_code},
-    models::user::User,
-    utils::passwords::{generate_verification_code, hash_password},
+    database::values::{DatabaseValue, nullable_param},
+    graphql::{
+        Ctx,
+        users::utils::{send_email_verification_code, send_phone_verification_code},
+    },
+    models::{
+        audit_log::AuditLog,
+        mnstr::Mnstr,
+        session::Session,
+        transaction::{Transaction, TransactionStatus},
+        user::{GuestClaimed, INVALID_CLAIM_TOKEN_ERROR, User},
+    },
+    payments::{self, find_package},
+    utils::{
+        admin::is_admin,
+        contact::{normalize_email, normalize_phone},
+        passwords::generate_verification_code,
+        redact::redact_debug,
+    },
 };
 
+/// A new guest's session and the token that later claims it.
+#[derive(Debug, Clone, GraphQLObject)]
+pub struct GuestAccount {
+    pub session: Session,
+    pub claim_token: String,
+}
+
 pub struct UserMutationType;
 
 #[juniper::graphql_object]
 impl UserMutationType {
     async fn register(
+        ctx: &Ctx,
         email: Option<String>,
         phone: Option<String>,
         password: String,
         display_name: String,
     ) -> Result<User, FieldError> {
-        register(email, phone, password, display_name).await
+        register(ctx, email, phone, password, display_name).await
+    }
+
+    async fn register_guest(display_name: String) -> Result<GuestAccount, FieldError> {
+        register_guest(display_name).await
+    }
+
+    async fn claim_guest(
+        ctx: &Ctx,
+        guest_user_id: String,
+        claim_token: String,
+    ) -> Result<GuestClaimed, FieldError> {
+        claim_guest(ctx, guest_user_id, claim_token).await
     }
 
     async fn verify_email(id: String, code: String) -> Result<bool, FieldError> {
@@ -31,18 +68,64 @@ impl UserMutationType {
         unregister(ctx).await
     }
 
+    async fn deactivate(ctx: &Ctx) -> Result<bool, FieldError> {
+        deactivate(ctx).await
+    }
+
+    async fn reactivate(ctx: &Ctx, user_id: String) -> Result<User, FieldError> {
+        reactivate(ctx, user_id).await
+    }
+
     async fn reset_password(id: String, password: String) -> Result<bool, FieldError> {
         reset_password(id, password).await
     }
+
+    async fn claim_daily(ctx: &Ctx) -> Result<User, FieldError> {
+        claim_daily(ctx).await
+    }
+
+    async fn purchase_coins(ctx: &Ctx, package_id: String) -> Result<Transaction, FieldError> {
+        purchase_coins(ctx, package_id).await
+    }
+
+    async fn update_profile(
+        ctx: &Ctx,
+        display_name: Option<String>,
+        phone: Nullable<String>,
+    ) -> Result<User, FieldError> {
+        update_profile(ctx, display_name, phone).await
+    }
+
+    async fn set_primary_mnstr(ctx: &Ctx, mnstr_id: String) -> Result<User, FieldError> {
+        set_primary_mnstr(ctx, mnstr_id).await
+    }
+
+    async fn set_notification_prefs(
+        ctx: &Ctx,
+        email_opt_in: Option<bool>,
+        sms_opt_in: Option<bool>,
+    ) -> Result<User, FieldError> {
+        set_notification_prefs(ctx, email_opt_in, sms_opt_in).await
+    }
 }
 
 pub async fn register(
+    ctx: &Ctx,
     email: Option<String>,
     phone: Option<String>,
     password: String,
     display_name: String,
 ) -> Result<User, FieldError> {
-    let mut user = User::new(email.clone(), phone.clone(), password, display_name.clone());
+    let email = match email.map(|email| normalize_email(&email)).transpose() {
+        Ok(email) => email,
+        Err(_) => return Err(FieldError::from("Invalid email address")),
+    };
+    let phone = match phone.map(|phone| normalize_phone(&phone)).transpose() {
+        Ok(phone) => phone,
+        Err(_) => return Err(FieldError::from("Invalid phone number")),
+    };
+
+    let mut user = User::new(email.clone(), phone.clone(), password, display_name);
 
     if email != None {
         user.email_verification_code = Some(generate_verification_code());
@@ -59,13 +142,8 @@ pub async fn register(
     }
 
     if email != None {
-        if let Err(error) = send_email_verification_code(
-            display_name,
-            email.unwrap(),
-            user.email_verification_code.unwrap(),
-        )
-        .await
-        {
+        let code = user.email_verification_code.clone().unwrap();
+        if let Err(error) = send_email_verification_code(&ctx.state, &user, code).await {
             println!(
                 "[register] Failed to send email verification code: {:?}",
                 error
@@ -95,12 +173,71 @@ pub async fn register(
     Ok(user)
 }
 
+pub async fn register_guest(display_name: String) -> Result<GuestAccount, FieldError> {
+    let (guest, claim_token) = match User::create_guest(display_name).await {
+        Ok(guest) => guest,
+        Err(e) => {
+            println!("[register_guest] Failed to register guest: {:?}", e);
+            return Err(FieldError::from("Failed to register guest"));
+        }
+    };
+
+    // Guests have no credentials to log in with, so their session starts here
+    let mut session = Session::new(guest.id.clone());
+    if let Some(error) = session.create().await {
+        println!("[register_guest] Failed to create session: {:?}", error);
+        return Err(FieldError::from("Failed to create session"));
+    }
+
+    Ok(GuestAccount {
+        session,
+        claim_token,
+    })
+}
+
+pub async fn claim_guest(
+    ctx: &Ctx,
+    guest_user_id: String,
+    claim_token: String,
+) -> Result<GuestClaimed, FieldError> {
+    if let None = ctx.session {
+        return Err(FieldError::from("Invalid session"));
+    }
+    let session = ctx.session.as_ref().unwrap().clone();
+
+    let mut user = match User::find_one(session.user_id.clone(), false).await {
+        Ok(user) => user,
+        Err(e) => {
+            println!("[claim_guest] Failed to get user: {:?}", e);
+            return Err(FieldError::from("Failed to get user"));
+        }
+    };
+    if user.is_guest() {
+        return Err(FieldError::from("Register before claiming a guest"));
+    }
+
+    let claimed = match user.claim_guest(guest_user_id.clone(), claim_token).await {
+        Ok(claimed) => claimed,
+        Err(e) if e.to_string() == INVALID_CLAIM_TOKEN_ERROR => {
+            return Err(FieldError::from(INVALID_CLAIM_TOKEN_ERROR));
+        }
+        Err(e) => {
+            println!("[claim_guest] Failed to claim guest: {:?}", e);
+            return Err(FieldError::from("Failed to claim guest"));
+        }
+    };
+    ctx.users.invalidate(&user.id);
+    ctx.users.invalidate(&guest_user_id);
+
+    Ok(claimed)
+}
+
 pub async fn verify_email(id: String, code: String) -> Result<bool, FieldError> {
     let user_params = vec![("id", id.into()), ("email_verification_code", code.into())];
     let mut user = match User::find_one_by(user_params, false).await {
         Ok(user) => user,
         Err(e) => {
-            println!("[verify_email] Failed to get user: {:?}", e);
+            println!("[verify_email] Failed to get user: {}", redact_debug(&e));
             return Err(FieldError::from(
                 "Failed to get user with verification code",
             ));
@@ -110,7 +247,10 @@ pub async fn verify_email(id: String, code: String) -> Result<bool, FieldError>
     user.email_verification_code = None;
     user.email_verified = true;
     if let Some(error) = user.update().await {
-        println!("[verify_email] Failed to update user: {:?}", error);
+        println!(
+            "[verify_email] Failed to update user: {}",
+            redact_debug(&error)
+        );
         return Err(FieldError::from("Failed to update user email verification"));
     }
     Ok(true)
@@ -121,7 +261,7 @@ pub async fn verify_phone(id: String, code: String) -> Result<bool, FieldError>
     let mut user = match User::find_one_by(user_params, false).await {
         Ok(user) => user,
         Err(e) => {
-            println!("[verify_phone] Failed to get user: {:?}", e);
+            println!("[verify_phone] Failed to get user: {}", redact_debug(&e));
             return Err(FieldError::from(
                 "Failed to get user with verification code",
             ));
@@ -131,7 +271,10 @@ pub async fn verify_phone(id: String, code: String) -> Result<bool, FieldError>
     user.phone_verification_code = None;
     user.phone_verified = true;
     if let Some(error) = user.update().await {
-        println!("[verify_phone] Failed to update user: {:?}", error);
+        println!(
+            "[verify_phone] Failed to update user: {}",
+            redact_debug(&error)
+        );
         return Err(FieldError::from("Failed to update user phone verification"));
     }
     Ok(true)
@@ -155,10 +298,61 @@ pub async fn unregister(ctx: &Ctx) -> Result<bool, FieldError> {
         println!("[unregister] Failed to delete user: {:?}", error);
         return Err(FieldError::from("Failed to delete user"));
     }
+    ctx.users.invalidate(&user.id);
+    AuditLog::account_deleted(user.id.clone()).record().await;
+
+    Ok(true)
+}
+
+pub async fn deactivate(ctx: &Ctx) -> Result<bool, FieldError> {
+    if let None = ctx.session {
+        return Err(FieldError::from("Invalid session"));
+    }
+    let session = ctx.session.as_ref().unwrap().clone();
+
+    let mut user = match User::find_one(session.user_id.clone(), false).await {
+        Ok(user) => user,
+        Err(e) => {
+            println!("[deactivate] Failed to get user: {:?}", e);
+            return Err(FieldError::from("Failed to get user"));
+        }
+    };
+
+    if let Some(error) = user.archive().await {
+        println!("[deactivate] Failed to archive user: {:?}", error);
+        return Err(FieldError::from("Failed to deactivate user"));
+    }
+    ctx.users.invalidate(&user.id);
 
     Ok(true)
 }
 
+pub async fn reactivate(ctx: &Ctx, user_id: String) -> Result<User, FieldError> {
+    if let None = ctx.session {
+        return Err(FieldError::from("Invalid session"));
+    }
+    let session = ctx.session.as_ref().unwrap().clone();
+    if !is_admin(&session.user_id) {
+        return Err(FieldError::from("Not authorized"));
+    }
+
+    let mut user = match User::find_one(user_id, false).await {
+        Ok(user) => user,
+        Err(e) => {
+            println!("[reactivate] Failed to get user: {:?}", e);
+            return Err(FieldError::from("Failed to get user"));
+        }
+    };
+
+    if let Some(error) = user.unarchive().await {
+        println!("[reactivate] Failed to unarchive user: {:?}", error);
+        return Err(FieldError::from("Failed to reactivate user"));
+    }
+    ctx.users.invalidate(&user.id);
+
+    Ok(user)
+}
+
 pub async fn reset_password(id: String, password: String) -> Result<bool, FieldError> {
     let mut user = match User::find_one(id, false).await {
         Ok(user) => user,
@@ -175,11 +369,195 @@ pub async fn reset_password(id: String, password: String) -> Result<bool, FieldE
         return Err(FieldError::from("User phone not verified"));
     }
 
-    user.password_hash = hash_password(&password);
-    if let Some(error) = user.update().await {
-        println!("[reset_password] Failed to update user: {:?}", error);
+    if let Some(error) = user.change_password(&password, None).await {
+        println!("[reset_password] Failed to change password: {:?}", error);
         return Err(FieldError::from("Failed to update user"));
     }
+    AuditLog::password_changed(user.id.clone()).record().await;
 
     Ok(true)
 }
+
+pub async fn claim_daily(ctx: &Ctx) -> Result<User, FieldError> {
+    if let None = ctx.session {
+        return Err(FieldError::from("Invalid session"));
+    }
+    let session = ctx.session.as_ref().unwrap().clone();
+
+    let mut user = match User::find_one(session.user_id.clone(), false).await {
+        Ok(user) => user,
+        Err(e) => {
+            println!("[claim_daily] Failed to get user: {:?}", e);
+            return Err(FieldError::from("Failed to get user"));
+        }
+    };
+
+    if !user.can_claim_daily(OffsetDateTime::now_utc()) {
+        return Err(FieldError::from("Daily reward already claimed"));
+    }
+
+    if let Some(error) = user.claim_daily().await {
+        println!("[claim_daily] Failed to claim daily reward: {:?}", error);
+        return Err(FieldError::from("Failed to claim daily reward"));
+    }
+    ctx.users.invalidate(&user.id);
+
+    Ok(user)
+}
+
+pub async fn purchase_coins(ctx: &Ctx, package_id: String) -> Result<Transaction, FieldError> {
+    if let None = ctx.session {
+        return Err(FieldError::from("Invalid session"));
+    }
+    let session = ctx.session.as_ref().unwrap().clone();
+
+    let package = match find_package(&package_id) {
+        Some(package) => package,
+        None => return Err(FieldError::from("Unknown coin package")),
+    };
+
+    let provider = ctx.state.payments.as_ref();
+    let transaction = match payments::purchase_coins(provider, &session.user_id, &package).await {
+        Ok(transaction) => transaction,
+        Err(e) => {
+            println!("[purchase_coins] Failed to purchase coins: {:?}", e);
+            return Err(FieldError::from("Failed to purchase coins"));
+        }
+    };
+    if let TransactionStatus::Failed = transaction.transaction_status {
+        let reason = transaction
+            .error_message
+            .unwrap_or("Payment failed".to_string());
+        return Err(FieldError::from(reason));
+    }
+    Ok(transaction)
+}
+
+pub async fn update_profile(
+    ctx: &Ctx,
+    display_name: Option<String>,
+    phone: Nullable<String>,
+) -> Result<User, FieldError> {
+    if let None = ctx.session {
+        return Err(FieldError::from("Invalid session"));
+    }
+    let session = ctx.session.as_ref().unwrap().clone();
+
+    let mut user = match User::find_one(session.user_id.clone(), false).await {
+        Ok(user) => user,
+        Err(e) => {
+            println!("[update_profile] Failed to get user: {:?}", e);
+            return Err(FieldError::from("Failed to get user"));
+        }
+    };
+
+    let phone = match phone.map(|phone| normalize_phone(&phone)) {
+        Nullable::Some(Ok(phone)) => Nullable::Some(phone),
+        Nullable::Some(Err(_)) => return Err(FieldError::from("Invalid phone number")),
+        Nullable::ExplicitNull => Nullable::ExplicitNull,
+        Nullable::ImplicitNull => Nullable::ImplicitNull,
+    };
+    if display_name.is_none() && phone.is_implicit_null() {
+        return Ok(user);
+    }
+
+    let mut params: Vec<(&str, Option<DatabaseValue>)> = vec![
+        ("display_name", display_name.map(|name| name.into())),
+        ("phone", nullable_param(phone.clone())),
+    ];
+    // A changed or cleared phone number has to be verified again
+    let code = match &phone {
+        Nullable::Some(_) => Some(generate_verification_code()),
+        _ => None,
+    };
+    if !phone.is_implicit_null() {
+        params.push(("phone_verification_code", Some(code.clone().into())));
+        params.push(("phone_verified", Some(false.into())));
+    }
+
+    if let Some(error) = user.update_fields(params).await {
+        println!("[update_profile] Failed to update user: {:?}", error);
+        return Err(FieldError::from("Failed to update user"));
+    }
+    ctx.users.invalidate(&user.id);
+    if let Some(code) = code {
+        send_phone_verification_code(&ctx.state, &user, code).await?;
+    }
+    if !phone.is_implicit_null() {
+        AuditLog::phone_changed(user.id.clone(), user.phone.as_deref())
+            .record()
+            .await;
+    }
+
+    Ok(user)
+}
+
+pub async fn set_primary_mnstr(ctx: &Ctx, mnstr_id: String) -> Result<User, FieldError> {
+    if let None = ctx.session {
+        return Err(FieldError::from("Invalid session"));
+    }
+    let session = ctx.session.as_ref().unwrap().clone();
+
+    let mut user = match User::find_one(session.user_id.clone(), false).await {
+        Ok(user) => user,
+        Err(e) => {
+            println!("[set_primary_mnstr] Failed to get user: {:?}", e);
+            return Err(FieldError::from("Failed to get user"));
+        }
+    };
+
+    let mnstr = match Mnstr::find_one(mnstr_id, false).await {
+        Ok(mnstr) => mnstr,
+        Err(e) => {
+            println!("[set_primary_mnstr] Failed to get mnstr: {:?}", e);
+            return Err(FieldError::from("Mnstr not found"));
+        }
+    };
+    if !user.owns_mnstr(&mnstr) {
+        return Err(FieldError::from("Mnstr not owned by user"));
+    }
+
+    if let Some(error) = user.set_primary_mnstr(&mnstr).await {
+        println!("[set_primary_mnstr] Failed to update user: {:?}", error);
+        return Err(FieldError::from("Failed to update user"));
+    }
+    ctx.users.invalidate(&user.id);
+
+    Ok(user)
+}
+
+/// Opts the user in or out of non-essential emails and text messages. Leaving
+/// a preference out keeps it as it is.
+pub async fn set_notification_prefs(
+    ctx: &Ctx,
+    email_opt_in: Option<bool>,
+    sms_opt_in: Option<bool>,
+) -> Result<User, FieldError> {
+    if let None = ctx.session {
+        return Err(FieldError::from("Invalid session"));
+    }
+    let session = ctx.session.as_ref().unwrap().clone();
+
+    let mut user = match User::find_one(session.user_id.clone(), false).await {
+        Ok(user) => user,
+        Err(e) => {
+            println!("[set_notification_prefs] Failed to get user: {:?}", e);
+            return Err(FieldError::from("Failed to get user"));
+        }
+    };
+
+    let params: Vec<(&str, Option<DatabaseValue>)> = vec![
+        ("email_opt_in", email_opt_in.map(|opt_in| opt_in.into())),
+        ("sms_opt_in", sms_opt_in.map(|opt_in| opt_in.into())),
+    ];
+    if let Some(error) = user.update_fields(params).await {
+        println!(
+            "[set_notification_prefs] Failed to update user: {:?}",
+            error
+        );
+        return Err(FieldError::from("Failed to update user"));
+    }
+    ctx.users.invalidate(&user.id);
+
+    Ok(user)
+}
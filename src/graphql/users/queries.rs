@@ -1,11 +1,66 @@
-use juniper::FieldError;
+use juniper::{FieldError, GraphQLObject};
 
 use crate::{
-    graphql::{Ctx, users::utils::send_email_verification_code},
-    models::user::User,
-    utils::passwords::{generate_verification_code, hash_password},
+    graphql::{
+        Ctx,
+        pagination::{PageInput, PageOrdering, SortDirection, apply_pagination},
+        users::utils::send_email_verification_code,
+    },
+    models::{audit_log::AuditLog, transaction::Transaction, user::User, wallet::Wallet},
+    utils::{
+        admin::is_admin,
+        contact::normalize_email,
+        passwords::{generate_verification_code, hash_password},
+    },
 };
 
+const LEADERBOARD_ORDERING: PageOrdering = PageOrdering {
+    columns: &[
+        ("level", "experience_level"),
+        ("experience", "experience_points"),
+        ("name", "display_name"),
+    ],
+    direction: SortDirection::Desc,
+};
+
+const SEARCH_ORDERING: PageOrdering = PageOrdering {
+    columns: &[("name", "display_name"), ("level", "experience_level")],
+    direction: SortDirection::Asc,
+};
+
+const TRANSACTION_ORDERING: PageOrdering = PageOrdering {
+    columns: &[
+        ("createdAt", "created_at"),
+        ("amount", "transaction_amount"),
+    ],
+    direction: SortDirection::Desc,
+};
+
+const AUDIT_LOG_ORDERING: PageOrdering = PageOrdering {
+    columns: &[("createdAt", "created_at")],
+    direction: SortDirection::Desc,
+};
+
+/// What other players can see of a user.
+#[derive(Debug, Clone, GraphQLObject)]
+pub struct UserSummary {
+    pub user_id: String,
+    pub display_name: String,
+    pub experience_level: i32,
+    pub experience_points: i32,
+}
+
+impl From<User> for UserSummary {
+    fn from(user: User) -> Self {
+        Self {
+            user_id: user.id,
+            display_name: user.display_name,
+            experience_level: user.experience_level,
+            experience_points: user.experience_points,
+        }
+    }
+}
+
 pub struct UserQueryType;
 
 #[juniper::graphql_object]
@@ -14,8 +69,38 @@ impl UserQueryType {
         get_user(ctx).await
     }
 
-    async fn forgot_password(email: String) -> Result<String, FieldError> {
-        forgot_password(email).await
+    async fn forgot_password(ctx: &Ctx, email: String) -> Result<String, FieldError> {
+        forgot_password(ctx, email).await
+    }
+
+    async fn leaderboard(
+        ctx: &Ctx,
+        page: Option<PageInput>,
+    ) -> Result<Vec<UserSummary>, FieldError> {
+        leaderboard(ctx, page).await
+    }
+
+    async fn search(
+        ctx: &Ctx,
+        query: String,
+        page: Option<PageInput>,
+    ) -> Result<Vec<UserSummary>, FieldError> {
+        search(ctx, query, page).await
+    }
+
+    async fn transactions(
+        ctx: &Ctx,
+        page: Option<PageInput>,
+    ) -> Result<Vec<Transaction>, FieldError> {
+        transactions(ctx, page).await
+    }
+
+    async fn audit_logs(
+        ctx: &Ctx,
+        user_id: Option<String>,
+        page: Option<PageInput>,
+    ) -> Result<Vec<AuditLog>, FieldError> {
+        audit_logs(ctx, user_id, page).await
     }
 }
 
@@ -25,7 +110,7 @@ async fn get_user(ctx: &Ctx) -> Result<User, FieldError> {
     }
     let session = ctx.session.as_ref().unwrap().clone();
 
-    let user = match User::find_one(session.user_id.clone(), true).await {
+    let user = match ctx.find_user(session.user_id.clone()).await {
         Ok(user) => user,
         Err(e) => {
             println!("[get_user] Failed to get user: {:?}", e);
@@ -35,7 +120,11 @@ async fn get_user(ctx: &Ctx) -> Result<User, FieldError> {
     Ok(user)
 }
 
-pub async fn forgot_password(email: String) -> Result<String, FieldError> {
+pub async fn forgot_password(ctx: &Ctx, email: String) -> Result<String, FieldError> {
+    let email = match normalize_email(&email) {
+        Ok(email) => email,
+        Err(_) => return Err(FieldError::from("Invalid email address")),
+    };
     let user_params = vec![("email", email.into())];
     let mut user = match User::find_one_by(user_params, false).await {
         Ok(user) => user,
@@ -51,14 +140,10 @@ pub async fn forgot_password(email: String) -> Result<String, FieldError> {
         println!("[forgot_password] Failed to update user: {:?}", error);
         return Err(FieldError::from("Failed to update user"));
     }
+    ctx.users.invalidate(&user.id);
 
-    if let Err(error) = send_email_verification_code(
-        user.display_name,
-        user.email.unwrap(),
-        user.email_verification_code.unwrap(),
-    )
-    .await
-    {
+    let code = user.email_verification_code.clone().unwrap();
+    if let Err(error) = send_email_verification_code(&ctx.state, &user, code).await {
         println!(
             "[forgot_password] Failed to send email verification code: {:?}",
             error
@@ -68,3 +153,105 @@ pub async fn forgot_password(email: String) -> Result<String, FieldError> {
 
     Ok(user.id)
 }
+
+async fn leaderboard(ctx: &Ctx, page: Option<PageInput>) -> Result<Vec<UserSummary>, FieldError> {
+    if let None = ctx.session {
+        return Err(FieldError::from("Invalid session"));
+    }
+    let page = match apply_pagination(page, &LEADERBOARD_ORDERING) {
+        Ok(page) => page,
+        Err(e) => return Err(FieldError::from(e.to_string())),
+    };
+
+    match User::find_leaderboard(&page).await {
+        Ok(users) => Ok(users.into_iter().map(UserSummary::from).collect()),
+        Err(e) => {
+            println!("[leaderboard] Failed to get users: {:?}", e);
+            Err(FieldError::from("Failed to get leaderboard"))
+        }
+    }
+}
+
+async fn search(
+    ctx: &Ctx,
+    query: String,
+    page: Option<PageInput>,
+) -> Result<Vec<UserSummary>, FieldError> {
+    if let None = ctx.session {
+        return Err(FieldError::from("Invalid session"));
+    }
+    let query = query.trim();
+    if query.is_empty() {
+        return Err(FieldError::from("Search query is required"));
+    }
+    let page = match apply_pagination(page, &SEARCH_ORDERING) {
+        Ok(page) => page,
+        Err(e) => return Err(FieldError::from(e.to_string())),
+    };
+
+    match User::search(query, &page).await {
+        Ok(users) => Ok(users.into_iter().map(UserSummary::from).collect()),
+        Err(e) => {
+            println!("[search] Failed to search users: {:?}", e);
+            Err(FieldError::from("Failed to search users"))
+        }
+    }
+}
+
+async fn transactions(ctx: &Ctx, page: Option<PageInput>) -> Result<Vec<Transaction>, FieldError> {
+    if let None = ctx.session {
+        return Err(FieldError::from("Invalid session"));
+    }
+    let session = ctx.session.as_ref().unwrap().clone();
+    let page = match apply_pagination(page, &TRANSACTION_ORDERING) {
+        Ok(page) => page,
+        Err(e) => return Err(FieldError::from(e.to_string())),
+    };
+
+    let wallet = match Wallet::find_one_by(vec![("user_id", session.user_id.clone().into())]).await
+    {
+        Ok(wallet) => wallet,
+        Err(e) => {
+            println!("[transactions] Failed to get wallet: {:?}", e);
+            return Err(FieldError::from("Failed to get wallet"));
+        }
+    };
+    match Transaction::find_history(wallet.id, &page).await {
+        Ok(transactions) => Ok(transactions),
+        Err(e) => {
+            println!("[transactions] Failed to get transactions: {:?}", e);
+            Err(FieldError::from("Failed to get transactions"))
+        }
+    }
+}
+
+/// Newest first, optionally for one user. Admins only.
+async fn audit_logs(
+    ctx: &Ctx,
+    user_id: Option<String>,
+    page: Option<PageInput>,
+) -> Result<Vec<AuditLog>, FieldError> {
+    if let None = ctx.session {
+        return Err(FieldError::from("Invalid session"));
+    }
+    let session = ctx.session.as_ref().unwrap().clone();
+    if !is_admin(&session.user_id) {
+        return Err(FieldError::from("Not authorized"));
+    }
+    let page = match apply_pagination(page, &AUDIT_LOG_ORDERING) {
+        Ok(page) => page,
+        Err(e) => return Err(FieldError::from(e.to_string())),
+    };
+
+    let params = match user_id {
+        Some(user_id) => vec![("user_id", user_id.into())],
+        None => vec![],
+    };
+    match AuditLog::find_page_by(params, &page).await {
+        Ok(audit_logs) => Ok(audit_logs),
+        Err(e) => {
+            println!("[audit_logs] Failed to get audit logs: {:?}", e);
+            Err(FieldError::from("Failed to get audit logs"))
+        }
+    }
+}
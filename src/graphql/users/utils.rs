@@ -1,18 +1,31 @@
-use std::env;
-
 use juniper::FieldError;
-use sendgrid::{Mail, SGClient};
-use twilio::{Client, OutboundMessage};
+use twilio::OutboundMessage;
+
+use crate::{
+    models::user::User,
+    state::AppState,
+    utils::{
+        emails::{self, EmailTemplate},
+        redact::{redact, redact_debug},
+    },
+};
 
-async fn send_phone_verification_code(phone: String, code: String) -> Result<bool, FieldError> {
-    let client = Client::new(
-        env::var("TWILIO_ACCOUNT_SSID").unwrap().as_str(),
-        env::var("TWILIO_AUTH_TOKEN").unwrap().as_str(),
-    );
+/// Texts the code to the user's phone. Verification codes are essential, so
+/// they are sent whatever the user's notification preferences.
+pub async fn send_phone_verification_code(
+    state: &AppState,
+    user: &User,
+    code: String,
+) -> Result<bool, FieldError> {
+    let phone = match &user.phone {
+        Some(phone) if user.receives_sms(true) => phone.clone(),
+        _ => return Ok(false),
+    };
     let message = format!("Your MNSTR verification code is: {}", code);
-    match client
+    match state
+        .twilio
         .send_message(OutboundMessage::new(
-            env::var("TWILIO_PHONE_NUMBER").unwrap().as_str(),
+            state.config.twilio_phone_number.as_str(),
             phone.as_str(),
             message.as_str(),
         ))
@@ -21,50 +34,25 @@ async fn send_phone_verification_code(phone: String, code: String) -> Result<boo
         Ok(_) => Ok(true),
         Err(e) => {
             println!(
-                "[send_phone_verification_code] Failed to send message: {:?}",
-                e
+                "[send_phone_verification_code] Failed to send message to {}: {}",
+                redact(&phone),
+                redact_debug(&e)
             );
             return Err(FieldError::from("Failed to send message"));
         }
     }
 }
 
+/// Emails the code to the user. Verification codes are essential, so they are
+/// sent whatever the user's notification preferences.
 pub async fn send_email_verification_code(
-    display_name: String,
-    email: String,
+    state: &AppState,
+    user: &User,
     code: String,
 ) -> Result<bool, FieldError> {
-    let api_key = match env::var("SENDGRID_API_KEY") {
-        Ok(key) => key,
-        Err(e) => {
-            println!(
-                "[send_email_verification_code] Failed to get API key: {:?}",
-                e
-            );
-            return Err(FieldError::from("Failed to get API key"));
-        }
-    };
-
-    let from_email = match env::var("SENDGRID_FROM_EMAIL") {
-        Ok(email) => email,
-        Err(e) => {
-            println!(
-                "[send_email_verification_code] Failed to get from email: {:?}",
-                e
-            );
-            return Err(FieldError::from("Failed to get from email"));
-        }
-    };
-
-    let client = SGClient::new(api_key.as_str());
-    let message = format!("Your MNSTR verification code is: {}", code);
-    let message = Mail::new()
-        .add_text(message.as_str())
-        .add_from(from_email.as_str())
-        .add_subject("MNSTR Verification Code")
-        .add_to((email.as_str(), display_name.as_str()).into());
-    match client.send(message).await {
-        Ok(_) => Ok(true),
+    let template = EmailTemplate::VerificationCode { code };
+    match emails::send_user_email(state, &template, user).await {
+        Ok(sent) => Ok(sent),
         Err(e) => {
             println!(
                 "[send_email_verification_code] Failed to send email: {:?}",
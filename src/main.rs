@@ -2,17 +2,24 @@
 extern crate rocket;
 
 use rocket_cors::CorsOptions;
-use sqlx::postgres::PgPoolOptions;
 use std::{env, net::SocketAddr};
 use tonic::transport::Server as GrpcServer;
 use tonic_reflection::server::Builder as GrpcReflectionBuilder;
 
-use crate::proto::{mnstr_service_server::MnstrServiceServer, session_service_server::SessionServiceServer, user_service_server::UserServiceServer};
+use crate::{
+    database::{
+        connection::get_connection,
+        schema::{validate_schema, validate_schema_enabled},
+    },
+    proto::{mnstr_service_server::MnstrServiceServer, session_service_server::SessionServiceServer, user_service_server::UserServiceServer},
+    state::AppState,
+};
 
 pub mod proto {
     tonic::include_proto!("mnstrv2");
 }
 
+mod admin;
 mod database;
 mod graphql;
 mod models;
@@ -20,6 +27,8 @@ mod services;
 mod utils;
 mod websocket;
 mod battle;
+mod payments;
+mod state;
 
 const FILE_DESCRIPTOR_SET: &[u8] = tonic::include_file_descriptor_set!("descriptor");
 
@@ -30,18 +39,23 @@ fn index() -> &'static str {
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    let _ = env::var("TWILIO_ACCOUNT_SSID")?;
-    let _ = env::var("TWILIO_AUTH_TOKEN")?;
-    let _ = env::var("TWILIO_PHONE_NUMBER")?;
-    let _ = env::var("SENDGRID_API_KEY")?;
-    let _ = env::var("SENDGRID_FROM_EMAIL")?;
+    tracing_subscriber::fmt().init();
     let grpc_port = env::var("GRPC_PORT")?.parse::<u16>()?;
-    let database_url = env::var("DATABASE_URL")?;
-    let pool = PgPoolOptions::new().connect(&*database_url).await?;
+    env::var("DATABASE_URL")?;
+    models::xp::validate_xp_tables()?;
+    // The shared pool connects lazily, so fail here rather than on the first
+    // request if the database can't be reached
+    let pool = get_connection().await;
+    pool.acquire().await?;
+    if validate_schema_enabled() {
+        validate_schema(&pool).await?;
+    }
+    let state = AppState::from_env()?;
+    websocket::battle_queue::handlers::spawn_idle_queue_sweeper(state.redis.clone());
     let cors = CorsOptions::default().to_cors().unwrap();
 
     let session_service =
-        SessionServiceServer::new(services::sessions::SessionServiceImpl::default());
+        SessionServiceServer::new(services::sessions::SessionServiceImpl::new(state.clone()));
     let mnstr_service = MnstrServiceServer::new(services::mnstrs::MnstrServiceImpl::default());
     let users_service = UserServiceServer::new(services::users::UserServiceImpl::default());
     let reflection_service = GrpcReflectionBuilder::configure()
@@ -61,10 +75,11 @@ async fn main() -> anyhow::Result<()> {
 
     rocket::build()
         .mount("/", routes![index])
+        .mount("/admin", admin::routes())
         .mount("/graphql", graphql::routes())
         .mount("/ws", websocket::routes())
         .mount("/static", rocket::fs::FileServer::from("static"))
-        .manage(pool)
+        .manage(state)
         .attach(cors)
         .launch()
         .await?;
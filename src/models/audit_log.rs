@@ -0,0 +1,265 @@
+//! A record of sensitive account actions for security review.
+//!
+//! Entries are written after the action succeeds and are never updated. They
+//! aren't tied to the user row, so they outlive a deleted account. Writing one
+//! is best effort: a failed write is logged rather than undoing the action.
+
+use juniper::{GraphQLEnum, GraphQLObject};
+use serde::{Deserialize, Serialize};
+use sqlx::{Error, Row, postgres::PgRow};
+use time::OffsetDateTime;
+
+use crate::{
+    database::{
+        connection::fetch_one,
+        traits::{ArchivedFilter, DatabaseResource},
+        values::DatabaseValue,
+    },
+    find_page_ordered,
+    graphql::pagination::PageRequest,
+    insert_resource,
+    utils::{
+        redact::mask_phone,
+        time::{deserialize_offset_date_time, serialize_offset_date_time},
+    },
+};
+
+#[derive(Debug, Serialize, Deserialize, GraphQLEnum, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum AuditAction {
+    PasswordChanged,
+    PhoneChanged,
+    NewDeviceLogin,
+    AccountDeleted,
+}
+
+impl std::fmt::Display for AuditAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AuditAction::PasswordChanged => write!(f, "passwordChanged"),
+            AuditAction::PhoneChanged => write!(f, "phoneChanged"),
+            AuditAction::NewDeviceLogin => write!(f, "newDeviceLogin"),
+            AuditAction::AccountDeleted => write!(f, "accountDeleted"),
+        }
+    }
+}
+
+impl From<String> for AuditAction {
+    fn from(value: String) -> Self {
+        match value.as_str() {
+            "passwordChanged" => AuditAction::PasswordChanged,
+            "phoneChanged" => AuditAction::PhoneChanged,
+            "newDeviceLogin" => AuditAction::NewDeviceLogin,
+            _ => AuditAction::AccountDeleted,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, GraphQLObject, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditLog {
+    pub id: String,
+    pub user_id: String,
+    pub action: AuditAction,
+    /// JSON with details of the action. Contact details are masked.
+    pub metadata: Option<String>,
+
+    #[serde(
+        serialize_with = "serialize_offset_date_time",
+        deserialize_with = "deserialize_offset_date_time"
+    )]
+    pub created_at: Option<OffsetDateTime>,
+}
+
+impl AuditLog {
+    pub fn new(user_id: String, action: AuditAction, metadata: Option<serde_json::Value>) -> Self {
+        Self {
+            id: "".to_string(),
+            user_id,
+            action,
+            metadata: metadata.map(|metadata| metadata.to_string()),
+            created_at: None,
+        }
+    }
+
+    pub fn password_changed(user_id: String) -> Self {
+        Self::new(user_id, AuditAction::PasswordChanged, None)
+    }
+
+    /// `phone` is the new number, `None` when it was removed.
+    pub fn phone_changed(user_id: String, phone: Option<&str>) -> Self {
+        let metadata = serde_json::json!({ "phone": phone.map(mask_phone) });
+        Self::new(user_id, AuditAction::PhoneChanged, Some(metadata))
+    }
+
+    /// An entry for a login, unless it came from a device the user has logged
+    /// in from before. Logins that don't name a device are always recorded.
+    pub fn login(user_id: String, device_id: Option<&str>, known_device: bool) -> Option<Self> {
+        if known_device && device_id.is_some() {
+            return None;
+        }
+        let metadata = serde_json::json!({ "deviceId": device_id });
+        Some(Self::new(
+            user_id,
+            AuditAction::NewDeviceLogin,
+            Some(metadata),
+        ))
+    }
+
+    pub fn account_deleted(user_id: String) -> Self {
+        Self::new(user_id, AuditAction::AccountDeleted, None)
+    }
+
+    pub async fn create(&mut self) -> Option<anyhow::Error> {
+        let params = vec![
+            ("user_id", self.user_id.clone().into()),
+            ("action", self.action.to_string().into()),
+            (
+                "metadata",
+                self.metadata.clone().map(DatabaseValue::Text).into(),
+            ),
+        ];
+        let audit_log = match insert_resource!(AuditLog, params).await {
+            Ok(audit_log) => audit_log,
+            Err(e) => {
+                println!("[AuditLog::create] Failed to create audit log: {:?}", e);
+                return Some(e.into());
+            }
+        };
+        *self = audit_log;
+        None
+    }
+
+    /// Writes the entry, logging rather than returning a failure.
+    pub async fn record(mut self) {
+        if let Some(error) = self.create().await {
+            println!(
+                "[AuditLog::record] Failed to record {} for {}: {:?}",
+                self.action, self.user_id, error
+            );
+        }
+    }
+
+    /// Whether `user_id` has an audited login from `device_id`.
+    pub async fn has_device(user_id: &str, device_id: &str) -> Result<bool, anyhow::Error> {
+        let query = sqlx::query(
+            "SELECT EXISTS (SELECT 1 FROM audit_logs WHERE user_id = $1 AND action = $2 \
+             AND CAST(metadata AS JSONB) ->> 'deviceId' = $3) AS known",
+        )
+        .bind(user_id)
+        .bind(AuditAction::NewDeviceLogin.to_string())
+        .bind(device_id);
+        match fetch_one("audit_logs", "has_device", query).await {
+            Ok(row) => Ok(row.get::<bool, _>("known")),
+            Err(e) => {
+                println!("[AuditLog::has_device] Failed to check device: {:?}", e);
+                Err(anyhow::Error::msg(e.to_string()))
+            }
+        }
+    }
+
+    /// Records a login from `device_id` if it's new for the user.
+    pub async fn record_login(user_id: String, device_id: Option<String>) {
+        let known_device = match &device_id {
+            Some(device_id) => match Self::has_device(&user_id, device_id).await {
+                Ok(known_device) => known_device,
+                Err(_) => false,
+            },
+            None => false,
+        };
+        if let Some(entry) = Self::login(user_id, device_id.as_deref(), known_device) {
+            entry.record().await;
+        }
+    }
+
+    pub async fn find_page_by(
+        params: Vec<(&str, DatabaseValue)>,
+        page: &PageRequest,
+    ) -> Result<Vec<Self>, anyhow::Error> {
+        match find_page_ordered!(AuditLog, params, ArchivedFilter::Any, page).await {
+            Ok(audit_logs) => Ok(audit_logs),
+            Err(e) => {
+                println!("[AuditLog::find_page_by] Failed to get audit logs: {:?}", e);
+                Err(e.into())
+            }
+        }
+    }
+}
+
+impl DatabaseResource for AuditLog {
+    fn from_row(row: &PgRow) -> Result<Self, Error> {
+        Ok(AuditLog {
+            id: row.get("id"),
+            user_id: row.get("user_id"),
+            action: row.get::<String, _>("action").into(),
+            metadata: row.get("metadata"),
+            created_at: row.get("created_at"),
+        })
+    }
+    fn has_id() -> bool {
+        true
+    }
+    fn is_archivable() -> bool {
+        false
+    }
+    fn is_updatable() -> bool {
+        false
+    }
+    fn is_creatable() -> bool {
+        true
+    }
+    fn is_expirable() -> bool {
+        false
+    }
+    fn is_verifiable() -> bool {
+        false
+    }
+    fn columns() -> &'static [&'static str] {
+        &[
+            "id",
+            "user_id",
+            "action",
+            "metadata",
+            "created_at",
+            "updated_at",
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metadata(entry: &AuditLog) -> serde_json::Value {
+        serde_json::from_str(entry.metadata.as_deref().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_password_change_is_audited() {
+        let entry = AuditLog::password_changed("user".to_string());
+        assert_eq!(entry.user_id, "user");
+        assert_eq!(entry.action, AuditAction::PasswordChanged);
+        assert!(entry.metadata.is_none());
+        assert_eq!(
+            AuditAction::from(entry.action.to_string()),
+            AuditAction::PasswordChanged
+        );
+
+        let entry = AuditLog::phone_changed("user".to_string(), Some("+15551234589"));
+        assert_eq!(metadata(&entry)["phone"], "***-**89");
+    }
+
+    #[test]
+    fn test_login_from_new_device_is_audited() {
+        let entry = AuditLog::login("user".to_string(), Some("phone-1"), false).unwrap();
+        assert_eq!(entry.user_id, "user");
+        assert_eq!(entry.action, AuditAction::NewDeviceLogin);
+        assert_eq!(metadata(&entry)["deviceId"], "phone-1");
+
+        // A device seen before isn't recorded again
+        assert!(AuditLog::login("user".to_string(), Some("phone-1"), true).is_none());
+        // Without a device id the login can't be recognized, so it's recorded
+        let entry = AuditLog::login("user".to_string(), None, true).unwrap();
+        assert!(metadata(&entry)["deviceId"].is_null());
+    }
+}
@@ -1,12 +1,23 @@
+use juniper::GraphQLObject;
 use rocket::serde;
 use serde::{Deserialize, Serialize};
 use sqlx::{Error, Row, postgres::PgRow};
-use time::OffsetDateTime;
+use time::{Duration, OffsetDateTime};
 
 use crate::{
-    database::{traits::DatabaseResource, values::DatabaseValue},
+    battle::helpers::new_battle_seed,
+    database::{
+        connection::{execute, fetch_all, fetch_one, fetch_optional},
+        traits::DatabaseResource,
+        values::DatabaseValue,
+    },
     delete_resource_where_fields, find_all_resources_where_fields, find_one_resource_where_fields,
-    insert_resource, update_resource,
+    find_one_unarchived_resource_where_fields, insert_resource,
+    models::{
+        battle_status::BattleStatus,
+        mnstr::{FAINTED_MNSTR_ERROR, Mnstr},
+    },
+    update_resource,
     utils::time::{deserialize_offset_date_time, serialize_offset_date_time},
 };
 
@@ -22,6 +33,13 @@ pub struct Battle {
     pub opponent_mnstr_id: Option<String>,
     pub winner_id: Option<String>,
     pub winner_mnstr_id: Option<String>,
+    pub outcome: Option<String>,
+    pub winner_xp_awarded: Option<i32>,
+    pub winner_coins_awarded: Option<i32>,
+    pub loser_xp_awarded: Option<i32>,
+    pub loser_coins_awarded: Option<i32>,
+    /// Seeds every combat roll so disputed battles can be replayed exactly.
+    pub seed: i64,
 
     #[serde(
         serialize_with = "serialize_offset_date_time",
@@ -42,6 +60,30 @@ pub struct Battle {
     pub archived_at: Option<OffsetDateTime>,
 }
 
+/// How long a mnstr rests after a battle ends before it can fight again.
+pub const BATTLE_COOLDOWN_SECONDS: i64 = 60;
+
+/// The `outcome` recorded for a battle that ended in a draw.
+pub const DRAW_OUTCOME: &str = "draw";
+
+/// A mnstr's part in a battle that is still running or only just ended.
+#[derive(Debug, Clone)]
+pub struct MnstrEngagement {
+    pub battle_id: String,
+    pub mnstr_id: String,
+    pub ended_at: Option<OffsetDateTime>,
+}
+
+impl MnstrEngagement {
+    /// Whether the mnstr is still fighting or cooling down at `now`.
+    pub fn keeps_out_of_battle(&self, now: OffsetDateTime) -> bool {
+        match self.ended_at {
+            Some(ended_at) => now - ended_at < Duration::seconds(BATTLE_COOLDOWN_SECONDS),
+            None => true,
+        }
+    }
+}
+
 impl Battle {
     pub fn new(
         challenger_id: String,
@@ -59,6 +101,12 @@ impl Battle {
             opponent_mnstr_id: None,
             winner_id: None,
             winner_mnstr_id: None,
+            outcome: None,
+            winner_xp_awarded: None,
+            winner_coins_awarded: None,
+            loser_xp_awarded: None,
+            loser_coins_awarded: None,
+            seed: new_battle_seed(),
             created_at: None,
             updated_at: None,
             archived_at: None,
@@ -71,6 +119,7 @@ impl Battle {
             ("challenger_name", self.challenger_name.clone().into()),
             ("opponent_id", self.opponent_id.clone().into()),
             ("opponent_name", self.opponent_name.clone().into()),
+            ("seed", self.seed.into()),
         ];
         let battle = match insert_resource!(Battle, params).await {
             Ok(battle) => battle,
@@ -89,6 +138,11 @@ impl Battle {
             ("opponent_mnstr_id", self.opponent_mnstr_id.clone().into()),
             ("winner_id", self.winner_id.clone().into()),
             ("winner_mnstr_id", self.winner_mnstr_id.clone().into()),
+            ("outcome", self.outcome.clone().into()),
+            ("winner_xp_awarded", self.winner_xp_awarded.into()),
+            ("winner_coins_awarded", self.winner_coins_awarded.into()),
+            ("loser_xp_awarded", self.loser_xp_awarded.into()),
+            ("loser_coins_awarded", self.loser_coins_awarded.into()),
         ];
         let battle = match update_resource!(Battle, self.id.clone(), params).await {
             Ok(battle) => battle,
@@ -107,7 +161,23 @@ impl Battle {
         None
     }
 
+    /// Only battles that haven't been archived, so a settled battle can't be
+    /// rejoined or played on. History and replays use `find_one_with_archived`.
     pub async fn find_one(id: String) -> Result<Self, anyhow::Error> {
+        let battle = match find_one_unarchived_resource_where_fields!(
+            Battle,
+            vec![("id", id.clone().into())]
+        )
+        .await
+        {
+            Ok(battle) => battle,
+            Err(e) => return Err(e.into()),
+        };
+        Ok(battle)
+    }
+
+    /// Archived battles included.
+    pub async fn find_one_with_archived(id: String) -> Result<Self, anyhow::Error> {
         let battle =
             match find_one_resource_where_fields!(Battle, vec![("id", id.clone().into())]).await {
                 Ok(battle) => battle,
@@ -141,6 +211,474 @@ impl Battle {
         };
         Ok(battles)
     }
+
+    /// The winner when `user_id` escapes or surrenders: always the other
+    /// participant, or `None` if the user isn't part of this battle.
+    pub fn forfeit_winner_id(&self, user_id: &str) -> Option<String> {
+        if self.challenger_id == user_id {
+            Some(self.opponent_id.clone())
+        } else if self.opponent_id == user_id {
+            Some(self.challenger_id.clone())
+        } else {
+            None
+        }
+    }
+
+    /// Whether `user_id` takes part in this battle but hasn't picked a mnstr yet.
+    pub fn needs_mnstr(&self, user_id: &str) -> bool {
+        (self.challenger_id == user_id && self.challenger_mnstr_id.is_none())
+            || (self.opponent_id == user_id && self.opponent_mnstr_id.is_none())
+    }
+
+    /// Whether both sides have picked a mnstr, so the battle can start.
+    pub fn mnstrs_chosen(&self) -> bool {
+        self.challenger_mnstr_id.is_some() && self.opponent_mnstr_id.is_some()
+    }
+
+    /// Records `mnstr` as the challenger's pick, or the opponent's when
+    /// `challenger` is false. Refuses a mnstr that side's player doesn't own,
+    /// has released or that has fainted.
+    pub fn choose_mnstr(&mut self, challenger: bool, mnstr: &Mnstr) -> Result<(), anyhow::Error> {
+        let (owner_id, mnstr_id) = match challenger {
+            true => (&self.challenger_id, &mut self.challenger_mnstr_id),
+            false => (&self.opponent_id, &mut self.opponent_mnstr_id),
+        };
+        if mnstr.user_id != *owner_id {
+            return Err(anyhow::anyhow!(
+                "Mnstr {} isn't owned by {}",
+                mnstr.id,
+                owner_id
+            ));
+        }
+        if mnstr.archived_at.is_some() {
+            return Err(anyhow::anyhow!("Mnstr {} has been released", mnstr.id));
+        }
+        if mnstr.is_fainted() {
+            return Err(anyhow::anyhow!(FAINTED_MNSTR_ERROR));
+        }
+        *mnstr_id = Some(mnstr.id.clone());
+        Ok(())
+    }
+
+    /// Records `mnstr` as `user_id`'s pick, on their own side only. Refuses it
+    /// the same way `choose_mnstr` does, and while `engagements` show it in
+    /// another battle or cooling down from one.
+    pub fn choose_mnstr_for(
+        &mut self,
+        user_id: &str,
+        mnstr: &Mnstr,
+        engagements: &[MnstrEngagement],
+        now: OffsetDateTime,
+    ) -> Result<(), anyhow::Error> {
+        if !self.is_participant(user_id) {
+            return Err(anyhow::anyhow!("{} isn't in battle {}", user_id, self.id));
+        }
+        let elsewhere = engagements.iter().any(|engagement| {
+            engagement.battle_id != self.id
+                && engagement.mnstr_id == mnstr.id
+                && engagement.keeps_out_of_battle(now)
+        });
+        if elsewhere {
+            return Err(anyhow::anyhow!("Mnstr {} is in another battle", mnstr.id));
+        }
+        self.choose_mnstr(self.challenger_id == user_id, mnstr)
+    }
+
+    /// Whether a winner or a draw has been recorded or the battle has been
+    /// archived.
+    pub fn is_settled(&self) -> bool {
+        self.winner_id.is_some() || self.is_draw() || self.archived_at.is_some()
+    }
+
+    /// Whether both mnstrs were knocked out at once, leaving no winner.
+    pub fn is_draw(&self) -> bool {
+        self.outcome.as_deref() == Some(DRAW_OUTCOME)
+    }
+
+    /// Whether `user_id` is the challenger or the opponent.
+    pub fn is_participant(&self, user_id: &str) -> bool {
+        self.challenger_id == user_id || self.opponent_id == user_id
+    }
+
+    /// Whether `user_id` takes part in this battle and it hasn't been settled.
+    pub fn can_rejoin(&self, user_id: &str) -> bool {
+        !self.is_settled() && self.is_participant(user_id)
+    }
+
+    /// Records `winner_id` unless the battle is already settled. Returns whether
+    /// this call recorded it, so only one of two racing knockouts settles.
+    pub async fn claim_winner(id: String, winner_id: String) -> Result<bool, anyhow::Error> {
+        let query = sqlx::query(
+            "UPDATE battles SET winner_id = $1, updated_at = CURRENT_TIMESTAMP \
+             WHERE id = $2 AND winner_id IS NULL AND archived_at IS NULL \
+             AND (outcome IS NULL OR outcome <> $3)",
+        )
+        .bind(&winner_id)
+        .bind(&id)
+        .bind(DRAW_OUTCOME);
+        match execute("battles", "claim_winner", query).await {
+            Ok(result) => Ok(result.rows_affected() == 1),
+            Err(e) => {
+                println!("[Battle::claim_winner] Failed to claim winner: {:?}", e);
+                Err(anyhow::Error::msg(e.to_string()))
+            }
+        }
+    }
+
+    /// Records a draw unless the battle is already settled. Returns whether this
+    /// call recorded it, the same way `claim_winner` does.
+    pub async fn claim_draw(id: String) -> Result<bool, anyhow::Error> {
+        let query = sqlx::query(
+            "UPDATE battles SET outcome = $1, updated_at = CURRENT_TIMESTAMP \
+             WHERE id = $2 AND winner_id IS NULL AND archived_at IS NULL \
+             AND (outcome IS NULL OR outcome <> $1)",
+        )
+        .bind(DRAW_OUTCOME)
+        .bind(&id);
+        match execute("battles", "claim_draw", query).await {
+            Ok(result) => Ok(result.rows_affected() == 1),
+            Err(e) => {
+                println!("[Battle::claim_draw] Failed to claim draw: {:?}", e);
+                Err(anyhow::Error::msg(e.to_string()))
+            }
+        }
+    }
+
+    /// Running battles `user_id` isn't in, newest first, with each
+    /// participant's display name and level. Battles with a player blocked
+    /// either way are left out.
+    pub async fn find_spectatable(
+        user_id: String,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<SpectatableBattle>, anyhow::Error> {
+        let query = sqlx::query(
+            "SELECT b.id, b.created_at, \
+             c.id AS challenger_id, c.display_name AS challenger_name, c.experience_level AS challenger_level, \
+             o.id AS opponent_id, o.display_name AS opponent_name, o.experience_level AS opponent_level \
+             FROM battles b \
+             JOIN users c ON c.id = b.challenger_id \
+             JOIN users o ON o.id = b.opponent_id \
+             WHERE b.winner_id IS NULL AND b.archived_at IS NULL \
+             AND (b.outcome IS NULL OR b.outcome <> $2) \
+             AND b.challenger_id <> $1 AND b.opponent_id <> $1 \
+             AND NOT EXISTS (SELECT 1 FROM blocks k \
+             WHERE (k.blocker_id = $1 AND k.blocked_id IN (b.challenger_id, b.opponent_id)) \
+             OR (k.blocked_id = $1 AND k.blocker_id IN (b.challenger_id, b.opponent_id))) \
+             ORDER BY b.created_at DESC, b.id LIMIT $3 OFFSET $4",
+        )
+        .bind(&user_id)
+        .bind(DRAW_OUTCOME)
+        .bind(limit)
+        .bind(offset);
+        let rows = match fetch_all("battles", "find_spectatable", query).await {
+            Ok(rows) => rows,
+            Err(e) => {
+                println!("[Battle::find_spectatable] Failed to get battles: {:?}", e);
+                return Err(anyhow::Error::msg(e.to_string()));
+            }
+        };
+        Ok(rows
+            .iter()
+            .map(|row| SpectatableBattle {
+                battle_id: row.get("id"),
+                challenger: BattleParticipant {
+                    user_id: row.get("challenger_id"),
+                    display_name: row.get("challenger_name"),
+                    level: row.get("challenger_level"),
+                },
+                opponent: BattleParticipant {
+                    user_id: row.get("opponent_id"),
+                    display_name: row.get("opponent_name"),
+                    level: row.get("opponent_level"),
+                },
+                started_at: row.get("created_at"),
+            })
+            .collect())
+    }
+
+    /// Participants can replay a battle at any time; anyone else only once it
+    /// has been settled and archived.
+    pub fn can_view_replay(&self, user_id: Option<&str>) -> bool {
+        if (self.winner_id.is_some() || self.is_draw()) && self.archived_at.is_some() {
+            return true;
+        }
+        match user_id {
+            Some(user_id) => self.challenger_id == user_id || self.opponent_id == user_id,
+            None => false,
+        }
+    }
+
+    /// The result of this battle from `user_id`'s side, once it has been settled
+    /// and archived.
+    pub fn result_for(&self, user_id: &str) -> Option<BattleResult> {
+        if self.winner_id.is_none() && !self.is_draw() {
+            return None;
+        }
+        let ended_at = self.archived_at?;
+        let (opponent_id, opponent_name) = if self.challenger_id == user_id {
+            (self.opponent_id.clone(), self.opponent_name.clone())
+        } else if self.opponent_id == user_id {
+            (self.challenger_id.clone(), self.challenger_name.clone())
+        } else {
+            return None;
+        };
+        let won = self.winner_id.as_deref() == Some(user_id);
+        // A draw records the challenger's awards in the winner columns
+        let winner_side = if self.is_draw() {
+            self.challenger_id == user_id
+        } else {
+            won
+        };
+        let (xp_awarded, coins_awarded) = if winner_side {
+            (self.winner_xp_awarded, self.winner_coins_awarded)
+        } else {
+            (self.loser_xp_awarded, self.loser_coins_awarded)
+        };
+        Some(BattleResult {
+            battle_id: self.id.clone(),
+            opponent_id,
+            opponent_name,
+            won,
+            outcome: self.outcome.clone(),
+            xp_awarded,
+            coins_awarded,
+            ended_at,
+        })
+    }
+
+    /// The most recently settled battle the user took part in, so a player who
+    /// disconnected before `GameEnded` arrived can still see how it went.
+    pub async fn find_last_result(user_id: String) -> Result<Option<BattleResult>, anyhow::Error> {
+        let query = sqlx::query(
+            "SELECT * FROM battles WHERE (challenger_id = $1 OR opponent_id = $1) \
+             AND archived_at IS NOT NULL AND (winner_id IS NOT NULL OR outcome = $2) \
+             ORDER BY archived_at DESC, id LIMIT 1",
+        )
+        .bind(&user_id)
+        .bind(DRAW_OUTCOME);
+        let row = match fetch_optional("battles", "find_last_result", query).await {
+            Ok(row) => row,
+            Err(e) => {
+                println!("[Battle::find_last_result] Failed to get battle: {:?}", e);
+                return Err(anyhow::Error::msg(e.to_string()));
+            }
+        };
+        match row {
+            Some(row) => Ok(Battle::from_row(&row)?.result_for(&user_id)),
+            None => Ok(None),
+        }
+    }
+
+    /// The user's most recently archived battles, newest first.
+    pub async fn find_recent_archived(
+        user_id: String,
+        limit: i64,
+    ) -> Result<Vec<Self>, anyhow::Error> {
+        let query = sqlx::query(
+            "SELECT * FROM battles WHERE (challenger_id = $1 OR opponent_id = $1) \
+             AND archived_at IS NOT NULL ORDER BY archived_at DESC, id LIMIT $2",
+        )
+        .bind(&user_id)
+        .bind(limit);
+        let rows = match fetch_all("battles", "find_recent_archived", query).await {
+            Ok(rows) => rows,
+            Err(e) => {
+                println!(
+                    "[Battle::find_recent_archived] Failed to get battles: {:?}",
+                    e
+                );
+                return Err(anyhow::Error::msg(e.to_string()));
+            }
+        };
+        Ok(rows
+            .iter()
+            .map(Battle::from_row)
+            .collect::<Result<Vec<Battle>, Error>>()?)
+    }
+
+    /// The distinct users `user_id` fought in archived `battles`, most recently
+    /// fought first, keeping at most `limit`. Their status is left offline.
+    pub fn recent_opponents(
+        battles: &[Battle],
+        user_id: &str,
+        limit: usize,
+    ) -> Vec<RecentOpponent> {
+        let mut archived = battles
+            .iter()
+            .filter(|battle| battle.archived_at.is_some())
+            .collect::<Vec<&Battle>>();
+        archived.sort_by(|a, b| {
+            b.archived_at
+                .cmp(&a.archived_at)
+                .then_with(|| a.id.cmp(&b.id))
+        });
+
+        let mut opponents: Vec<RecentOpponent> = Vec::new();
+        for battle in archived {
+            let (opponent_id, opponent_name) = if battle.challenger_id == user_id {
+                (&battle.opponent_id, &battle.opponent_name)
+            } else if battle.opponent_id == user_id {
+                (&battle.challenger_id, &battle.challenger_name)
+            } else {
+                continue;
+            };
+            if opponents
+                .iter()
+                .any(|opponent| &opponent.user_id == opponent_id)
+            {
+                continue;
+            }
+            opponents.push(RecentOpponent {
+                user_id: opponent_id.clone(),
+                display_name: opponent_name.clone(),
+                last_battled_at: battle.archived_at.unwrap(),
+                online: false,
+                status: None,
+            });
+            if opponents.len() >= limit {
+                break;
+            }
+        }
+        opponents
+    }
+
+    /// Whether the mnstr is taking part in a battle that hasn't been archived yet.
+    pub async fn is_mnstr_locked(mnstr_id: String) -> Result<bool, anyhow::Error> {
+        for field in ["challenger_mnstr_id", "opponent_mnstr_id"] {
+            let battles = Self::find_all_by(vec![(field, mnstr_id.clone().into())]).await?;
+            if battles.iter().any(|battle| battle.archived_at.is_none()) {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Counts the mnstr's wins and losses across settled, archived battles in
+    /// one query. Battles without a winning mnstr count as neither.
+    pub async fn find_mnstr_record(mnstr_id: String) -> Result<MnstrRecord, anyhow::Error> {
+        let query = sqlx::query(
+            "SELECT COUNT(*) FILTER (WHERE winner_mnstr_id = $1) AS wins, \
+             COUNT(*) FILTER (WHERE winner_mnstr_id <> $1) AS losses FROM battles \
+             WHERE (challenger_mnstr_id = $1 OR opponent_mnstr_id = $1) \
+             AND winner_mnstr_id IS NOT NULL AND archived_at IS NOT NULL",
+        )
+        .bind(&mnstr_id);
+        let row = match fetch_one("battles", "find_mnstr_record", query).await {
+            Ok(row) => row,
+            Err(e) => {
+                println!(
+                    "[Battle::find_mnstr_record] Failed to count battles: {:?}",
+                    e
+                );
+                return Err(anyhow::Error::msg(e.to_string()));
+            }
+        };
+        Ok(MnstrRecord {
+            mnstr_id,
+            wins: row.get::<i64, _>("wins") as i32,
+            losses: row.get::<i64, _>("losses") as i32,
+        })
+    }
+
+    /// The user's mnstrs in battles that are unarchived or were archived within
+    /// the cooldown, fetched in a single query.
+    pub async fn find_mnstr_engagements(
+        user_id: String,
+        now: OffsetDateTime,
+    ) -> Result<Vec<MnstrEngagement>, anyhow::Error> {
+        let query = sqlx::query(
+            "SELECT id, challenger_id, challenger_mnstr_id, opponent_mnstr_id, archived_at FROM battles \
+             WHERE (challenger_id = $1 OR opponent_id = $1) AND (archived_at IS NULL OR archived_at > $2)",
+        )
+        .bind(&user_id)
+        .bind(now - Duration::seconds(BATTLE_COOLDOWN_SECONDS));
+        let rows = match fetch_all("battles", "find_mnstr_engagements", query).await {
+            Ok(rows) => rows,
+            Err(e) => {
+                println!(
+                    "[Battle::find_mnstr_engagements] Failed to get battles: {:?}",
+                    e
+                );
+                return Err(anyhow::Error::msg(e.to_string()));
+            }
+        };
+        Ok(rows
+            .iter()
+            .filter_map(|row| {
+                let mnstr_id: Option<String> =
+                    match row.get::<String, _>("challenger_id") == user_id {
+                        true => row.get("challenger_mnstr_id"),
+                        false => row.get("opponent_mnstr_id"),
+                    };
+                mnstr_id.map(|mnstr_id| MnstrEngagement {
+                    battle_id: row.get("id"),
+                    mnstr_id,
+                    ended_at: row.get("archived_at"),
+                })
+            })
+            .collect())
+    }
+}
+
+/// A settled battle from one participant's point of view.
+#[derive(Debug, Clone, GraphQLObject)]
+pub struct BattleResult {
+    pub battle_id: String,
+    pub opponent_id: String,
+    pub opponent_name: String,
+    pub won: bool,
+    pub outcome: Option<String>,
+    pub xp_awarded: Option<i32>,
+    pub coins_awarded: Option<i32>,
+    pub ended_at: OffsetDateTime,
+}
+
+/// Someone the user fought recently, to suggest a rematch with.
+#[derive(Debug, Clone, GraphQLObject)]
+pub struct RecentOpponent {
+    pub user_id: String,
+    pub display_name: String,
+    pub last_battled_at: OffsetDateTime,
+    /// Whether they are connected to the battle queue right now.
+    pub online: bool,
+    /// Their battle queue status (`inQueue`, `inBattle` or `watching`) while
+    /// online.
+    pub status: Option<String>,
+}
+
+impl RecentOpponent {
+    /// Fills in the opponent's status from their battle queue status, if they
+    /// have one.
+    pub fn with_status(mut self, battle_status: Option<&BattleStatus>) -> Self {
+        self.online = battle_status.is_some();
+        self.status = battle_status.map(|battle_status| battle_status.status.to_string());
+        self
+    }
+}
+
+/// A mnstr's wins and losses across its settled battles.
+#[derive(Debug, Clone, PartialEq, GraphQLObject)]
+pub struct MnstrRecord {
+    pub mnstr_id: String,
+    pub wins: i32,
+    pub losses: i32,
+}
+
+#[derive(Debug, Clone, GraphQLObject)]
+pub struct BattleParticipant {
+    pub user_id: String,
+    pub display_name: String,
+    pub level: i32,
+}
+
+/// A running battle open to spectators.
+#[derive(Debug, Clone, GraphQLObject)]
+pub struct SpectatableBattle {
+    pub battle_id: String,
+    pub challenger: BattleParticipant,
+    pub opponent: BattleParticipant,
+    pub started_at: Option<OffsetDateTime>,
 }
 
 impl DatabaseResource for Battle {
@@ -161,6 +699,12 @@ impl DatabaseResource for Battle {
         };
 
         Ok(Battle {
+            outcome: row.get("outcome"),
+            winner_xp_awarded: row.get("winner_xp_awarded"),
+            winner_coins_awarded: row.get("winner_coins_awarded"),
+            loser_xp_awarded: row.get("loser_xp_awarded"),
+            loser_coins_awarded: row.get("loser_coins_awarded"),
+            seed: row.get("seed"),
             id: row.get("id"),
             challenger_id: row.get("challenger_id"),
             challenger_name: row.get("challenger_name"),
@@ -194,3 +738,409 @@ impl DatabaseResource for Battle {
         false
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::{
+        database::connection::rolled_back,
+        models::{block::Block, mnstr::FAINTED_HEALTH, user::User},
+    };
+
+    #[test]
+    fn test_mnstr_chosen_defaults_to_primary() {
+        let mut battle = Battle::new(
+            "challenger".to_string(),
+            "Challenger".to_string(),
+            "opponent".to_string(),
+            "Opponent".to_string(),
+        );
+        assert!(battle.needs_mnstr("challenger"));
+        assert!(!battle.needs_mnstr("intruder"));
+
+        // The opponent picked explicitly, the challenger sent no mnstr
+        battle.opponent_mnstr_id = Some("chosen".to_string());
+        assert!(!battle.needs_mnstr("opponent"));
+
+        let mut primary = Mnstr::new("challenger".to_string(), None, None, "qr-1".to_string());
+        primary.id = "primary".to_string();
+        let now = OffsetDateTime::now_utc();
+        assert!(
+            battle
+                .choose_mnstr_for("challenger", &primary, &[], now)
+                .is_ok()
+        );
+        assert_eq!(battle.challenger_mnstr_id.as_deref(), Some("primary"));
+        assert_eq!(battle.opponent_mnstr_id.as_deref(), Some("chosen"));
+        assert!(!battle.needs_mnstr("challenger"));
+        assert!(battle.mnstrs_chosen());
+    }
+
+    #[test]
+    fn test_choosing_unowned_mnstr_is_rejected() {
+        let mut battle = Battle::new(
+            "challenger".to_string(),
+            "Challenger".to_string(),
+            "opponent".to_string(),
+            "Opponent".to_string(),
+        );
+        let mnstr = |id: &str, user_id: &str| {
+            let mut mnstr = Mnstr::new(user_id.to_string(), None, None, format!("qr-{}", id));
+            mnstr.id = id.to_string();
+            mnstr
+        };
+
+        // The opponent's or a third party's mnstr can't be played as the challenger's
+        assert!(
+            battle
+                .choose_mnstr(true, &mnstr("theirs", "opponent"))
+                .is_err()
+        );
+        assert!(
+            battle
+                .choose_mnstr(true, &mnstr("stranger", "intruder"))
+                .is_err()
+        );
+        assert!(
+            battle
+                .choose_mnstr(false, &mnstr("mine", "challenger"))
+                .is_err()
+        );
+        assert!(battle.challenger_mnstr_id.is_none());
+        assert!(battle.opponent_mnstr_id.is_none());
+
+        assert!(
+            battle
+                .choose_mnstr(true, &mnstr("mine", "challenger"))
+                .is_ok()
+        );
+        assert!(
+            battle
+                .choose_mnstr(false, &mnstr("theirs", "opponent"))
+                .is_ok()
+        );
+        assert_eq!(battle.challenger_mnstr_id.as_deref(), Some("mine"));
+        assert_eq!(battle.opponent_mnstr_id.as_deref(), Some("theirs"));
+    }
+
+    #[test]
+    fn test_fainted_mnstr_cannot_be_chosen() {
+        let mut battle = Battle::new(
+            "challenger".to_string(),
+            "Challenger".to_string(),
+            "opponent".to_string(),
+            "Opponent".to_string(),
+        );
+        let mut fainted = Mnstr::new("challenger".to_string(), None, None, "qr-1".to_string());
+        fainted.id = "fainted".to_string();
+        fainted.current_health = FAINTED_HEALTH;
+        let mut healthy = Mnstr::new("challenger".to_string(), None, None, "qr-2".to_string());
+        healthy.id = "healthy".to_string();
+        let mut opponent = Mnstr::new("opponent".to_string(), None, None, "qr-3".to_string());
+        opponent.id = "opponent-mnstr".to_string();
+
+        assert!(battle.choose_mnstr(false, &opponent).is_ok());
+        let error = battle.choose_mnstr(true, &fainted).unwrap_err();
+        assert_eq!(error.to_string(), FAINTED_MNSTR_ERROR);
+        // The challenger still has to pick, so the battle can't start yet
+        assert!(battle.needs_mnstr("challenger"));
+        assert!(!battle.mnstrs_chosen());
+
+        assert!(battle.choose_mnstr(true, &healthy).is_ok());
+        assert_eq!(battle.challenger_mnstr_id.as_deref(), Some("healthy"));
+        assert!(battle.mnstrs_chosen());
+    }
+
+    #[test]
+    fn test_players_only_choose_for_their_own_side() {
+        let now = OffsetDateTime::now_utc();
+        let mut battle = Battle::new(
+            "challenger".to_string(),
+            "Challenger".to_string(),
+            "opponent".to_string(),
+            "Opponent".to_string(),
+        );
+        battle.id = "battle".to_string();
+        let mnstr = |id: &str, user_id: &str| {
+            let mut mnstr = Mnstr::new(user_id.to_string(), None, None, format!("qr-{}", id));
+            mnstr.id = id.to_string();
+            mnstr
+        };
+        let engagements = vec![
+            MnstrEngagement {
+                battle_id: "battle".to_string(),
+                mnstr_id: "mine".to_string(),
+                ended_at: None,
+            },
+            MnstrEngagement {
+                battle_id: "other".to_string(),
+                mnstr_id: "busy".to_string(),
+                ended_at: None,
+            },
+        ];
+
+        // The challenger can't pick the opponent's side, even with the
+        // opponent's own mnstr
+        let theirs = mnstr("theirs", "opponent");
+        assert!(
+            battle
+                .choose_mnstr_for("challenger", &theirs, &engagements, now)
+                .is_err()
+        );
+        assert!(
+            battle
+                .choose_mnstr_for("intruder", &theirs, &engagements, now)
+                .is_err()
+        );
+        assert!(battle.opponent_mnstr_id.is_none());
+
+        let mut released = mnstr("released", "challenger");
+        released.archived_at = Some(now);
+        assert!(
+            battle
+                .choose_mnstr_for("challenger", &released, &engagements, now)
+                .is_err()
+        );
+        let busy = mnstr("busy", "challenger");
+        assert!(
+            battle
+                .choose_mnstr_for("challenger", &busy, &engagements, now)
+                .is_err()
+        );
+        assert!(battle.challenger_mnstr_id.is_none());
+
+        // Being in this battle already doesn't count against the mnstr
+        let mine = mnstr("mine", "challenger");
+        assert!(
+            battle
+                .choose_mnstr_for("challenger", &mine, &engagements, now)
+                .is_ok()
+        );
+        assert!(
+            battle
+                .choose_mnstr_for("opponent", &theirs, &engagements, now)
+                .is_ok()
+        );
+        assert_eq!(battle.challenger_mnstr_id.as_deref(), Some("mine"));
+        assert_eq!(battle.opponent_mnstr_id.as_deref(), Some("theirs"));
+    }
+
+    #[test]
+    fn test_settled_battle_is_not_rejoinable_but_is_in_history() {
+        let mut battle = Battle::new(
+            "challenger".to_string(),
+            "Challenger".to_string(),
+            "opponent".to_string(),
+            "Opponent".to_string(),
+        );
+        assert!(battle.can_rejoin("challenger"));
+        assert!(!battle.can_rejoin("intruder"));
+        assert!(battle.result_for("challenger").is_none());
+
+        battle.winner_id = Some("challenger".to_string());
+        battle.archived_at = Some(OffsetDateTime::now_utc());
+        assert!(!battle.can_rejoin("challenger"));
+        assert!(!battle.can_rejoin("opponent"));
+
+        let result = battle
+            .result_for("opponent")
+            .expect("archived battle in history");
+        assert!(!result.won);
+        assert_eq!(result.opponent_id, "challenger");
+        assert!(battle.can_view_replay(None));
+    }
+
+    async fn create_test_user() -> Result<User, anyhow::Error> {
+        let name = Uuid::new_v4().to_string();
+        let mut user = User::new(
+            Some(format!("{}@example.com", name)),
+            None,
+            "password".to_string(),
+            name,
+        );
+        if let Some(error) = user.create().await {
+            return Err(error);
+        }
+        Ok(user)
+    }
+
+    async fn create_test_battle(
+        challenger: &User,
+        opponent: &User,
+    ) -> Result<Battle, anyhow::Error> {
+        let params = vec![
+            ("challenger_id", challenger.id.clone().into()),
+            ("challenger_name", challenger.display_name.clone().into()),
+            ("opponent_id", opponent.id.clone().into()),
+            ("opponent_name", opponent.display_name.clone().into()),
+        ];
+        Ok(insert_resource!(Battle, params).await?)
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a migrated database at DATABASE_URL"]
+    async fn test_only_running_battles_of_others_are_spectatable() {
+        rolled_back(async {
+            let viewer = create_test_user().await?;
+            let mut users = Vec::new();
+            for _ in 0..8 {
+                users.push(create_test_user().await?);
+            }
+
+            let running = create_test_battle(&users[0], &users[1]).await?;
+            let own = create_test_battle(&viewer, &users[2]).await?;
+            let won = create_test_battle(&users[2], &users[3]).await?;
+            update_resource!(
+                Battle,
+                won.id.clone(),
+                vec![("winner_id", users[2].id.clone().into())]
+            )
+            .await?;
+            let drawn = create_test_battle(&users[3], &users[4]).await?;
+            update_resource!(
+                Battle,
+                drawn.id.clone(),
+                vec![("outcome", DRAW_OUTCOME.into())]
+            )
+            .await?;
+            let archived = create_test_battle(&users[4], &users[5]).await?;
+            delete_resource_where_fields!(Battle, vec![("id", archived.id.clone().into())]).await?;
+            let blocking = create_test_battle(&users[5], &users[6]).await?;
+            let mut block = Block::new(viewer.id.clone(), users[6].id.clone());
+            if let Some(error) = block.create().await {
+                return Err(error);
+            }
+            let blocked = create_test_battle(&users[7], &users[0]).await?;
+            let mut block = Block::new(users[7].id.clone(), viewer.id.clone());
+            if let Some(error) = block.create().await {
+                return Err(error);
+            }
+
+            let ours = [&running, &own, &won, &drawn, &archived, &blocking, &blocked]
+                .iter()
+                .map(|battle| battle.id.clone())
+                .collect::<Vec<String>>();
+            let spectatable = Battle::find_spectatable(viewer.id.clone(), 100, 0)
+                .await?
+                .into_iter()
+                .filter(|battle| ours.contains(&battle.battle_id))
+                .collect::<Vec<SpectatableBattle>>();
+            assert_eq!(spectatable.len(), 1);
+            assert_eq!(spectatable[0].battle_id, running.id);
+            assert_eq!(spectatable[0].challenger.user_id, users[0].id);
+            assert_eq!(spectatable[0].opponent.display_name, users[1].display_name);
+            Ok(())
+        })
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a migrated database at DATABASE_URL"]
+    async fn test_mnstr_record_counts_settled_battles() {
+        rolled_back(async {
+            let user = create_test_user().await?;
+            let other = create_test_user().await?;
+            let mut mnstrs = Vec::new();
+            for owner in [&user, &other, &other] {
+                let mut mnstr =
+                    Mnstr::new(owner.id.clone(), None, None, Uuid::new_v4().to_string());
+                if let Some(error) = mnstr.create().await {
+                    return Err(error);
+                }
+                mnstrs.push(mnstr.id);
+            }
+            let (mnstr, rival, bystander) = (&mnstrs[0], &mnstrs[1], &mnstrs[2]);
+
+            let battle = |challenger_mnstr_id: &String,
+                          opponent_mnstr_id: &String,
+                          winner: Option<&String>,
+                          archived: bool| {
+                let (user, other) = (user.clone(), other.clone());
+                let params = vec![
+                    ("challenger_mnstr_id", challenger_mnstr_id.clone().into()),
+                    ("opponent_mnstr_id", opponent_mnstr_id.clone().into()),
+                    ("winner_mnstr_id", winner.cloned().into()),
+                ];
+                async move {
+                    let battle = create_test_battle(&user, &other).await?;
+                    update_resource!(Battle, battle.id.clone(), params).await?;
+                    if archived {
+                        delete_resource_where_fields!(Battle, vec![("id", battle.id.into())])
+                            .await?;
+                    }
+                    Ok::<(), anyhow::Error>(())
+                }
+            };
+            battle(mnstr, rival, Some(mnstr), true).await?;
+            battle(rival, mnstr, Some(mnstr), true).await?;
+            battle(mnstr, rival, Some(rival), true).await?;
+            battle(mnstr, bystander, Some(mnstr), true).await?;
+            // Not the mnstr's battle
+            battle(rival, bystander, Some(rival), true).await?;
+            // Not settled yet, or settled without a winning mnstr
+            battle(mnstr, rival, None, false).await?;
+            battle(mnstr, rival, Some(mnstr), false).await?;
+            battle(mnstr, rival, None, true).await?;
+
+            assert_eq!(
+                Battle::find_mnstr_record(mnstr.clone()).await?,
+                MnstrRecord {
+                    mnstr_id: mnstr.clone(),
+                    wins: 3,
+                    losses: 1,
+                }
+            );
+            let rival = Battle::find_mnstr_record(rival.clone()).await?;
+            assert_eq!((rival.wins, rival.losses), (2, 2));
+            Ok(())
+        })
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a migrated database at DATABASE_URL"]
+    async fn test_last_result_is_the_latest_settled_battle() {
+        rolled_back(async {
+            let user = create_test_user().await?;
+            let other = create_test_user().await?;
+            assert!(Battle::find_last_result(user.id.clone()).await?.is_none());
+
+            let now = OffsetDateTime::now_utc();
+            let settle = |params: Vec<(&'static str, DatabaseValue)>,
+                          archived_at: OffsetDateTime| {
+                let (user, other) = (user.clone(), other.clone());
+                async move {
+                    let battle = create_test_battle(&user, &other).await?;
+                    let mut params = params;
+                    params.push(("archived_at", archived_at.into()));
+                    Ok::<Battle, anyhow::Error>(update_resource!(Battle, battle.id, params).await?)
+                }
+            };
+            settle(
+                vec![("winner_id", user.id.clone().into())],
+                now - Duration::hours(3),
+            )
+            .await?;
+            let drawn = settle(
+                vec![("outcome", DRAW_OUTCOME.into())],
+                now - Duration::hours(2),
+            )
+            .await?;
+            // Abandoned without a winner, so it has no result
+            settle(vec![], now - Duration::hours(1)).await?;
+            create_test_battle(&user, &other).await?;
+
+            let result = Battle::find_last_result(other.id.clone()).await?.unwrap();
+            assert_eq!(result.battle_id, drawn.id);
+            assert_eq!(result.opponent_id, user.id);
+            assert!(!result.won);
+            Ok(())
+        })
+        .await
+        .unwrap();
+    }
+}
@@ -1,12 +1,19 @@
+use juniper::GraphQLObject;
 use rocket::serde;
 use serde::{Deserialize, Serialize};
 use sqlx::{Error, Row, postgres::PgRow};
 use time::OffsetDateTime;
 
 use crate::{
-    database::{traits::DatabaseResource, values::DatabaseValue},
-    find_all_resources_where_fields, find_one_resource_where_fields, insert_resource,
+    database::{
+        connection::fetch_one,
+        traits::{DatabaseResource, OrderDirection},
+        values::DatabaseValue,
+    },
+    find_all_resources_where_fields, find_all_resources_where_fields_ordered,
+    find_one_resource_where_fields, insert_resource,
     utils::time::{deserialize_offset_date_time, serialize_offset_date_time},
+    websocket::battle_queue::models::BattleLogData,
 };
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -20,6 +27,7 @@ pub enum BattleLogAction {
     Killed,
     Won,
     Lost,
+    Surrendered,
     Error,
 }
 
@@ -34,6 +42,7 @@ impl std::fmt::Display for BattleLogAction {
             BattleLogAction::Killed => write!(f, "killed"),
             BattleLogAction::Won => write!(f, "won"),
             BattleLogAction::Lost => write!(f, "lost"),
+            BattleLogAction::Surrendered => write!(f, "surrendered"),
             BattleLogAction::Error => write!(f, "error"),
         }
     }
@@ -50,6 +59,7 @@ impl From<String> for BattleLogAction {
             "killed" => BattleLogAction::Killed,
             "won" => BattleLogAction::Won,
             "lost" => BattleLogAction::Lost,
+            "surrendered" => BattleLogAction::Surrendered,
             "error" => BattleLogAction::Error,
             _ => BattleLogAction::Joined,
         }
@@ -73,6 +83,21 @@ pub struct BattleLog {
     pub created_at: Option<OffsetDateTime>,
 }
 
+/// A battle log with its `data` payload unpacked, as served by `battleReplay`.
+#[derive(Debug, Clone, GraphQLObject)]
+pub struct BattleReplayEntry {
+    pub id: String,
+    pub user_id: String,
+    pub mnstr_id: String,
+    pub action: String,
+    pub missed: Option<bool>,
+    pub hit: Option<bool>,
+    pub damage: Option<i32>,
+    pub defense: Option<i32>,
+    pub crit: Option<bool>,
+    pub created_at: Option<OffsetDateTime>,
+}
+
 impl BattleLog {
     pub fn new(
         battle_id: String,
@@ -143,6 +168,68 @@ impl BattleLog {
         };
         Ok(battle_logs)
     }
+
+    /// Counts the battle's logs, which is also the number of turns played.
+    pub async fn count_for_battle(battle_id: String) -> Result<i64, anyhow::Error> {
+        let query = sqlx::query("SELECT COUNT(*) AS count FROM battle_logs WHERE battle_id = $1")
+            .bind(&battle_id);
+        match fetch_one("battle_logs", "count_for_battle", query).await {
+            Ok(row) => Ok(row.get::<i64, _>("count")),
+            Err(e) => {
+                println!(
+                    "[BattleLog::count_for_battle] Failed to count battle logs: {:?}",
+                    e
+                );
+                Err(anyhow::Error::msg(e.to_string()))
+            }
+        }
+    }
+
+    /// Every log of the battle, oldest first, ready to be replayed.
+    pub async fn find_replay(battle_id: String) -> Result<Vec<BattleReplayEntry>, anyhow::Error> {
+        let battle_logs = match find_all_resources_where_fields_ordered!(
+            BattleLog,
+            vec![("battle_id", battle_id.clone().into())],
+            "created_at",
+            OrderDirection::Asc
+        )
+        .await
+        {
+            Ok(battle_logs) => battle_logs,
+            Err(e) => {
+                println!(
+                    "[BattleLog::find_replay] Failed to get battle logs: {:?}",
+                    e
+                );
+                return Err(e.into());
+            }
+        };
+        Ok(replay_entries(battle_logs))
+    }
+
+    /// Unpacks `data`. Logs written without a payload (surrenders, joins) or
+    /// with one that no longer parses come back with every field unset.
+    pub fn replay_entry(&self) -> BattleReplayEntry {
+        let data = serde_json::from_str::<BattleLogData>(&self.data).ok();
+        BattleReplayEntry {
+            id: self.id.clone(),
+            user_id: self.user_id.clone(),
+            mnstr_id: self.mnstr_id.clone(),
+            action: self.action.to_string(),
+            missed: data.as_ref().and_then(|data| data.missed),
+            hit: data.as_ref().and_then(|data| data.hit),
+            damage: data.as_ref().and_then(|data| data.damage),
+            defense: data.as_ref().and_then(|data| data.defense),
+            crit: data.as_ref().and_then(|data| data.crit),
+            created_at: self.created_at,
+        }
+    }
+}
+
+/// Logs written in the same instant keep the order they were fetched in.
+pub fn replay_entries(mut battle_logs: Vec<BattleLog>) -> Vec<BattleReplayEntry> {
+    battle_logs.sort_by(|a, b| a.created_at.cmp(&b.created_at));
+    battle_logs.iter().map(BattleLog::replay_entry).collect()
 }
 
 impl DatabaseResource for BattleLog {
@@ -183,3 +270,60 @@ impl DatabaseResource for BattleLog {
         false
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::Duration;
+
+    #[test]
+    fn test_replay_entries_are_chronological_with_parsed_data() {
+        let start = OffsetDateTime::now_utc();
+        let log = |id: &str, action: BattleLogAction, data: &str, seconds: i64| {
+            let mut log = BattleLog::new(
+                "battle".to_string(),
+                "user".to_string(),
+                "mnstr".to_string(),
+                action,
+                data.to_string(),
+            );
+            log.id = id.to_string();
+            log.created_at = Some(start + Duration::seconds(seconds));
+            log
+        };
+        let battle_logs = vec![
+            log("surrender", BattleLogAction::Surrendered, "", 3),
+            log(
+                "hit",
+                BattleLogAction::Hit,
+                r#"{"missed":false,"hit":true,"damage":12,"defense":null}"#,
+                2,
+            ),
+            log(
+                "miss",
+                BattleLogAction::Missed,
+                r#"{"missed":true,"hit":false,"damage":null,"defense":null}"#,
+                1,
+            ),
+        ];
+
+        let entries = replay_entries(battle_logs);
+        let ids = entries
+            .iter()
+            .map(|entry| entry.id.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(ids, vec!["miss", "hit", "surrender"]);
+
+        assert_eq!(entries[0].action, "missed");
+        assert_eq!(entries[0].missed, Some(true));
+        assert_eq!(entries[0].damage, None);
+
+        assert_eq!(entries[1].action, "hit");
+        assert_eq!(entries[1].hit, Some(true));
+        assert_eq!(entries[1].damage, Some(12));
+
+        assert_eq!(entries[2].action, "surrendered");
+        assert_eq!(entries[2].missed, None);
+        assert_eq!(entries[2].hit, None);
+    }
+}
@@ -1,11 +1,14 @@
 use serde::{Deserialize, Serialize};
 use sqlx::{Error, Row, postgres::PgRow};
-use time::OffsetDateTime;
+use time::{Duration, OffsetDateTime};
+use uuid::Uuid;
 
 use crate::{
-    database::{traits::DatabaseResource, values::DatabaseValue},
-    delete_resource_where_fields, find_all_resources_where_fields, find_one_resource_where_fields,
-    insert_resource, update_resource,
+    count_resources_where_fields,
+    database::{connection::fetch_one, traits::DatabaseResource, values::DatabaseValue},
+    delete_resource_where_fields, find_all_resources_where_fields,
+    find_all_resources_where_fields_in, find_one_resource_where_fields,
+    find_optional_resource_where_fields, update_resource,
     utils::time::{deserialize_offset_date_time, serialize_offset_date_time},
 };
 
@@ -38,6 +41,19 @@ impl From<String> for BattleStatusState {
     }
 }
 
+/// Used when `MAX_QUEUE_DURATION_SECONDS` isn't set.
+pub const DEFAULT_MAX_QUEUE_DURATION_SECONDS: i64 = 600;
+
+/// How long a player may sit in the queue without being matched or changing
+/// their status before they are removed from it.
+pub fn max_queue_duration() -> Duration {
+    let seconds = std::env::var("MAX_QUEUE_DURATION_SECONDS")
+        .ok()
+        .and_then(|seconds| seconds.parse().ok())
+        .unwrap_or(DEFAULT_MAX_QUEUE_DURATION_SECONDS);
+    Duration::seconds(seconds)
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct BattleStatus {
@@ -48,6 +64,7 @@ pub struct BattleStatus {
     pub opponent_name: Option<String>,
     pub battle_id: Option<String>,
     pub status: BattleStatusState,
+    pub ready: bool,
 
     #[serde(
         serialize_with = "serialize_offset_date_time",
@@ -79,22 +96,39 @@ impl BattleStatus {
             opponent_name,
             battle_id,
             status,
+            ready: false,
             created_at: None,
             updated_at: None,
         }
     }
 
     pub async fn create(&mut self) -> Option<anyhow::Error> {
-        let params = vec![
-            ("user_id", self.user_id.clone().into()),
-            ("display_name", self.display_name.clone().into()),
-            ("opponent_id", self.opponent_id.clone().into()),
-            ("opponent_name", self.opponent_name.clone().into()),
-            ("battle_id", self.battle_id.clone().into()),
-            ("status", self.status.clone().to_string().into()),
-            ("updated_at", self.updated_at.clone().into()),
-        ];
-        let battle_status = match insert_resource!(BattleStatus, params).await {
+        // A user only ever has one status; a stale row from a previous
+        // connection is reused rather than duplicated, even when two
+        // connections create one at the same time.
+        let query = sqlx::query(
+            "INSERT INTO battle_statuses \
+             (id, user_id, display_name, opponent_id, opponent_name, battle_id, status, ready) \
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8) \
+             ON CONFLICT (user_id) DO UPDATE SET display_name = EXCLUDED.display_name, \
+             opponent_id = EXCLUDED.opponent_id, opponent_name = EXCLUDED.opponent_name, \
+             battle_id = EXCLUDED.battle_id, status = EXCLUDED.status, ready = EXCLUDED.ready, \
+             updated_at = CURRENT_TIMESTAMP \
+             RETURNING *",
+        )
+        .bind(Uuid::new_v4().to_string())
+        .bind(&self.user_id)
+        .bind(&self.display_name)
+        .bind(&self.opponent_id)
+        .bind(&self.opponent_name)
+        .bind(&self.battle_id)
+        .bind(self.status.to_string())
+        .bind(self.ready);
+        let row = match fetch_one("battle_statuses", "create", query).await {
+            Ok(row) => row,
+            Err(e) => return Some(e.into()),
+        };
+        let battle_status = match BattleStatus::from_row(&row) {
             Ok(battle_status) => battle_status,
             Err(e) => return Some(e.into()),
         };
@@ -104,10 +138,12 @@ impl BattleStatus {
 
     pub async fn update(&mut self) -> Option<anyhow::Error> {
         let params = vec![
+            ("display_name", self.display_name.clone().into()),
             ("opponent_id", self.opponent_id.clone().into()),
             ("opponent_name", self.opponent_name.clone().into()),
             ("battle_id", self.battle_id.clone().into()),
             ("status", self.status.clone().to_string().into()),
+            ("ready", self.ready.into()),
         ];
         let battle_status = match update_resource!(BattleStatus, self.id.clone(), params).await {
             Ok(battle_status) => battle_status,
@@ -117,6 +153,35 @@ impl BattleStatus {
         None
     }
 
+    /// Players can only be paired while both are queued and have readied up.
+    pub fn can_match(&self, other: &BattleStatus) -> bool {
+        matches!(self.status, BattleStatusState::InQueue)
+            && matches!(other.status, BattleStatusState::InQueue)
+            && self.ready
+            && other.ready
+    }
+
+    /// A challenge can be withdrawn until it has been accepted and the battle started.
+    pub fn can_cancel_challenge(&self) -> bool {
+        matches!(self.status, BattleStatusState::InQueue) && self.battle_id.is_none()
+    }
+
+    /// Queued with an open challenge to `user_id` that hasn't been accepted yet.
+    pub fn has_challenged(&self, user_id: &str) -> bool {
+        self.can_cancel_challenge() && self.opponent_id.as_deref() == Some(user_id)
+    }
+
+    /// Still queued and unmatched, with no change for longer than `max_duration`.
+    pub fn is_idle_in_queue(&self, now: OffsetDateTime, max_duration: Duration) -> bool {
+        if !matches!(self.status, BattleStatusState::InQueue) || self.battle_id.is_some() {
+            return false;
+        }
+        match self.updated_at {
+            Some(updated_at) => now - updated_at > max_duration,
+            None => false,
+        }
+    }
+
     pub async fn delete(&mut self) -> Option<anyhow::Error> {
         let params = vec![("id", self.id.clone().into())];
         match delete_resource_where_fields!(BattleStatus, params).await {
@@ -145,6 +210,23 @@ impl BattleStatus {
         Ok(battle_status)
     }
 
+    pub async fn find_optional_by(
+        params: Vec<(&str, DatabaseValue)>,
+    ) -> Result<Option<Self>, anyhow::Error> {
+        match find_optional_resource_where_fields!(BattleStatus, params).await {
+            Ok(battle_status) => Ok(battle_status),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// The statuses of whichever of `user_ids` are connected to the queue.
+    pub async fn find_all_by_user_ids(user_ids: Vec<String>) -> Result<Vec<Self>, anyhow::Error> {
+        match find_all_resources_where_fields_in!(BattleStatus, "user_id", user_ids).await {
+            Ok(battle_statuses) => Ok(battle_statuses),
+            Err(e) => Err(e.into()),
+        }
+    }
+
     pub async fn find_all() -> Result<Vec<Self>, anyhow::Error> {
         let battle_statuses = match find_all_resources_where_fields!(BattleStatus, vec![]).await {
             Ok(battle_statuses) => battle_statuses,
@@ -162,6 +244,15 @@ impl BattleStatus {
         };
         Ok(battle_statuses)
     }
+
+    /// How many players are waiting in the battle queue.
+    pub async fn count_in_queue() -> Result<i64, anyhow::Error> {
+        let params = vec![("status", BattleStatusState::InQueue.to_string().into())];
+        match count_resources_where_fields!(BattleStatus, params).await {
+            Ok(count) => Ok(count),
+            Err(e) => Err(e.into()),
+        }
+    }
 }
 
 impl DatabaseResource for BattleStatus {
@@ -177,6 +268,7 @@ impl DatabaseResource for BattleStatus {
             opponent_name: row.get("opponent_name"),
             battle_id: row.get("battle_id"),
             status: row.get::<String, _>("status").into(),
+            ready: row.get("ready"),
             created_at: Some(created_at),
             updated_at: Some(updated_at),
         })
@@ -201,3 +293,167 @@ impl DatabaseResource for BattleStatus {
         false
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{database::connection::rolled_back, models::user::User};
+
+    fn queued(user_id: &str, ready: bool) -> BattleStatus {
+        let mut status = BattleStatus::new(
+            user_id.to_string(),
+            user_id.to_string(),
+            None,
+            None,
+            None,
+            BattleStatusState::InQueue,
+        );
+        status.ready = ready;
+        status
+    }
+
+    #[test]
+    fn test_can_match() {
+        assert!(queued("a", true).can_match(&queued("b", true)));
+        assert!(!queued("a", true).can_match(&queued("b", false)));
+        assert!(!queued("a", false).can_match(&queued("b", true)));
+
+        let mut in_battle = queued("b", true);
+        in_battle.status = BattleStatusState::InBattle;
+        assert!(!queued("a", true).can_match(&in_battle));
+    }
+
+    #[test]
+    fn test_can_cancel_challenge() {
+        let mut status = queued("a", true);
+        status.opponent_id = Some("b".to_string());
+        assert!(status.can_cancel_challenge());
+
+        status.status = BattleStatusState::InBattle;
+        status.battle_id = Some("battle".to_string());
+        assert!(!status.can_cancel_challenge());
+    }
+
+    #[test]
+    fn test_has_challenged() {
+        let mut status = queued("a", true);
+        assert!(!status.has_challenged("b"));
+
+        status.opponent_id = Some("b".to_string());
+        assert!(status.has_challenged("b"));
+        assert!(!status.has_challenged("c"));
+
+        status.status = BattleStatusState::InBattle;
+        status.battle_id = Some("battle".to_string());
+        assert!(!status.has_challenged("b"));
+    }
+
+    #[test]
+    fn test_is_idle_in_queue() {
+        let now = OffsetDateTime::now_utc();
+        let max_duration = Duration::seconds(DEFAULT_MAX_QUEUE_DURATION_SECONDS);
+
+        let mut status = queued("idle", false);
+        assert!(!status.is_idle_in_queue(now, max_duration));
+
+        status.updated_at = Some(now - max_duration + Duration::seconds(1));
+        assert!(!status.is_idle_in_queue(now, max_duration));
+
+        status.updated_at = Some(now - max_duration - Duration::seconds(1));
+        assert!(status.is_idle_in_queue(now, max_duration));
+
+        status.battle_id = Some("battle".to_string());
+        assert!(!status.is_idle_in_queue(now, max_duration));
+
+        status.battle_id = None;
+        status.status = BattleStatusState::InBattle;
+        assert!(!status.is_idle_in_queue(now, max_duration));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a migrated database at DATABASE_URL"]
+    async fn test_creating_again_reuses_the_users_status() {
+        rolled_back(async {
+            let name = Uuid::new_v4().to_string();
+            let mut user = User::new(
+                Some(format!("{}@example.com", name)),
+                None,
+                "password".to_string(),
+                name.clone(),
+            );
+            if let Some(error) = user.create().await {
+                return Err(error);
+            }
+
+            let mut first = queued(&user.id, true);
+            first.display_name = name.clone();
+            if let Some(error) = first.create().await {
+                return Err(error);
+            }
+
+            let mut second = BattleStatus::new(
+                user.id.clone(),
+                name,
+                None,
+                None,
+                None,
+                BattleStatusState::Watching,
+            );
+            if let Some(error) = second.create().await {
+                return Err(error);
+            }
+            assert_eq!(second.id, first.id);
+            assert!(matches!(second.status, BattleStatusState::Watching));
+            assert!(!second.ready);
+
+            let statuses =
+                BattleStatus::find_all_by(vec![("user_id", user.id.clone().into())]).await?;
+            assert_eq!(statuses.len(), 1);
+            Ok(())
+        })
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a migrated database at DATABASE_URL"]
+    async fn test_find_all_by_user_ids() {
+        rolled_back(async {
+            assert!(BattleStatus::find_all_by_user_ids(vec![]).await?.is_empty());
+
+            let mut user_ids = Vec::new();
+            for _ in 0..2 {
+                let name = Uuid::new_v4().to_string();
+                let mut user = User::new(
+                    Some(format!("{}@example.com", name)),
+                    None,
+                    "password".to_string(),
+                    name,
+                );
+                if let Some(error) = user.create().await {
+                    return Err(error);
+                }
+                if let Some(error) = queued(&user.id, true).create().await {
+                    return Err(error);
+                }
+                user_ids.push(user.id);
+            }
+
+            let mut found = BattleStatus::find_all_by_user_ids(vec![
+                user_ids[0].clone(),
+                user_ids[1].clone(),
+                Uuid::new_v4().to_string(),
+            ])
+            .await?
+            .into_iter()
+            .map(|status| status.user_id)
+            .collect::<Vec<String>>();
+            found.sort();
+            user_ids.sort();
+            assert_eq!(found, user_ids);
+            Ok(())
+        })
+        .await
+        .unwrap();
+    }
+}
@@ -0,0 +1,175 @@
+use juniper::GraphQLObject;
+use serde::{Deserialize, Serialize};
+use sqlx::{Error, Row, postgres::PgRow};
+use time::OffsetDateTime;
+
+use crate::{
+    database::{traits::DatabaseResource, values::DatabaseValue},
+    delete_resource_where_fields, find_all_resources_where_fields, insert_resource,
+    utils::time::{deserialize_offset_date_time, serialize_offset_date_time},
+};
+
+/// One user blocking another. Blocks work both ways: neither user can match
+/// with or challenge the other.
+#[derive(Debug, Serialize, Deserialize, GraphQLObject, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Block {
+    pub id: String,
+    pub blocker_id: String,
+    pub blocked_id: String,
+
+    #[serde(
+        serialize_with = "serialize_offset_date_time",
+        deserialize_with = "deserialize_offset_date_time"
+    )]
+    pub created_at: Option<OffsetDateTime>,
+}
+
+impl Block {
+    pub fn new(blocker_id: String, blocked_id: String) -> Self {
+        Self {
+            id: "".to_string(),
+            blocker_id,
+            blocked_id,
+            created_at: None,
+        }
+    }
+
+    pub async fn create(&mut self) -> Option<anyhow::Error> {
+        if self.blocker_id == self.blocked_id {
+            return Some(anyhow::anyhow!("Cannot block yourself"));
+        }
+
+        let params = vec![
+            ("blocker_id", self.blocker_id.clone().into()),
+            ("blocked_id", self.blocked_id.clone().into()),
+        ];
+        let block = match insert_resource!(Block, params).await {
+            Ok(block) => block,
+            Err(e) => {
+                println!("[Block::create] Failed to create block: {:?}", e);
+                return Some(e.into());
+            }
+        };
+        *self = block;
+        None
+    }
+
+    pub async fn delete(&mut self) -> Option<anyhow::Error> {
+        let params = vec![
+            ("blocker_id", self.blocker_id.clone().into()),
+            ("blocked_id", self.blocked_id.clone().into()),
+        ];
+        match delete_resource_where_fields!(Block, params).await {
+            Ok(_) => (),
+            Err(e) => {
+                println!("[Block::delete] Failed to delete block: {:?}", e);
+                return Some(e.into());
+            }
+        };
+        None
+    }
+
+    pub async fn find_all_by(
+        params: Vec<(&str, DatabaseValue)>,
+    ) -> Result<Vec<Self>, anyhow::Error> {
+        let blocks = match find_all_resources_where_fields!(Block, params).await {
+            Ok(blocks) => blocks,
+            Err(e) => {
+                println!("[Block::find_all_by] Failed to get blocks: {:?}", e);
+                return Err(e.into());
+            }
+        };
+        Ok(blocks)
+    }
+
+    /// Every block the user made or is the target of.
+    pub async fn find_all_involving(user_id: String) -> Result<Vec<Self>, anyhow::Error> {
+        let mut blocks = Vec::new();
+        for field in ["blocker_id", "blocked_id"] {
+            blocks.extend(Self::find_all_by(vec![(field, user_id.clone().into())]).await?);
+        }
+        Ok(blocks)
+    }
+
+    /// The users `user_id` must not be paired with, whoever made the block.
+    pub async fn blocked_user_ids(user_id: String) -> Result<Vec<String>, anyhow::Error> {
+        let blocks = Self::find_all_involving(user_id.clone()).await?;
+        Ok(blocks
+            .iter()
+            .filter_map(|block| block.other_user_id(&user_id))
+            .collect())
+    }
+
+    pub async fn is_blocked_between(
+        user_id: String,
+        other_id: String,
+    ) -> Result<bool, anyhow::Error> {
+        let blocks = Self::find_all_involving(user_id.clone()).await?;
+        Ok(blocks
+            .iter()
+            .any(|block| block.separates(&user_id, &other_id)))
+    }
+
+    /// Whether this block stands between the two users, in either direction.
+    pub fn separates(&self, user_id: &str, other_id: &str) -> bool {
+        (self.blocker_id == user_id && self.blocked_id == other_id)
+            || (self.blocker_id == other_id && self.blocked_id == user_id)
+    }
+
+    fn other_user_id(&self, user_id: &str) -> Option<String> {
+        if self.blocker_id == user_id {
+            Some(self.blocked_id.clone())
+        } else if self.blocked_id == user_id {
+            Some(self.blocker_id.clone())
+        } else {
+            None
+        }
+    }
+}
+
+impl DatabaseResource for Block {
+    fn from_row(row: &PgRow) -> Result<Self, Error> {
+        Ok(Block {
+            id: row.get("id"),
+            blocker_id: row.get("blocker_id"),
+            blocked_id: row.get("blocked_id"),
+            created_at: row.get("created_at"),
+        })
+    }
+    fn has_id() -> bool {
+        true
+    }
+    fn is_archivable() -> bool {
+        false
+    }
+    fn is_updatable() -> bool {
+        false
+    }
+    fn is_creatable() -> bool {
+        true
+    }
+    fn is_expirable() -> bool {
+        false
+    }
+    fn is_verifiable() -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_block_separates_both_ways() {
+        let block = Block::new("blocker".to_string(), "blocked".to_string());
+        assert!(block.separates("blocker", "blocked"));
+        assert!(block.separates("blocked", "blocker"));
+        assert!(!block.separates("blocker", "someone"));
+
+        assert_eq!(block.other_user_id("blocker").as_deref(), Some("blocked"));
+        assert_eq!(block.other_user_id("blocked").as_deref(), Some("blocker"));
+        assert_eq!(block.other_user_id("someone"), None);
+    }
+}
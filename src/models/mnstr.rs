@@ -5,14 +5,31 @@ use sqlx::{Error, Row, postgres::PgRow};
 use time::OffsetDateTime;
 
 use crate::{
-    database::{traits::DatabaseResource, values::DatabaseValue},
+    database::{
+        connection::{fetch_all, fetch_one, fetch_optional, transaction},
+        traits::{ArchivedFilter, DatabaseResource, in_clause, order_by_clause},
+        values::DatabaseValue,
+    },
     delete_resource_where_fields, find_all_resources_where_fields,
-    find_all_resources_where_fields_in, find_one_resource_where_fields,
-    insert_resource, insert_resource_batch,
-    models::{generated::mnstr_xp::XP_FOR_LEVEL, user::User},
+    find_all_resources_where_fields_in, find_all_resources_where_fields_paginated,
+    find_all_unarchived_resources_where_fields, find_one_resource_where_fields,
+    find_optional_resource_where_fields, find_page_after, find_page_ordered,
+    graphql::pagination::PageRequest,
+    insert_resource, insert_resource_batch, lock_resources_where_fields,
+    models::{
+        battle::{Battle, MnstrEngagement},
+        generated::mnstr_xp::XP_FOR_LEVEL,
+        transaction::{Transaction, TransactionType},
+        user::User,
+        wallet::Wallet,
+        xp::{xp_for_level, xp_to_next_level},
+    },
     proto::{Mnstr as GrpcMnstr, MnstrOrderBy as GrpcMnstrOrderBy },
-    update_resource, update_resource_batch,
-    utils::time::{deserialize_offset_date_time, serialize_offset_date_time},
+    update_resource, update_resource_batch, update_resource_fields,
+    utils::{
+        multipliers::award_xp,
+        time::{deserialize_offset_date_time, serialize_offset_date_time},
+    },
 };
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, GraphQLEnum, Serialize, Deserialize)]
@@ -192,12 +209,263 @@ pub struct Mnstr {
     pub max_intelligence: i32,
     pub current_magic: i32,
     pub max_magic: i32,
+    pub stat_points: i32,
 
     pub experience_to_next_level: i32,
+
+    #[serde(default)]
+    pub power_score: i32,
+
+    /// Set by the collection queries for a mnstr fighting in a running
+    /// battle, whose current stats are then shown at rest.
+    #[serde(default)]
+    pub in_battle: bool,
 }
 
 pub const DEFAULT_STAT_VALUE: i32 = 10;
 
+/// Health at or below which a mnstr has fainted and can't battle.
+pub const FAINTED_HEALTH: i32 = 0;
+
+pub const FAINTED_MNSTR_ERROR: &str = "Mnstr has fainted, choose another mnstr";
+
+/// Weights of the power score: points per level and per point of each current stat.
+pub const POWER_LEVEL_WEIGHT: i64 = 10;
+pub const POWER_HEALTH_WEIGHT: i64 = 1;
+pub const POWER_ATTACK_WEIGHT: i64 = 2;
+pub const POWER_DEFENSE_WEIGHT: i64 = 2;
+pub const POWER_SPEED_WEIGHT: i64 = 1;
+pub const POWER_INTELLIGENCE_WEIGHT: i64 = 1;
+pub const POWER_MAGIC_WEIGHT: i64 = 2;
+
+/// Used when `MAX_MNSTRS_PER_USER` isn't set.
+pub const DEFAULT_MAX_MNSTRS_PER_USER: i64 = 100;
+
+pub const USER_NOT_FOUND_ERROR: &str = "User not found";
+
+pub const COLLECTION_FULL_ERROR: &str = "Collection full";
+
+/// The most ids `Mnstr::find_all_by_ids` looks up in one call.
+pub const MAX_MNSTR_IDS: usize = 100;
+
+pub const TOO_MANY_MNSTR_IDS_ERROR: &str = "Too many mnstr ids";
+
+pub fn max_mnstrs_per_user() -> i64 {
+    std::env::var("MAX_MNSTRS_PER_USER")
+        .ok()
+        .and_then(|max| max.parse().ok())
+        .unwrap_or(DEFAULT_MAX_MNSTRS_PER_USER)
+}
+
+/// Returned when adding mnstrs would take a collection past
+/// `max_mnstrs_per_user()`. Callers find it with `downcast_ref`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CollectionFullError;
+
+impl std::fmt::Display for CollectionFullError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", COLLECTION_FULL_ERROR)
+    }
+}
+
+impl std::error::Error for CollectionFullError {}
+
+/// Rejects adding `adding` mnstrs to a collection of `owned` when that would
+/// go past `max`.
+pub fn check_collection_capacity(owned: i64, adding: i64, max: i64) -> Option<anyhow::Error> {
+    if owned + adding > max {
+        return Some(CollectionFullError.into());
+    }
+    None
+}
+
+/// Used when `RESTORE_ALL_COIN_COST` isn't set: restoring every mnstr is free.
+pub const DEFAULT_RESTORE_ALL_COIN_COST: i32 = 0;
+
+pub const NOT_ENOUGH_COINS_ERROR: &str = "Not enough coins";
+
+/// Coins charged once per `restoreAll` that restores at least one mnstr.
+pub fn restore_all_coin_cost() -> i32 {
+    std::env::var("RESTORE_ALL_COIN_COST")
+        .ok()
+        .and_then(|cost| cost.parse::<i32>().ok())
+        .map(|cost| cost.max(0))
+        .unwrap_or(DEFAULT_RESTORE_ALL_COIN_COST)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, GraphQLEnum)]
+pub enum MnstrStat {
+    Health,
+    Attack,
+    Defense,
+    Speed,
+    Intelligence,
+    Magic,
+}
+
+impl MnstrStat {
+    /// The column holding the stat's maximum.
+    pub fn max_column(&self) -> &'static str {
+        match self {
+            MnstrStat::Health => "max_health",
+            MnstrStat::Attack => "max_attack",
+            MnstrStat::Defense => "max_defense",
+            MnstrStat::Speed => "max_speed",
+            MnstrStat::Intelligence => "max_intelligence",
+            MnstrStat::Magic => "max_magic",
+        }
+    }
+}
+
+/// The last level on the mnstr XP curve; nothing can level past it.
+pub const MAX_MNSTR_LEVEL: i32 = XP_FOR_LEVEL.len() as i32 - 1;
+
+/// The level mnstrs stop at, `MAX_MNSTR_LEVEL` unless `MAX_MNSTR_LEVEL` sets a
+/// lower cap.
+pub fn max_mnstr_level() -> i32 {
+    std::env::var("MAX_MNSTR_LEVEL")
+        .ok()
+        .and_then(|max| max.parse::<i32>().ok())
+        .map(|max| max.clamp(0, MAX_MNSTR_LEVEL.max(0)))
+        .unwrap_or(MAX_MNSTR_LEVEL)
+}
+
+/// Stat points added to a mnstr's pool on every level-up.
+pub const STAT_POINTS_PER_LEVEL: i32 = 3;
+
+pub const NOT_ENOUGH_STAT_POINTS_ERROR: &str = "Not enough stat points";
+
+/// Stat ceilings as `(ceiling at level 0, growth per level)`.
+const STAT_CEILINGS: [(MnstrStat, i32, i32); 6] = [
+    (MnstrStat::Health, 30, 10),
+    (MnstrStat::Attack, 20, 5),
+    (MnstrStat::Defense, 20, 5),
+    (MnstrStat::Speed, 20, 5),
+    (MnstrStat::Intelligence, 20, 5),
+    (MnstrStat::Magic, 20, 5),
+];
+
+/// The highest value a stat may hold for a mnstr at `level`.
+pub fn max_allowed_stat(level: i32, stat: MnstrStat) -> i32 {
+    let (_, base, growth) = STAT_CEILINGS
+        .iter()
+        .find(|(s, _, _)| *s == stat)
+        .copied()
+        .unwrap();
+    base + growth * level.max(0)
+}
+
+/// Collects `mnstr` for its user and awards the user's XP and coins in one
+/// database transaction, so a failed award leaves no mnstr behind.
+///
+/// A QR code the user already holds returns the mnstr they collected before,
+/// so retried requests don't create or reward twice. Nothing is inserted for a
+/// user who doesn't exist or has been archived.
+async fn collect(mnstr: &Mnstr) -> Result<Mnstr, anyhow::Error> {
+    transaction(async {
+        // Locking the user holds off archiving and makes their collections
+        // run one after the other
+        let mut user =
+            match lock_resources_where_fields!(User, vec![("id", mnstr.user_id.clone().into())])
+                .await?
+                .pop()
+            {
+                Some(user) => user,
+                None => return Err(anyhow::anyhow!(USER_NOT_FOUND_ERROR)),
+            };
+
+        if !mnstr.mnstr_qr_code.is_empty() {
+            let params = vec![
+                ("user_id", mnstr.user_id.clone().into()),
+                ("mnstr_qr_code", mnstr.mnstr_qr_code.clone().into()),
+                ("archived_at", DatabaseValue::None),
+            ];
+            if let Some(collected) = find_optional_resource_where_fields!(Mnstr, params).await? {
+                return Ok(collected);
+            }
+        }
+
+        if let Some(error) = Mnstr::ensure_collection_capacity(&mnstr.user_id, 1).await {
+            return Err(error);
+        }
+
+        let created = insert_resource!(Mnstr, mnstr.insert_params()).await?;
+        let xp = xp_for_level(&XP_FOR_LEVEL, user.experience_level);
+        user.apply_xp(award_xp(xp, "Mnstr::collect"));
+        update_resource_fields!(
+            User,
+            user.id.clone(),
+            vec![
+                ("experience_level", Some(user.experience_level.into())),
+                ("experience_points", Some(user.experience_points.into())),
+            ]
+        )
+        .await?;
+        if let Some(error) = user.add_coins(created.coins()).await {
+            return Err(error);
+        }
+        Ok(created)
+    })
+    .await
+}
+
+/// Restores every one of the user's mnstrs that isn't fighting in a running
+/// battle and charges `cost` once, all in one database transaction.
+///
+/// Nothing is charged when no mnstr needs restoring.
+async fn restore_all_for(user_id: &str, cost: i32) -> Result<Vec<Mnstr>, anyhow::Error> {
+    transaction(async {
+        let mnstrs =
+            lock_resources_where_fields!(Mnstr, vec![("user_id", user_id.to_string().into())])
+                .await?;
+        let engagements =
+            Battle::find_mnstr_engagements(user_id.to_string(), OffsetDateTime::now_utc()).await?;
+        let restoring = mnstrs
+            .iter()
+            .filter(|mnstr| !mnstr.is_rested())
+            .filter(|mnstr| {
+                !engagements.iter().any(|engagement| {
+                    engagement.mnstr_id == mnstr.id && engagement.ended_at.is_none()
+                })
+            })
+            .map(|mnstr| {
+                vec![
+                    ("id", mnstr.id.clone().into()),
+                    ("current_health", mnstr.max_health.into()),
+                    ("current_attack", mnstr.max_attack.into()),
+                    ("current_defense", mnstr.max_defense.into()),
+                    ("current_speed", mnstr.max_speed.into()),
+                    ("current_intelligence", mnstr.max_intelligence.into()),
+                    ("current_magic", mnstr.max_magic.into()),
+                ]
+            })
+            .collect::<Vec<Vec<(&str, DatabaseValue)>>>();
+        if restoring.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        if cost > 0 {
+            // Concurrent charges to the same wallet wait for this one
+            let wallet = match lock_resources_where_fields!(
+                Wallet,
+                vec![("user_id", user_id.to_string().into())]
+            )
+            .await?
+            .pop()
+            {
+                Some(wallet) => wallet,
+                None => return Err(anyhow::anyhow!("Wallet not found")),
+            };
+            if Wallet::balance_for_user(user_id.to_string()).await? < cost {
+                return Err(anyhow::Error::msg(NOT_ENOUGH_COINS_ERROR));
+            }
+            Transaction::record(wallet.id, TransactionType::Debit, cost, None).await?;
+        }
+        update_resource_batch!(Mnstr, restoring).await
+    })
+    .await
+}
+
 impl Mnstr {
     pub fn new(
         user_id: String,
@@ -205,7 +473,7 @@ impl Mnstr {
         mnstr_description: Option<String>,
         mnstr_qr_code: String,
     ) -> Self {
-        Self {
+        let mut mnstr = Self {
             id: "".to_string(),
             user_id,
             mnstr_name: mnstr_name.unwrap_or(String::new()),
@@ -228,8 +496,13 @@ impl Mnstr {
             max_intelligence: DEFAULT_STAT_VALUE,
             current_magic: DEFAULT_STAT_VALUE,
             max_magic: DEFAULT_STAT_VALUE,
+            stat_points: 0,
             experience_to_next_level: 0,
-        }
+            power_score: 0,
+            in_battle: false,
+        };
+        mnstr.update_power_score();
+        mnstr
     }
 
     pub fn to_grpc(&self) -> GrpcMnstr {
@@ -294,7 +567,7 @@ impl Mnstr {
             None => None,
         };
 
-        Self {
+        let mut mnstr = Self {
             id: self.id.clone(),
             user_id: self.user_id.clone(),
             mnstr_name: mnstr_name.unwrap_or(self.mnstr_name.clone()),
@@ -317,61 +590,32 @@ impl Mnstr {
             max_intelligence: max_intelligence.unwrap_or(self.max_intelligence),
             current_magic: current_magic.unwrap_or(self.current_magic),
             max_magic: max_magic.unwrap_or(self.max_magic),
+            stat_points: self.stat_points,
             experience_to_next_level: experience_to_next_level
                 .unwrap_or(self.experience_to_next_level),
-        }
+            power_score: 0,
+            in_battle: false,
+        };
+        mnstr.update_power_score();
+        mnstr
     }
 
+    /// Collects the mnstr, awarding the user's XP and coins in the same
+    /// transaction. Collecting a QR code the user already holds loads the
+    /// existing mnstr instead.
     pub async fn create(&mut self) -> Option<anyhow::Error> {
-        let params = vec![
-            ("user_id", self.user_id.clone().into()),
-            ("mnstr_name", self.mnstr_name.clone().into()),
-            ("mnstr_description", self.mnstr_description.clone().into()),
-            ("mnstr_qr_code", self.mnstr_qr_code.clone().into()),
-            ("current_level", self.current_level.clone().into()),
-            ("current_experience", self.current_experience.clone().into()),
-            ("current_health", self.current_health.clone().into()),
-            ("max_health", self.max_health.clone().into()),
-            ("current_attack", self.current_attack.clone().into()),
-            ("max_attack", self.max_attack.clone().into()),
-            ("current_defense", self.current_defense.clone().into()),
-            ("max_defense", self.max_defense.clone().into()),
-            ("current_speed", self.current_speed.clone().into()),
-            ("max_speed", self.max_speed.clone().into()),
-            (
-                "current_intelligence",
-                self.current_intelligence.clone().into(),
-            ),
-            ("max_intelligence", self.max_intelligence.clone().into()),
-            ("current_magic", self.current_magic.clone().into()),
-            ("max_magic", self.max_magic.clone().into()),
-        ];
-        let mnstr = match insert_resource!(Mnstr, params).await {
-            Ok(mnstr) => mnstr,
-            Err(e) => {
-                println!("[Mnstr::create] Failed to create mnstr: {:?}", e);
-                return Some(e.into());
-            }
-        };
-        *self = mnstr;
+        if let Some(error) = self.prepare_stats() {
+            println!("[Mnstr::create] Invalid mnstr stats: {:?}", error);
+            return Some(error);
+        }
 
-        let mut user = match User::find_one(self.user_id.clone(), false).await {
-            Ok(user) => user,
+        match collect(self).await {
+            Ok(mnstr) => *self = mnstr,
             Err(e) => {
-                println!("[Mnstr::create] Failed to get user: {:?}", e);
-                return Some(e.into());
+                println!("[Mnstr::create] Failed to create mnstr: {:?}", e);
+                return Some(e);
             }
         };
-        let xp = XP_FOR_LEVEL[user.experience_level as usize];
-        println!("[Mnstr::create] XP: {:?}", xp);
-        if let Some(error) = user.update_xp(xp).await {
-            println!("[Mnstr::create] Failed to update user xp: {:?}", error);
-            return Some(error.into());
-        }
-        if let Some(error) = user.add_coins(self.coins()).await {
-            println!("[Mnstr::create] Failed to add coins: {:?}", error);
-            return Some(error.into());
-        }
 
         self.update_experience_to_next_level();
 
@@ -385,74 +629,282 @@ impl Mnstr {
         if mnstrs.is_empty() {
             return Err(anyhow::Error::msg("No mnstrs to create"));
         }
-
-        let mut user = match User::find_one(user_id.clone(), false).await {
-            Ok(user) => user,
-            Err(e) => {
-                println!("[Mnstr::create_batch] Failed to get user: {:?}", e);
-                return Err(e.into());
+        let created = transaction(async {
+            // Locking the user keeps other collections and trades from taking
+            // the free slots before these mnstrs are inserted
+            let mut user =
+                match lock_resources_where_fields!(User, vec![("id", user_id.clone().into())])
+                    .await?
+                    .pop()
+                {
+                    Some(user) => user,
+                    None => return Err(anyhow::anyhow!(USER_NOT_FOUND_ERROR)),
+                };
+            if let Some(error) =
+                Self::ensure_collection_capacity(&user_id, mnstrs.len() as i64).await
+            {
+                return Err(error);
             }
-        };
 
-        let xp = XP_FOR_LEVEL[user.experience_level as usize];
-        println!("[Mnstr::create_batch] XP: {:?}", xp);
-        if let Some(error) = user.update_xp(xp).await {
-            println!(
-                "[Mnstr::create_batch] Failed to update user xp: {:?}",
-                error
-            );
-            return Err(error.into());
-        }
+            let xp = xp_for_level(&XP_FOR_LEVEL, user.experience_level);
+            println!("[Mnstr::create_batch] XP: {:?}", xp);
+            if let Some(error) = user.update_xp(xp).await {
+                println!(
+                    "[Mnstr::create_batch] Failed to update user xp: {:?}",
+                    error
+                );
+                return Err(error.into());
+            }
 
-        let mut params: Vec<Vec<(&str, DatabaseValue)>> = Vec::new();
-        for mnstr in mnstrs.iter() {
-            let mut mnstr_params: Vec<(&str, DatabaseValue)> = Vec::new();
-            for (field, value) in mnstr.iter() {
-                if let Some(v) = value {
-                    mnstr_params.push((*field, v.clone().into()));
+            let mut params: Vec<Vec<(&str, DatabaseValue)>> = Vec::new();
+            for mnstr in mnstrs.iter() {
+                let mut mnstr_params: Vec<(&str, DatabaseValue)> = Vec::new();
+                for (field, value) in mnstr.iter() {
+                    if let Some(v) = value {
+                        mnstr_params.push((*field, v.clone().into()));
+                    }
                 }
+                mnstr_params.extend(Self::starting_stat_params());
+                params.push(mnstr_params);
             }
-            params.push(mnstr_params);
-        }
 
-        match insert_resource_batch!(Mnstr, params).await {
-            Ok(mut results) => {
-                for mnstr in results.iter_mut() {
-                    if let Some(error) = user.add_coins(mnstr.coins()).await {
-                        println!("[Mnstr::create_batch] Failed to add coins: {:?}", error);
-                        return Err(error.into());
-                    }
-                    mnstr.update_experience_to_next_level();
+            let mut results = insert_resource_batch!(Mnstr, params).await?;
+            for mnstr in results.iter_mut() {
+                if let Some(error) = user.add_coins(mnstr.coins()).await {
+                    println!("[Mnstr::create_batch] Failed to add coins: {:?}", error);
+                    return Err(error.into());
                 }
-                Ok(results)
+                mnstr.update_experience_to_next_level();
             }
+            Ok(results)
+        })
+        .await;
+        if let Err(e) = &created {
+            println!("[Mnstr::create_batch] Failed to create mnstrs: {:?}", e);
+        }
+        created
+    }
+
+    /// Counts the user's unarchived mnstrs.
+    pub async fn count_for_user(user_id: String) -> Result<i64, anyhow::Error> {
+        let query = sqlx::query(
+            "SELECT COUNT(*) AS count FROM mnstrs WHERE user_id = $1 AND archived_at IS NULL",
+        )
+        .bind(&user_id);
+        match fetch_one("mnstrs", "count_for_user", query).await {
+            Ok(row) => Ok(row.get::<i64, _>("count")),
             Err(e) => {
-                println!("[Mnstr::create_batch] Failed to create mnstrs: {:?}", e);
-                return Err(e.into());
+                println!("[Mnstr::count_for_user] Failed to count mnstrs: {:?}", e);
+                Err(anyhow::Error::msg(e.to_string()))
             }
         }
     }
 
+    /// Rejects adding `adding` mnstrs to the user's collection with
+    /// `CollectionFullError`. Call it inside the `transaction` that locked the
+    /// user's row, so the count still holds when the mnstrs are written.
+    pub async fn ensure_collection_capacity(user_id: &str, adding: i64) -> Option<anyhow::Error> {
+        if adding <= 0 {
+            return None;
+        }
+        let owned = match Self::count_for_user(user_id.to_string()).await {
+            Ok(owned) => owned,
+            Err(e) => return Some(e),
+        };
+        check_collection_capacity(owned, adding, max_mnstrs_per_user())
+    }
+
+    /// Rejects stats above the ceiling for the mnstr's current level.
+    pub fn validate_stats(&self) -> Option<anyhow::Error> {
+        let stats = [
+            (MnstrStat::Health, self.current_health, self.max_health),
+            (MnstrStat::Attack, self.current_attack, self.max_attack),
+            (MnstrStat::Defense, self.current_defense, self.max_defense),
+            (MnstrStat::Speed, self.current_speed, self.max_speed),
+            (
+                MnstrStat::Intelligence,
+                self.current_intelligence,
+                self.max_intelligence,
+            ),
+            (MnstrStat::Magic, self.current_magic, self.max_magic),
+        ];
+        for (stat, current, max) in stats {
+            let allowed = max_allowed_stat(self.current_level, stat);
+            if current > allowed || max > allowed {
+                return Some(anyhow::anyhow!(
+                    "{:?} exceeds the maximum of {} for level {}",
+                    stat,
+                    allowed,
+                    self.current_level
+                ));
+            }
+        }
+        None
+    }
+
+    /// Lowers each current stat to its maximum, so no write leaves a mnstr
+    /// above what healing could bring it back to.
+    pub fn clamp_current_stats(&mut self) {
+        self.current_health = self.current_health.min(self.max_health);
+        self.current_attack = self.current_attack.min(self.max_attack);
+        self.current_defense = self.current_defense.min(self.max_defense);
+        self.current_speed = self.current_speed.min(self.max_speed);
+        self.current_intelligence = self.current_intelligence.min(self.max_intelligence);
+        self.current_magic = self.current_magic.min(self.max_magic);
+    }
+
+    /// The columns a new mnstr is inserted with.
+    fn insert_params(&self) -> Vec<(&'static str, DatabaseValue)> {
+        let mut params = vec![
+            ("user_id", self.user_id.clone().into()),
+            ("mnstr_name", self.mnstr_name.clone().into()),
+            ("mnstr_description", self.mnstr_description.clone().into()),
+            ("mnstr_qr_code", self.mnstr_qr_code.clone().into()),
+        ];
+        params.extend(self.stat_params());
+        params
+    }
+
+    /// The level, XP and stat columns.
+    fn stat_params(&self) -> Vec<(&'static str, DatabaseValue)> {
+        vec![
+            ("current_level", self.current_level.into()),
+            ("current_experience", self.current_experience.into()),
+            ("current_health", self.current_health.into()),
+            ("max_health", self.max_health.into()),
+            ("current_attack", self.current_attack.into()),
+            ("max_attack", self.max_attack.into()),
+            ("current_defense", self.current_defense.into()),
+            ("max_defense", self.max_defense.into()),
+            ("current_speed", self.current_speed.into()),
+            ("max_speed", self.max_speed.into()),
+            ("current_intelligence", self.current_intelligence.into()),
+            ("max_intelligence", self.max_intelligence.into()),
+            ("current_magic", self.current_magic.into()),
+            ("max_magic", self.max_magic.into()),
+        ]
+    }
+
+    /// The level, XP and stat columns a new mnstr starts with. Clients can't
+    /// set them; they only change through battles and levelling up.
+    fn starting_stat_params() -> Vec<(&'static str, DatabaseValue)> {
+        Self::new(String::new(), None, None, String::new()).stat_params()
+    }
+
+    /// Clamps and validates the stats before they are written.
+    fn prepare_stats(&mut self) -> Option<anyhow::Error> {
+        self.clamp_current_stats();
+        self.validate_stats()
+    }
+
+    fn max_stat_mut(&mut self, stat: MnstrStat) -> &mut i32 {
+        match stat {
+            MnstrStat::Health => &mut self.max_health,
+            MnstrStat::Attack => &mut self.max_attack,
+            MnstrStat::Defense => &mut self.max_defense,
+            MnstrStat::Speed => &mut self.max_speed,
+            MnstrStat::Intelligence => &mut self.max_intelligence,
+            MnstrStat::Magic => &mut self.max_magic,
+        }
+    }
+
+    /// Spends `points` from the stat point pool on `stat`'s maximum, for the
+    /// owner only and never past the ceiling for the mnstr's level.
+    pub fn apply_stat_points(
+        &mut self,
+        user_id: &str,
+        stat: MnstrStat,
+        points: i32,
+    ) -> Option<anyhow::Error> {
+        if self.user_id != user_id {
+            return Some(anyhow::anyhow!("Mnstr {} is not owned by user", self.id));
+        }
+        if points <= 0 {
+            return Some(anyhow::anyhow!("Points must be positive"));
+        }
+        if points > self.stat_points {
+            return Some(anyhow::Error::msg(NOT_ENOUGH_STAT_POINTS_ERROR));
+        }
+        let level = self.current_level;
+        let allowed = max_allowed_stat(level, stat);
+        let max = self.max_stat_mut(stat);
+        if *max + points > allowed {
+            return Some(anyhow::anyhow!(
+                "{:?} exceeds the maximum of {} for level {}",
+                stat,
+                allowed,
+                level
+            ));
+        }
+        *max += points;
+        self.stat_points -= points;
+        self.update_power_score();
+        None
+    }
+
+    /// Persists `apply_stat_points` in one conditional update, so concurrent
+    /// allocations can't spend the same points twice.
+    pub async fn allocate_stat(
+        &mut self,
+        user_id: &str,
+        stat: MnstrStat,
+        points: i32,
+    ) -> Option<anyhow::Error> {
+        let mut allocated = self.clone();
+        if let Some(error) = allocated.apply_stat_points(user_id, stat, points) {
+            return Some(error);
+        }
+
+        let query = format!(
+            "UPDATE mnstrs SET {column} = {column} + $1, stat_points = stat_points - $1, \
+             updated_at = CURRENT_TIMESTAMP \
+             WHERE id = $2 AND user_id = $3 AND stat_points >= $1 AND {column} + $1 <= $4 \
+             RETURNING *",
+            column = stat.max_column()
+        );
+        let query = sqlx::query(sqlx::AssertSqlSafe(query))
+            .bind(points)
+            .bind(&self.id)
+            .bind(user_id)
+            .bind(max_allowed_stat(self.current_level, stat));
+        let row = match fetch_optional("mnstrs", "allocate_stat", query).await {
+            Ok(Some(row)) => row,
+            Ok(None) => return Some(anyhow::Error::msg(NOT_ENOUGH_STAT_POINTS_ERROR)),
+            Err(e) => {
+                println!("[Mnstr::allocate_stat] Failed to allocate stat: {:?}", e);
+                return Some(e.into());
+            }
+        };
+        match Mnstr::from_row(&row) {
+            Ok(mnstr) => *self = mnstr,
+            Err(e) => return Some(e.into()),
+        };
+        None
+    }
+
+    /// Saves the mnstr's name, description, level, XP and current stats.
+    ///
+    /// Battles and levelling write through here, so it only clamps: mnstrs
+    /// saved before the level ceilings existed may sit above them and must
+    /// still save. Ceilings are checked where stats come from the client.
     pub async fn update(&mut self) -> Option<anyhow::Error> {
+        self.clamp_current_stats();
+
+        // Maximums and stat points only move in SQL, through allocate_stat and
+        // grant_stat_points, so saving a stale copy can't undo them
         let params = vec![
             ("mnstr_name", self.mnstr_name.clone().into()),
             ("mnstr_description", self.mnstr_description.clone().into()),
             ("current_level", self.current_level.clone().into()),
             ("current_experience", self.current_experience.clone().into()),
             ("current_health", self.current_health.clone().into()),
-            ("max_health", self.max_health.clone().into()),
-            ("max_attack", self.max_attack.clone().into()),
             ("current_attack", self.current_attack.clone().into()),
-            ("max_defense", self.max_defense.clone().into()),
             ("current_defense", self.current_defense.clone().into()),
-            ("max_speed", self.max_speed.clone().into()),
             ("current_speed", self.current_speed.clone().into()),
-            ("max_intelligence", self.max_intelligence.clone().into()),
             (
                 "current_intelligence",
                 self.current_intelligence.clone().into(),
             ),
-            ("max_magic", self.max_magic.clone().into()),
             ("current_magic", self.current_magic.clone().into()),
         ];
         let mnstr = match update_resource!(Mnstr, self.id.clone(), params).await {
@@ -524,6 +976,7 @@ impl Mnstr {
                 if let Some(idx) = id_idx {
                     mnstr_params.remove(idx);
                 }
+                mnstr_params.extend(Self::starting_stat_params());
                 new_mnstrs.push(mnstr_params);
                 continue;
             }
@@ -575,7 +1028,19 @@ impl Mnstr {
         self.max_intelligence = DEFAULT_STAT_VALUE;
         self.current_magic = DEFAULT_STAT_VALUE;
         self.max_magic = DEFAULT_STAT_VALUE;
-        self.update().await
+        let mnstr = match update_resource!(Mnstr, self.id.clone(), self.stat_params()).await {
+            Ok(mnstr) => mnstr,
+            Err(e) => {
+                println!(
+                    "[Mnstr::update_with_defaults] Failed to update mnstr: {:?}",
+                    e
+                );
+                return Some(e.into());
+            }
+        };
+        *self = mnstr;
+        self.update_experience_to_next_level();
+        None
     }
 
     pub async fn delete_permanent(&mut self) -> Option<anyhow::Error> {
@@ -726,6 +1191,309 @@ impl Mnstr {
         Ok(mnstrs)
     }
 
+    /// Like `find_all_by`, but leaves out archived (released) mnstrs.
+    pub async fn find_all_unarchived_by(
+        params: Vec<(&str, DatabaseValue)>,
+        get_relationships: bool,
+        order_by: Option<MnstrOrderBy>,
+        order_direction: Option<MnstrOrderDirection>,
+    ) -> Result<Vec<Self>, anyhow::Error> {
+        let mut mnstrs = match find_all_unarchived_resources_where_fields!(
+            Mnstr,
+            params,
+            order_by,
+            order_direction
+        )
+        .await
+        {
+            Ok(mnstrs) => mnstrs,
+            Err(e) => {
+                println!(
+                    "[Mnstr::find_all_unarchived_by] Failed to get mnstrs: {:?}",
+                    e
+                );
+                return Err(e.into());
+            }
+        };
+        for mnstr in mnstrs.iter_mut() {
+            if mnstr.max_health == 0 {
+                if let Some(error) = mnstr.update_with_defaults().await {
+                    println!(
+                        "[Mnstr::find_all_unarchived_by] Failed to update with defaults: {:?}",
+                        error
+                    );
+                    return Err(error.into());
+                }
+            }
+
+            mnstr.update_experience_to_next_level();
+
+            if get_relationships {
+                if let Some(error) = mnstr.get_relationships().await {
+                    println!(
+                        "[Mnstr::find_all_unarchived_by] Failed to get relationships: {:?}",
+                        error
+                    );
+                    return Err(error.into());
+                }
+            }
+        }
+        Ok(mnstrs)
+    }
+
+    /// The unarchived mnstrs of every user in `user_ids`, in one query.
+    pub async fn find_all_unarchived_by_user_ids(
+        user_ids: Vec<String>,
+    ) -> Result<Vec<Self>, anyhow::Error> {
+        let mut mnstrs = match find_all_resources_where_fields_in!(Mnstr, "user_id", user_ids).await
+        {
+            Ok(mnstrs) => mnstrs,
+            Err(e) => {
+                println!(
+                    "[Mnstr::find_all_unarchived_by_user_ids] Failed to get mnstrs: {:?}",
+                    e
+                );
+                return Err(e.into());
+            }
+        };
+        mnstrs.retain(|mnstr| mnstr.archived_at.is_none());
+        for mnstr in mnstrs.iter_mut() {
+            if mnstr.max_health == 0 {
+                if let Some(error) = mnstr.update_with_defaults().await {
+                    println!(
+                        "[Mnstr::find_all_unarchived_by_user_ids] Failed to update with defaults: {:?}",
+                        error
+                    );
+                    return Err(error.into());
+                }
+            }
+
+            mnstr.update_experience_to_next_level();
+        }
+        Ok(mnstrs)
+    }
+
+    /// The user's unarchived mnstrs among `ids`, in one query. Ids of other
+    /// users' mnstrs are skipped.
+    pub async fn find_all_by_ids(
+        user_id: String,
+        ids: Vec<String>,
+        get_relationships: bool,
+    ) -> Result<Vec<Self>, anyhow::Error> {
+        if ids.is_empty() {
+            return Ok(vec![]);
+        }
+        if ids.len() > MAX_MNSTR_IDS {
+            return Err(anyhow::anyhow!(TOO_MANY_MNSTR_IDS_ERROR));
+        }
+
+        let mut values = ids
+            .into_iter()
+            .map(DatabaseValue::from)
+            .collect::<Vec<DatabaseValue>>();
+        let mut query = format!("SELECT * FROM mnstrs{}", in_clause("id", &values));
+        query.push_str(&format!(
+            " AND user_id = ${} AND archived_at IS NULL",
+            values.len() + 1
+        ));
+        query.push_str(&order_by_clause::<Mnstr>(None, None));
+        values.push(user_id.into());
+        let mut query = sqlx::query(sqlx::AssertSqlSafe(query));
+        for value in values.iter() {
+            query = query.bind(value);
+        }
+
+        let rows = match fetch_all("mnstrs", "find_all_by_ids", query).await {
+            Ok(rows) => rows,
+            Err(e) => {
+                println!("[Mnstr::find_all_by_ids] Failed to get mnstrs: {:?}", e);
+                return Err(anyhow::Error::msg(e.to_string()));
+            }
+        };
+        let mut mnstrs = rows
+            .iter()
+            .map(Mnstr::from_row)
+            .collect::<Result<Vec<Mnstr>, Error>>()?;
+        for mnstr in mnstrs.iter_mut() {
+            mnstr.update_experience_to_next_level();
+
+            if get_relationships {
+                if let Some(error) = mnstr.get_relationships().await {
+                    println!(
+                        "[Mnstr::find_all_by_ids] Failed to get relationships: {:?}",
+                        error
+                    );
+                    return Err(error.into());
+                }
+            }
+        }
+        Ok(mnstrs)
+    }
+
+    pub async fn find_page_by(
+        params: Vec<(&str, DatabaseValue)>,
+        after: Option<String>,
+        limit: i64,
+    ) -> Result<Vec<Self>, anyhow::Error> {
+        let mut mnstrs =
+            match find_page_after!(Mnstr, params, "id", after.map(|after| after.into()), limit)
+                .await
+            {
+                Ok(mnstrs) => mnstrs,
+                Err(e) => {
+                    println!("[Mnstr::find_page_by] Failed to get mnstrs: {:?}", e);
+                    return Err(e.into());
+                }
+            };
+        for mnstr in mnstrs.iter_mut() {
+            mnstr.update_experience_to_next_level();
+        }
+        Ok(mnstrs)
+    }
+
+    /// An offset page of unarchived mnstrs matching `params`.
+    pub async fn find_ordered_page_by(
+        params: Vec<(&str, DatabaseValue)>,
+        page: &PageRequest,
+    ) -> Result<Vec<Self>, anyhow::Error> {
+        let mut mnstrs =
+            match find_page_ordered!(Mnstr, params, ArchivedFilter::Unarchived, page).await {
+                Ok(mnstrs) => mnstrs,
+                Err(e) => {
+                    println!(
+                        "[Mnstr::find_ordered_page_by] Failed to get mnstrs: {:?}",
+                        e
+                    );
+                    return Err(e.into());
+                }
+            };
+        for mnstr in mnstrs.iter_mut() {
+            mnstr.update_experience_to_next_level();
+        }
+        Ok(mnstrs)
+    }
+
+    /// Up to `limit` unarchived mnstrs matching `params` after skipping
+    /// `offset`, oldest first.
+    pub async fn find_offset_page_by(
+        mut params: Vec<(&str, DatabaseValue)>,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<Self>, anyhow::Error> {
+        params.push(("archived_at", DatabaseValue::None));
+        let mut mnstrs =
+            match find_all_resources_where_fields_paginated!(Mnstr, params, limit, offset).await {
+                Ok(mnstrs) => mnstrs,
+                Err(e) => {
+                    println!("[Mnstr::find_offset_page_by] Failed to get mnstrs: {:?}", e);
+                    return Err(e.into());
+                }
+            };
+        for mnstr in mnstrs.iter_mut() {
+            mnstr.update_experience_to_next_level();
+        }
+        Ok(mnstrs)
+    }
+
+    pub fn is_fainted(&self) -> bool {
+        self.current_health <= FAINTED_HEALTH
+    }
+
+    /// Whether the mnstr can be chosen for a battle: not archived, not fainted
+    /// and not in a running battle or its cooldown.
+    pub fn can_battle(&self, engagements: &[MnstrEngagement], now: OffsetDateTime) -> bool {
+        self.archived_at.is_none()
+            && !self.is_fainted()
+            && !engagements.iter().any(|engagement| {
+                engagement.mnstr_id == self.id && engagement.keeps_out_of_battle(now)
+            })
+    }
+
+    /// The mnstrs that can battle, strongest first.
+    pub fn battle_ready(
+        mnstrs: Vec<Self>,
+        engagements: &[MnstrEngagement],
+        now: OffsetDateTime,
+    ) -> Vec<Self> {
+        let mut ready = mnstrs
+            .into_iter()
+            .filter(|mnstr| mnstr.can_battle(engagements, now))
+            .collect::<Vec<Self>>();
+        ready.sort_by_key(|mnstr| std::cmp::Reverse(mnstr.power_score()));
+        ready
+    }
+
+    /// The user's mnstrs that can be chosen for a battle right now.
+    pub async fn find_battle_ready(user_id: String) -> Result<Vec<Self>, anyhow::Error> {
+        let now = OffsetDateTime::now_utc();
+        let mnstrs =
+            Self::find_all_by(vec![("user_id", user_id.clone().into())], false, None, None).await?;
+        let engagements = match Battle::find_mnstr_engagements(user_id, now).await {
+            Ok(engagements) => engagements,
+            Err(e) => {
+                println!(
+                    "[Mnstr::find_battle_ready] Failed to get engagements: {:?}",
+                    e
+                );
+                return Err(e);
+            }
+        };
+        Ok(Self::battle_ready(mnstrs, &engagements, now))
+    }
+
+    /// Flags the mnstr as in battle and shows its maximum stats as current,
+    /// so damage taken in the fight doesn't show in the collection.
+    pub fn show_at_rest(&mut self) {
+        self.in_battle = true;
+        self.current_health = self.max_health;
+        self.current_attack = self.max_attack;
+        self.current_defense = self.max_defense;
+        self.current_speed = self.max_speed;
+        self.current_intelligence = self.max_intelligence;
+        self.current_magic = self.max_magic;
+        self.update_power_score();
+    }
+
+    /// Whether every current stat is at its maximum.
+    pub fn is_rested(&self) -> bool {
+        self.current_health == self.max_health
+            && self.current_attack == self.max_attack
+            && self.current_defense == self.max_defense
+            && self.current_speed == self.max_speed
+            && self.current_intelligence == self.max_intelligence
+            && self.current_magic == self.max_magic
+    }
+
+    /// Restores the current stats of every one of the user's mnstrs not in a
+    /// running battle to their maximums, charging `restore_all_coin_cost()`.
+    ///
+    /// # Returns
+    ///
+    /// Returns the restored mnstrs.
+    pub async fn restore_all(user_id: String) -> Result<Vec<Self>, anyhow::Error> {
+        match restore_all_for(&user_id, restore_all_coin_cost()).await {
+            Ok(mnstrs) => Ok(mnstrs),
+            Err(e) => {
+                println!("[Mnstr::restore_all] Failed to restore mnstrs: {:?}", e);
+                Err(e)
+            }
+        }
+    }
+
+    /// Shows the mnstrs fighting in a running battle at rest. Mnstrs only
+    /// cooling down are left as they are.
+    pub fn at_rest(mut mnstrs: Vec<Self>, engagements: &[MnstrEngagement]) -> Vec<Self> {
+        for mnstr in mnstrs.iter_mut() {
+            let fighting = engagements
+                .iter()
+                .any(|engagement| engagement.mnstr_id == mnstr.id && engagement.ended_at.is_none());
+            if fighting {
+                mnstr.show_at_rest();
+            }
+        }
+        mnstrs
+    }
+
     pub fn coins(&self) -> i32 {
         let hash = sha2::Sha256::digest(self.mnstr_qr_code.as_bytes());
         let coins_byte = hash[(hash.len() - 1) / 2];
@@ -776,45 +1544,115 @@ impl Mnstr {
         None
     }
 
+    /// XP needed to reach the next level; mnstrs at the last level of the
+    /// curve keep its final entry.
+    pub fn experience_to_next_level(&self) -> i32 {
+        xp_to_next_level(&XP_FOR_LEVEL, self.current_level)
+    }
+
     pub fn update_experience_to_next_level(&mut self) {
-        let last_level_index = XP_FOR_LEVEL.len() as i32 - 1;
-        let mut xp_to_next_level = XP_FOR_LEVEL[last_level_index as usize];
-        if self.current_level < last_level_index {
-            xp_to_next_level = XP_FOR_LEVEL[self.current_level as usize + 1];
-        }
-        self.experience_to_next_level = xp_to_next_level;
+        self.experience_to_next_level = self.experience_to_next_level();
+    }
+
+    /// Weighted sum of the level and current stats, used to rank mnstrs.
+    /// Stats drained below zero in battle count as zero.
+    pub fn power_score(&self) -> i32 {
+        let weighted = [
+            (self.current_level, POWER_LEVEL_WEIGHT),
+            (self.current_health, POWER_HEALTH_WEIGHT),
+            (self.current_attack, POWER_ATTACK_WEIGHT),
+            (self.current_defense, POWER_DEFENSE_WEIGHT),
+            (self.current_speed, POWER_SPEED_WEIGHT),
+            (self.current_intelligence, POWER_INTELLIGENCE_WEIGHT),
+            (self.current_magic, POWER_MAGIC_WEIGHT),
+        ];
+        let score: i64 = weighted
+            .iter()
+            .map(|(value, weight)| (*value).max(0) as i64 * weight)
+            .sum();
+        score.min(i32::MAX as i64) as i32
+    }
+
+    pub fn update_power_score(&mut self) {
+        self.power_score = self.power_score();
     }
 
+    /// Awards `xp`, scaled by the event XP multiplier, and saves the mnstr.
     pub async fn update_xp(&mut self, xp: i32) -> Option<anyhow::Error> {
-        self.current_experience += xp;
+        let stat_points = self.stat_points;
+        let absorbed = self.apply_xp(award_xp(xp, "Mnstr::update_xp"), max_mnstr_level());
+        if absorbed > 0 {
+            println!(
+                "[Mnstr::update_xp] Mnstr {} is at the level cap, absorbed {} xp",
+                self.id, absorbed
+            );
+        }
+        let earned = self.stat_points - stat_points;
 
-        let last_level_index = XP_FOR_LEVEL.len() as i32 - 1;
-        let mut xp_to_next_level = XP_FOR_LEVEL[last_level_index as usize];
-        if self.current_level < last_level_index {
-            xp_to_next_level = XP_FOR_LEVEL[self.current_level as usize + 1];
+        let result = transaction(async {
+            if let Some(error) = self.update().await {
+                return Err(error);
+            }
+            if earned > 0 {
+                self.grant_stat_points(earned).await?;
+            }
+            Ok(())
+        })
+        .await;
+        if let Err(error) = result {
+            println!("[Mnstr::update_xp] Failed to update mnstr xp: {:?}", error);
+            return Some(error);
         }
-        let xp_overage = self.current_experience - xp_to_next_level;
+        None
+    }
 
-        let mut remaining_overage = xp_overage;
-        while remaining_overage >= 0 {
-            self.current_experience = remaining_overage;
-            self.current_level += 1;
-            xp_to_next_level = XP_FOR_LEVEL[self.current_level as usize + 1];
-            remaining_overage -= xp_to_next_level;
+    /// Adds `points` to the stat point pool in SQL rather than writing the
+    /// in-memory total, so a stale copy can't undo an `allocate_stat`.
+    async fn grant_stat_points(&mut self, points: i32) -> Result<(), anyhow::Error> {
+        let query = sqlx::query(
+            "UPDATE mnstrs SET stat_points = stat_points + $1, updated_at = CURRENT_TIMESTAMP \
+             WHERE id = $2 RETURNING *",
+        )
+        .bind(points)
+        .bind(&self.id);
+        let row = match fetch_one("mnstrs", "grant_stat_points", query).await {
+            Ok(row) => row,
+            Err(e) => {
+                println!(
+                    "[Mnstr::grant_stat_points] Failed to grant stat points: {:?}",
+                    e
+                );
+                return Err(e.into());
+            }
+        };
+        *self = Mnstr::from_row(&row)?;
+        self.update_experience_to_next_level();
+        Ok(())
+    }
+
+    /// Adds `xp` and levels the mnstr up in memory, without saving, stopping at
+    /// `max_level`. XP past the cap is absorbed and its amount returned.
+    pub fn apply_xp(&mut self, xp: i32, max_level: i32) -> i32 {
+        self.current_experience = self.current_experience.saturating_add(xp.max(0));
 
-            xp_to_next_level = XP_FOR_LEVEL[self.current_level as usize + 1];
-            if remaining_overage < 0 {
-                self.current_experience = 0;
+        while self.current_level < max_level {
+            let xp_to_next_level = self.experience_to_next_level();
+            if self.current_experience < xp_to_next_level {
+                break;
             }
+            self.current_experience -= xp_to_next_level;
+            self.current_level += 1;
+            self.stat_points += STAT_POINTS_PER_LEVEL;
         }
 
-        self.experience_to_next_level = xp_to_next_level;
-
-        if let Some(error) = self.update().await {
-            println!("[Mnstr::update_xp] Failed to update mnstr xp: {:?}", error);
-            return Some(error.into());
+        let mut absorbed = 0;
+        if self.current_level >= max_level {
+            absorbed = self.current_experience;
+            self.current_experience = 0;
         }
-        None
+
+        self.update_experience_to_next_level();
+        absorbed
     }
 }
 
@@ -827,7 +1665,7 @@ impl DatabaseResource for Mnstr {
             None => None,
         };
 
-        Ok(Mnstr {
+        let mut mnstr = Mnstr {
             id: row.get("id"),
             user_id: row.get("user_id"),
             mnstr_name: row.get("mnstr_name"),
@@ -850,8 +1688,14 @@ impl DatabaseResource for Mnstr {
             max_intelligence: row.get("max_intelligence"),
             current_magic: row.get("current_magic"),
             max_magic: row.get("max_magic"),
+            stat_points: row.get("stat_points"),
             experience_to_next_level: 0,
-        })
+            power_score: 0,
+            in_battle: false,
+        };
+        mnstr.update_experience_to_next_level();
+        mnstr.update_power_score();
+        Ok(mnstr)
     }
     fn has_id() -> bool {
         true
@@ -872,3 +1716,675 @@ impl DatabaseResource for Mnstr {
         false
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::{
+        count_resources_where_fields,
+        database::connection::{execute, rolled_back},
+        graphql::pagination::SortDirection,
+        models::battle::BATTLE_COOLDOWN_SECONDS,
+    };
+
+    #[test]
+    fn test_battle_ready_skips_fainted_cooling_down_and_locked() {
+        let now = OffsetDateTime::now_utc();
+        let mnstr = |id: &str| {
+            let mut mnstr = Mnstr::new("owner".to_string(), None, None, format!("qr-{}", id));
+            mnstr.id = id.to_string();
+            mnstr
+        };
+        let mut fainted = mnstr("fainted");
+        fainted.current_health = FAINTED_HEALTH;
+        let engagements = vec![
+            MnstrEngagement {
+                battle_id: "battle".to_string(),
+                mnstr_id: "cooling-down".to_string(),
+                ended_at: Some(now - time::Duration::seconds(BATTLE_COOLDOWN_SECONDS - 5)),
+            },
+            MnstrEngagement {
+                battle_id: "battle".to_string(),
+                mnstr_id: "locked".to_string(),
+                ended_at: None,
+            },
+            MnstrEngagement {
+                battle_id: "battle".to_string(),
+                mnstr_id: "healthy".to_string(),
+                ended_at: Some(now - time::Duration::seconds(BATTLE_COOLDOWN_SECONDS + 5)),
+            },
+        ];
+        let mnstrs = vec![
+            fainted,
+            mnstr("cooling-down"),
+            mnstr("locked"),
+            mnstr("healthy"),
+        ];
+
+        let ready = Mnstr::battle_ready(mnstrs, &engagements, now);
+        let ready = ready
+            .iter()
+            .map(|mnstr| mnstr.id.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(ready, vec!["healthy"]);
+    }
+
+    #[test]
+    fn test_mnstr_in_running_battle_shows_resting_stats() {
+        let now = OffsetDateTime::now_utc();
+        let mnstr = |id: &str| {
+            let mut mnstr = Mnstr::new("owner".to_string(), None, None, format!("qr-{}", id));
+            mnstr.id = id.to_string();
+            mnstr.current_health = 3;
+            mnstr.current_defense = mnstr.max_defense - 4;
+            mnstr.update_power_score();
+            mnstr
+        };
+        let engagements = vec![
+            MnstrEngagement {
+                battle_id: "battle".to_string(),
+                mnstr_id: "fighting".to_string(),
+                ended_at: None,
+            },
+            MnstrEngagement {
+                battle_id: "battle".to_string(),
+                mnstr_id: "cooling-down".to_string(),
+                ended_at: Some(now),
+            },
+        ];
+        let mnstrs = vec![mnstr("fighting"), mnstr("cooling-down"), mnstr("idle")];
+
+        let shown = Mnstr::at_rest(mnstrs, &engagements);
+        let fighting = &shown[0];
+        assert!(fighting.in_battle);
+        assert_eq!(fighting.current_health, fighting.max_health);
+        assert_eq!(fighting.current_defense, fighting.max_defense);
+        assert_eq!(fighting.power_score, fighting.power_score());
+
+        // Out of battle, the stored stats are what the mnstr has
+        for mnstr in &shown[1..] {
+            assert!(!mnstr.in_battle);
+            assert_eq!(mnstr.current_health, 3);
+        }
+    }
+
+    #[test]
+    fn test_power_score_increases_with_stats_and_level() {
+        let base = Mnstr::new("owner".to_string(), None, None, "qr".to_string());
+        assert_eq!(base.power_score, base.power_score());
+
+        let raises: Vec<fn(&mut Mnstr)> = vec![
+            |mnstr| mnstr.current_level += 1,
+            |mnstr| mnstr.current_health += 1,
+            |mnstr| mnstr.current_attack += 1,
+            |mnstr| mnstr.current_defense += 1,
+            |mnstr| mnstr.current_speed += 1,
+            |mnstr| mnstr.current_intelligence += 1,
+            |mnstr| mnstr.current_magic += 1,
+        ];
+        for raise in raises {
+            let mut mnstr = base.clone();
+            let mut previous = mnstr.power_score();
+            for _ in 0..5 {
+                raise(&mut mnstr);
+                assert!(mnstr.power_score() > previous);
+                previous = mnstr.power_score();
+            }
+        }
+
+        let mut drained = base.clone();
+        drained.current_attack = -5;
+        assert!(drained.power_score() >= 0);
+        assert!(drained.power_score() < base.power_score());
+    }
+
+    #[test]
+    fn test_apply_stat_points() {
+        let mut mnstr = Mnstr::new("owner".to_string(), None, None, "qr".to_string());
+        mnstr.current_level = 2;
+        mnstr.stat_points = 5;
+
+        assert!(
+            mnstr
+                .apply_stat_points("owner", MnstrStat::Attack, 3)
+                .is_none()
+        );
+        assert_eq!(mnstr.max_attack, DEFAULT_STAT_VALUE + 3);
+        assert_eq!(mnstr.stat_points, 2);
+        assert_eq!(mnstr.power_score, mnstr.power_score());
+
+        // Over budget
+        let error = mnstr
+            .apply_stat_points("owner", MnstrStat::Magic, 3)
+            .unwrap();
+        assert_eq!(error.to_string(), NOT_ENOUGH_STAT_POINTS_ERROR);
+        assert_eq!(mnstr.max_magic, DEFAULT_STAT_VALUE);
+        assert_eq!(mnstr.stat_points, 2);
+
+        // Someone else's mnstr
+        assert!(
+            mnstr
+                .apply_stat_points("other", MnstrStat::Magic, 1)
+                .is_some()
+        );
+        assert_eq!(mnstr.stat_points, 2);
+
+        assert!(
+            mnstr
+                .apply_stat_points("owner", MnstrStat::Speed, 0)
+                .is_some()
+        );
+        assert!(
+            mnstr
+                .apply_stat_points("owner", MnstrStat::Speed, -1)
+                .is_some()
+        );
+
+        // Never past the ceiling for the level
+        mnstr.stat_points = 100;
+        let allowed = max_allowed_stat(mnstr.current_level, MnstrStat::Defense);
+        let over = allowed - mnstr.max_defense + 1;
+        assert!(
+            mnstr
+                .apply_stat_points("owner", MnstrStat::Defense, over)
+                .is_some()
+        );
+        assert_eq!(mnstr.stat_points, 100);
+    }
+
+    #[test]
+    fn test_validate_stats() {
+        let mut mnstr = Mnstr::new("owner".to_string(), None, None, "qr".to_string());
+        assert!(mnstr.validate_stats().is_none());
+
+        mnstr.max_attack = max_allowed_stat(0, MnstrStat::Attack);
+        mnstr.current_attack = mnstr.max_attack;
+        assert!(mnstr.validate_stats().is_none());
+
+        mnstr.max_attack = max_allowed_stat(50, MnstrStat::Attack);
+        assert!(mnstr.validate_stats().is_some());
+
+        mnstr.current_level = 50;
+        assert!(mnstr.validate_stats().is_none());
+
+        mnstr.current_health = max_allowed_stat(50, MnstrStat::Health) + 1;
+        assert!(mnstr.validate_stats().is_some());
+    }
+
+    #[test]
+    fn test_over_max_current_stats_are_clamped() {
+        // As collected: current health far above its maximum
+        let mut created = Mnstr::new("owner".to_string(), None, None, "qr".to_string());
+        created.current_health = 100;
+        created.max_health = 10;
+        assert!(created.prepare_stats().is_none());
+        assert_eq!(created.current_health, 10);
+        assert_eq!(created.current_attack, created.max_attack);
+
+        // As updated: a lowered maximum pulls the current value down with it
+        let mut updated = created.clone();
+        updated.current_level = 5;
+        updated.max_magic = max_allowed_stat(5, MnstrStat::Magic);
+        updated.current_magic = updated.max_magic;
+        updated.max_magic -= 10;
+        updated.current_speed = 200;
+        assert!(updated.prepare_stats().is_none());
+        assert_eq!(updated.current_magic, updated.max_magic);
+        assert_eq!(updated.current_speed, updated.max_speed);
+        assert_eq!(updated.current_health, 10);
+    }
+
+    #[test]
+    fn test_check_collection_capacity() {
+        let max = 3;
+        for owned in 0..max {
+            assert!(check_collection_capacity(owned, 1, max).is_none());
+        }
+        let error = check_collection_capacity(max, 1, max).unwrap();
+        assert!(error.downcast_ref::<CollectionFullError>().is_some());
+        assert_eq!(error.to_string(), COLLECTION_FULL_ERROR);
+
+        assert!(check_collection_capacity(1, 2, max).is_none());
+        assert!(check_collection_capacity(1, 3, max).is_some());
+    }
+
+    #[test]
+    fn test_experience_to_next_level() {
+        let mut mnstr = Mnstr::new("user".to_string(), None, None, "qr".to_string());
+        mnstr.current_level = 3;
+        assert_eq!(mnstr.experience_to_next_level(), XP_FOR_LEVEL[4]);
+
+        let last_level_index = XP_FOR_LEVEL.len() as i32 - 1;
+        mnstr.current_level = last_level_index;
+        assert_eq!(
+            mnstr.experience_to_next_level(),
+            XP_FOR_LEVEL[last_level_index as usize]
+        );
+
+        mnstr.update_experience_to_next_level();
+        assert_eq!(
+            mnstr.experience_to_next_level,
+            XP_FOR_LEVEL[last_level_index as usize]
+        );
+    }
+
+    #[test]
+    fn test_apply_xp_levels_up_and_keeps_remainder() {
+        let mut mnstr = Mnstr::new("user".to_string(), None, None, "qr".to_string());
+        let xp = XP_FOR_LEVEL[1] + XP_FOR_LEVEL[2] + 7;
+
+        assert_eq!(mnstr.apply_xp(xp, MAX_MNSTR_LEVEL), 0);
+        assert_eq!(mnstr.current_level, 2);
+        assert_eq!(mnstr.current_experience, 7);
+        assert_eq!(mnstr.stat_points, 2 * STAT_POINTS_PER_LEVEL);
+        assert_eq!(mnstr.experience_to_next_level, XP_FOR_LEVEL[3]);
+    }
+
+    #[test]
+    fn test_apply_huge_xp_near_max_level_caps_cleanly() {
+        let mut mnstr = Mnstr::new("user".to_string(), None, None, "qr".to_string());
+        mnstr.current_level = MAX_MNSTR_LEVEL - 1;
+
+        let absorbed = mnstr.apply_xp(i32::MAX, MAX_MNSTR_LEVEL);
+        assert_eq!(mnstr.current_level, MAX_MNSTR_LEVEL);
+        assert_eq!(mnstr.current_experience, 0);
+        assert_eq!(mnstr.stat_points, STAT_POINTS_PER_LEVEL);
+        assert_eq!(absorbed, i32::MAX - XP_FOR_LEVEL[MAX_MNSTR_LEVEL as usize]);
+
+        // Already capped: everything is absorbed
+        assert_eq!(mnstr.apply_xp(i32::MAX, MAX_MNSTR_LEVEL), i32::MAX);
+        assert_eq!(mnstr.current_level, MAX_MNSTR_LEVEL);
+        assert_eq!(mnstr.current_experience, 0);
+    }
+
+    #[test]
+    fn test_apply_xp_stops_at_a_lower_cap() {
+        let mut mnstr = Mnstr::new("user".to_string(), None, None, "qr".to_string());
+        mnstr.apply_xp(i32::MAX, 10);
+        assert_eq!(mnstr.current_level, 10);
+        assert_eq!(mnstr.current_experience, 0);
+    }
+
+    /// Creates a user with a wallet, as signing up does.
+    async fn create_test_user() -> Result<User, anyhow::Error> {
+        let name = Uuid::new_v4().to_string();
+        let mut user = User::new(
+            Some(format!("{}@example.com", name)),
+            None,
+            "password".to_string(),
+            name,
+        );
+        if let Some(error) = user.create().await {
+            return Err(error);
+        }
+        Ok(user)
+    }
+
+    async fn count_collected(user_id: &str) -> i64 {
+        count_resources_where_fields!(Mnstr, vec![("user_id", user_id.to_string().into())])
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a migrated database at DATABASE_URL"]
+    async fn test_failed_reward_rolls_back_collected_mnstr() {
+        rolled_back(async {
+            let user = create_test_user().await?;
+            let query = sqlx::query("DELETE FROM wallets WHERE user_id = $1").bind(user.id.clone());
+            fetch_all("wallets", "delete", query).await?;
+
+            let mnstr = Mnstr::new(user.id.clone(), None, None, "qr".to_string());
+            assert!(collect(&mnstr).await.is_err());
+
+            assert_eq!(count_collected(&user.id).await, 0);
+            let user = User::find_one(user.id.clone(), false).await?;
+            assert_eq!(user.experience_points, 0);
+            Ok(())
+        })
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a migrated database at DATABASE_URL"]
+    async fn test_collecting_for_inactive_user_creates_nothing() {
+        rolled_back(async {
+            let user = create_test_user().await?;
+            delete_resource_where_fields!(User, vec![("id", user.id.clone().into())]).await?;
+
+            let mnstr = Mnstr::new(user.id.clone(), None, None, "qr".to_string());
+            let error = collect(&mnstr).await.unwrap_err();
+            assert_eq!(error.to_string(), USER_NOT_FOUND_ERROR);
+            assert_eq!(count_collected(&user.id).await, 0);
+            Ok(())
+        })
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a migrated database at DATABASE_URL"]
+    async fn test_collecting_same_qr_code_twice_is_idempotent() {
+        rolled_back(async {
+            let mut user = create_test_user().await?;
+            let mnstr = Mnstr::new(user.id.clone(), None, None, "qr".to_string());
+
+            let first = collect(&mnstr).await?;
+            let second = collect(&mnstr).await?;
+            assert_eq!(first.id, second.id);
+            assert_eq!(count_collected(&user.id).await, 1);
+
+            user.get_wallet().await;
+            let mut wallet = user.wallet.clone().unwrap();
+            wallet.get_coins().await;
+            assert_eq!(wallet.transactions.len(), 1);
+            assert_eq!(wallet.coins, first.coins());
+
+            // Another user scanning the same code collects their own
+            let other = create_test_user().await?;
+            let mnstr = Mnstr::new(other.id.clone(), None, None, "qr".to_string());
+            let third = collect(&mnstr).await?;
+            assert_ne!(third.id, first.id);
+            assert_eq!(count_collected(&other.id).await, 1);
+            Ok(())
+        })
+        .await
+        .unwrap();
+    }
+
+    async fn collect_damaged_mnstr(user_id: &str) -> Mnstr {
+        let mut mnstr = Mnstr::new(user_id.to_string(), None, None, Uuid::new_v4().to_string());
+        assert!(mnstr.create().await.is_none());
+        let params = vec![("current_health", 1.into()), ("current_magic", 0.into())];
+        update_resource!(Mnstr, mnstr.id.clone(), params)
+            .await
+            .unwrap()
+    }
+
+    async fn start_test_battle(challenger: &User, mnstr: &Mnstr, opponent: &User) -> Battle {
+        let params = vec![
+            ("challenger_id", challenger.id.clone().into()),
+            ("challenger_name", challenger.display_name.clone().into()),
+            ("challenger_mnstr_id", mnstr.id.clone().into()),
+            ("opponent_id", opponent.id.clone().into()),
+            ("opponent_name", opponent.display_name.clone().into()),
+        ];
+        insert_resource!(Battle, params).await.unwrap()
+    }
+
+    async fn is_rested(id: &str) -> bool {
+        Mnstr::find_one(id.to_string(), false)
+            .await
+            .unwrap()
+            .is_rested()
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a migrated database at DATABASE_URL"]
+    async fn test_restore_all_skips_mnstrs_in_battle() {
+        rolled_back(async {
+            let user = create_test_user().await?;
+            let other = create_test_user().await?;
+            let fighting = collect_damaged_mnstr(&user.id).await;
+            let cooling_down = collect_damaged_mnstr(&user.id).await;
+            let idle = collect_damaged_mnstr(&user.id).await;
+            let rested = collect(&Mnstr::new(
+                user.id.clone(),
+                None,
+                None,
+                "rested".to_string(),
+            ))
+            .await?;
+            let others = collect_damaged_mnstr(&other.id).await;
+            start_test_battle(&user, &fighting, &other).await;
+            let ended = start_test_battle(&user, &cooling_down, &other).await;
+            delete_resource_where_fields!(Battle, vec![("id", ended.id.into())]).await?;
+            let coins = Wallet::balance_for_user(user.id.clone()).await?;
+
+            let restored = restore_all_for(&user.id, 25).await?;
+            let mut restored_ids = restored
+                .iter()
+                .map(|mnstr| mnstr.id.clone())
+                .collect::<Vec<String>>();
+            restored_ids.sort();
+            let mut expected = vec![cooling_down.id.clone(), idle.id.clone()];
+            expected.sort();
+            assert_eq!(restored_ids, expected);
+            assert_eq!(Wallet::balance_for_user(user.id.clone()).await?, coins - 25);
+
+            assert!(is_rested(&idle.id).await);
+            assert!(is_rested(&cooling_down.id).await);
+            assert!(is_rested(&rested.id).await);
+            assert!(!is_rested(&fighting.id).await);
+            assert!(!is_rested(&others.id).await);
+            Ok(())
+        })
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a migrated database at DATABASE_URL"]
+    async fn test_restore_all_needs_enough_coins_and_charges_nothing_when_rested() {
+        rolled_back(async {
+            let user = create_test_user().await?;
+            let damaged = collect_damaged_mnstr(&user.id).await;
+            let coins = Wallet::balance_for_user(user.id.clone()).await?;
+
+            let error = restore_all_for(&user.id, coins + 1).await.unwrap_err();
+            assert_eq!(error.to_string(), NOT_ENOUGH_COINS_ERROR);
+            assert!(!is_rested(&damaged.id).await);
+            assert_eq!(Wallet::balance_for_user(user.id.clone()).await?, coins);
+
+            assert_eq!(restore_all_for(&user.id, 0).await?.len(), 1);
+            assert!(restore_all_for(&user.id, 5).await?.is_empty());
+            assert_eq!(Wallet::balance_for_user(user.id.clone()).await?, coins);
+            Ok(())
+        })
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a migrated database at DATABASE_URL"]
+    async fn test_saving_a_stale_copy_keeps_spent_stat_points() {
+        rolled_back(async {
+            let user = create_test_user().await?;
+            let mut mnstr = Mnstr::new(user.id.clone(), None, None, Uuid::new_v4().to_string());
+            assert!(mnstr.create().await.is_none());
+            assert!(mnstr.update_xp(10_000).await.is_none());
+            let earned = mnstr.stat_points;
+            assert!(earned > 0);
+
+            let mut stale = Mnstr::find_one(mnstr.id.clone(), false).await?;
+            let mut fresh = Mnstr::find_one(mnstr.id.clone(), false).await?;
+            assert!(
+                fresh
+                    .allocate_stat(&user.id, MnstrStat::Attack, 1)
+                    .await
+                    .is_none()
+            );
+            stale.mnstr_name = "Renamed".to_string();
+            assert!(stale.update().await.is_none());
+
+            let saved = Mnstr::find_one(mnstr.id.clone(), false).await?;
+            assert_eq!(saved.mnstr_name, "Renamed");
+            assert_eq!(saved.stat_points, earned - 1);
+            assert_eq!(saved.max_attack, mnstr.max_attack + 1);
+
+            // Levelling up again adds to what's left rather than the stale total
+            assert!(stale.update_xp(10_000).await.is_none());
+            assert!(stale.stat_points > earned - 1);
+            let saved = Mnstr::find_one(mnstr.id.clone(), false).await?;
+            assert_eq!(saved.stat_points, stale.stat_points);
+            Ok(())
+        })
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a migrated database at DATABASE_URL"]
+    async fn test_mnstrs_above_the_ceiling_still_save() {
+        rolled_back(async {
+            let user = create_test_user().await?;
+            let mut mnstr = Mnstr::new(user.id.clone(), None, None, Uuid::new_v4().to_string());
+            assert!(mnstr.create().await.is_none());
+
+            // Saved before the level ceilings existed
+            let attack = max_allowed_stat(0, MnstrStat::Attack) + 10;
+            let query =
+                sqlx::query("UPDATE mnstrs SET max_attack = $1, current_attack = $1 WHERE id = $2")
+                    .bind(attack)
+                    .bind(&mnstr.id);
+            execute("mnstrs", "legacy_stats", query).await?;
+
+            let mut legacy = Mnstr::find_one(mnstr.id.clone(), false).await?;
+            assert!(legacy.validate_stats().is_some());
+            legacy.current_attack -= 5;
+            assert!(legacy.update().await.is_none());
+            assert!(legacy.update_xp(10).await.is_none());
+
+            let saved = Mnstr::find_one(mnstr.id.clone(), false).await?;
+            assert_eq!(saved.current_attack, attack - 5);
+            assert_eq!(saved.max_attack, attack);
+            Ok(())
+        })
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a migrated database at DATABASE_URL"]
+    async fn test_find_page_by_pages_by_id() {
+        rolled_back(async {
+            let user = create_test_user().await?;
+            let mut ids = Vec::new();
+            for i in 0..3 {
+                let mnstr = Mnstr::new(user.id.clone(), None, None, format!("page-{}", i));
+                ids.push(collect(&mnstr).await?.id);
+            }
+            ids.sort();
+
+            let params = vec![("user_id", user.id.clone().into())];
+            let page_ids = |page: Vec<Mnstr>| {
+                page.into_iter()
+                    .map(|mnstr| mnstr.id)
+                    .collect::<Vec<String>>()
+            };
+            let first = Mnstr::find_page_by(params.clone(), None, 2).await?;
+            assert_eq!(page_ids(first), ids[..2]);
+
+            let second = Mnstr::find_page_by(params, Some(ids[1].clone()), 2).await?;
+            assert_eq!(page_ids(second), ids[2..]);
+            Ok(())
+        })
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a migrated database at DATABASE_URL"]
+    async fn test_find_ordered_page_by_applies_limit_and_offset() {
+        rolled_back(async {
+            let user = create_test_user().await?;
+            let mut ids = Vec::new();
+            for i in 0..3 {
+                let mnstr = Mnstr::new(user.id.clone(), None, None, format!("ordered-{}", i));
+                ids.push(collect(&mnstr).await?.id);
+            }
+            ids.sort();
+
+            let page = PageRequest {
+                limit: 1,
+                offset: 1,
+                order_column: "id",
+                direction: SortDirection::Asc,
+            };
+            let mnstrs =
+                Mnstr::find_ordered_page_by(vec![("user_id", user.id.clone().into())], &page)
+                    .await?;
+            assert_eq!(mnstrs.len(), 1);
+            assert_eq!(mnstrs[0].id, ids[1]);
+            Ok(())
+        })
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a migrated database at DATABASE_URL"]
+    async fn test_create_batch_refuses_to_overfill_a_collection() {
+        rolled_back(async {
+            let user = create_test_user().await?;
+            let query = sqlx::query(
+                "INSERT INTO mnstrs (id, user_id, mnstr_qr_code) \
+                 SELECT gen_random_uuid()::text, $1, gen_random_uuid()::text \
+                 FROM generate_series(1, $2)",
+            )
+            .bind(&user.id)
+            .bind(max_mnstrs_per_user() as i32 - 1);
+            fetch_all("mnstrs", "fill", query).await?;
+
+            let mnstrs = (0..2)
+                .map(|i| {
+                    vec![
+                        ("user_id", Some(user.id.clone().into())),
+                        ("mnstr_qr_code", Some(format!("batch-{}", i).into())),
+                    ]
+                })
+                .collect::<Vec<_>>();
+            let error = Mnstr::create_batch(user.id.clone(), mnstrs)
+                .await
+                .unwrap_err();
+            assert!(error.downcast_ref::<CollectionFullError>().is_some());
+            assert_eq!(count_collected(&user.id).await, max_mnstrs_per_user() - 1);
+            let user = User::find_one(user.id.clone(), false).await?;
+            assert_eq!(user.experience_points, 0);
+            Ok(())
+        })
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a migrated database at DATABASE_URL"]
+    async fn test_find_all_by_ids_returns_only_the_users_unarchived_mnstrs() {
+        rolled_back(async {
+            let user = create_test_user().await?;
+            let other = create_test_user().await?;
+            let mut owned = Vec::new();
+            for i in 0..3 {
+                let mnstr = Mnstr::new(user.id.clone(), None, None, format!("by-ids-{}", i));
+                owned.push(collect(&mnstr).await?.id);
+            }
+            let archived = owned.pop().unwrap();
+            delete_resource_where_fields!(Mnstr, vec![("id", archived.clone().into())]).await?;
+            let others = Mnstr::new(other.id.clone(), None, None, "by-ids".to_string());
+            let others = collect(&others).await?.id;
+
+            let mut ids = owned.clone();
+            ids.extend([archived, others, Uuid::new_v4().to_string()]);
+            let mut found = Mnstr::find_all_by_ids(user.id.clone(), ids, false)
+                .await?
+                .into_iter()
+                .map(|mnstr| mnstr.id)
+                .collect::<Vec<String>>();
+            found.sort();
+            owned.sort();
+            assert_eq!(found, owned);
+
+            let too_many = (0..=MAX_MNSTR_IDS)
+                .map(|_| Uuid::new_v4().to_string())
+                .collect::<Vec<String>>();
+            let error = Mnstr::find_all_by_ids(user.id.clone(), too_many, false).await;
+            assert!(error.is_err());
+            Ok(())
+        })
+        .await
+        .unwrap();
+    }
+}
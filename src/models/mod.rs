@@ -1,14 +1,19 @@
+pub mod audit_log;
 pub mod battle;
 pub mod battle_log;
 pub mod battle_status;
+pub mod block;
 pub mod effect;
 pub mod generated;
 pub mod item;
 pub mod item_effect;
 pub mod mnstr;
 pub mod mnstr_user_item;
+pub mod report;
 pub mod session;
+pub mod trade_offer;
 pub mod transaction;
 pub mod user;
 pub mod user_item;
 pub mod wallet;
+pub mod xp;
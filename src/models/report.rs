@@ -0,0 +1,160 @@
+use juniper::GraphQLObject;
+use serde::{Deserialize, Serialize};
+use sqlx::{Error, Row, postgres::PgRow};
+use time::OffsetDateTime;
+
+use crate::{
+    database::{traits::DatabaseResource, values::DatabaseValue},
+    insert_resource,
+    utils::time::{deserialize_offset_date_time, serialize_offset_date_time},
+};
+
+pub const MAX_REPORT_REASON_LENGTH: usize = 1000;
+
+/// A player reported for review. Reports are archived once a moderator has
+/// dealt with them.
+#[derive(Debug, Serialize, Deserialize, GraphQLObject, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Report {
+    pub id: String,
+    pub reporter_id: String,
+    pub reported_id: String,
+    pub reason: String,
+
+    #[serde(
+        serialize_with = "serialize_offset_date_time",
+        deserialize_with = "deserialize_offset_date_time"
+    )]
+    pub created_at: Option<OffsetDateTime>,
+
+    #[serde(
+        serialize_with = "serialize_offset_date_time",
+        deserialize_with = "deserialize_offset_date_time"
+    )]
+    pub updated_at: Option<OffsetDateTime>,
+
+    #[serde(
+        serialize_with = "serialize_offset_date_time",
+        deserialize_with = "deserialize_offset_date_time"
+    )]
+    pub archived_at: Option<OffsetDateTime>,
+}
+
+impl Report {
+    pub fn new(reporter_id: String, reported_id: String, reason: String) -> Self {
+        Self {
+            id: "".to_string(),
+            reporter_id,
+            reported_id,
+            reason: reason.trim().to_string(),
+            created_at: None,
+            updated_at: None,
+            archived_at: None,
+        }
+    }
+
+    pub fn validate(&self) -> Option<anyhow::Error> {
+        if self.reporter_id == self.reported_id {
+            return Some(anyhow::anyhow!("Cannot report yourself"));
+        }
+        if self.reason.is_empty() {
+            return Some(anyhow::anyhow!("A reason is required"));
+        }
+        if self.reason.chars().count() > MAX_REPORT_REASON_LENGTH {
+            return Some(anyhow::anyhow!("Reason is too long"));
+        }
+        None
+    }
+
+    pub async fn create(&mut self) -> Option<anyhow::Error> {
+        if let Some(error) = self.validate() {
+            return Some(error);
+        }
+
+        let params = vec![
+            ("reporter_id", self.reporter_id.clone().into()),
+            ("reported_id", self.reported_id.clone().into()),
+            ("reason", DatabaseValue::Text(self.reason.clone())),
+        ];
+        let report = match insert_resource!(Report, params).await {
+            Ok(report) => report,
+            Err(e) => {
+                println!("[Report::create] Failed to create report: {:?}", e);
+                return Some(e.into());
+            }
+        };
+        *self = report;
+        None
+    }
+}
+
+impl DatabaseResource for Report {
+    fn from_row(row: &PgRow) -> Result<Self, Error> {
+        let archived_at = match row.get("archived_at") {
+            Some(archived_at) => archived_at,
+            None => None,
+        };
+
+        Ok(Report {
+            id: row.get("id"),
+            reporter_id: row.get("reporter_id"),
+            reported_id: row.get("reported_id"),
+            reason: row.get("reason"),
+            created_at: row.get("created_at"),
+            updated_at: row.get("updated_at"),
+            archived_at,
+        })
+    }
+    fn has_id() -> bool {
+        true
+    }
+    fn is_archivable() -> bool {
+        true
+    }
+    fn is_updatable() -> bool {
+        true
+    }
+    fn is_creatable() -> bool {
+        true
+    }
+    fn is_expirable() -> bool {
+        false
+    }
+    fn is_verifiable() -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_report() {
+        let report = Report::new(
+            "reporter".to_string(),
+            "reported".to_string(),
+            " spam ".to_string(),
+        );
+        assert_eq!(report.reason, "spam");
+        assert!(report.validate().is_none());
+
+        let report = Report::new(
+            "reporter".to_string(),
+            "reporter".to_string(),
+            "spam".to_string(),
+        );
+        assert!(report.validate().is_some());
+
+        let report = Report::new(
+            "reporter".to_string(),
+            "reported".to_string(),
+            "  ".to_string(),
+        );
+        assert!(report.validate().is_some());
+
+        let reason = "a".repeat(MAX_REPORT_REASON_LENGTH + 1);
+        let report = Report::new("reporter".to_string(), "reported".to_string(), reason);
+        assert!(report.validate().is_some());
+    }
+}
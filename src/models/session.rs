@@ -1,19 +1,43 @@
+use std::collections::HashMap;
+
 use juniper::GraphQLObject;
 use serde::{Deserialize, Serialize};
 use sqlx::{Row, postgres::PgRow};
-use time::OffsetDateTime;
+use time::{OffsetDateTime, UtcOffset};
 use uuid::Uuid;
 
 use crate::{
-    database::{traits::DatabaseResource, values::DatabaseValue},
+    database::{connection::transaction, traits::DatabaseResource, values::DatabaseValue},
     delete_resource_where_fields, find_all_resources_where_fields, find_one_resource_where_fields,
-    insert_resource,
+    insert_resource, lock_resources_where_fields,
     models::user::User,
     proto::Session as GrpcSession,
     update_resource,
     utils::time::{deserialize_offset_date_time, serialize_offset_date_time},
 };
 
+/// How many fresh tokens `Session::create` tries before giving up.
+pub const SESSION_TOKEN_ATTEMPTS: usize = 3;
+
+const SESSION_TOKEN_CONSTRAINT: &str = "sessions_session_token_key";
+
+/// Used when `MAX_SESSIONS_PER_USER` isn't set.
+pub const DEFAULT_MAX_SESSIONS_PER_USER: usize = 5;
+
+pub fn max_sessions_per_user() -> usize {
+    std::env::var("MAX_SESSIONS_PER_USER")
+        .ok()
+        .and_then(|max| max.parse().ok())
+        .unwrap_or(DEFAULT_MAX_SESSIONS_PER_USER)
+}
+
+/// The sessions to evict, oldest first, so that one more still fits within
+/// `max`. `sessions` must be ordered oldest first.
+pub fn sessions_to_evict(sessions: &[Session], max: usize) -> &[Session] {
+    let keep = max.max(1) - 1;
+    &sessions[..sessions.len().saturating_sub(keep)]
+}
+
 #[derive(Debug, Serialize, Deserialize, GraphQLObject, Clone)]
 pub struct Session {
     pub id: String,
@@ -62,6 +86,18 @@ impl Session {
         }
     }
 
+    /// Whether the session had expired by `now`. Both instants are moved to UTC
+    /// before comparing, so neither the database's nor the server's offset
+    /// shifts the expiry.
+    pub fn expired_at(&self, now: OffsetDateTime) -> bool {
+        match self.expires_at {
+            Some(expires_at) => {
+                expires_at.to_offset(UtcOffset::UTC) < now.to_offset(UtcOffset::UTC)
+            }
+            None => false,
+        }
+    }
+
     pub fn to_grpc(&self) -> GrpcSession {
         GrpcSession {
             id: self.id.clone(),
@@ -71,15 +107,44 @@ impl Session {
         }
     }
 
+    /// Inserts the session in one transaction with the evictions that make
+    /// room for it. The user's row stays locked until it commits, so two
+    /// logins at once can't both squeeze in under the cap.
     pub async fn create(&mut self) -> Option<anyhow::Error> {
-        let token = Uuid::new_v4().to_string();
-        let params = vec![
-            ("user_id", self.user_id.clone().into()),
-            ("session_token", token.into()),
-        ];
-        let mut session = match insert_resource!(Session, params).await {
+        let user_id = self.user_id.clone();
+        let created =
+            transaction(async {
+                // Archived users aren't locked, so a login that waited on
+                // User::archive gets no session
+                if lock_resources_where_fields!(User, vec![("id", user_id.clone().into())])
+                    .await?
+                    .is_empty()
+                {
+                    return Err(anyhow::anyhow!("User {} not found", user_id));
+                }
+                if let Some(error) = self.evict_oldest().await {
+                    return Err(error);
+                }
+                let insert = |token: String| {
+                    let params = vec![
+                        ("user_id", user_id.clone().into()),
+                        ("session_token", token.into()),
+                    ];
+                    // Each attempt gets its own savepoint, so a colliding token
+                    // doesn't abort the evictions before it
+                    async move {
+                        transaction(async { Ok(insert_resource!(Session, params).await?) }).await
+                    }
+                };
+                insert_with_fresh_token(insert).await
+            })
+            .await;
+        let mut session = match created {
             Ok(session) => session,
-            Err(e) => return Some(e.into()),
+            Err(e) => {
+                println!("[Session::create] Failed to create session: {:?}", e);
+                return Some(e);
+            }
         };
         if let Some(error) = session.get_relationships().await {
             return Some(error);
@@ -89,6 +154,31 @@ impl Session {
         None
     }
 
+    /// Makes room for this session under the user's session cap by deleting
+    /// their oldest live sessions. Runs inside `create`'s transaction.
+    async fn evict_oldest(&self) -> Option<anyhow::Error> {
+        let sessions =
+            match Self::find_all_by(vec![("user_id", self.user_id.clone().into())], false).await {
+                Ok(sessions) => sessions,
+                Err(e) => {
+                    println!("[Session::create] Failed to get sessions: {:?}", e);
+                    return Some(e);
+                }
+            };
+        let live = sessions
+            .into_iter()
+            .filter(|session| session.archived_at.is_none())
+            .collect::<Vec<Session>>();
+        for session in sessions_to_evict(&live, max_sessions_per_user()) {
+            println!("[Session::create] Evicting session: {:?}", session.id);
+            if let Some(error) = session.clone().delete_permanent().await {
+                println!("[Session::create] Failed to evict session: {:?}", error);
+                return Some(error);
+            }
+        }
+        None
+    }
+
     pub async fn update(&mut self) -> Option<anyhow::Error> {
         let mut session = match update_resource!(Session, self.id.clone(), vec![]).await {
             Ok(session) => session,
@@ -160,13 +250,22 @@ impl Session {
         Ok(sessions)
     }
 
+    /// With `get_relationships`, the sessions' users are loaded together in
+    /// one query.
     pub async fn find_all_by(
         params: Vec<(&str, DatabaseValue)>,
+        get_relationships: bool,
     ) -> Result<Vec<Self>, anyhow::Error> {
-        let sessions = match find_all_resources_where_fields!(Session, params).await {
+        let mut sessions = match find_all_resources_where_fields!(Session, params).await {
             Ok(sessions) => sessions,
             Err(e) => return Err(e.into()),
         };
+        if get_relationships {
+            if let Some(error) = attach_users(&mut sessions, User::find_all_by_ids).await {
+                println!("[Session::find_all_by] Failed to get users: {:?}", error);
+                return Err(error);
+            }
+        }
         Ok(sessions)
     }
 
@@ -185,6 +284,66 @@ impl Session {
     }
 }
 
+/// Loads the users of `sessions` with a single call to `load` and attaches
+/// each session's user.
+async fn attach_users<F, Fut>(sessions: &mut [Session], load: F) -> Option<anyhow::Error>
+where
+    F: FnOnce(Vec<String>) -> Fut,
+    Fut: Future<Output = Result<Vec<User>, anyhow::Error>>,
+{
+    let mut user_ids = sessions
+        .iter()
+        .map(|session| session.user_id.clone())
+        .collect::<Vec<String>>();
+    user_ids.sort();
+    user_ids.dedup();
+    if user_ids.is_empty() {
+        return None;
+    }
+
+    let users = match load(user_ids).await {
+        Ok(users) => users,
+        Err(e) => return Some(e),
+    };
+    let users = users
+        .into_iter()
+        .map(|user| (user.id.clone(), user))
+        .collect::<HashMap<String, User>>();
+    for session in sessions.iter_mut() {
+        session.user = users.get(&session.user_id).cloned();
+    }
+    None
+}
+
+/// Runs `insert` with a new UUID token, retrying with another one when the
+/// token collides with an existing session.
+async fn insert_with_fresh_token<F, Fut>(mut insert: F) -> Result<Session, anyhow::Error>
+where
+    F: FnMut(String) -> Fut,
+    Fut: Future<Output = Result<Session, anyhow::Error>>,
+{
+    let mut attempt = 1;
+    loop {
+        match insert(Uuid::new_v4().to_string()).await {
+            Ok(session) => return Ok(session),
+            Err(e) if attempt < SESSION_TOKEN_ATTEMPTS && is_token_conflict(&e) => {
+                println!(
+                    "[Session::create] Session token collided, retrying ({}/{})",
+                    attempt, SESSION_TOKEN_ATTEMPTS
+                );
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+// `insert_resource!` flattens the database error into its message, so the
+// violated constraint is matched by name.
+fn is_token_conflict(error: &anyhow::Error) -> bool {
+    error.to_string().contains(SESSION_TOKEN_CONSTRAINT)
+}
+
 impl DatabaseResource for Session {
     fn from_row(row: &PgRow) -> Result<Self, sqlx::Error> {
         let created_at = row.get("created_at");
@@ -237,7 +396,7 @@ impl DatabaseResource for Session {
 
 impl crate::utils::sessions::SessionTrait<Session> for Session {
     fn expired(&self) -> bool {
-        self.expires_at.is_some() && self.expires_at.unwrap() < OffsetDateTime::now_utc()
+        self.expired_at(OffsetDateTime::now_utc())
     }
 
     async fn update_expired(&mut self) -> Option<anyhow::Error> {
@@ -262,3 +421,183 @@ impl crate::utils::sessions::SessionTrait<Session> for Session {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+    use crate::database::connection::{execute, rolled_back};
+
+    #[tokio::test]
+    async fn test_session_token_conflict_is_retried() {
+        let mut tokens: Vec<String> = Vec::new();
+        let session = insert_with_fresh_token(|token: String| {
+            tokens.push(token.clone());
+            let attempt = tokens.len();
+            async move {
+                if attempt == 1 {
+                    return Err(anyhow::anyhow!(
+                        "error returned from database: duplicate key value violates unique constraint \"sessions_session_token_key\""
+                    ));
+                }
+                let mut session = Session::new("user".to_string());
+                session.session_token = token;
+                Ok(session)
+            }
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(tokens.len(), 2);
+        assert_ne!(tokens[0], tokens[1]);
+        assert_eq!(session.session_token, tokens[1]);
+    }
+
+    #[tokio::test]
+    async fn test_session_token_retries_are_bounded() {
+        let mut attempts = 0;
+        let result = insert_with_fresh_token(|_token: String| {
+            attempts += 1;
+            async {
+                Err(anyhow::anyhow!(
+                    "duplicate key value violates unique constraint \"sessions_session_token_key\""
+                ))
+            }
+        })
+        .await;
+
+        assert!(result.is_err());
+        assert_eq!(attempts, SESSION_TOKEN_ATTEMPTS);
+    }
+
+    #[test]
+    fn test_session_expires_after_its_expiry_instant() {
+        let now = OffsetDateTime::from_unix_timestamp(1_760_000_000).unwrap();
+        let mut session = Session::new("user".to_string());
+        assert!(!session.expired_at(now));
+
+        // Stored the way the insert macro writes it, 30 days out
+        let expires_at = now + time::Duration::days(30);
+        session.expires_at = Some(expires_at);
+        assert!(!session.expired_at(now));
+        assert!(!session.expired_at(expires_at));
+        assert!(session.expired_at(expires_at + time::Duration::seconds(1)));
+
+        // The same instants read back at other offsets compare the same way
+        let offset = UtcOffset::from_hms(9, 30, 0).unwrap();
+        session.expires_at = Some(expires_at.to_offset(offset));
+        assert!(!session.expired_at(now.to_offset(UtcOffset::from_hms(-8, 0, 0).unwrap())));
+        assert!(session.expired_at((expires_at + time::Duration::seconds(1)).to_offset(offset)));
+    }
+
+    #[test]
+    fn test_sessions_past_the_cap_evict_the_oldest() {
+        let max = 3;
+        let mut sessions: Vec<Session> = Vec::new();
+        for login in 0..5 {
+            let evicted = sessions_to_evict(&sessions, max).len();
+            sessions.drain(..evicted);
+
+            let mut session = Session::new("user".to_string());
+            session.id = format!("session-{}", login);
+            sessions.push(session);
+            assert!(sessions.len() <= max);
+        }
+
+        let ids = sessions
+            .iter()
+            .map(|session| session.id.as_str())
+            .collect::<Vec<&str>>();
+        assert_eq!(ids, vec!["session-2", "session-3", "session-4"]);
+
+        // Below the cap nothing is evicted, and a cap of 0 still allows one
+        assert!(sessions_to_evict(&sessions[..1], max).is_empty());
+        assert_eq!(sessions_to_evict(&sessions, 0).len(), 3);
+    }
+    #[tokio::test]
+    async fn test_sessions_load_their_users_in_one_query() {
+        let mut sessions = ["a", "b", "a", "c", "b"]
+            .iter()
+            .map(|user_id| Session::new(user_id.to_string()))
+            .collect::<Vec<Session>>();
+        let queries = AtomicUsize::new(0);
+        let load = |ids: Vec<String>| {
+            queries.fetch_add(1, Ordering::SeqCst);
+            async move {
+                Ok(ids
+                    .into_iter()
+                    .map(|id| {
+                        let mut user =
+                            User::new(None, None, "password".to_string(), id.to_uppercase());
+                        user.id = id;
+                        user
+                    })
+                    .collect())
+            }
+        };
+
+        assert!(attach_users(&mut sessions, load).await.is_none());
+        assert_eq!(queries.load(Ordering::SeqCst), 1);
+        for session in sessions.iter() {
+            let user = session.user.as_ref().unwrap();
+            assert_eq!(user.id, session.user_id);
+            assert_eq!(user.display_name, session.user_id.to_uppercase());
+        }
+
+        let mut none: Vec<Session> = Vec::new();
+        assert!(attach_users(&mut none, load).await.is_none());
+        assert_eq!(queries.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a migrated database at DATABASE_URL"]
+    async fn test_creating_past_the_cap_evicts_the_oldest_session() {
+        rolled_back(async {
+            let name = Uuid::new_v4().to_string();
+            let mut user = User::new(
+                Some(format!("{}@example.com", name)),
+                None,
+                "password".to_string(),
+                name,
+            );
+            if let Some(error) = user.create().await {
+                return Err(error);
+            }
+
+            let max = max_sessions_per_user();
+            let mut ids = Vec::new();
+            for i in 0..max {
+                let mut session = Session::new(user.id.clone());
+                if let Some(error) = session.create().await {
+                    return Err(error);
+                }
+                // Everything in the test transaction shares one timestamp
+                let query = sqlx::query(
+                    "UPDATE sessions SET created_at = created_at - make_interval(mins => $1) \
+                     WHERE id = $2",
+                )
+                .bind((max - i) as i32)
+                .bind(&session.id);
+                execute("sessions", "backdate", query).await?;
+                ids.push(session.id);
+            }
+
+            let mut session = Session::new(user.id.clone());
+            if let Some(error) = session.create().await {
+                return Err(error);
+            }
+            let live = Session::find_all_by(vec![("user_id", user.id.clone().into())], false)
+                .await?
+                .into_iter()
+                .map(|session| session.id)
+                .collect::<Vec<String>>();
+            assert_eq!(live.len(), max);
+            assert!(!live.contains(&ids[0]));
+            assert!(live.contains(&session.id));
+            Ok(())
+        })
+        .await
+        .unwrap();
+    }
+}
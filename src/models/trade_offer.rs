@@ -0,0 +1,569 @@
+use juniper::{GraphQLEnum, GraphQLObject};
+use serde::{Deserialize, Serialize};
+use sqlx::{Error, Row, postgres::PgRow};
+use time::OffsetDateTime;
+
+use crate::{
+    database::{
+        connection::{execute, fetch_optional, transaction},
+        traits::DatabaseResource,
+        values::DatabaseValue,
+    },
+    find_all_resources_where_fields, find_one_resource_where_fields, insert_resource,
+    lock_resources_where_fields,
+    models::{battle::Battle, mnstr::Mnstr, user::User},
+    update_resource,
+    utils::time::{deserialize_offset_date_time, serialize_offset_date_time},
+};
+
+#[derive(Debug, Serialize, Deserialize, GraphQLEnum, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum TradeOfferStatus {
+    Pending,
+    Accepted,
+    Declined,
+}
+
+impl std::fmt::Display for TradeOfferStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TradeOfferStatus::Pending => write!(f, "pending"),
+            TradeOfferStatus::Accepted => write!(f, "accepted"),
+            TradeOfferStatus::Declined => write!(f, "declined"),
+        }
+    }
+}
+
+impl From<String> for TradeOfferStatus {
+    fn from(value: String) -> Self {
+        match value.as_str() {
+            "pending" => TradeOfferStatus::Pending,
+            "accepted" => TradeOfferStatus::Accepted,
+            "declined" => TradeOfferStatus::Declined,
+            _ => TradeOfferStatus::Pending,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, GraphQLObject, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct TradeOffer {
+    pub id: String,
+    pub from_user_id: String,
+    pub to_user_id: String,
+    pub offered_mnstr_id: String,
+    pub requested_mnstr_id: Option<String>,
+    pub status: TradeOfferStatus,
+
+    #[serde(
+        serialize_with = "serialize_offset_date_time",
+        deserialize_with = "deserialize_offset_date_time"
+    )]
+    pub created_at: Option<OffsetDateTime>,
+
+    #[serde(
+        serialize_with = "serialize_offset_date_time",
+        deserialize_with = "deserialize_offset_date_time"
+    )]
+    pub updated_at: Option<OffsetDateTime>,
+
+    #[serde(
+        serialize_with = "serialize_offset_date_time",
+        deserialize_with = "deserialize_offset_date_time"
+    )]
+    pub archived_at: Option<OffsetDateTime>,
+}
+
+impl TradeOffer {
+    pub fn new(
+        from_user_id: String,
+        to_user_id: String,
+        offered_mnstr_id: String,
+        requested_mnstr_id: Option<String>,
+    ) -> Self {
+        Self {
+            id: "".to_string(),
+            from_user_id,
+            to_user_id,
+            offered_mnstr_id,
+            requested_mnstr_id,
+            status: TradeOfferStatus::Pending,
+            created_at: None,
+            updated_at: None,
+            archived_at: None,
+        }
+    }
+
+    pub async fn create(&mut self) -> Option<anyhow::Error> {
+        if self.from_user_id == self.to_user_id {
+            return Some(anyhow::anyhow!("Cannot trade with yourself"));
+        }
+        if let Some(error) = self.validate_mnstrs().await {
+            println!("[TradeOffer::create] Invalid trade offer: {:?}", error);
+            return Some(error);
+        }
+
+        let params = vec![
+            ("from_user_id", self.from_user_id.clone().into()),
+            ("to_user_id", self.to_user_id.clone().into()),
+            ("offered_mnstr_id", self.offered_mnstr_id.clone().into()),
+            ("requested_mnstr_id", self.requested_mnstr_id.clone().into()),
+            ("status", self.status.clone().to_string().into()),
+        ];
+        let trade_offer = match insert_resource!(TradeOffer, params).await {
+            Ok(trade_offer) => trade_offer,
+            Err(e) => {
+                println!("[TradeOffer::create] Failed to create trade offer: {:?}", e);
+                return Some(e.into());
+            }
+        };
+        *self = trade_offer;
+        None
+    }
+
+    pub async fn update(&mut self) -> Option<anyhow::Error> {
+        let params = vec![("status", self.status.clone().to_string().into())];
+        let trade_offer = match update_resource!(TradeOffer, self.id.clone(), params).await {
+            Ok(trade_offer) => trade_offer,
+            Err(e) => {
+                println!("[TradeOffer::update] Failed to update trade offer: {:?}", e);
+                return Some(e.into());
+            }
+        };
+        *self = trade_offer;
+        None
+    }
+
+    /// Accepts the offer, swapping mnstr ownership and marking the offer
+    /// accepted in a single database transaction.
+    pub async fn accept(&mut self, user_id: &str) -> Option<anyhow::Error> {
+        if !self.can_respond(user_id) {
+            return Some(anyhow::anyhow!("Trade offer cannot be accepted"));
+        }
+        let swapped = transaction(async {
+            if let Some(error) = self.ensure_capacity().await {
+                println!("[TradeOffer::accept] Collection full: {:?}", error);
+                return Err(error);
+            }
+            // The mnstrs stay locked until the swap commits, so they can't be
+            // archived, traded away or sent into battle in between
+            if let Some(error) = self.validate_mnstrs().await {
+                println!("[TradeOffer::accept] Invalid trade offer: {:?}", error);
+                return Err(error);
+            }
+            self.swap_owners().await
+        })
+        .await;
+        if let Err(e) = swapped {
+            println!("[TradeOffer::accept] Failed to swap owners: {:?}", e);
+            return Some(e);
+        }
+
+        match Self::find_one(self.id.clone()).await {
+            Ok(trade_offer) => *self = trade_offer,
+            Err(e) => return Some(e),
+        };
+        None
+    }
+
+    pub async fn decline(&mut self, user_id: &str) -> Option<anyhow::Error> {
+        if !self.can_respond(user_id) {
+            return Some(anyhow::anyhow!("Trade offer cannot be declined"));
+        }
+        // Only a pending offer can be declined, even if it was accepted since
+        // this copy was loaded
+        let query = sqlx::query(
+            "UPDATE trade_offers SET status = $1, updated_at = CURRENT_TIMESTAMP \
+             WHERE id = $2 AND status = $3 RETURNING *",
+        )
+        .bind(TradeOfferStatus::Declined.to_string())
+        .bind(&self.id)
+        .bind(TradeOfferStatus::Pending.to_string());
+        let row = match fetch_optional("trade_offers", "decline", query).await {
+            Ok(Some(row)) => row,
+            Ok(None) => return Some(anyhow::anyhow!("Trade offer is no longer pending")),
+            Err(e) => {
+                println!(
+                    "[TradeOffer::decline] Failed to decline trade offer: {:?}",
+                    e
+                );
+                return Some(e.into());
+            }
+        };
+        match TradeOffer::from_row(&row) {
+            Ok(trade_offer) => *self = trade_offer,
+            Err(e) => return Some(e.into()),
+        };
+        None
+    }
+
+    pub fn can_respond(&self, user_id: &str) -> bool {
+        self.status == TradeOfferStatus::Pending && self.to_user_id == user_id
+    }
+
+    /// Locks both traders and rejects a trade that would take either past the
+    /// collection cap. The locks are the ones collecting takes, so neither user
+    /// can fill their free slots before the swap commits.
+    async fn ensure_capacity(&self) -> Option<anyhow::Error> {
+        let requested = self.requested_mnstr_id.is_some() as i64;
+        let mut traders = vec![
+            (self.from_user_id.clone(), requested - 1),
+            (self.to_user_id.clone(), 1 - requested),
+        ];
+        // Lock in id order so two trades between the same users can't deadlock
+        traders.sort();
+
+        for (user_id, _) in traders.iter() {
+            let params = vec![("id", user_id.clone().into())];
+            if let Err(e) = lock_resources_where_fields!(User, params).await {
+                return Some(e.into());
+            }
+        }
+        for (user_id, adding) in traders {
+            if let Some(error) = Mnstr::ensure_collection_capacity(&user_id, adding).await {
+                return Some(error);
+            }
+        }
+        None
+    }
+
+    /// Locks the traded mnstrs and checks each is unarchived, still owned by
+    /// its trader and not in a battle. Inside a `transaction` the locks are
+    /// held until it ends.
+    async fn validate_mnstrs(&self) -> Option<anyhow::Error> {
+        let mut checks = vec![(self.offered_mnstr_id.clone(), self.from_user_id.clone())];
+        if let Some(requested_mnstr_id) = &self.requested_mnstr_id {
+            checks.push((requested_mnstr_id.clone(), self.to_user_id.clone()));
+        }
+        // Lock in id order so two trades over the same mnstrs can't deadlock
+        checks.sort();
+
+        for (mnstr_id, owner_id) in checks {
+            let params = vec![("id", mnstr_id.clone().into())];
+            let mnstr = match lock_resources_where_fields!(Mnstr, params).await {
+                Ok(mut mnstrs) => match mnstrs.pop() {
+                    Some(mnstr) => mnstr,
+                    None => return Some(anyhow::anyhow!("Mnstr {} not found", mnstr_id)),
+                },
+                Err(e) => return Some(e.into()),
+            };
+            if mnstr.user_id != owner_id {
+                return Some(anyhow::anyhow!("Mnstr {} is not owned by trader", mnstr_id));
+            }
+            match Battle::is_mnstr_locked(mnstr_id.clone()).await {
+                Ok(true) => {
+                    return Some(anyhow::anyhow!("Mnstr {} is in a battle", mnstr_id));
+                }
+                Ok(false) => (),
+                Err(e) => return Some(e),
+            }
+        }
+        None
+    }
+
+    /// Moves the mnstrs to their new owners and marks the offer accepted. Runs
+    /// inside the `transaction` that locked the mnstrs in `validate_mnstrs`.
+    async fn swap_owners(&self) -> Result<(), anyhow::Error> {
+        let query = sqlx::query(
+            "UPDATE mnstrs SET user_id = $1, updated_at = CURRENT_TIMESTAMP \
+             WHERE id = $2 AND user_id = $3 AND archived_at IS NULL",
+        )
+        .bind(&self.to_user_id)
+        .bind(&self.offered_mnstr_id)
+        .bind(&self.from_user_id);
+        let result = execute("mnstrs", "swap_owners", query).await?;
+        if result.rows_affected() != 1 {
+            return Err(anyhow::anyhow!("Offered mnstr changed owner"));
+        }
+
+        if let Some(requested_mnstr_id) = &self.requested_mnstr_id {
+            let query = sqlx::query(
+                "UPDATE mnstrs SET user_id = $1, updated_at = CURRENT_TIMESTAMP \
+                 WHERE id = $2 AND user_id = $3 AND archived_at IS NULL",
+            )
+            .bind(&self.from_user_id)
+            .bind(requested_mnstr_id)
+            .bind(&self.to_user_id);
+            let result = execute("mnstrs", "swap_owners", query).await?;
+            if result.rows_affected() != 1 {
+                return Err(anyhow::anyhow!("Requested mnstr changed owner"));
+            }
+        }
+
+        let query = sqlx::query(
+            "UPDATE trade_offers SET status = $1, updated_at = CURRENT_TIMESTAMP WHERE id = $2 AND status = $3",
+        )
+        .bind(TradeOfferStatus::Accepted.to_string())
+        .bind(&self.id)
+        .bind(TradeOfferStatus::Pending.to_string());
+        let result = execute("trade_offers", "swap_owners", query).await?;
+        if result.rows_affected() != 1 {
+            return Err(anyhow::anyhow!("Trade offer is no longer pending"));
+        }
+        Ok(())
+    }
+
+    pub async fn find_one(id: String) -> Result<Self, anyhow::Error> {
+        let trade_offer = match find_one_resource_where_fields!(
+            TradeOffer,
+            vec![("id", id.clone().into())]
+        )
+        .await
+        {
+            Ok(trade_offer) => trade_offer,
+            Err(e) => {
+                println!("[TradeOffer::find_one] Failed to get trade offer: {:?}", e);
+                return Err(e.into());
+            }
+        };
+        Ok(trade_offer)
+    }
+
+    pub async fn find_all_by(
+        params: Vec<(&str, DatabaseValue)>,
+    ) -> Result<Vec<Self>, anyhow::Error> {
+        let trade_offers = match find_all_resources_where_fields!(TradeOffer, params).await {
+            Ok(trade_offers) => trade_offers,
+            Err(e) => {
+                println!(
+                    "[TradeOffer::find_all_by] Failed to get trade offers: {:?}",
+                    e
+                );
+                return Err(e.into());
+            }
+        };
+        Ok(trade_offers)
+    }
+}
+
+impl DatabaseResource for TradeOffer {
+    fn from_row(row: &PgRow) -> Result<Self, Error> {
+        let created_at = row.get("created_at");
+        let updated_at = row.get("updated_at");
+        let archived_at = match row.get("archived_at") {
+            Some(archived_at) => archived_at,
+            None => None,
+        };
+
+        Ok(TradeOffer {
+            id: row.get("id"),
+            from_user_id: row.get("from_user_id"),
+            to_user_id: row.get("to_user_id"),
+            offered_mnstr_id: row.get("offered_mnstr_id"),
+            requested_mnstr_id: row.get("requested_mnstr_id"),
+            status: row.get::<String, _>("status").into(),
+            created_at,
+            updated_at,
+            archived_at,
+        })
+    }
+    fn has_id() -> bool {
+        true
+    }
+    fn is_archivable() -> bool {
+        true
+    }
+    fn is_updatable() -> bool {
+        true
+    }
+    fn is_creatable() -> bool {
+        true
+    }
+    fn is_expirable() -> bool {
+        false
+    }
+    fn is_verifiable() -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        database::connection::{execute, rolled_back},
+        delete_resource_where_fields,
+        models::mnstr::{CollectionFullError, max_mnstrs_per_user},
+    };
+    use uuid::Uuid;
+
+    #[test]
+    fn test_can_respond() {
+        let mut trade_offer = TradeOffer::new(
+            "from".to_string(),
+            "to".to_string(),
+            "mnstr".to_string(),
+            None,
+        );
+        assert!(trade_offer.can_respond("to"));
+        assert!(!trade_offer.can_respond("from"));
+
+        trade_offer.status = TradeOfferStatus::Declined;
+        assert!(!trade_offer.can_respond("to"));
+
+        trade_offer.status = TradeOfferStatus::Accepted;
+        assert!(!trade_offer.can_respond("to"));
+    }
+
+    async fn create_test_user() -> Result<User, anyhow::Error> {
+        let name = Uuid::new_v4().to_string();
+        let mut user = User::new(
+            Some(format!("{}@example.com", name)),
+            None,
+            "password".to_string(),
+            name,
+        );
+        if let Some(error) = user.create().await {
+            return Err(error);
+        }
+        Ok(user)
+    }
+
+    async fn create_test_mnstr(user: &User) -> Result<Mnstr, anyhow::Error> {
+        let mut mnstr = Mnstr::new(user.id.clone(), None, None, Uuid::new_v4().to_string());
+        if let Some(error) = mnstr.create().await {
+            return Err(error);
+        }
+        Ok(mnstr)
+    }
+
+    /// Two users, each with a mnstr, and an offer to swap them.
+    async fn create_test_trade() -> Result<(TradeOffer, Mnstr, Mnstr), anyhow::Error> {
+        let from = create_test_user().await?;
+        let to = create_test_user().await?;
+        let offered = create_test_mnstr(&from).await?;
+        let requested = create_test_mnstr(&to).await?;
+        let mut trade_offer = TradeOffer::new(
+            from.id.clone(),
+            to.id.clone(),
+            offered.id.clone(),
+            Some(requested.id.clone()),
+        );
+        if let Some(error) = trade_offer.create().await {
+            return Err(error);
+        }
+        Ok((trade_offer, offered, requested))
+    }
+
+    async fn owner_of(mnstr: &Mnstr) -> String {
+        Mnstr::find_one(mnstr.id.clone(), false)
+            .await
+            .unwrap()
+            .user_id
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a migrated database at DATABASE_URL"]
+    async fn test_accepting_swaps_owners() {
+        rolled_back(async {
+            let (mut trade_offer, offered, requested) = create_test_trade().await?;
+            let to_user_id = trade_offer.to_user_id.clone();
+
+            assert!(
+                trade_offer
+                    .accept(&trade_offer.from_user_id.clone())
+                    .await
+                    .is_some()
+            );
+            assert!(trade_offer.accept(&to_user_id).await.is_none());
+            assert_eq!(trade_offer.status, TradeOfferStatus::Accepted);
+            assert_eq!(owner_of(&offered).await, trade_offer.to_user_id);
+            assert_eq!(owner_of(&requested).await, trade_offer.from_user_id);
+            Ok(())
+        })
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a migrated database at DATABASE_URL"]
+    async fn test_accepting_refuses_an_archived_mnstr() {
+        rolled_back(async {
+            let (mut trade_offer, offered, requested) = create_test_trade().await?;
+            delete_resource_where_fields!(Mnstr, vec![("id", offered.id.clone().into())]).await?;
+
+            let to_user_id = trade_offer.to_user_id.clone();
+            assert!(trade_offer.accept(&to_user_id).await.is_some());
+            let trade_offer = TradeOffer::find_one(trade_offer.id.clone()).await?;
+            assert_eq!(trade_offer.status, TradeOfferStatus::Pending);
+            assert_eq!(owner_of(&requested).await, trade_offer.to_user_id);
+            Ok(())
+        })
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a migrated database at DATABASE_URL"]
+    async fn test_declined_offer_cannot_be_accepted() {
+        rolled_back(async {
+            let (mut trade_offer, offered, requested) = create_test_trade().await?;
+            let mut stale = trade_offer.clone();
+            let to_user_id = trade_offer.to_user_id.clone();
+
+            assert!(
+                trade_offer
+                    .decline(&trade_offer.from_user_id.clone())
+                    .await
+                    .is_some()
+            );
+            assert!(trade_offer.decline(&to_user_id).await.is_none());
+            assert_eq!(trade_offer.status, TradeOfferStatus::Declined);
+
+            assert!(stale.accept(&to_user_id).await.is_some());
+            assert_eq!(owner_of(&offered).await, trade_offer.from_user_id);
+            assert_eq!(owner_of(&requested).await, trade_offer.to_user_id);
+            Ok(())
+        })
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a migrated database at DATABASE_URL"]
+    async fn test_accepted_offer_cannot_be_declined() {
+        rolled_back(async {
+            let (mut trade_offer, _, _) = create_test_trade().await?;
+            let mut stale = trade_offer.clone();
+            let to_user_id = trade_offer.to_user_id.clone();
+
+            assert!(trade_offer.accept(&to_user_id).await.is_none());
+            assert!(stale.decline(&to_user_id).await.is_some());
+            let trade_offer = TradeOffer::find_one(trade_offer.id.clone()).await?;
+            assert_eq!(trade_offer.status, TradeOfferStatus::Accepted);
+            Ok(())
+        })
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a migrated database at DATABASE_URL"]
+    async fn test_accepting_refuses_to_overfill_a_collection() {
+        rolled_back(async {
+            let from = create_test_user().await?;
+            let to = create_test_user().await?;
+            let offered = create_test_mnstr(&from).await?;
+            let query = sqlx::query(
+                "INSERT INTO mnstrs (id, user_id, mnstr_qr_code) \
+                 SELECT gen_random_uuid()::text, $1, gen_random_uuid()::text \
+                 FROM generate_series(1, $2)",
+            )
+            .bind(&to.id)
+            .bind(max_mnstrs_per_user() as i32);
+            execute("mnstrs", "fill", query).await?;
+
+            let mut trade_offer =
+                TradeOffer::new(from.id.clone(), to.id.clone(), offered.id.clone(), None);
+            if let Some(error) = trade_offer.create().await {
+                return Err(error);
+            }
+            let error = trade_offer.accept(&to.id).await.unwrap();
+            assert!(error.downcast_ref::<CollectionFullError>().is_some());
+            assert_eq!(owner_of(&offered).await, from.id);
+            Ok(())
+        })
+        .await
+        .unwrap();
+    }
+}
@@ -7,14 +7,23 @@ use sqlx::{
 use time::OffsetDateTime;
 
 use crate::{
-    database::{traits::DatabaseResource, values::DatabaseValue},
+    database::{
+        traits::{ArchivedFilter, DatabaseResource},
+        values::DatabaseValue,
+    },
     delete_resource_where_fields, find_all_resources_where_fields, find_one_resource_where_fields,
+    find_page_after, find_page_ordered,
+    graphql::pagination::PageRequest,
     insert_resource,
     proto::Transaction as GrpcTransaction,
     update_resource,
     utils::time::{deserialize_offset_date_time, serialize_offset_date_time},
 };
 
+/// Upper bound for a single transaction amount. Amounts are always stored as
+/// positive magnitudes; the transaction type carries the sign.
+pub const MAX_TRANSACTION: i32 = 1_000_000;
+
 #[derive(Debug, Serialize, Deserialize, GraphQLEnum, Clone)]
 pub enum TransactionType {
     Credit,
@@ -171,7 +180,28 @@ impl Transaction {
         }
     }
 
+    pub fn validate_amount(&self) -> Option<anyhow::Error> {
+        if self.transaction_amount < 0 {
+            return Some(anyhow::anyhow!(
+                "Transaction amount must not be negative: {}",
+                self.transaction_amount
+            ));
+        }
+        if self.transaction_amount > MAX_TRANSACTION {
+            return Some(anyhow::anyhow!(
+                "Transaction amount exceeds maximum of {}: {}",
+                MAX_TRANSACTION,
+                self.transaction_amount
+            ));
+        }
+        None
+    }
+
     pub async fn create(&mut self) -> Option<anyhow::Error> {
+        if let Some(error) = self.validate_amount() {
+            println!("[Transaction::create] Invalid amount: {:?}", error);
+            return Some(error);
+        }
         let params = vec![
             ("wallet_id", self.wallet_id.clone().into()),
             (
@@ -183,17 +213,8 @@ impl Transaction {
                 "transaction_status",
                 self.transaction_status.clone().to_string().into(),
             ),
-            (
-                "transaction_data",
-                self.transaction_data
-                    .clone()
-                    .unwrap_or("".to_string())
-                    .into(),
-            ),
-            (
-                "error_message",
-                self.error_message.clone().unwrap_or("".to_string()).into(),
-            ),
+            ("transaction_data", self.transaction_data.clone().into()),
+            ("error_message", self.error_message.clone().into()),
         ];
         let transaction = match insert_resource!(Transaction, params).await {
             Ok(transaction) => transaction,
@@ -209,31 +230,42 @@ impl Transaction {
         None
     }
 
+    /// Records a completed credit or debit on `wallet_id`. Coins only move
+    /// through here, so every amount is validated before it's stored.
+    pub async fn record(
+        wallet_id: String,
+        transaction_type: TransactionType,
+        amount: i32,
+        data: Option<String>,
+    ) -> Result<Self, anyhow::Error> {
+        let mut transaction = Self::new(wallet_id);
+        transaction.transaction_type = transaction_type;
+        transaction.transaction_amount = amount;
+        transaction.transaction_status = TransactionStatus::Completed;
+        transaction.transaction_data = data;
+        match transaction.create().await {
+            Some(error) => Err(error),
+            None => Ok(transaction),
+        }
+    }
+
     pub async fn update(&mut self) -> Option<anyhow::Error> {
+        if let Some(error) = self.validate_amount() {
+            println!("[Transaction::update] Invalid amount: {:?}", error);
+            return Some(error);
+        }
         let params = vec![
             (
                 "transaction_type",
                 self.transaction_type.clone().to_string().into(),
             ),
-            (
-                "transaction_amount",
-                self.transaction_amount.clone().to_string().into(),
-            ),
+            ("transaction_amount", self.transaction_amount.clone().into()),
             (
                 "transaction_status",
                 self.transaction_status.clone().to_string().into(),
             ),
-            (
-                "transaction_data",
-                self.transaction_data
-                    .clone()
-                    .unwrap_or("".to_string())
-                    .into(),
-            ),
-            (
-                "error_message",
-                self.error_message.clone().unwrap_or("".to_string()).into(),
-            ),
+            ("transaction_data", self.transaction_data.clone().into()),
+            ("error_message", self.error_message.clone().into()),
         ];
         let transaction = match update_resource!(Transaction, self.id.clone(), params).await {
             Ok(transaction) => transaction,
@@ -358,6 +390,50 @@ impl Transaction {
         Ok(transactions)
     }
 
+    /// Keyset page of transactions with ids after `after`, ordered by `id`.
+    pub async fn find_page_by(
+        params: Vec<(&str, DatabaseValue)>,
+        after: Option<String>,
+        limit: i64,
+    ) -> Result<Vec<Self>, anyhow::Error> {
+        match find_page_after!(
+            Transaction,
+            params,
+            "id",
+            after.map(|after| after.into()),
+            limit
+        )
+        .await
+        {
+            Ok(transactions) => Ok(transactions),
+            Err(e) => {
+                println!(
+                    "[Transaction::find_page_by] Failed to find transactions: {:?}",
+                    e
+                );
+                Err(e.into())
+            }
+        }
+    }
+
+    /// An offset page of a wallet's transactions.
+    pub async fn find_history(
+        wallet_id: String,
+        page: &PageRequest,
+    ) -> Result<Vec<Self>, anyhow::Error> {
+        let params = vec![("wallet_id", wallet_id.into())];
+        match find_page_ordered!(Transaction, params, ArchivedFilter::Any, page).await {
+            Ok(transactions) => Ok(transactions),
+            Err(e) => {
+                println!(
+                    "[Transaction::find_history] Failed to find transactions: {:?}",
+                    e
+                );
+                Err(e.into())
+            }
+        }
+    }
+
     pub async fn get_relationships(&mut self) -> Option<anyhow::Error> {
         None
     }
@@ -399,3 +475,59 @@ impl DatabaseResource for Transaction {
         false
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        database::connection::rolled_back,
+        models::{user::User, wallet::Wallet},
+    };
+
+    #[test]
+    fn test_validate_amount() {
+        let mut transaction = Transaction::new("wallet".to_string());
+        transaction.transaction_amount = 0;
+        assert!(transaction.validate_amount().is_none());
+        transaction.transaction_amount = MAX_TRANSACTION;
+        assert!(transaction.validate_amount().is_none());
+        transaction.transaction_amount = -1;
+        assert!(transaction.validate_amount().is_some());
+        transaction.transaction_amount = MAX_TRANSACTION + 1;
+        assert!(transaction.validate_amount().is_some());
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a migrated database at DATABASE_URL"]
+    async fn test_record_rejects_invalid_amounts() {
+        rolled_back(async {
+            let name = uuid::Uuid::new_v4().to_string();
+            let mut user = User::new(
+                Some(format!("{}@example.com", name)),
+                None,
+                "password".to_string(),
+                name,
+            );
+            assert!(user.create().await.is_none());
+            user.get_wallet().await;
+            let wallet_id = user.wallet.unwrap().id;
+
+            for amount in [-1, MAX_TRANSACTION + 1] {
+                let recorded =
+                    Transaction::record(wallet_id.clone(), TransactionType::Credit, amount, None)
+                        .await;
+                assert!(recorded.is_err());
+            }
+            let recorded =
+                Transaction::record(wallet_id.clone(), TransactionType::Credit, 25, None).await?;
+            assert!(matches!(
+                recorded.transaction_status,
+                TransactionStatus::Completed
+            ));
+            assert_eq!(Wallet::balance_for_user(user.id.clone()).await?, 25);
+            Ok(())
+        })
+        .await
+        .unwrap();
+    }
+}
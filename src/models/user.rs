@@ -1,22 +1,45 @@
-use juniper::GraphQLObject;
+use juniper::{FieldError, GraphQLObject};
 use serde::{Deserialize, Serialize};
 use sqlx::{Row, postgres::PgRow};
-use time::OffsetDateTime;
+use time::{Duration, OffsetDateTime, UtcOffset};
+use uuid::Uuid;
 
 use crate::{
-    database::{traits::DatabaseResource, values::DatabaseValue},
-    delete_resource_where_fields, find_all_resources_where_fields, find_one_resource_where_fields,
-    insert_resource,
-    models::{generated::level_xp::XP_FOR_LEVEL, mnstr::Mnstr, session::Session, wallet::Wallet},
+    count_resources_where_fields,
+    database::{
+        connection::{fetch_all, fetch_optional, transaction},
+        traits::{ArchivedFilter, DatabaseResource},
+        values::DatabaseValue,
+    },
+    delete_resource_where_fields, find_all_resources_where_fields,
+    find_all_resources_where_fields_in, find_all_unarchived_resources_where_fields,
+    find_one_resource_where_fields, find_page_after, find_page_ordered,
+    graphql::pagination::PageRequest,
+    insert_resource, lock_resources_where_fields,
+    models::{
+        generated::level_xp::XP_FOR_LEVEL,
+        mnstr::Mnstr,
+        session::Session,
+        transaction::{Transaction, TransactionType},
+        wallet::Wallet,
+        xp::xp_to_next_level,
+    },
     proto::User as GrpcUser,
-    update_resource,
+    update_resource, update_resource_fields,
     utils::{
+        contact::{normalize_email, normalize_phone},
+        emails::EmailTemplate,
+        multipliers::{award_coins, award_xp},
         passwords::hash_password,
+        redact::redact_debug,
+        strings::escape_like,
         time::{deserialize_offset_date_time, serialize_offset_date_time},
     },
 };
 
-#[derive(Debug, Serialize, Deserialize, GraphQLObject, Clone)]
+pub const DAILY_REWARD_COINS: i32 = 100;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct User {
     pub id: String,
     pub email: Option<String>,
@@ -25,6 +48,11 @@ pub struct User {
     pub phone_verification_code: Option<String>,
     pub email_verified: bool,
     pub phone_verified: bool,
+    /// Whether the user accepts non-essential emails. Security emails, like
+    /// verification codes, are sent either way.
+    pub email_opt_in: bool,
+    /// Whether the user accepts non-essential text messages.
+    pub sms_opt_in: bool,
     pub display_name: String,
     pub password_hash: String,
     pub experience_level: i32,
@@ -50,6 +78,18 @@ pub struct User {
     )]
     pub archived_at: Option<OffsetDateTime>,
 
+    #[serde(
+        serialize_with = "serialize_offset_date_time",
+        deserialize_with = "deserialize_offset_date_time"
+    )]
+    pub last_daily_claim_at: Option<OffsetDateTime>,
+
+    pub primary_mnstr_id: Option<String>,
+
+    /// Set while the account is a guest; hashed like passwords.
+    #[serde(skip)]
+    pub guest_claim_token_hash: Option<String>,
+
     // Relationships
     pub wallet: Option<Wallet>,
     pub mnstrs: Vec<Mnstr>,
@@ -71,6 +111,8 @@ impl User {
             phone_verification_code: None,
             email_verified: false,
             phone_verified: false,
+            email_opt_in: true,
+            sms_opt_in: true,
             password_hash,
             display_name,
             experience_level: 0,
@@ -80,6 +122,9 @@ impl User {
             created_at: None,
             updated_at: None,
             archived_at: None,
+            last_daily_claim_at: None,
+            primary_mnstr_id: None,
+            guest_claim_token_hash: None,
             wallet: None,
             mnstrs: Vec::new(),
         }
@@ -105,13 +150,19 @@ impl User {
             "[User::create] Creating user: {:?}",
             self.display_name.clone()
         );
+        if let Some(error) = self.normalize_contact() {
+            println!("[User::create] Invalid contact details: {:?}", error);
+            return Some(error);
+        }
         let params = vec![
             ("password_hash", self.password_hash.clone().into()),
             ("phone", self.phone.clone().into()),
             ("email", self.email.clone().into()),
-            ("display_name ", self.display_name.clone().into()),
+            ("display_name", self.display_name.clone().into()),
             ("email_verified", self.email_verified.clone().into()),
             ("phone_verified", self.phone_verified.clone().into()),
+            ("email_opt_in", self.email_opt_in.into()),
+            ("sms_opt_in", self.sms_opt_in.into()),
             (
                 "email_verification_code",
                 self.email_verification_code.clone().into(),
@@ -120,11 +171,15 @@ impl User {
                 "phone_verification_code",
                 self.phone_verification_code.clone().into(),
             ),
+            (
+                "guest_claim_token_hash",
+                self.guest_claim_token_hash.clone().into(),
+            ),
         ];
         let mut user = match insert_resource!(User, params).await {
             Ok(user) => user,
             Err(e) => {
-                println!("[User::create] Failed to create user: {:?}", e);
+                println!("[User::create] Failed to create user: {}", redact_debug(&e));
                 return Some(e.into());
             }
         };
@@ -140,6 +195,10 @@ impl User {
 
     pub async fn update(&mut self) -> Option<anyhow::Error> {
         println!("[User::update] Updating user: {:?}", self.id);
+        if let Some(error) = self.normalize_contact() {
+            println!("[User::update] Invalid contact details: {:?}", error);
+            return Some(error);
+        }
 
         let params = vec![
             ("display_name", self.display_name.clone().into()),
@@ -155,14 +214,18 @@ impl User {
             ),
             ("email_verified", self.email_verified.clone().into()),
             ("phone_verified", self.phone_verified.clone().into()),
+            ("email_opt_in", self.email_opt_in.into()),
+            ("sms_opt_in", self.sms_opt_in.into()),
             ("experience_level", self.experience_level.clone().into()),
             ("experience_points", self.experience_points.clone().into()),
             ("password_hash", self.password_hash.clone().into()),
         ];
+        // last_daily_claim_at is only written by claim_daily's conditional
+        // update, so saving a stale copy can't undo a claim
         let mut user = match update_resource!(User, self.id.clone(), params).await {
             Ok(user) => user,
             Err(e) => {
-                println!("[User::update] Failed to update user: {:?}", e);
+                println!("[User::update] Failed to update user: {}", redact_debug(&e));
                 return Some(e.into());
             }
         };
@@ -176,6 +239,36 @@ impl User {
         None
     }
 
+    /// Writes only the given columns; see `update_resource_fields!` for how
+    /// absent and null entries are treated.
+    pub async fn update_fields(
+        &mut self,
+        params: Vec<(&str, Option<DatabaseValue>)>,
+    ) -> Option<anyhow::Error> {
+        println!("[User::update_fields] Updating user: {:?}", self.id);
+        let mut user = match update_resource_fields!(User, self.id.clone(), params).await {
+            Ok(user) => user,
+            Err(e) => {
+                println!(
+                    "[User::update_fields] Failed to update user: {}",
+                    redact_debug(&e)
+                );
+                return Some(e.into());
+            }
+        };
+
+        if let Some(error) = user.get_relationships().await {
+            println!(
+                "[User::update_fields] Failed to get relationships: {:?}",
+                error
+            );
+            return Some(error);
+        }
+
+        *self = user;
+        None
+    }
+
     pub async fn delete_permanent(&mut self) -> Option<anyhow::Error> {
         let user = match Self::find_one(self.id.clone(), false).await {
             Ok(user) => user,
@@ -198,7 +291,7 @@ impl User {
         }
 
         let mut sessions =
-            match Session::find_all_by(vec![("user_id", self.id.clone().into())]).await {
+            match Session::find_all_by(vec![("user_id", self.id.clone().into())], false).await {
                 Ok(sessions) => sessions,
                 Err(e) => {
                     println!("[User::delete_permanent] Failed to get sessions: {:?}", e);
@@ -216,7 +309,16 @@ impl User {
             }
         }
 
-        if let Some(error) = self.wallet.as_mut().unwrap().delete_permanent().await {
+        let wallet = match Wallet::find_optional_by(vec![("user_id", self.id.clone().into())]).await
+        {
+            Ok(wallet) => wallet,
+            Err(error) => {
+                println!("[User::delete_permanent] Failed to get wallet: {:?}", error);
+                return Some(error);
+            }
+        };
+
+        if let Some(error) = delete_wallet(wallet).await {
             println!(
                 "[User::delete_permanent] Failed to delete wallet: {:?}",
                 error
@@ -235,6 +337,48 @@ impl User {
         None
     }
 
+    /// Deactivates the account: the user is archived and every session is
+    /// revoked, while mnstrs, wallet and history are kept for `unarchive`.
+    pub async fn archive(&mut self) -> Option<anyhow::Error> {
+        let archived = transaction(async {
+            // The lock Session::create takes, so a login can't add a session
+            // between revoking them and archiving the user
+            lock_resources_where_fields!(User, vec![("id", self.id.clone().into())]).await?;
+            let sessions = find_all_resources_where_fields!(
+                Session,
+                vec![("user_id", self.id.clone().into())]
+            )
+            .await?;
+            for session in sessions {
+                delete_resource_where_fields!(Session, vec![("id", session.id.into())], true)
+                    .await?;
+            }
+            Ok(delete_resource_where_fields!(User, vec![("id", self.id.clone().into())]).await?)
+        })
+        .await;
+        match archived {
+            Ok(user) => *self = user,
+            Err(e) => {
+                println!("[User::archive] Failed to archive user: {:?}", e);
+                return Some(e);
+            }
+        };
+        None
+    }
+
+    pub async fn unarchive(&mut self) -> Option<anyhow::Error> {
+        let params = vec![("archived_at", DatabaseValue::None)];
+        let user = match update_resource!(User, self.id.clone(), params).await {
+            Ok(user) => user,
+            Err(e) => {
+                println!("[User::unarchive] Failed to unarchive user: {:?}", e);
+                return Some(e.into());
+            }
+        };
+        *self = user;
+        None
+    }
+
     pub async fn find_one(id: String, get_relationships: bool) -> Result<Self, anyhow::Error> {
         let params = vec![("id", id.clone().into())];
         let mut user = match find_one_resource_where_fields!(User, params).await {
@@ -324,6 +468,113 @@ impl User {
         Ok(users)
     }
 
+    /// How many users match `params`, without loading them.
+    pub async fn count_by(params: Vec<(&str, DatabaseValue)>) -> Result<i64, anyhow::Error> {
+        match count_resources_where_fields!(User, params).await {
+            Ok(count) => Ok(count),
+            Err(e) => {
+                println!("[User::count_by] Failed to count users: {:?}", e);
+                Err(e.into())
+            }
+        }
+    }
+
+    /// The users with `ids`, in one query. Ids without a user are skipped.
+    pub async fn find_all_by_ids(ids: Vec<String>) -> Result<Vec<Self>, anyhow::Error> {
+        if ids.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let mut users = match find_all_resources_where_fields_in!(User, "id", ids).await {
+            Ok(users) => users,
+            Err(e) => {
+                println!("[User::find_all_by_ids] Failed to get users: {:?}", e);
+                return Err(e.into());
+            }
+        };
+        for user in users.iter_mut() {
+            user.update_experience_to_next_level();
+        }
+        Ok(users)
+    }
+
+    /// Keyset page of users with ids after `after`, ordered by `id`.
+    pub async fn find_page_by(
+        params: Vec<(&str, DatabaseValue)>,
+        after: Option<String>,
+        limit: i64,
+    ) -> Result<Vec<Self>, anyhow::Error> {
+        let mut users = match find_page_after!(
+            User,
+            params,
+            "id",
+            after.map(|after| after.into()),
+            limit
+        )
+        .await
+        {
+            Ok(users) => users,
+            Err(e) => {
+                println!("[User::find_page_by] Failed to get users: {:?}", e);
+                return Err(e.into());
+            }
+        };
+        for user in users.iter_mut() {
+            user.update_experience_to_next_level();
+        }
+        Ok(users)
+    }
+
+    /// A page of active users, ordered as the leaderboard was asked for.
+    pub async fn find_leaderboard(page: &PageRequest) -> Result<Vec<Self>, anyhow::Error> {
+        let mut users =
+            match find_page_ordered!(User, vec![], ArchivedFilter::Unarchived, page).await {
+                Ok(users) => users,
+                Err(e) => {
+                    println!("[User::find_leaderboard] Failed to get users: {:?}", e);
+                    return Err(e.into());
+                }
+            };
+        for user in users.iter_mut() {
+            user.update_experience_to_next_level();
+        }
+        Ok(users)
+    }
+
+    /// A page of active users whose display name contains `term`, ignoring
+    /// case.
+    pub async fn search(term: &str, page: &PageRequest) -> Result<Vec<Self>, anyhow::Error> {
+        let query = format!(
+            "SELECT * FROM users WHERE archived_at IS NULL AND display_name ILIKE $1{} \
+             LIMIT $2 OFFSET $3",
+            page.order_clause()
+        );
+        let query = sqlx::query(sqlx::AssertSqlSafe(query))
+            .bind(format!("%{}%", escape_like(term)))
+            .bind(page.limit)
+            .bind(page.offset);
+        let rows = match fetch_all("users", "search", query).await {
+            Ok(rows) => rows,
+            Err(e) => {
+                println!("[User::search] Failed to search users: {:?}", e);
+                return Err(anyhow::Error::msg(e.to_string()));
+            }
+        };
+        let mut users = rows
+            .iter()
+            .map(User::from_row)
+            .collect::<Result<Vec<User>, _>>()?;
+        for user in users.iter_mut() {
+            user.update_experience_to_next_level();
+        }
+        Ok(users)
+    }
+
+    /// The balance computed while loading relationships, if the wallet was loaded.
+    pub fn loaded_coins(&self) -> Option<i32> {
+        self.wallet.as_ref().map(|_| self.coins)
+    }
+
     pub async fn get_relationships(&mut self) -> Option<anyhow::Error> {
         if let Some(error) = self.get_wallet().await {
             println!(
@@ -366,7 +617,7 @@ impl User {
 
     pub async fn get_mnstrs(&mut self) -> Option<anyhow::Error> {
         println!("[User::get_mnstrs] Getting mnstrs: {:?}", self.id);
-        let mnstrs = match find_all_resources_where_fields!(
+        let mnstrs = match find_all_unarchived_resources_where_fields!(
             Mnstr,
             vec![("user_id", self.id.clone().into())]
         )
@@ -410,18 +661,14 @@ impl User {
     pub async fn create_wallet(&mut self) -> Option<anyhow::Error> {
         println!("[User::create_wallet] Creating wallet: {:?}", self.id);
         let found_wallet =
-            match Wallet::find_one_by(vec![("user_id", self.id.clone().into())]).await {
-                Ok(wallet) => Some(wallet),
-                Err(_) => None,
+            match Wallet::find_optional_by(vec![("user_id", self.id.clone().into())]).await {
+                Ok(wallet) => wallet,
+                Err(error) => {
+                    println!("[User::create_wallet] Failed to find wallet: {:?}", error);
+                    return Some(error);
+                }
             };
-        if let Some(mut found_wallet) = found_wallet {
-            if let Some(error) = found_wallet.get_relationships().await {
-                println!(
-                    "[User::create_wallet] Failed to get wallet relationships: {:?}",
-                    error
-                );
-                return Some(error.into());
-            }
+        if let Some(found_wallet) = found_wallet {
             self.wallet = Some(found_wallet);
             return None;
         }
@@ -436,47 +683,47 @@ impl User {
     }
 
     pub fn update_experience_to_next_level(&mut self) {
-        let last_level_index = XP_FOR_LEVEL.len() as i32 - 1;
-        let mut xp_to_next_level = XP_FOR_LEVEL[last_level_index as usize];
-        if self.experience_level < last_level_index {
-            xp_to_next_level = XP_FOR_LEVEL[self.experience_level as usize + 1];
-        }
-        self.experience_to_next_level = xp_to_next_level;
+        self.experience_to_next_level = xp_to_next_level(&XP_FOR_LEVEL, self.experience_level);
     }
 
+    /// Awards `xp`, scaled by the event XP multiplier, and saves the user.
     pub async fn update_xp(&mut self, xp: i32) -> Option<anyhow::Error> {
+        self.apply_xp(award_xp(xp, "User::update_xp"));
+
+        if let Some(error) = self.update().await {
+            println!("[User::update_xp] Failed to update user xp: {:?}", error);
+            return Some(error.into());
+        }
+        None
+    }
+
+    /// Adds `xp` and levels the user up in memory, without saving.
+    pub fn apply_xp(&mut self, xp: i32) {
         self.experience_points += xp;
 
         let last_level_index = XP_FOR_LEVEL.len() as i32 - 1;
-        let mut xp_to_next_level = XP_FOR_LEVEL[last_level_index as usize];
-        if self.experience_level < last_level_index {
-            xp_to_next_level = XP_FOR_LEVEL[self.experience_level as usize + 1];
-        }
-        let xp_overage = self.experience_points - xp_to_next_level;
+        let mut xp_needed = xp_to_next_level(&XP_FOR_LEVEL, self.experience_level);
+        let xp_overage = self.experience_points - xp_needed;
 
+        // Users stop at the last level of the curve
         let mut remaining_overage = xp_overage;
-        while remaining_overage >= 0 {
+        while remaining_overage >= 0 && self.experience_level < last_level_index {
             self.experience_points = remaining_overage;
             self.experience_level += 1;
-            xp_to_next_level = XP_FOR_LEVEL[self.experience_level as usize + 1];
-            remaining_overage -= xp_to_next_level;
+            xp_needed = xp_to_next_level(&XP_FOR_LEVEL, self.experience_level);
+            remaining_overage -= xp_needed;
 
-            xp_to_next_level = XP_FOR_LEVEL[self.experience_level as usize + 1];
             if remaining_overage < 0 {
                 self.experience_points = 0;
             }
         }
 
-        self.experience_to_next_level = xp_to_next_level;
-
-        if let Some(error) = self.update().await {
-            println!("[User::update_xp] Failed to update user xp: {:?}", error);
-            return Some(error.into());
-        }
-        None
+        self.experience_to_next_level = xp_needed;
     }
 
+    /// Credits `coins`, scaled by the event coin multiplier.
     pub async fn add_coins(&mut self, coins: i32) -> Option<anyhow::Error> {
+        let coins = award_coins(coins, "User::add_coins");
         println!("[User::add_coins] Adding coins: {:?}", coins);
         if let Some(error) = self.get_wallet().await {
             println!("[User::add_coins] Failed to get wallet: {:?}", error);
@@ -491,6 +738,523 @@ impl User {
         }
         None
     }
+
+    pub fn normalize_contact(&mut self) -> Option<anyhow::Error> {
+        if let Some(email) = &self.email {
+            match normalize_email(email) {
+                Ok(email) => self.email = Some(email),
+                Err(e) => return Some(e),
+            }
+        }
+        if let Some(phone) = &self.phone {
+            match normalize_phone(phone) {
+                Ok(phone) => self.phone = Some(phone),
+                Err(e) => return Some(e),
+            }
+        }
+        None
+    }
+
+    /// Only accounts that haven't been archived and have verified their email
+    /// may obtain sessions.
+    pub fn is_active(&self) -> bool {
+        self.archived_at.is_none() && self.email_verified
+    }
+
+    /// Why this user can't log in with `identifier` even though their password
+    /// matched, or `None` if they can. Each identifier has to be verified on its
+    /// own, so a phone-only account logs in with its verified phone.
+    pub fn login_refusal(&self, identifier: &LoginIdentifier) -> Option<LoginRefusal> {
+        if self.archived_at.is_some() {
+            return Some(LoginRefusal::Deactivated);
+        }
+        match identifier {
+            LoginIdentifier::Email(_) if !self.email_verified => {
+                Some(LoginRefusal::EmailNotVerified)
+            }
+            LoginIdentifier::Phone(_) if !self.phone_verified => {
+                Some(LoginRefusal::PhoneNotVerified)
+            }
+            _ => None,
+        }
+    }
+
+    pub fn can_claim_daily(&self, now: OffsetDateTime) -> bool {
+        match self.last_daily_claim_at {
+            Some(last_daily_claim_at) => {
+                last_daily_claim_at.to_offset(UtcOffset::UTC).date()
+                    < now.to_offset(UtcOffset::UTC).date()
+            }
+            None => true,
+        }
+    }
+
+    /// Whether `template` may be emailed to the user. Essential emails, like
+    /// verification codes, ignore the opt-out.
+    pub fn receives_email(&self, template: &EmailTemplate) -> bool {
+        template.is_essential() || self.email_opt_in
+    }
+
+    /// Whether a text message may be sent to the user, with `essential` ones
+    /// ignoring the opt-out.
+    pub fn receives_sms(&self, essential: bool) -> bool {
+        essential || self.sms_opt_in
+    }
+
+    /// Only mnstrs the user still owns can be their primary.
+    pub fn owns_mnstr(&self, mnstr: &Mnstr) -> bool {
+        mnstr.user_id == self.id && mnstr.archived_at.is_none()
+    }
+
+    pub async fn set_primary_mnstr(&mut self, mnstr: &Mnstr) -> Option<anyhow::Error> {
+        if !self.owns_mnstr(mnstr) {
+            return Some(anyhow::anyhow!("Mnstr {} is not owned by user", mnstr.id));
+        }
+        let params = vec![("primary_mnstr_id", Some(mnstr.id.clone().into()))];
+        self.update_fields(params).await
+    }
+
+    /// The primary mnstr, unless it has since been traded away or released.
+    pub async fn primary_mnstr(&self) -> Result<Option<Mnstr>, anyhow::Error> {
+        let primary_mnstr_id = match &self.primary_mnstr_id {
+            Some(primary_mnstr_id) => primary_mnstr_id.clone(),
+            None => return Ok(None),
+        };
+        let mnstr = match Mnstr::find_one(primary_mnstr_id, false).await {
+            Ok(mnstr) => mnstr,
+            Err(e) => {
+                println!("[User::primary_mnstr] Failed to get mnstr: {:?}", e);
+                return Err(e);
+            }
+        };
+        if !self.owns_mnstr(&mnstr) {
+            return Ok(None);
+        }
+        Ok(Some(mnstr))
+    }
+
+    /// Records today's claim and credits `DAILY_REWARD_COINS` in one
+    /// transaction. The claim is a conditional update, so of two racing
+    /// claims only one finds the user unclaimed for the day.
+    pub async fn claim_daily(&mut self) -> Option<anyhow::Error> {
+        let now = OffsetDateTime::now_utc();
+        let start_of_day = now.to_offset(UtcOffset::UTC).date().midnight().assume_utc();
+
+        let result = transaction(async {
+            let query = sqlx::query(
+                "UPDATE users SET last_daily_claim_at = $1, updated_at = CURRENT_TIMESTAMP \
+                 WHERE id = $2 AND archived_at IS NULL \
+                 AND (last_daily_claim_at IS NULL OR last_daily_claim_at < $3) \
+                 RETURNING last_daily_claim_at",
+            )
+            .bind(now)
+            .bind(&self.id)
+            .bind(start_of_day);
+            let row = match fetch_optional("users", "claim_daily", query).await? {
+                Some(row) => row,
+                None => return Err(anyhow::anyhow!("Daily reward already claimed")),
+            };
+            self.last_daily_claim_at = row.get("last_daily_claim_at");
+
+            if let Some(error) = self.add_coins(DAILY_REWARD_COINS).await {
+                return Err(error);
+            }
+            Ok(())
+        })
+        .await;
+        if let Err(error) = result {
+            println!(
+                "[User::claim_daily] Failed to claim daily reward: {:?}",
+                error
+            );
+            return Some(error);
+        }
+        None
+    }
+
+    /// Records a practice reward unless the user had one less than `cooldown`
+    /// ago. The claim is a conditional update, so of two racing claims only one
+    /// succeeds.
+    ///
+    /// # Returns
+    ///
+    /// Returns whether the reward may be paid.
+    pub async fn claim_practice_reward(
+        user_id: String,
+        cooldown: Duration,
+    ) -> Result<bool, anyhow::Error> {
+        let now = OffsetDateTime::now_utc();
+        let query = sqlx::query(
+            "UPDATE users SET last_practice_reward_at = $1, updated_at = CURRENT_TIMESTAMP \
+             WHERE id = $2 AND archived_at IS NULL \
+             AND (last_practice_reward_at IS NULL OR last_practice_reward_at <= $3) \
+             RETURNING id",
+        )
+        .bind(now)
+        .bind(&user_id)
+        .bind(now - cooldown);
+        match fetch_optional("users", "claim_practice_reward", query).await {
+            Ok(row) => Ok(row.is_some()),
+            Err(e) => {
+                println!(
+                    "[User::claim_practice_reward] Failed to claim practice reward: {:?}",
+                    e
+                );
+                Err(e.into())
+            }
+        }
+    }
+
+    /// Guests play without contact details until they're claimed into a
+    /// registered account with their claim token.
+    pub fn is_guest(&self) -> bool {
+        self.guest_claim_token_hash.is_some()
+    }
+
+    /// Creates a guest account that can't log in with a password.
+    ///
+    /// # Returns
+    ///
+    /// The guest and its one-time claim token. Only the token's hash is stored,
+    /// so it can't be shown again.
+    pub async fn create_guest(display_name: String) -> Result<(Self, String), anyhow::Error> {
+        let token = Uuid::new_v4().to_string();
+        let mut guest = Self::new(None, None, Uuid::new_v4().to_string(), display_name);
+        guest.guest_claim_token_hash = Some(hash_password(&token));
+        if let Some(error) = guest.create().await {
+            println!("[User::create_guest] Failed to create guest: {:?}", error);
+            return Err(error);
+        }
+        Ok((guest, token))
+    }
+
+    /// Moves `guest_id`'s mnstrs and coins onto this account and archives the
+    /// guest, all in one database transaction.
+    pub async fn claim_guest(
+        &mut self,
+        guest_id: String,
+        token: String,
+    ) -> Result<GuestClaimed, anyhow::Error> {
+        if self.is_guest() {
+            return Err(anyhow::anyhow!("Guest accounts can't claim guests"));
+        }
+        let claimed = match claim_guest_into(&guest_id, &self.id, &token).await {
+            Ok(claimed) => claimed,
+            Err(e) => {
+                println!("[User::claim_guest] Failed to claim guest: {:?}", e);
+                return Err(e);
+            }
+        };
+        if let Some(error) = self.get_relationships().await {
+            println!(
+                "[User::claim_guest] Failed to get relationships: {:?}",
+                error
+            );
+            return Err(error);
+        }
+        Ok(claimed)
+    }
+
+    /// Sets a new password and revokes every session except `keep_session_id`
+    /// in one database transaction, so a stolen session doesn't outlive a
+    /// password change.
+    pub async fn change_password(
+        &mut self,
+        password: &str,
+        keep_session_id: Option<&str>,
+    ) -> Option<anyhow::Error> {
+        let password_hash = hash_password(password);
+        let changed = transaction(async {
+            update_resource!(
+                User,
+                self.id.clone(),
+                vec![("password_hash", password_hash.clone().into())]
+            )
+            .await?;
+            let sessions = find_all_resources_where_fields!(
+                Session,
+                vec![("user_id", self.id.clone().into())]
+            )
+            .await?;
+            let mut revoked = 0;
+            for session in sessions {
+                if Some(session.id.as_str()) == keep_session_id {
+                    continue;
+                }
+                delete_resource_where_fields!(Session, vec![("id", session.id.into())], true)
+                    .await?;
+                revoked += 1;
+            }
+            Ok(revoked)
+        })
+        .await;
+        match changed {
+            Ok(revoked) => println!("[User::change_password] Revoked {} sessions", revoked),
+            Err(e) => {
+                println!("[User::change_password] Failed to change password: {:?}", e);
+                return Some(e);
+            }
+        }
+        self.password_hash = password_hash;
+        None
+    }
+}
+
+/// The email address or phone number a user logs in with.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LoginIdentifier {
+    Email(String),
+    Phone(String),
+}
+
+impl LoginIdentifier {
+    /// Reads `identifier` as an email address when it has an `@`, otherwise as
+    /// a phone number, normalized the way both are stored.
+    pub fn parse(identifier: &str) -> Result<Self, anyhow::Error> {
+        match identifier.contains('@') {
+            true => Ok(LoginIdentifier::Email(normalize_email(identifier)?)),
+            false => Ok(LoginIdentifier::Phone(normalize_phone(identifier)?)),
+        }
+    }
+
+    /// The `users` column and value to look the identifier up by.
+    pub fn param(&self) -> (&'static str, DatabaseValue) {
+        match self {
+            LoginIdentifier::Email(email) => ("email", email.clone().into()),
+            LoginIdentifier::Phone(phone) => ("phone", phone.clone().into()),
+        }
+    }
+}
+
+/// Why a user whose password matched still can't have a session.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LoginRefusal {
+    Deactivated,
+    EmailNotVerified,
+    PhoneNotVerified,
+}
+
+impl LoginRefusal {
+    pub fn message(&self) -> &'static str {
+        match self {
+            LoginRefusal::Deactivated => "Account has been deactivated",
+            LoginRefusal::EmailNotVerified => "Email address has not been verified",
+            LoginRefusal::PhoneNotVerified => "Phone number has not been verified",
+        }
+    }
+}
+
+pub const INVALID_CLAIM_TOKEN_ERROR: &str = "Invalid claim token";
+
+/// What claiming a guest moved onto the registered account.
+#[derive(Debug, Clone, PartialEq, GraphQLObject)]
+pub struct GuestClaimed {
+    pub mnstrs: i32,
+    pub coins: i32,
+}
+
+/// Claims `guest_id` into `user_id` in one database transaction: the guest's
+/// token is cleared, the guest is archived and logged out, and its mnstrs and
+/// coins move to the user. A token works once.
+///
+/// Mnstrs whose QR code the user has already collected stay with the guest.
+/// A claim that would take the user past the collection cap fails with
+/// `CollectionFullError`.
+async fn claim_guest_into(
+    guest_id: &str,
+    user_id: &str,
+    token: &str,
+) -> Result<GuestClaimed, anyhow::Error> {
+    if guest_id == user_id {
+        return Err(anyhow::anyhow!("Accounts can't claim themselves"));
+    }
+    transaction(async {
+        let params = vec![
+            ("id", guest_id.to_string().into()),
+            ("guest_claim_token_hash", hash_password(token).into()),
+        ];
+        if lock_resources_where_fields!(User, params).await?.is_empty() {
+            return Err(anyhow::anyhow!(INVALID_CLAIM_TOKEN_ERROR));
+        }
+        // The lock collecting takes, so the user can't fill the slots the
+        // guest's mnstrs are checked against before they move
+        if lock_resources_where_fields!(User, vec![("id", user_id.to_string().into())])
+            .await?
+            .is_empty()
+        {
+            return Err(anyhow::anyhow!("User {} not found", user_id));
+        }
+        update_resource_fields!(
+            User,
+            guest_id.to_string(),
+            vec![("guest_claim_token_hash", Some(DatabaseValue::None))]
+        )
+        .await?;
+        delete_resource_where_fields!(User, vec![("id", guest_id.to_string().into())]).await?;
+        let sessions = find_all_resources_where_fields!(
+            Session,
+            vec![("user_id", guest_id.to_string().into())]
+        )
+        .await?;
+        for session in sessions {
+            delete_resource_where_fields!(Session, vec![("id", session.id.into())], true).await?;
+        }
+
+        let mnstrs = move_guest_mnstrs(guest_id, user_id).await?;
+        let coins = Wallet::balance_for_user(guest_id.to_string()).await?;
+        if coins > 0 {
+            move_guest_coins(guest_id, user_id, coins).await?;
+        }
+        Ok(GuestClaimed {
+            mnstrs,
+            coins: coins.max(0),
+        })
+    })
+    .await
+}
+
+async fn move_guest_mnstrs(guest_id: &str, user_id: &str) -> Result<i32, anyhow::Error> {
+    let collected = find_all_unarchived_resources_where_fields!(
+        Mnstr,
+        vec![("user_id", user_id.to_string().into())]
+    )
+    .await?
+    .into_iter()
+    .map(|mnstr| mnstr.mnstr_qr_code)
+    .collect::<Vec<String>>();
+    let guest_mnstrs = find_all_unarchived_resources_where_fields!(
+        Mnstr,
+        vec![("user_id", guest_id.to_string().into())]
+    )
+    .await?;
+
+    let to_move = guest_mnstrs
+        .into_iter()
+        .filter(|mnstr| mnstr.mnstr_qr_code.is_empty() || !collected.contains(&mnstr.mnstr_qr_code))
+        .collect::<Vec<Mnstr>>();
+    if let Some(error) = Mnstr::ensure_collection_capacity(user_id, to_move.len() as i64).await {
+        return Err(error);
+    }
+
+    let mut moved = 0;
+    for mnstr in to_move {
+        update_resource!(
+            Mnstr,
+            mnstr.id,
+            vec![("user_id", user_id.to_string().into())]
+        )
+        .await?;
+        moved += 1;
+    }
+    Ok(moved)
+}
+
+/// Debits `coins` from the guest's wallet and credits them to the user's, both
+/// tagged with the guest they came from.
+async fn move_guest_coins(guest_id: &str, user_id: &str, coins: i32) -> Result<(), anyhow::Error> {
+    let data = serde_json::json!({ "claimedGuestId": guest_id }).to_string();
+    for (owner_id, transaction_type) in [
+        (guest_id, TransactionType::Debit),
+        (user_id, TransactionType::Credit),
+    ] {
+        let wallet =
+            find_one_resource_where_fields!(Wallet, vec![("user_id", owner_id.to_string().into())])
+                .await?;
+        Transaction::record(wallet.id, transaction_type, coins, Some(data.clone())).await?;
+    }
+    Ok(())
+}
+
+/// Relationship-backed fields resolve lazily so that selecting `coins` doesn't
+/// load the wallet, its transactions and every mnstr.
+#[juniper::graphql_object]
+impl User {
+    fn id(&self) -> &str {
+        &self.id
+    }
+    fn email(&self) -> &Option<String> {
+        &self.email
+    }
+    fn phone(&self) -> &Option<String> {
+        &self.phone
+    }
+    /// Lets clients prompt for verification; the codes themselves are never exposed.
+    fn email_verified(&self) -> bool {
+        self.email_verified
+    }
+    fn phone_verified(&self) -> bool {
+        self.phone_verified
+    }
+    fn email_opt_in(&self) -> bool {
+        self.email_opt_in
+    }
+    fn sms_opt_in(&self) -> bool {
+        self.sms_opt_in
+    }
+    fn display_name(&self) -> &str {
+        &self.display_name
+    }
+    fn experience_level(&self) -> i32 {
+        self.experience_level
+    }
+    fn experience_points(&self) -> i32 {
+        self.experience_points
+    }
+    fn experience_to_next_level(&self) -> i32 {
+        self.experience_to_next_level
+    }
+    fn created_at(&self) -> Option<OffsetDateTime> {
+        self.created_at
+    }
+    fn updated_at(&self) -> Option<OffsetDateTime> {
+        self.updated_at
+    }
+    fn archived_at(&self) -> Option<OffsetDateTime> {
+        self.archived_at
+    }
+    fn last_daily_claim_at(&self) -> Option<OffsetDateTime> {
+        self.last_daily_claim_at
+    }
+    fn primary_mnstr_id(&self) -> &Option<String> {
+        &self.primary_mnstr_id
+    }
+
+    async fn coins(&self) -> Result<i32, FieldError> {
+        if let Some(coins) = self.loaded_coins() {
+            return Ok(coins);
+        }
+        match Wallet::balance_for_user(self.id.clone()).await {
+            Ok(coins) => Ok(coins),
+            Err(_) => Err(FieldError::from("Failed to get coins")),
+        }
+    }
+
+    async fn wallet(&self) -> Result<Option<Wallet>, FieldError> {
+        if self.wallet.is_some() {
+            return Ok(self.wallet.clone());
+        }
+        match Wallet::find_optional_by(vec![("user_id", self.id.clone().into())]).await {
+            Ok(wallet) => Ok(wallet),
+            Err(_) => Err(FieldError::from("Failed to get wallet")),
+        }
+    }
+
+    async fn mnstrs(&self) -> Result<Vec<Mnstr>, FieldError> {
+        if !self.mnstrs.is_empty() {
+            return Ok(self.mnstrs.clone());
+        }
+        match Mnstr::find_all_unarchived_by(
+            vec![("user_id", self.id.clone().into())],
+            false,
+            None,
+            None,
+        )
+        .await
+        {
+            Ok(mnstrs) => Ok(mnstrs),
+            Err(_) => Err(FieldError::from("Failed to get mnstrs")),
+        }
+    }
 }
 
 impl DatabaseResource for User {
@@ -517,8 +1281,12 @@ impl DatabaseResource for User {
             None => None,
         };
 
+        let last_daily_claim_at = row.get("last_daily_claim_at");
+
         let email_verified = row.get::<bool, _>("email_verified");
         let phone_verified = row.get::<bool, _>("phone_verified");
+        let email_opt_in = row.get::<bool, _>("email_opt_in");
+        let sms_opt_in = row.get::<bool, _>("sms_opt_in");
 
         Ok(User {
             id: row.get("id"),
@@ -530,6 +1298,8 @@ impl DatabaseResource for User {
             phone_verification_code,
             email_verified,
             phone_verified,
+            email_opt_in,
+            sms_opt_in,
             experience_level,
             experience_points,
             experience_to_next_level: 0,
@@ -537,6 +1307,9 @@ impl DatabaseResource for User {
             created_at,
             updated_at,
             archived_at,
+            last_daily_claim_at,
+            primary_mnstr_id: row.get("primary_mnstr_id"),
+            guest_claim_token_hash: row.get("guest_claim_token_hash"),
             wallet: None,
             mnstrs: Vec::new(),
         })
@@ -559,4 +1332,656 @@ impl DatabaseResource for User {
     fn is_verifiable() -> bool {
         false
     }
+    fn columns() -> &'static [&'static str] {
+        &[
+            "id",
+            "email",
+            "phone",
+            "email_verification_code",
+            "phone_verification_code",
+            "email_verified",
+            "phone_verified",
+            "email_opt_in",
+            "sms_opt_in",
+            "display_name",
+            "password_hash",
+            "experience_level",
+            "experience_points",
+            "created_at",
+            "updated_at",
+            "archived_at",
+            "last_daily_claim_at",
+            "primary_mnstr_id",
+            "guest_claim_token_hash",
+        ]
+    }
+}
+
+/// Deletes `wallet` if there is one. Sign up creates the wallet after the
+/// user, so a user can be left without one.
+async fn delete_wallet(wallet: Option<Wallet>) -> Option<anyhow::Error> {
+    match wallet {
+        Some(mut wallet) => wallet.delete_permanent().await,
+        None => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        database::{
+            connection::{execute, rolled_back},
+            traits::validate_columns,
+        },
+        graphql::sessions::create_session,
+        models::mnstr::{CollectionFullError, max_mnstrs_per_user},
+        utils::time::{format_rfc3339, parse_rfc3339},
+    };
+    use time::Duration;
+
+    #[test]
+    fn test_can_claim_daily() {
+        let mut user = User::new(None, None, "password".to_string(), "user".to_string());
+        let now = OffsetDateTime::now_utc();
+        assert!(user.can_claim_daily(now));
+
+        user.last_daily_claim_at = Some(now);
+        assert!(!user.can_claim_daily(now));
+
+        assert!(user.can_claim_daily(now + Duration::days(1)));
+    }
+
+    #[test]
+    fn test_is_active() {
+        let mut user = User::new(
+            Some("user@example.com".to_string()),
+            None,
+            "password".to_string(),
+            "user".to_string(),
+        );
+        assert!(!user.is_active());
+
+        user.email_verified = true;
+        assert!(user.is_active());
+
+        user.archived_at = Some(OffsetDateTime::now_utc());
+        assert!(!user.is_active());
+    }
+
+    #[test]
+    fn test_login_identifier_is_an_email_or_a_phone() {
+        assert_eq!(
+            LoginIdentifier::parse(" User@Example.COM ").unwrap(),
+            LoginIdentifier::Email("user@example.com".to_string())
+        );
+        assert_eq!(
+            LoginIdentifier::parse("(555) 123-4567").unwrap(),
+            LoginIdentifier::Phone("+15551234567".to_string())
+        );
+        assert!(LoginIdentifier::parse("not a login").is_err());
+    }
+
+    #[test]
+    fn test_normalize_contact() {
+        let mut padded = User::new(
+            Some(" User@Example.COM ".to_string()),
+            Some("(555) 123-4567".to_string()),
+            "password".to_string(),
+            "user".to_string(),
+        );
+        let mut plain = User::new(
+            Some("user@example.com".to_string()),
+            Some("+15551234567".to_string()),
+            "password".to_string(),
+            "user".to_string(),
+        );
+        assert!(padded.normalize_contact().is_none());
+        assert!(plain.normalize_contact().is_none());
+        assert_eq!(padded.email, plain.email);
+        assert_eq!(padded.phone, plain.phone);
+
+        let mut invalid = User::new(
+            Some("not-an-email".to_string()),
+            None,
+            "password".to_string(),
+            "user".to_string(),
+        );
+        assert!(invalid.normalize_contact().is_some());
+    }
+
+    #[test]
+    fn test_xp_at_the_last_level_does_not_overrun_the_curve() {
+        let mut user = User::new(None, None, "password".to_string(), "user".to_string());
+        let last_level = XP_FOR_LEVEL.len() as i32 - 1;
+        user.experience_level = last_level - 1;
+
+        user.apply_xp(i32::MAX / 2);
+        assert_eq!(user.experience_level, last_level);
+        assert_eq!(
+            user.experience_to_next_level,
+            XP_FOR_LEVEL[last_level as usize]
+        );
+    }
+
+    #[test]
+    fn test_opted_out_user_still_gets_essential_notifications() {
+        let mut user = User::new(
+            Some("user@example.com".to_string()),
+            Some("+15551234567".to_string()),
+            "password".to_string(),
+            "user".to_string(),
+        );
+        let verification = EmailTemplate::VerificationCode {
+            code: "482913".to_string(),
+        };
+        let battle_result = EmailTemplate::BattleResult {
+            opponent_name: "opponent".to_string(),
+            won: true,
+        };
+        assert!(user.receives_email(&verification));
+        assert!(user.receives_email(&battle_result));
+        assert!(user.receives_sms(false));
+
+        user.email_opt_in = false;
+        user.sms_opt_in = false;
+        assert!(user.receives_email(&verification));
+        assert!(!user.receives_email(&battle_result));
+        assert!(user.receives_sms(true));
+        assert!(!user.receives_sms(false));
+    }
+
+    #[test]
+    fn test_validate_columns() {
+        let fields = vec!["display_name".to_string(), "experience_level".to_string()];
+        assert!(validate_columns::<User>("users", &fields).is_ok());
+        let fields = vec!["email_opt_in".to_string(), "sms_opt_in".to_string()];
+        assert!(validate_columns::<User>("users", &fields).is_ok());
+
+        let fields = vec!["display_name".to_string(), "experince_level".to_string()];
+        let error = validate_columns::<User>("users", &fields).unwrap_err();
+        assert!(error.to_string().contains("experince_level"));
+    }
+
+    #[test]
+    fn test_owns_mnstr() {
+        let mut user = User::new(None, None, "password".to_string(), "user".to_string());
+        user.id = "user".to_string();
+        let mut mnstr = Mnstr::new("user".to_string(), None, None, "qr".to_string());
+        assert!(user.owns_mnstr(&mnstr));
+
+        let unowned = Mnstr::new("someone-else".to_string(), None, None, "qr".to_string());
+        assert!(!user.owns_mnstr(&unowned));
+
+        mnstr.archived_at = Some(OffsetDateTime::now_utc());
+        assert!(!user.owns_mnstr(&mnstr));
+    }
+
+    #[test]
+    fn test_loaded_coins() {
+        let mut user = User::new(None, None, "password".to_string(), "user".to_string());
+        user.coins = 42;
+        // Without a loaded wallet the `coins` field falls back to the SUM query
+        assert_eq!(user.loaded_coins(), None);
+
+        user.wallet = Some(Wallet::new(user.id.clone()));
+        assert_eq!(user.loaded_coins(), Some(42));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a migrated database at DATABASE_URL"]
+    async fn test_coins_are_summed_without_loading_relationships() {
+        rolled_back(async {
+            let mut user = create_test_user().await?;
+            collect_test_mnstr(&user.id, "qr-1").await;
+            collect_test_mnstr(&user.id, "qr-2").await;
+            user.get_wallet().await;
+            let wallet_id = user.wallet.clone().unwrap().id;
+            Transaction::record(wallet_id.clone(), TransactionType::Credit, 50, None).await?;
+            Transaction::record(wallet_id.clone(), TransactionType::Debit, 20, None).await?;
+            // Purchases that haven't completed don't count
+            let mut pending = Transaction::new(wallet_id);
+            pending.transaction_amount = 500;
+            assert!(pending.create().await.is_none());
+            user.get_relationships().await;
+            let expected = user.coins;
+            assert_eq!(user.mnstrs.len(), 2);
+
+            let user = User::find_one(user.id.clone(), false).await?;
+            assert_eq!(user.loaded_coins(), None);
+            assert!(user.wallet.is_none());
+            assert!(user.mnstrs.is_empty());
+            assert_eq!(Wallet::balance_for_user(user.id.clone()).await?, expected);
+            Ok(())
+        })
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a migrated database at DATABASE_URL"]
+    async fn test_daily_reward_is_claimed_once_per_day() {
+        rolled_back(async {
+            let mut user = create_test_user().await?;
+            let mut stale = User::find_one(user.id.clone(), false).await?;
+            let coins = Wallet::balance_for_user(user.id.clone()).await?;
+
+            assert!(user.claim_daily().await.is_none());
+            assert!(user.last_daily_claim_at.is_some());
+            let claimed = Wallet::balance_for_user(user.id.clone()).await?;
+            assert!(claimed > coins);
+
+            // A copy loaded before the claim still can't claim again today
+            assert!(stale.can_claim_daily(OffsetDateTime::now_utc()));
+            assert!(stale.claim_daily().await.is_some());
+            assert_eq!(Wallet::balance_for_user(user.id.clone()).await?, claimed);
+            Ok(())
+        })
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a migrated database at DATABASE_URL"]
+    async fn test_saving_a_stale_copy_keeps_the_daily_claim() {
+        rolled_back(async {
+            let mut user = create_test_user().await?;
+            let mut stale = User::find_one(user.id.clone(), false).await?;
+
+            assert!(user.claim_daily().await.is_none());
+            // As a battle settles: a copy loaded before the claim is saved
+            assert!(stale.update_xp(10).await.is_none());
+            assert!(user.claim_daily().await.is_some());
+            let saved = User::find_one(user.id.clone(), false).await?;
+            assert_eq!(saved.last_daily_claim_at, user.last_daily_claim_at);
+            Ok(())
+        })
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a migrated database at DATABASE_URL"]
+    async fn test_daily_reward_can_be_claimed_again_the_next_day() {
+        rolled_back(async {
+            let mut user = create_test_user().await?;
+            assert!(user.claim_daily().await.is_none());
+            let coins = Wallet::balance_for_user(user.id.clone()).await?;
+
+            let query = sqlx::query(
+                "UPDATE users SET last_daily_claim_at = last_daily_claim_at - INTERVAL '1 day' \
+                 WHERE id = $1",
+            )
+            .bind(&user.id);
+            execute("users", "backdate", query).await?;
+
+            assert!(user.claim_daily().await.is_none());
+            assert!(Wallet::balance_for_user(user.id.clone()).await? > coins);
+            Ok(())
+        })
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a migrated database at DATABASE_URL"]
+    async fn test_practice_reward_is_claimed_once_per_cooldown() {
+        rolled_back(async {
+            let user = create_test_user().await?;
+            let cooldown = Duration::minutes(30);
+
+            assert!(User::claim_practice_reward(user.id.clone(), cooldown).await?);
+            assert!(!User::claim_practice_reward(user.id.clone(), cooldown).await?);
+            assert!(User::claim_practice_reward(user.id.clone(), Duration::ZERO).await?);
+            Ok(())
+        })
+        .await
+        .unwrap();
+    }
+
+    struct TimestampQuery;
+
+    #[juniper::graphql_object]
+    impl TimestampQuery {
+        fn user() -> User {
+            let mut user = User::new(None, None, "password".to_string(), "user".to_string());
+            user.created_at = Some(OffsetDateTime::from_unix_timestamp(1_760_000_000).unwrap());
+            user
+        }
+    }
+
+    #[tokio::test]
+    async fn test_created_at_is_rfc3339_over_graphql() {
+        let schema = juniper::RootNode::new(
+            TimestampQuery,
+            juniper::EmptyMutation::<()>::new(),
+            juniper::EmptySubscription::<()>::new(),
+        );
+        let (value, errors) = juniper::execute(
+            "{ user { createdAt updatedAt } }",
+            None,
+            &schema,
+            &juniper::Variables::new(),
+            &(),
+        )
+        .await
+        .unwrap();
+        assert!(errors.is_empty());
+
+        let value = serde_json::to_value(&value).unwrap();
+        let created_at = value["user"]["createdAt"].as_str().unwrap();
+        let expected = OffsetDateTime::from_unix_timestamp(1_760_000_000).unwrap();
+        assert_eq!(parse_rfc3339(created_at).unwrap(), expected);
+        // Matches what the serde helpers write for the same field
+        assert_eq!(created_at, format_rfc3339(&expected).unwrap());
+        assert!(value["user"]["updatedAt"].is_null());
+    }
+
+    struct MyQuery;
+
+    #[juniper::graphql_object]
+    impl MyQuery {
+        fn my() -> User {
+            let mut user = User::new(
+                Some("user@example.com".to_string()),
+                Some("+15551234567".to_string()),
+                "password".to_string(),
+                "user".to_string(),
+            );
+            user.email_verified = true;
+            user.email_verification_code = Some("123456".to_string());
+            user.phone_verification_code = Some("654321".to_string());
+            user
+        }
+    }
+
+    #[tokio::test]
+    async fn test_my_exposes_verification_status_but_not_codes() {
+        let schema = juniper::RootNode::new(
+            MyQuery,
+            juniper::EmptyMutation::<()>::new(),
+            juniper::EmptySubscription::<()>::new(),
+        );
+        let (value, errors) = juniper::execute(
+            "{ my { emailVerified phoneVerified } }",
+            None,
+            &schema,
+            &juniper::Variables::new(),
+            &(),
+        )
+        .await
+        .unwrap();
+        assert!(errors.is_empty());
+
+        let value = serde_json::to_value(&value).unwrap();
+        assert_eq!(value["my"]["emailVerified"], true);
+        assert_eq!(value["my"]["phoneVerified"], false);
+
+        for field in [
+            "emailVerificationCode",
+            "phoneVerificationCode",
+            "passwordHash",
+        ] {
+            let query = format!("{{ my {{ {} }} }}", field);
+            let result =
+                juniper::execute(&query, None, &schema, &juniper::Variables::new(), &()).await;
+            assert!(result.is_err(), "{} should not be queryable", field);
+        }
+    }
+
+    /// Creates a registered user with a wallet.
+    async fn create_test_user() -> Result<User, anyhow::Error> {
+        let name = Uuid::new_v4().to_string();
+        let mut user = User::new(
+            Some(format!("{}@example.com", name)),
+            None,
+            "password".to_string(),
+            name,
+        );
+        if let Some(error) = user.create().await {
+            return Err(error);
+        }
+        Ok(user)
+    }
+
+    async fn collect_test_mnstr(user_id: &str, qr_code: &str) -> Mnstr {
+        let mut mnstr = Mnstr::new(user_id.to_string(), None, None, qr_code.to_string());
+        assert!(mnstr.create().await.is_none());
+        mnstr
+    }
+
+    async fn owned_qr_codes(user_id: &str) -> Vec<String> {
+        let mut qr_codes = find_all_unarchived_resources_where_fields!(
+            Mnstr,
+            vec![("user_id", user_id.to_string().into())]
+        )
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|mnstr| mnstr.mnstr_qr_code)
+        .collect::<Vec<String>>();
+        qr_codes.sort();
+        qr_codes
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a migrated database at DATABASE_URL"]
+    async fn test_claiming_guest_moves_assets() {
+        rolled_back(async {
+            let (guest, token) = User::create_guest(Uuid::new_v4().to_string()).await?;
+            let user = create_test_user().await?;
+            collect_test_mnstr(&guest.id, "qr-1").await;
+            collect_test_mnstr(&guest.id, "qr-2").await;
+            collect_test_mnstr(&user.id, "qr-2").await;
+            let guest_coins = Wallet::balance_for_user(guest.id.clone()).await?;
+            let user_coins = Wallet::balance_for_user(user.id.clone()).await?;
+
+            let claimed = claim_guest_into(&guest.id, &user.id, &token).await?;
+            assert_eq!(
+                claimed,
+                GuestClaimed {
+                    mnstrs: 1,
+                    coins: guest_coins
+                }
+            );
+
+            // The user already had qr-2, so the guest keeps theirs
+            assert_eq!(owned_qr_codes(&user.id).await, vec!["qr-1", "qr-2"]);
+            assert_eq!(owned_qr_codes(&guest.id).await, vec!["qr-2"]);
+            assert_eq!(Wallet::balance_for_user(guest.id.clone()).await?, 0);
+            assert_eq!(
+                Wallet::balance_for_user(user.id.clone()).await?,
+                user_coins + guest_coins
+            );
+            let guest = User::find_one(guest.id.clone(), false).await?;
+            assert!(guest.archived_at.is_some());
+            assert!(!guest.is_guest());
+
+            // The token only works once
+            let error = claim_guest_into(&guest.id, &user.id, &token)
+                .await
+                .unwrap_err();
+            assert_eq!(error.to_string(), INVALID_CLAIM_TOKEN_ERROR);
+            Ok(())
+        })
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a migrated database at DATABASE_URL"]
+    async fn test_invalid_claim_token_is_rejected() {
+        rolled_back(async {
+            let (guest, token) = User::create_guest(Uuid::new_v4().to_string()).await?;
+            let user = create_test_user().await?;
+            collect_test_mnstr(&guest.id, "qr-1").await;
+            let guest_coins = Wallet::balance_for_user(guest.id.clone()).await?;
+
+            let error = claim_guest_into(&guest.id, &user.id, "wrong-token")
+                .await
+                .unwrap_err();
+            assert_eq!(error.to_string(), INVALID_CLAIM_TOKEN_ERROR);
+            assert!(
+                claim_guest_into(&guest.id, &guest.id, &token)
+                    .await
+                    .is_err()
+            );
+
+            let guest = User::find_one(guest.id.clone(), false).await?;
+            assert!(guest.archived_at.is_none());
+            assert!(guest.is_guest());
+            assert_eq!(owned_qr_codes(&guest.id).await, vec!["qr-1"]);
+            assert_eq!(
+                Wallet::balance_for_user(guest.id.clone()).await?,
+                guest_coins
+            );
+            Ok(())
+        })
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a migrated database at DATABASE_URL"]
+    async fn test_claim_that_would_overfill_the_collection_is_refused() {
+        rolled_back(async {
+            let (guest, token) = User::create_guest(Uuid::new_v4().to_string()).await?;
+            let user = create_test_user().await?;
+            collect_test_mnstr(&guest.id, "qr-1").await;
+            collect_test_mnstr(&guest.id, "qr-2").await;
+            let query = sqlx::query(
+                "INSERT INTO mnstrs (id, user_id, mnstr_name, mnstr_description, mnstr_qr_code) \
+                 SELECT gen_random_uuid()::text, $1, '', '', gen_random_uuid()::text \
+                 FROM generate_series(1, $2)",
+            )
+            .bind(&user.id)
+            .bind(max_mnstrs_per_user() as i32 - 1);
+            fetch_all("mnstrs", "fill", query).await?;
+
+            let error = claim_guest_into(&guest.id, &user.id, &token)
+                .await
+                .unwrap_err();
+            assert!(error.downcast_ref::<CollectionFullError>().is_some());
+
+            // Nothing moved, and the guest can still be claimed later
+            assert_eq!(owned_qr_codes(&guest.id).await, vec!["qr-1", "qr-2"]);
+            let guest = User::find_one(guest.id.clone(), false).await?;
+            assert!(guest.archived_at.is_none());
+            assert!(guest.is_guest());
+            Ok(())
+        })
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_deleting_user_without_wallet_skips_wallet() {
+        let user = User::new(None, None, "password".to_string(), "Player".to_string());
+        assert!(user.wallet.is_none());
+        assert!(delete_wallet(user.wallet).await.is_none());
+    }
+    async fn create_test_session(user_id: &str) -> Session {
+        let mut session = Session::new(user_id.to_string());
+        assert!(session.create().await.is_none());
+        session
+    }
+
+    async fn live_session_ids(user_id: &str) -> Vec<String> {
+        let sessions = find_all_resources_where_fields!(
+            Session,
+            vec![("user_id", user_id.to_string().into())]
+        )
+        .await
+        .unwrap();
+        sessions.into_iter().map(|session| session.id).collect()
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a migrated database at DATABASE_URL"]
+    async fn test_deactivated_user_cannot_log_in_until_reactivated() {
+        rolled_back(async {
+            let mut user = create_test_user().await?;
+            user.email_verified = true;
+            if let Some(error) = user.update().await {
+                return Err(error);
+            }
+            let email = user.email.clone().unwrap();
+            create_test_session(&user.id).await;
+            create_test_session(&user.id).await;
+
+            if let Some(error) = user.archive().await {
+                return Err(error);
+            }
+            assert!(live_session_ids(&user.id).await.is_empty());
+            assert!(
+                create_session(email.clone(), "password".to_string(), None)
+                    .await
+                    .is_err()
+            );
+
+            if let Some(error) = user.unarchive().await {
+                return Err(error);
+            }
+            let session = create_session(email, "password".to_string(), None)
+                .await
+                .unwrap();
+            assert_eq!(live_session_ids(&user.id).await, vec![session.id]);
+            Ok(())
+        })
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a migrated database at DATABASE_URL"]
+    async fn test_password_change_revokes_issued_sessions() {
+        rolled_back(async {
+            let mut user = create_test_user().await?;
+            let other = create_test_user().await?;
+            for _ in 0..3 {
+                create_test_session(&user.id).await;
+            }
+            let elsewhere = create_test_session(&other.id).await;
+
+            assert!(user.change_password("new-password", None).await.is_none());
+            let stored = User::find_one(user.id.clone(), false).await?;
+            assert_eq!(stored.password_hash, hash_password("new-password"));
+            assert!(live_session_ids(&user.id).await.is_empty());
+            assert_eq!(live_session_ids(&other.id).await, vec![elsewhere.id]);
+            Ok(())
+        })
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a migrated database at DATABASE_URL"]
+    async fn test_password_change_can_keep_current_session() {
+        rolled_back(async {
+            let mut user = create_test_user().await?;
+            create_test_session(&user.id).await;
+            let current = create_test_session(&user.id).await;
+
+            assert!(
+                user.change_password("new-password", Some(&current.id))
+                    .await
+                    .is_none()
+            );
+            assert_eq!(live_session_ids(&user.id).await, vec![current.id.clone()]);
+
+            // Nothing changes for a user that doesn't exist
+            let mut missing = User::new(None, None, "password".to_string(), "missing".to_string());
+            missing.id = "missing".to_string();
+            assert!(
+                missing
+                    .change_password("new-password", None)
+                    .await
+                    .is_some()
+            );
+            assert_eq!(live_session_ids(&user.id).await, vec![current.id]);
+            Ok(())
+        })
+        .await
+        .unwrap();
+    }
 }
@@ -4,9 +4,15 @@ use sqlx::{Error, Row, postgres::PgRow};
 use time::OffsetDateTime;
 
 use crate::{
-    database::{traits::DatabaseResource, values::DatabaseValue},
-    delete_resource_where_fields, find_all_resources_where_fields, find_one_resource_where_fields,
-    insert_resource,
+    count_resources_where_fields,
+    database::{
+        connection::{fetch_one, transaction},
+        traits::{DatabaseResource, OrderDirection},
+        values::DatabaseValue,
+    },
+    delete_resource_where_fields, find_all_resources_where_fields,
+    find_all_resources_where_fields_ordered, find_one_resource_where_fields,
+    find_optional_resource_where_fields, insert_resource,
     models::transaction::{Transaction, TransactionStatus, TransactionType},
     proto::Wallet as GrpcWallet,
     utils::time::{deserialize_offset_date_time, serialize_offset_date_time},
@@ -72,26 +78,29 @@ impl Wallet {
         None
     }
 
+    /// Deletes the wallet and every one of its transactions in one database
+    /// transaction, so no transaction is left behind without its wallet.
     pub async fn delete_permanent(&mut self) -> Option<anyhow::Error> {
-        let wallet = match Self::find_one(self.id.clone()).await {
-            Ok(wallet) => wallet,
-            Err(e) => return Some(e.into()),
-        };
-        *self = wallet;
-
-        for transaction in self.transactions.iter_mut() {
-            if let Some(error) = transaction.delete_permanent().await {
-                println!("[Wallet::delete] Failed to delete transaction: {:?}", error);
-                return Some(error);
+        let wallet_id = self.id.clone();
+        let deleted = transaction(async {
+            let params = vec![("wallet_id", wallet_id.clone().into())];
+            if count_resources_where_fields!(Transaction, params).await? > 0 {
+                delete_resource_where_fields!(Transaction, params, true).await?;
             }
+            delete_resource_where_fields!(Wallet, vec![("id", wallet_id.clone().into())], true)
+                .await?;
+            Ok(())
+        })
+        .await;
+        if let Err(e) = deleted {
+            println!(
+                "[Wallet::delete_permanent] Failed to delete wallet: {:?}",
+                e
+            );
+            return Some(e);
         }
-
-        match delete_resource_where_fields!(Wallet, vec![("id", self.id.clone().into())], true)
-            .await
-        {
-            Ok(_) => (),
-            Err(e) => return Some(e.into()),
-        };
+        self.transactions.clear();
+        self.coins = 0;
         None
     }
 
@@ -126,6 +135,24 @@ impl Wallet {
         Ok(wallet)
     }
 
+    pub async fn find_optional_by(
+        params: Vec<(&str, DatabaseValue)>,
+    ) -> Result<Option<Self>, anyhow::Error> {
+        let mut wallet = match find_optional_resource_where_fields!(Wallet, params).await {
+            Ok(Some(wallet)) => wallet,
+            Ok(None) => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        if let Some(error) = wallet.get_relationships().await {
+            println!(
+                "[Wallet::find_optional_by] Failed to get relationships: {:?}",
+                error
+            );
+            return Err(error.into());
+        }
+        Ok(Some(wallet))
+    }
+
     pub async fn find_all() -> Result<Vec<Self>, anyhow::Error> {
         let mut wallets = match find_all_resources_where_fields!(Wallet, vec![], None, None).await {
             Ok(wallets) => wallets,
@@ -170,11 +197,11 @@ impl Wallet {
     }
 
     pub async fn get_coins(&mut self) -> Option<anyhow::Error> {
-        let transactions = match find_all_resources_where_fields!(
+        let transactions = match find_all_resources_where_fields_ordered!(
             Transaction,
             vec![("wallet_id", self.id.clone().into())],
-            None,
-            None
+            "created_at",
+            OrderDirection::Asc
         )
         .await
         {
@@ -184,18 +211,50 @@ impl Wallet {
                 return Some(e.into());
             }
         };
-        self.transactions = transactions.clone();
-        self.coins = transactions.iter().map(|t| t.transaction_amount).sum();
+        self.coins = Self::balance_of(&transactions);
+        self.transactions = transactions;
         None
     }
 
+    /// Sums completed transactions. Purchases still being prepared or that
+    /// failed don't count.
+    pub fn balance_of(transactions: &[Transaction]) -> i32 {
+        transactions
+            .iter()
+            .filter(|t| matches!(t.transaction_status, TransactionStatus::Completed))
+            .map(|t| match t.transaction_type {
+                TransactionType::Credit => t.transaction_amount,
+                TransactionType::Debit => -t.transaction_amount,
+            })
+            .sum()
+    }
+
+    /// Sums a user's completed transactions in the database without loading
+    /// the wallet.
+    pub async fn balance_for_user(user_id: String) -> Result<i32, anyhow::Error> {
+        let query = sqlx::query(
+            "SELECT COALESCE(SUM(CASE WHEN t.transaction_type = $1 THEN -t.transaction_amount ELSE t.transaction_amount END), 0)::BIGINT AS coins \
+             FROM transactions t JOIN wallets w ON w.id = t.wallet_id \
+             WHERE w.user_id = $2 AND w.archived_at IS NULL AND t.transaction_status = $3",
+        )
+        .bind(TransactionType::Debit.to_string())
+        .bind(user_id)
+        .bind(TransactionStatus::Completed.to_string());
+        let row = fetch_one("transactions", "balance_for_user", query).await;
+        match row {
+            Ok(row) => Ok(row.get::<i64, _>("coins") as i32),
+            Err(e) => {
+                println!("[Wallet::balance_for_user] Failed to sum coins: {:?}", e);
+                Err(anyhow::Error::msg(e.to_string()))
+            }
+        }
+    }
+
     pub async fn add_coins(&mut self, coins: i32) -> Option<anyhow::Error> {
         println!("[Wallet::add_coins] Adding coins: {:?}", coins);
-        let mut transaction = Transaction::new(self.id.clone());
-        transaction.transaction_amount = coins;
-        transaction.transaction_type = TransactionType::Credit;
-        transaction.transaction_status = TransactionStatus::Completed;
-        if let Some(error) = transaction.create().await {
+        if let Err(error) =
+            Transaction::record(self.id.clone(), TransactionType::Credit, coins, None).await
+        {
             println!("Failed to create transaction: {:?}", error);
             return Some(error.into());
         }
@@ -246,3 +305,76 @@ impl DatabaseResource for Wallet {
         false
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{database::connection::rolled_back, models::user::User};
+
+    /// Creates a user, which creates their wallet.
+    async fn create_test_wallet() -> Result<Wallet, anyhow::Error> {
+        let name = uuid::Uuid::new_v4().to_string();
+        let mut user = User::new(
+            Some(format!("{}@example.com", name)),
+            None,
+            "password".to_string(),
+            name,
+        );
+        if let Some(error) = user.create().await {
+            return Err(error);
+        }
+        find_one_resource_where_fields!(Wallet, vec![("user_id", user.id.into())]).await
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a migrated database at DATABASE_URL"]
+    async fn test_deleting_wallet_removes_its_transactions() {
+        rolled_back(async {
+            let mut wallet = create_test_wallet().await?;
+            let mut other = create_test_wallet().await?;
+            for wallet in [&mut wallet, &mut other] {
+                assert!(wallet.add_coins(10).await.is_none());
+                assert!(wallet.add_coins(20).await.is_none());
+            }
+
+            assert!(wallet.delete_permanent().await.is_none());
+            let params = vec![("id", wallet.id.clone().into())];
+            assert!(
+                find_optional_resource_where_fields!(Wallet, params)
+                    .await?
+                    .is_none()
+            );
+            let params = vec![("wallet_id", wallet.id.clone().into())];
+            assert!(
+                find_all_resources_where_fields!(Transaction, params)
+                    .await?
+                    .is_empty()
+            );
+
+            other.get_coins().await;
+            assert_eq!(other.transactions.len(), 2);
+            Ok(())
+        })
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a migrated database at DATABASE_URL"]
+    async fn test_missing_wallet_deletes_nothing() {
+        rolled_back(async {
+            let mut wallet = create_test_wallet().await?;
+            assert!(wallet.add_coins(10).await.is_none());
+
+            let mut missing = Wallet::new(wallet.user_id.clone());
+            missing.id = "missing".to_string();
+            assert!(missing.delete_permanent().await.is_some());
+
+            wallet.get_coins().await;
+            assert_eq!(wallet.transactions.len(), 1);
+            Ok(())
+        })
+        .await
+        .unwrap();
+    }
+}
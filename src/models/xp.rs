@@ -0,0 +1,82 @@
+//! Bounds-safe lookups into the generated XP curves.
+//!
+//! The curves are written by a generation step, so `validate_xp_tables` runs
+//! at startup and refuses to serve with a curve that is too short or out of
+//! order. Lookups clamp the level to the curve instead of indexing past it.
+
+use crate::models::generated::{level_xp, mnstr_xp};
+
+/// Level 0 and at least one level to reach.
+pub const MIN_XP_TABLE_LEN: usize = 2;
+
+/// Checks that `table` has at least `MIN_XP_TABLE_LEN` entries, none of them
+/// negative, each one larger than the one before.
+pub fn validate_xp_table(name: &str, table: &[i32]) -> Result<(), anyhow::Error> {
+    if table.len() < MIN_XP_TABLE_LEN {
+        return Err(anyhow::anyhow!(
+            "{} has {} levels, at least {} are needed",
+            name,
+            table.len(),
+            MIN_XP_TABLE_LEN
+        ));
+    }
+    if let Some(level) = table.iter().position(|xp| *xp < 0) {
+        return Err(anyhow::anyhow!(
+            "{} has negative XP at level {}",
+            name,
+            level
+        ));
+    }
+    if let Some(level) = table.windows(2).position(|pair| pair[1] <= pair[0]) {
+        return Err(anyhow::anyhow!(
+            "{} doesn't increase from level {} to level {}",
+            name,
+            level,
+            level + 1
+        ));
+    }
+    Ok(())
+}
+
+/// Checks both generated curves with `validate_xp_table`.
+pub fn validate_xp_tables() -> Result<(), anyhow::Error> {
+    validate_xp_table("level_xp::XP_FOR_LEVEL", &level_xp::XP_FOR_LEVEL)?;
+    validate_xp_table("mnstr_xp::XP_FOR_LEVEL", &mnstr_xp::XP_FOR_LEVEL)
+}
+
+/// The entry for `level`, with levels before the start or past the end of the
+/// curve using its first or last entry. An empty curve gives 0.
+pub fn xp_for_level(table: &[i32], level: i32) -> i32 {
+    match table.len() {
+        0 => 0,
+        len => table[(level.max(0) as usize).min(len - 1)],
+    }
+}
+
+/// The entry for the level after `level`; see `xp_for_level`.
+pub fn xp_to_next_level(table: &[i32], level: i32) -> i32 {
+    xp_for_level(table, level.saturating_add(1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_malformed_xp_tables_are_rejected() {
+        assert!(validate_xp_tables().is_ok());
+
+        assert!(validate_xp_table("empty", &[]).is_err());
+        assert!(validate_xp_table("short", &[0]).is_err());
+        assert!(validate_xp_table("negative", &[-1, 10, 20]).is_err());
+        assert!(validate_xp_table("flat", &[0, 10, 10]).is_err());
+        assert!(validate_xp_table("valid", &[0, 10, 20]).is_ok());
+
+        // Lookups on a bad curve fall back instead of panicking
+        assert_eq!(xp_for_level(&[], 3), 0);
+        assert_eq!(xp_to_next_level(&[], i32::MAX), 0);
+        assert_eq!(xp_for_level(&[0, 10, 20], -4), 0);
+        assert_eq!(xp_to_next_level(&[0, 10, 20], 1), 20);
+        assert_eq!(xp_to_next_level(&[0, 10, 20], 7), 20);
+    }
+}
@@ -0,0 +1,307 @@
+//! Coin purchases through an external payment provider.
+//!
+//! A purchase is recorded as a `Preparing` credit before the provider is
+//! charged, so every attempt leaves a transaction behind. The charge's outcome
+//! moves it to `Completed` or `Failed`; only completed transactions count
+//! towards a wallet's balance. The provider is picked with `PAYMENT_PROVIDER`.
+
+use std::sync::Arc;
+
+use futures::future::BoxFuture;
+use serde::Serialize;
+
+use crate::{
+    models::{
+        transaction::{Transaction, TransactionStatus, TransactionType},
+        wallet::Wallet,
+    },
+    payments::sandbox::SandboxProvider,
+};
+
+pub mod sandbox;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CoinPackage {
+    pub id: &'static str,
+    pub coins: i32,
+    pub price_cents: i32,
+}
+
+pub const COIN_PACKAGES: [CoinPackage; 3] = [
+    CoinPackage {
+        id: "coins_500",
+        coins: 500,
+        price_cents: 99,
+    },
+    CoinPackage {
+        id: "coins_3000",
+        coins: 3_000,
+        price_cents: 499,
+    },
+    CoinPackage {
+        id: "coins_7000",
+        coins: 7_000,
+        price_cents: 999,
+    },
+];
+
+pub fn find_package(id: &str) -> Option<CoinPackage> {
+    COIN_PACKAGES
+        .iter()
+        .find(|package| package.id == id)
+        .copied()
+}
+
+/// What a provider answered for a charge it was able to process.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PaymentOutcome {
+    Completed { reference: String },
+    Failed { reason: String },
+}
+
+pub trait PaymentProvider: Send + Sync {
+    /// Stored with each purchase so it can be traced back to the provider.
+    fn name(&self) -> &'static str;
+
+    /// Charges `user_id` the package's price.
+    ///
+    /// # Returns
+    ///
+    /// An error only when the provider couldn't be reached; declined charges
+    /// are `PaymentOutcome::Failed`.
+    fn charge<'a>(
+        &'a self,
+        user_id: &'a str,
+        package: &'a CoinPackage,
+    ) -> BoxFuture<'a, Result<PaymentOutcome, anyhow::Error>>;
+}
+
+/// The provider named by `PAYMENT_PROVIDER`.
+///
+/// # Returns
+///
+/// Returns an error when it is unset, empty or names an unknown provider, so
+/// a misconfigured server doesn't hand out sandbox coins.
+pub fn provider_from_env() -> Result<Arc<dyn PaymentProvider>, anyhow::Error> {
+    provider_named(std::env::var("PAYMENT_PROVIDER").ok().as_deref())
+}
+
+fn provider_named(name: Option<&str>) -> Result<Arc<dyn PaymentProvider>, anyhow::Error> {
+    match name.map(str::trim) {
+        None | Some("") => Err(anyhow::anyhow!("PAYMENT_PROVIDER must be set")),
+        Some("sandbox") => Ok(Arc::new(SandboxProvider::default())),
+        Some("sandbox_decline") => Ok(Arc::new(SandboxProvider::declining())),
+        Some(other) => Err(anyhow::anyhow!("Unknown payment provider: {}", other)),
+    }
+}
+
+#[derive(Serialize)]
+struct PurchaseData<'a> {
+    package_id: &'a str,
+    price_cents: i32,
+    provider: &'a str,
+    reference: Option<&'a str>,
+}
+
+fn purchase_data(package: &CoinPackage, provider: &str, reference: Option<&str>) -> String {
+    let data = PurchaseData {
+        package_id: package.id,
+        price_cents: package.price_cents,
+        provider,
+        reference,
+    };
+    serde_json::to_string(&data).unwrap_or_default()
+}
+
+/// A `Preparing` credit for the package, before anything is charged.
+pub fn prepare_purchase(wallet_id: String, package: &CoinPackage, provider: &str) -> Transaction {
+    let mut transaction = Transaction::new(wallet_id);
+    transaction.transaction_type = TransactionType::Credit;
+    transaction.transaction_amount = package.coins;
+    transaction.transaction_status = TransactionStatus::Preparing;
+    transaction.transaction_data = Some(purchase_data(package, provider, None));
+    transaction
+}
+
+/// Moves a prepared purchase to `Completed` or `Failed` from the charge's
+/// outcome.
+pub fn settle_purchase(
+    transaction: &mut Transaction,
+    package: &CoinPackage,
+    provider: &str,
+    outcome: Result<PaymentOutcome, anyhow::Error>,
+) {
+    match outcome {
+        Ok(PaymentOutcome::Completed { reference }) => {
+            transaction.transaction_status = TransactionStatus::Completed;
+            transaction.transaction_data =
+                Some(purchase_data(package, provider, Some(reference.as_str())));
+            transaction.error_message = None;
+        }
+        Ok(PaymentOutcome::Failed { reason }) => {
+            transaction.transaction_status = TransactionStatus::Failed;
+            transaction.error_message = Some(reason);
+        }
+        Err(e) => {
+            transaction.transaction_status = TransactionStatus::Failed;
+            transaction.error_message = Some(e.to_string());
+        }
+    }
+}
+
+/// Records the purchase, charges the provider and settles the transaction.
+///
+/// # Returns
+///
+/// The settled transaction, `Failed` with its `error_message` set if the charge
+/// didn't go through. Errors are for the database steps only.
+pub async fn purchase_coins(
+    provider: &dyn PaymentProvider,
+    user_id: &str,
+    package: &CoinPackage,
+) -> Result<Transaction, anyhow::Error> {
+    let wallet = Wallet::find_one_by(vec![("user_id", user_id.to_string().into())]).await?;
+
+    let mut transaction = prepare_purchase(wallet.id.clone(), package, provider.name());
+    if let Some(error) = transaction.create().await {
+        println!("[purchase_coins] Failed to create transaction: {:?}", error);
+        return Err(error);
+    }
+
+    let outcome = provider.charge(user_id, package).await;
+    settle_purchase(&mut transaction, package, provider.name(), outcome);
+    if let Some(error) = transaction.update().await {
+        println!("[purchase_coins] Failed to update transaction: {:?}", error);
+        return Err(error);
+    }
+    Ok(transaction)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{database::connection::rolled_back, models::user::User};
+
+    async fn sandbox_purchase(provider: SandboxProvider, balance_before: &[Transaction]) -> i32 {
+        let package = find_package("coins_500").unwrap();
+        let mut transactions = balance_before.to_vec();
+
+        transactions.push(prepare_purchase(
+            "wallet".to_string(),
+            &package,
+            provider.name(),
+        ));
+        // A prepared purchase isn't spendable yet
+        assert_eq!(
+            Wallet::balance_of(&transactions),
+            Wallet::balance_of(balance_before)
+        );
+
+        let outcome = provider.charge("user", &package).await;
+        let transaction = transactions.last_mut().unwrap();
+        settle_purchase(transaction, &package, provider.name(), outcome);
+        Wallet::balance_of(&transactions)
+    }
+
+    fn completed_credit(amount: i32) -> Transaction {
+        let mut transaction = Transaction::new("wallet".to_string());
+        transaction.transaction_amount = amount;
+        transaction.transaction_status = TransactionStatus::Completed;
+        transaction
+    }
+
+    #[tokio::test]
+    async fn test_sandbox_purchase_credits_wallet() {
+        let existing = vec![completed_credit(100)];
+
+        let balance = sandbox_purchase(SandboxProvider::default(), &existing).await;
+        assert_eq!(balance, 600);
+    }
+
+    #[tokio::test]
+    async fn test_declined_purchase_fails_without_crediting() {
+        let package = find_package("coins_500").unwrap();
+        let provider = SandboxProvider::declining();
+        let mut transaction = prepare_purchase("wallet".to_string(), &package, provider.name());
+        let outcome = provider.charge("user", &package).await;
+        settle_purchase(&mut transaction, &package, provider.name(), outcome);
+        assert!(matches!(
+            transaction.transaction_status,
+            TransactionStatus::Failed
+        ));
+        assert!(transaction.error_message.is_some());
+
+        let existing = vec![completed_credit(100)];
+        let balance = sandbox_purchase(SandboxProvider::declining(), &existing).await;
+        assert_eq!(balance, 100);
+    }
+
+    #[test]
+    fn test_unreachable_provider_fails_purchase() {
+        let package = find_package("coins_3000").unwrap();
+        let mut transaction = prepare_purchase("wallet".to_string(), &package, "sandbox");
+        settle_purchase(
+            &mut transaction,
+            &package,
+            "sandbox",
+            Err(anyhow::anyhow!("Provider unavailable")),
+        );
+        assert!(matches!(
+            transaction.transaction_status,
+            TransactionStatus::Failed
+        ));
+        assert_eq!(
+            transaction.error_message.as_deref(),
+            Some("Provider unavailable")
+        );
+        assert!(find_package("coins_1").is_none());
+    }
+
+    #[test]
+    fn test_provider_must_be_named() {
+        assert!(provider_named(None).is_err());
+        assert!(provider_named(Some(" ")).is_err());
+        assert!(provider_named(Some("stripe")).is_err());
+        assert_eq!(provider_named(Some("sandbox")).unwrap().name(), "sandbox");
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a migrated database at DATABASE_URL"]
+    async fn test_purchase_coins_records_settled_transactions() {
+        rolled_back(async {
+            let name = uuid::Uuid::new_v4().to_string();
+            let mut user = User::new(
+                Some(format!("{}@example.com", name)),
+                None,
+                "password".to_string(),
+                name,
+            );
+            if let Some(error) = user.create().await {
+                return Err(error);
+            }
+            let package = find_package("coins_500").unwrap();
+
+            let completed = purchase_coins(&SandboxProvider::default(), &user.id, &package).await?;
+            assert!(matches!(
+                completed.transaction_status,
+                TransactionStatus::Completed
+            ));
+            let declined =
+                purchase_coins(&SandboxProvider::declining(), &user.id, &package).await?;
+            assert!(matches!(
+                declined.transaction_status,
+                TransactionStatus::Failed
+            ));
+
+            // Both attempts are stored, but only the completed one is spendable
+            let stored =
+                Transaction::find_all_by(vec![("wallet_id", completed.wallet_id.clone().into())])
+                    .await?;
+            assert_eq!(stored.len(), 2);
+            assert_eq!(Wallet::balance_for_user(user.id.clone()).await?, 500);
+            Ok(())
+        })
+        .await
+        .unwrap();
+    }
+}
@@ -0,0 +1,40 @@
+use futures::future::BoxFuture;
+use uuid::Uuid;
+
+use crate::payments::{CoinPackage, PaymentOutcome, PaymentProvider};
+
+/// Completes every charge without contacting anyone, or declines every
+/// charge when built with `declining`.
+#[derive(Debug, Default)]
+pub struct SandboxProvider {
+    decline: bool,
+}
+
+impl SandboxProvider {
+    pub fn declining() -> Self {
+        Self { decline: true }
+    }
+}
+
+impl PaymentProvider for SandboxProvider {
+    fn name(&self) -> &'static str {
+        "sandbox"
+    }
+
+    fn charge<'a>(
+        &'a self,
+        _user_id: &'a str,
+        _package: &'a CoinPackage,
+    ) -> BoxFuture<'a, Result<PaymentOutcome, anyhow::Error>> {
+        Box::pin(async move {
+            if self.decline {
+                return Ok(PaymentOutcome::Failed {
+                    reason: "Payment declined".to_string(),
+                });
+            }
+            Ok(PaymentOutcome::Completed {
+                reference: format!("sandbox_{}", Uuid::new_v4()),
+            })
+        })
+    }
+}
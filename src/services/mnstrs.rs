@@ -2,7 +2,7 @@ use tonic::{Request, Response, Status};
 
 use crate::{
     database::values::DatabaseValue,
-    models::mnstr::{DEFAULT_STAT_VALUE, Mnstr, MnstrOrderBy, MnstrOrderDirection},
+    models::mnstr::{Mnstr, MnstrOrderBy, MnstrOrderDirection},
     proto::{
         CollectMnstrRequest, CollectMnstrResponse, CreateMnstrBatchRequest,
         CreateMnstrBatchResponse, CreateMnstrRequest, CreateMnstrResponse, GetMnstrByQrCodeRequest,
@@ -108,6 +108,7 @@ impl MnstrService for MnstrServiceImpl {
             }
         };
 
+        // Stats in the request are ignored, a new mnstr starts at the defaults
         let mut mnstr = Mnstr::new(
             user.id,
             request.mnstr_name,
@@ -115,19 +116,6 @@ impl MnstrService for MnstrServiceImpl {
             request.mnstr_qr_code,
         );
 
-        mnstr.current_health = request.current_health.unwrap_or(DEFAULT_STAT_VALUE);
-        mnstr.max_health = request.max_health.unwrap_or(DEFAULT_STAT_VALUE);
-        mnstr.current_attack = request.current_attack.unwrap_or(DEFAULT_STAT_VALUE);
-        mnstr.max_attack = request.max_attack.unwrap_or(DEFAULT_STAT_VALUE);
-        mnstr.current_defense = request.current_defense.unwrap_or(DEFAULT_STAT_VALUE);
-        mnstr.max_defense = request.max_defense.unwrap_or(DEFAULT_STAT_VALUE);
-        mnstr.current_speed = request.current_speed.unwrap_or(DEFAULT_STAT_VALUE);
-        mnstr.max_speed = request.max_speed.unwrap_or(DEFAULT_STAT_VALUE);
-        mnstr.current_intelligence = request.current_intelligence.unwrap_or(DEFAULT_STAT_VALUE);
-        mnstr.max_intelligence = request.max_intelligence.unwrap_or(DEFAULT_STAT_VALUE);
-        mnstr.current_magic = request.current_magic.unwrap_or(DEFAULT_STAT_VALUE);
-        mnstr.max_magic = request.max_magic.unwrap_or(DEFAULT_STAT_VALUE);
-
         let mnstr = match mnstr.create().await {
             Some(error) => {
                 println!(
@@ -180,78 +168,6 @@ impl MnstrService for MnstrServiceImpl {
                     "mnstr_qr_code",
                     mnstr.mnstr_qr_code.as_ref().map(|s| s.into()),
                 ));
-                mnstr_params.push((
-                    "current_health",
-                    mnstr
-                        .current_health
-                        .map_or(Some(DEFAULT_STAT_VALUE.into()), |i| Some(i.into())),
-                ));
-                mnstr_params.push((
-                    "max_health",
-                    mnstr
-                        .max_health
-                        .map_or(Some(DEFAULT_STAT_VALUE.into()), |i| Some(i.into())),
-                ));
-                mnstr_params.push((
-                    "current_attack",
-                    mnstr
-                        .current_attack
-                        .map_or(Some(DEFAULT_STAT_VALUE.into()), |i| Some(i.into())),
-                ));
-                mnstr_params.push((
-                    "max_attack",
-                    mnstr
-                        .max_attack
-                        .map_or(Some(DEFAULT_STAT_VALUE.into()), |i| Some(i.into())),
-                ));
-                mnstr_params.push((
-                    "current_defense",
-                    mnstr
-                        .current_defense
-                        .map_or(Some(DEFAULT_STAT_VALUE.into()), |i| Some(i.into())),
-                ));
-                mnstr_params.push((
-                    "max_defense",
-                    mnstr
-                        .max_defense
-                        .map_or(Some(DEFAULT_STAT_VALUE.into()), |i| Some(i.into())),
-                ));
-                mnstr_params.push((
-                    "current_speed",
-                    mnstr
-                        .current_speed
-                        .map_or(Some(DEFAULT_STAT_VALUE.into()), |i| Some(i.into())),
-                ));
-                mnstr_params.push((
-                    "max_speed",
-                    mnstr
-                        .max_speed
-                        .map_or(Some(DEFAULT_STAT_VALUE.into()), |i| Some(i.into())),
-                ));
-                mnstr_params.push((
-                    "current_intelligence",
-                    mnstr
-                        .current_intelligence
-                        .map_or(Some(DEFAULT_STAT_VALUE.into()), |i| Some(i.into())),
-                ));
-                mnstr_params.push((
-                    "max_intelligence",
-                    mnstr
-                        .max_intelligence
-                        .map_or(Some(DEFAULT_STAT_VALUE.into()), |i| Some(i.into())),
-                ));
-                mnstr_params.push((
-                    "current_magic",
-                    mnstr
-                        .current_magic
-                        .map_or(Some(DEFAULT_STAT_VALUE.into()), |i| Some(i.into())),
-                ));
-                mnstr_params.push((
-                    "max_magic",
-                    mnstr
-                        .max_magic
-                        .map_or(Some(DEFAULT_STAT_VALUE.into()), |i| Some(i.into())),
-                ));
                 mnstr_params
             })
             .collect::<Vec<Vec<(&str, Option<DatabaseValue>)>>>();
@@ -293,20 +209,7 @@ impl MnstrService for MnstrServiceImpl {
 
         mnstr.mnstr_name = request.mnstr_name.unwrap_or(mnstr.mnstr_name);
         mnstr.mnstr_description = request.mnstr_description.unwrap_or(mnstr.mnstr_description);
-        mnstr.current_health = request.current_health.unwrap_or(mnstr.current_health);
-        mnstr.max_health = request.max_health.unwrap_or(mnstr.max_health);
-        mnstr.current_attack = request.current_attack.unwrap_or(mnstr.current_attack);
-        mnstr.max_attack = request.max_attack.unwrap_or(mnstr.max_attack);
-        mnstr.current_defense = request.current_defense.unwrap_or(mnstr.current_defense);
-        mnstr.max_defense = request.max_defense.unwrap_or(mnstr.max_defense);
-        mnstr.current_speed = request.current_speed.unwrap_or(mnstr.current_speed);
-        mnstr.max_speed = request.max_speed.unwrap_or(mnstr.max_speed);
-        mnstr.current_intelligence = request
-            .current_intelligence
-            .unwrap_or(mnstr.current_intelligence);
-        mnstr.max_intelligence = request.max_intelligence.unwrap_or(mnstr.max_intelligence);
-        mnstr.current_magic = request.current_magic.unwrap_or(mnstr.current_magic);
-        mnstr.max_magic = request.max_magic.unwrap_or(mnstr.max_magic);
+        // Stats in the request are ignored, they only change through play
 
         let mnstr = match mnstr.update().await {
             Some(error) => {
@@ -360,78 +263,6 @@ impl MnstrService for MnstrServiceImpl {
                     "mnstr_description",
                     mnstr.mnstr_description.as_ref().map(|s| s.into()),
                 ));
-                mnstr_params.push((
-                    "current_health",
-                    mnstr
-                        .current_health
-                        .map_or(Some(DEFAULT_STAT_VALUE.into()), |i| Some(i.into())),
-                ));
-                mnstr_params.push((
-                    "max_health",
-                    mnstr
-                        .max_health
-                        .map_or(Some(DEFAULT_STAT_VALUE.into()), |i| Some(i.into())),
-                ));
-                mnstr_params.push((
-                    "current_attack",
-                    mnstr
-                        .current_attack
-                        .map_or(Some(DEFAULT_STAT_VALUE.into()), |i| Some(i.into())),
-                ));
-                mnstr_params.push((
-                    "max_attack",
-                    mnstr
-                        .max_attack
-                        .map_or(Some(DEFAULT_STAT_VALUE.into()), |i| Some(i.into())),
-                ));
-                mnstr_params.push((
-                    "current_defense",
-                    mnstr
-                        .current_defense
-                        .map_or(Some(DEFAULT_STAT_VALUE.into()), |i| Some(i.into())),
-                ));
-                mnstr_params.push((
-                    "max_defense",
-                    mnstr
-                        .max_defense
-                        .map_or(Some(DEFAULT_STAT_VALUE.into()), |i| Some(i.into())),
-                ));
-                mnstr_params.push((
-                    "current_speed",
-                    mnstr
-                        .current_speed
-                        .map_or(Some(DEFAULT_STAT_VALUE.into()), |i| Some(i.into())),
-                ));
-                mnstr_params.push((
-                    "max_speed",
-                    mnstr
-                        .max_speed
-                        .map_or(Some(DEFAULT_STAT_VALUE.into()), |i| Some(i.into())),
-                ));
-                mnstr_params.push((
-                    "current_intelligence",
-                    mnstr
-                        .current_intelligence
-                        .map_or(Some(DEFAULT_STAT_VALUE.into()), |i| Some(i.into())),
-                ));
-                mnstr_params.push((
-                    "max_intelligence",
-                    mnstr
-                        .max_intelligence
-                        .map_or(Some(DEFAULT_STAT_VALUE.into()), |i| Some(i.into())),
-                ));
-                mnstr_params.push((
-                    "current_magic",
-                    mnstr
-                        .current_magic
-                        .map_or(Some(DEFAULT_STAT_VALUE.into()), |i| Some(i.into())),
-                ));
-                mnstr_params.push((
-                    "max_magic",
-                    mnstr
-                        .max_magic
-                        .map_or(Some(DEFAULT_STAT_VALUE.into()), |i| Some(i.into())),
-                ));
                 mnstr_params
             })
             .collect::<Vec<Vec<(&str, Option<DatabaseValue>)>>>();
@@ -471,7 +302,7 @@ impl MnstrService for MnstrServiceImpl {
             .order_direction
             .unwrap_or(GrpcMnstrOrderDirection::default().into());
 
-        let mnstrs = match Mnstr::find_all_by(
+        let mnstrs = match Mnstr::find_all_unarchived_by(
             vec![("user_id", user.id.clone().into())],
             false,
             Some(MnstrOrderBy::from_grpc(order_by)),
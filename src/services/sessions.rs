@@ -1,19 +1,82 @@
 use crate::{
-    models::{session::Session, user::User},
+    graphql::users::utils::send_phone_verification_code,
+    models::{
+        audit_log::AuditLog,
+        session::Session,
+        user::{LoginIdentifier, LoginRefusal, User},
+    },
     proto::{
         ForgotPasswordRequest, ForgotPasswordResponse, LoginRequest, LoginResponse, LogoutRequest, LogoutResponse, RegisterRequest, RegisterResponse, ResetPasswordRequest, ResetPasswordResponse, UnregisterRequest, UnregisterResponse, VerifyEmailRequest, VerifyEmailResponse, VerifyPhoneRequest, VerifyPhoneResponse, session_service_server::SessionService
     },
     services::helpers::get_user_from_token,
+    state::AppState,
     utils::{
+        contact::{normalize_email, normalize_phone},
         emails::send_email_verification_code,
         passwords::{generate_verification_code, hash_password},
+        redact::redact_debug,
     },
 };
 
 use tonic::{Request, Response, Status};
 
-#[derive(Debug, Default, Clone)]
-pub struct SessionServiceImpl;
+#[derive(Clone)]
+pub struct SessionServiceImpl {
+    state: AppState,
+}
+
+impl SessionServiceImpl {
+    pub fn new(state: AppState) -> Self {
+        Self { state }
+    }
+
+    /// Sends a fresh code to an unverified account that has none pending, such
+    /// as one registered before codes were sent, so it isn't locked out. Returns
+    /// whether a code was sent.
+    async fn resend_verification_code(&self, user: &mut User, refusal: LoginRefusal) -> bool {
+        let pending = match refusal {
+            LoginRefusal::EmailNotVerified => &mut user.email_verification_code,
+            LoginRefusal::PhoneNotVerified => &mut user.phone_verification_code,
+            LoginRefusal::Deactivated => return false,
+        };
+        if pending.is_some() {
+            return false;
+        }
+        let code = generate_verification_code();
+        *pending = Some(code.clone());
+        if let Some(error) = user.update().await {
+            println!(
+                "[SessionServiceImpl::resend_verification_code] Failed to update user: {}",
+                redact_debug(&error)
+            );
+            return false;
+        }
+
+        let sent = match refusal {
+            LoginRefusal::EmailNotVerified => send_email_verification_code(
+                &self.state,
+                user.display_name.as_str(),
+                user.email.clone().unwrap_or_default().as_str(),
+                code.as_str(),
+            )
+            .await
+            .map(|_| true),
+            _ => send_phone_verification_code(&self.state, user, code)
+                .await
+                .map_err(|e| anyhow::anyhow!(e.message().to_string())),
+        };
+        match sent {
+            Ok(sent) => sent,
+            Err(error) => {
+                println!(
+                    "[SessionServiceImpl::resend_verification_code] Failed to send code: {}",
+                    redact_debug(&error)
+                );
+                false
+            }
+        }
+    }
+}
 
 #[tonic::async_trait]
 impl SessionService for SessionServiceImpl {
@@ -27,6 +90,14 @@ impl SessionService for SessionServiceImpl {
         if email.clone().is_empty() {
             return Err(Status::invalid_argument("Email is required"));
         }
+        let email = match normalize_email(&email) {
+            Ok(email) => email,
+            Err(_) => return Err(Status::invalid_argument("Invalid email address")),
+        };
+        let phone = match request.phone.map(|phone| normalize_phone(&phone)).transpose() {
+            Ok(phone) => phone,
+            Err(_) => return Err(Status::invalid_argument("Invalid phone number")),
+        };
 
         let password = request.password;
         if password.clone().is_empty() {
@@ -37,7 +108,7 @@ impl SessionService for SessionServiceImpl {
 
         let mut user = User::new(
             Some(email.clone()),
-            request.phone,
+            phone,
             password.clone(),
             request.display_name.clone(),
         );
@@ -47,6 +118,7 @@ impl SessionService for SessionServiceImpl {
         }
 
         if let Err(error) = send_email_verification_code(
+            &self.state,
             request.display_name.as_str(),
             email.as_str(),
             code.as_str(),
@@ -68,32 +140,52 @@ impl SessionService for SessionServiceImpl {
         _request: Request<LoginRequest>,
     ) -> Result<Response<LoginResponse>, Status> {
         let request = _request.into_inner();
-        let email = request.email;
-        if email.clone().is_empty() {
+        // `email` also takes a phone number, for accounts without an email
+        if request.email.is_empty() {
             return Err(Status::invalid_argument("Email is required"));
         }
+        let identifier = match LoginIdentifier::parse(&request.email) {
+            Ok(identifier) => identifier,
+            Err(_) => return Err(Status::not_found("Unable to login")),
+        };
 
         let password = request.password;
         if password.clone().is_empty() {
             return Err(Status::invalid_argument("Password is required"));
         }
 
-        let user = User::new(Some(email.clone()), None, password.clone(), "".to_string());
-
         let params = vec![
-            ("email", user.email.clone().unwrap().into()),
-            ("password_hash", user.password_hash.clone().into()),
+            identifier.param(),
+            ("password_hash", hash_password(&password).into()),
         ];
-        let user = match User::find_one_by(params, false).await {
+        let mut user = match User::find_one_by(params, false).await {
             Ok(user) => user,
             Err(e) => {
                 println!(
-                    "[SessionServiceImpl::login] Failed to get user by email: {:?}",
-                    e
+                    "[SessionServiceImpl::login] Failed to get user: {}",
+                    redact_debug(&e)
                 );
                 return Err(Status::not_found("Unable to login"));
             }
         };
+        match user.login_refusal(&identifier) {
+            Some(LoginRefusal::Deactivated) => {
+                return Err(Status::permission_denied(
+                    LoginRefusal::Deactivated.message(),
+                ));
+            }
+            Some(refusal) => {
+                let message = match self.resend_verification_code(&mut user, refusal).await {
+                    true => format!(
+                        "{}, a new verification code has been sent",
+                        refusal.message()
+                    ),
+                    false => refusal.message().to_string(),
+                };
+                return Err(Status::failed_precondition(message));
+            }
+            None => (),
+        }
         let mut session = Session::new(user.id.clone());
         if let Some(error) = session.create().await {
             println!(
@@ -102,6 +194,7 @@ impl SessionService for SessionServiceImpl {
             );
             return Err(Status::internal(error.to_string()));
         }
+        AuditLog::record_login(user.id.clone(), None).await;
 
         Ok(Response::new(LoginResponse {
             session: Some(session.to_grpc()),
@@ -148,32 +241,33 @@ impl SessionService for SessionServiceImpl {
         if email.clone().is_empty() {
             return Err(Status::invalid_argument("Email is required"));
         }
+        let email = match normalize_email(&email) {
+            Ok(email) => email,
+            Err(_) => return Err(Status::invalid_argument("Invalid email address")),
+        };
         let params = vec![("email", email.clone().into())];
         let mut user = match User::find_one_by(params, false).await {
             Ok(user) => user,
             Err(e) => {
                 println!(
-                    "[SessionServiceImpl::forgot_password] Failed to get user: {:?}",
-                    e
+                    "[SessionServiceImpl::forgot_password] Failed to get user: {}",
+                    redact_debug(&e)
                 );
                 return Err(Status::not_found("Unable to forgot password"));
             }
         };
         let code = generate_verification_code();
         user.email_verification_code = Some(code.clone());
-        println!(
-            "[SessionServiceImpl::forgot_password] Updating user with code {}",
-            code
-        );
         if let Some(error) = user.update().await {
             println!(
-                "[SessionServiceImpl::forgot_password] Failed to update user: {:?}",
-                error
+                "[SessionServiceImpl::forgot_password] Failed to update user: {}",
+                redact_debug(&error)
             );
             return Err(Status::internal(error.to_string()));
         }
 
         if let Err(error) = send_email_verification_code(
+            &self.state,
             user.display_name.clone().as_str(),
             user.email.clone().unwrap().as_str(),
             code.as_str(),
@@ -181,8 +275,8 @@ impl SessionService for SessionServiceImpl {
         .await
         {
             println!(
-                "[SessionServiceImpl::forgot_password] Failed to send password reset code: {:?}",
-                error
+                "[SessionServiceImpl::forgot_password] Failed to send password reset code: {}",
+                redact_debug(&error)
             );
             return Err(Status::internal(error.to_string()));
         }
@@ -213,24 +307,30 @@ impl SessionService for SessionServiceImpl {
             Ok(user) => user,
             Err(e) => {
                 println!(
-                    "[SessionServiceImpl::reset_password] Failed to get user for code {}: {:?}",
-                    code,
-                    e
+                    "[SessionServiceImpl::reset_password] Failed to get user: {}",
+                    redact_debug(&e)
                 );
                 return Err(Status::not_found("Unable to reset password"));
             }
         };
 
-        user.password_hash = hash_password(&password.clone());
+        if let Some(error) = user.change_password(&password, None).await {
+            println!(
+                "[SessionServiceImpl::reset_password] Failed to change password: {}",
+                redact_debug(&error)
+            );
+            return Err(Status::internal(error.to_string()));
+        }
         user.email_verification_code = None;
         user.email_verified = true;
         if let Some(error) = user.update().await {
             println!(
-                "[SessionServiceImpl::reset_password] Failed to update user: {:?}",
-                error
+                "[SessionServiceImpl::reset_password] Failed to update user: {}",
+                redact_debug(&error)
             );
             return Err(Status::internal(error.to_string()));
         }
+        AuditLog::password_changed(user.id.clone()).record().await;
         Ok(Response::new(ResetPasswordResponse { success: true }))
     }
 
@@ -253,8 +353,8 @@ impl SessionService for SessionServiceImpl {
             Ok(user) => user,
             Err(e) => {
                 println!(
-                    "[SessionServiceImpl::verify_email] Failed to get user: {:?}",
-                    e
+                    "[SessionServiceImpl::verify_email] Failed to get user: {}",
+                    redact_debug(&e)
                 );
                 return Err(Status::not_found("Unable to verify email"));
             }
@@ -263,8 +363,8 @@ impl SessionService for SessionServiceImpl {
         user.email_verified = true;
         if let Some(error) = user.update().await {
             println!(
-                "[SessionServiceImpl::verify_email] Failed to update user: {:?}",
-                error
+                "[SessionServiceImpl::verify_email] Failed to update user: {}",
+                redact_debug(&error)
             );
             return Err(Status::internal(error.to_string()));
         }
@@ -291,8 +391,8 @@ impl SessionService for SessionServiceImpl {
             Ok(user) => user,
             Err(e) => {
                 println!(
-                    "[SessionServiceImpl::verify_phone] Failed to get user: {:?}",
-                    e
+                    "[SessionServiceImpl::verify_phone] Failed to get user: {}",
+                    redact_debug(&e)
                 );
                 return Err(Status::not_found("Unable to verify phone"));
             }
@@ -302,8 +402,8 @@ impl SessionService for SessionServiceImpl {
         user.phone_verification_code = None;
         if let Some(error) = user.update().await {
             println!(
-                "[SessionServiceImpl::verify_phone] Failed to update user: {:?}",
-                error
+                "[SessionServiceImpl::verify_phone] Failed to update user: {}",
+                redact_debug(&error)
             );
             return Err(Status::internal(error.to_string()));
         }
@@ -326,6 +426,120 @@ impl SessionService for SessionServiceImpl {
             println!("[SessionServiceImpl::unregister] Failed to delete user: {:?}", error);
             return Err(Status::internal(error.to_string()));
         }
+        AuditLog::account_deleted(user.id.clone()).record().await;
         Ok(Response::new(UnregisterResponse { success: true }))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use sendgrid::SGClient;
+    use tonic::Code;
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::{
+        database::connection::rolled_back, payments::sandbox::SandboxProvider, state::AppConfig,
+    };
+
+    fn service() -> SessionServiceImpl {
+        // None of the clients connect until first used
+        SessionServiceImpl::new(AppState::new(
+            redis::Client::open("redis://127.0.0.1:6379").unwrap(),
+            SGClient::new("key"),
+            twilio::Client::new("ssid", "token"),
+            Arc::new(SandboxProvider::default()),
+            AppConfig {
+                sendgrid_from_email: "mnstr@example.com".to_string(),
+                twilio_phone_number: "+15551234567".to_string(),
+            },
+        ))
+    }
+
+    /// A user with a pending code for each contact, so a refused login doesn't
+    /// try to send a new one.
+    async fn create_test_user(email: bool, phone: bool) -> Result<User, anyhow::Error> {
+        let name = Uuid::new_v4().to_string();
+        let mut user = User::new(
+            email.then(|| format!("{}@example.com", name)),
+            phone.then(|| format!("+1555{:07}", Uuid::new_v4().as_u128() % 10_000_000)),
+            "password".to_string(),
+            name,
+        );
+        user.email_verification_code = Some(generate_verification_code());
+        user.phone_verification_code = Some(generate_verification_code());
+        if let Some(error) = user.create().await {
+            return Err(error);
+        }
+        Ok(user)
+    }
+
+    async fn login(identifier: &str, password: &str) -> Result<Response<LoginResponse>, Status> {
+        service()
+            .login(Request::new(LoginRequest {
+                email: identifier.to_string(),
+                password: password.to_string(),
+            }))
+            .await
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a migrated database at DATABASE_URL"]
+    async fn test_login_refuses_each_case_with_its_own_error() {
+        rolled_back(async {
+            let mut user = create_test_user(true, false).await?;
+            let email = user.email.clone().unwrap();
+
+            let error = login(&email, "wrong").await.unwrap_err();
+            assert_eq!(error.code(), Code::NotFound);
+
+            let error = login(&email, "password").await.unwrap_err();
+            assert_eq!(error.code(), Code::FailedPrecondition);
+            assert_eq!(error.message(), LoginRefusal::EmailNotVerified.message());
+
+            user.email_verified = true;
+            if let Some(error) = user.update().await {
+                return Err(error);
+            }
+            let response = login(&email.to_uppercase(), "password").await.unwrap();
+            let session = response.into_inner().session.unwrap();
+            assert_eq!(session.user.unwrap().id, user.id);
+
+            if let Some(error) = user.archive().await {
+                return Err(error);
+            }
+            let error = login(&email, "password").await.unwrap_err();
+            assert_eq!(error.code(), Code::PermissionDenied);
+            assert_eq!(error.message(), LoginRefusal::Deactivated.message());
+            Ok(())
+        })
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a migrated database at DATABASE_URL"]
+    async fn test_phone_only_account_logs_in_with_its_phone() {
+        rolled_back(async {
+            let mut user = create_test_user(false, true).await?;
+            let phone = user.phone.clone().unwrap();
+
+            let error = login(&phone, "password").await.unwrap_err();
+            assert_eq!(error.code(), Code::FailedPrecondition);
+            assert_eq!(error.message(), LoginRefusal::PhoneNotVerified.message());
+
+            user.phone_verified = true;
+            if let Some(error) = user.update().await {
+                return Err(error);
+            }
+            let response = login(&phone, "password").await.unwrap();
+            let session = response.into_inner().session.unwrap();
+            assert_eq!(session.user.unwrap().id, user.id);
+            Ok(())
+        })
+        .await
+        .unwrap();
+    }
+}
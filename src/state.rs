@@ -0,0 +1,112 @@
+//! Application State
+//!
+//! Shared clients and configuration built once in `main` and handed to
+//! Rocket with `.manage()`. Route handlers take it as `&State<AppState>`,
+//! GraphQL resolvers reach it through `Ctx::state` and the gRPC services hold
+//! their own clone.
+//!
+//! The database pool isn't part of it: every query goes through the one pool
+//! in `database::connection`.
+
+use std::{env, sync::Arc};
+
+use sendgrid::SGClient;
+
+use crate::payments::{PaymentProvider, provider_from_env};
+
+/// Settings read from the environment at startup.
+pub struct AppConfig {
+    pub sendgrid_from_email: String,
+    pub twilio_phone_number: String,
+}
+
+/// Cheap to clone: every field is either reference counted or a handle.
+#[derive(Clone)]
+pub struct AppState {
+    pub redis: redis::Client,
+    pub sendgrid: Arc<SGClient>,
+    pub twilio: Arc<twilio::Client>,
+    pub payments: Arc<dyn PaymentProvider>,
+    pub config: Arc<AppConfig>,
+}
+
+impl AppState {
+    pub fn new(
+        redis: redis::Client,
+        sendgrid: SGClient,
+        twilio: twilio::Client,
+        payments: Arc<dyn PaymentProvider>,
+        config: AppConfig,
+    ) -> Self {
+        Self {
+            redis,
+            sendgrid: Arc::new(sendgrid),
+            twilio: Arc::new(twilio),
+            payments,
+            config: Arc::new(config),
+        }
+    }
+
+    /// Builds the state from the environment.
+    ///
+    /// # Environment Variables
+    ///
+    /// - `REDIS_URL`
+    /// - `SENDGRID_API_KEY`, `SENDGRID_FROM_EMAIL`
+    /// - `TWILIO_ACCOUNT_SSID`, `TWILIO_AUTH_TOKEN`, `TWILIO_PHONE_NUMBER`
+    /// - `PAYMENT_PROVIDER`
+    ///
+    /// # Returns
+    ///
+    /// Returns an error if any of them is missing, `REDIS_URL` isn't a valid url
+    /// or `PAYMENT_PROVIDER` names an unknown provider.
+    pub fn from_env() -> Result<Self, anyhow::Error> {
+        let redis = redis::Client::open(env::var("REDIS_URL")?)?;
+        let sendgrid = SGClient::new(env::var("SENDGRID_API_KEY")?);
+        let twilio = twilio::Client::new(
+            env::var("TWILIO_ACCOUNT_SSID")?.as_str(),
+            env::var("TWILIO_AUTH_TOKEN")?.as_str(),
+        );
+        let payments = provider_from_env()?;
+        let config = AppConfig {
+            sendgrid_from_email: env::var("SENDGRID_FROM_EMAIL")?,
+            twilio_phone_number: env::var("TWILIO_PHONE_NUMBER")?,
+        };
+        Ok(Self::new(redis, sendgrid, twilio, payments, config))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rocket::State;
+
+    use super::*;
+    use crate::payments::sandbox::SandboxProvider;
+
+    fn app_state() -> AppState {
+        // The redis client doesn't connect until first used
+        AppState::new(
+            redis::Client::open("redis://127.0.0.1:6379").unwrap(),
+            SGClient::new("key"),
+            twilio::Client::new("ssid", "token"),
+            Arc::new(SandboxProvider::default()),
+            AppConfig {
+                sendgrid_from_email: "mnstr@example.com".to_string(),
+                twilio_phone_number: "+15551234567".to_string(),
+            },
+        )
+    }
+
+    #[tokio::test]
+    async fn test_handlers_resolve_managed_state() {
+        let rocket = rocket::build().manage(app_state());
+
+        let state = State::<AppState>::get(&rocket).expect("AppState is managed");
+        assert_eq!(state.config.sendgrid_from_email, "mnstr@example.com");
+
+        // Resolvers see the same clients through their context
+        let ctx = crate::graphql::Ctx::new(state.inner().clone(), None);
+        assert!(Arc::ptr_eq(&ctx.state.sendgrid, &state.sendgrid));
+        assert!(Arc::ptr_eq(&ctx.state.config, &state.config));
+    }
+}
@@ -0,0 +1,30 @@
+//! Admin access for operator-only GraphQL mutations.
+//!
+//! Admins are configured with `ADMIN_USER_IDS`, a comma-separated list of user ids.
+
+/// Whether `user_id` is listed in the `ADMIN_USER_IDS` environment variable.
+pub fn is_admin(user_id: &str) -> bool {
+    match std::env::var("ADMIN_USER_IDS") {
+        Ok(admin_user_ids) => is_listed_admin(&admin_user_ids, user_id),
+        Err(_) => false,
+    }
+}
+
+fn is_listed_admin(admin_user_ids: &str, user_id: &str) -> bool {
+    !user_id.is_empty()
+        && admin_user_ids
+            .split(',')
+            .any(|admin_user_id| admin_user_id.trim() == user_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_listed_admin() {
+        assert!(is_listed_admin("admin-1, admin-2", "admin-2"));
+        assert!(!is_listed_admin("admin-1, admin-2", "user"));
+        assert!(!is_listed_admin("", ""));
+    }
+}
@@ -0,0 +1,124 @@
+//! Normalization and validation of user contact details.
+//!
+//! Emails and phone numbers are normalized before they are stored or looked up so
+//! that `User@Example.COM ` and `user@example.com` resolve to the same account.
+
+/// Default country calling code applied to phone numbers entered without one.
+pub const DEFAULT_COUNTRY_CODE: &str = "1";
+
+/// Trims and lowercases an email address and checks it has a usable shape.
+///
+/// # Examples
+///
+/// ```
+/// use crate::utils::contact::normalize_email;
+///
+/// assert_eq!(normalize_email(" User@Example.COM ").unwrap(), "user@example.com");
+/// assert!(normalize_email("not-an-email").is_err());
+/// ```
+///
+/// # Returns
+///
+/// Returns the normalized email, or an error if it isn't a valid address.
+pub fn normalize_email(email: &str) -> Result<String, anyhow::Error> {
+    let email = email.trim().to_lowercase();
+
+    let (local, domain) = match email.split_once('@') {
+        Some(parts) => parts,
+        None => return Err(anyhow::anyhow!("Invalid email address")),
+    };
+    if local.is_empty()
+        || domain.contains('@')
+        || !domain.contains('.')
+        || domain.starts_with('.')
+        || domain.ends_with('.')
+        || email.chars().any(|c| c.is_whitespace())
+    {
+        return Err(anyhow::anyhow!("Invalid email address"));
+    }
+
+    Ok(email)
+}
+
+/// Normalizes a phone number to E.164 (`+<country code><number>`).
+///
+/// Spaces, dashes, dots and parentheses are stripped. Numbers without a leading
+/// `+` are assumed to be in the default country.
+///
+/// # Examples
+///
+/// ```
+/// use crate::utils::contact::normalize_phone;
+///
+/// assert_eq!(normalize_phone("(555) 123-4567").unwrap(), "+15551234567");
+/// assert_eq!(normalize_phone("+44 20 7946 0958").unwrap(), "+442079460958");
+/// ```
+///
+/// # Returns
+///
+/// Returns the normalized phone number, or an error if it isn't a valid number.
+pub fn normalize_phone(phone: &str) -> Result<String, anyhow::Error> {
+    let phone = phone.trim();
+    let has_country_code = phone.starts_with('+');
+
+    let mut digits = String::with_capacity(phone.len());
+    for (i, c) in phone.chars().enumerate() {
+        match c {
+            '0'..='9' => digits.push(c),
+            '+' if i == 0 => (),
+            ' ' | '-' | '.' | '(' | ')' => (),
+            _ => return Err(anyhow::anyhow!("Invalid phone number")),
+        }
+    }
+
+    if !has_country_code {
+        if digits.len() == 10 {
+            digits = format!("{}{}", DEFAULT_COUNTRY_CODE, digits);
+        } else if !(digits.len() == 11 && digits.starts_with(DEFAULT_COUNTRY_CODE)) {
+            return Err(anyhow::anyhow!("Invalid phone number"));
+        }
+    }
+
+    // E.164 allows at most 15 digits and country codes never start with 0
+    if digits.len() < 8 || digits.len() > 15 || digits.starts_with('0') {
+        return Err(anyhow::anyhow!("Invalid phone number"));
+    }
+
+    Ok(format!("+{}", digits))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_email() {
+        assert_eq!(
+            normalize_email("  User@Example.COM ").unwrap(),
+            normalize_email("user@example.com").unwrap()
+        );
+        assert_eq!(
+            normalize_email("user@example.com").unwrap(),
+            "user@example.com"
+        );
+        assert!(normalize_email("userexample.com").is_err());
+        assert!(normalize_email("@example.com").is_err());
+        assert!(normalize_email("user@example").is_err());
+        assert!(normalize_email("user@@example.com").is_err());
+        assert!(normalize_email("us er@example.com").is_err());
+    }
+
+    #[test]
+    fn test_normalize_phone() {
+        assert_eq!(normalize_phone("(555) 123-4567").unwrap(), "+15551234567");
+        assert_eq!(normalize_phone(" 1-555-123-4567 ").unwrap(), "+15551234567");
+        assert_eq!(normalize_phone("+1 555.123.4567").unwrap(), "+15551234567");
+        assert_eq!(
+            normalize_phone("+44 20 7946 0958").unwrap(),
+            "+442079460958"
+        );
+        assert!(normalize_phone("555-1234").is_err());
+        assert!(normalize_phone("555-123-456a").is_err());
+        assert!(normalize_phone("+1234567890123456").is_err());
+    }
+}
@@ -1,49 +1,196 @@
+//! Transactional emails.
+//!
+//! Each kind of email is an `EmailTemplate` variant that renders a subject, a
+//! plain text body and an HTML body wrapped in the shared layout. SendGrid only
+//! sees the rendered parts.
+//!
+//! Emails to users go through `send_user_email`, which skips non-essential
+//! templates for users who opted out.
+
 use anyhow::anyhow;
-use sendgrid::{Mail, SGClient};
-use std::env;
+use sendgrid::Mail;
 
-pub async fn send_email_verification_code(
-    display_name: &str,
-    email: &str,
-    code: &str,
-) -> Result<(), anyhow::Error> {
-    let api_key = match env::var("SENDGRID_API_KEY") {
-        Ok(key) => key,
-        Err(e) => {
-            println!(
-                "[send_email_verification_code] Failed to get API key: {:?}",
-                e
-            );
-            return Err(anyhow!("Failed to get API key"));
+use crate::{
+    models::user::User,
+    state::AppState,
+    utils::redact::{redact, redact_debug},
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum EmailTemplate {
+    VerificationCode {
+        code: String,
+    },
+    /// Not sent yet; battle results will be emailed to players who opted in.
+    #[allow(dead_code)]
+    BattleResult {
+        opponent_name: String,
+        won: bool,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RenderedEmail {
+    pub subject: String,
+    pub text: String,
+    pub html: String,
+}
+
+impl EmailTemplate {
+    pub fn render(&self) -> RenderedEmail {
+        match self {
+            EmailTemplate::VerificationCode { code } => RenderedEmail {
+                subject: "MNSTR Verification Code".to_string(),
+                text: format!("Your MNSTR verification code is: {}", code),
+                html: html_layout(
+                    "MNSTR Verification Code",
+                    &format!(
+                        "<p>Your MNSTR verification code is:</p>\
+                         <p style=\"font-size: 24px; font-weight: bold; letter-spacing: 4px;\">{}</p>",
+                        escape_html(code)
+                    ),
+                ),
+            },
+            EmailTemplate::BattleResult { opponent_name, won } => {
+                let result = if *won { "won" } else { "lost" };
+                RenderedEmail {
+                    subject: "MNSTR Battle Result".to_string(),
+                    text: format!("You {} your battle against {}.", result, opponent_name),
+                    html: html_layout(
+                        "MNSTR Battle Result",
+                        &format!(
+                            "<p>You {} your battle against {}.</p>",
+                            result,
+                            escape_html(opponent_name)
+                        ),
+                    ),
+                }
+            }
         }
-    };
+    }
 
-    let from_email = match env::var("SENDGRID_FROM_EMAIL") {
-        Ok(email) => email,
-        Err(e) => {
-            println!(
-                "[send_email_verification_code] Failed to get from email: {:?}",
-                e
-            );
-            return Err(anyhow!("Failed to get from email"));
+    /// Security emails go out whatever the user's notification preferences.
+    pub fn is_essential(&self) -> bool {
+        match self {
+            EmailTemplate::VerificationCode { .. } => true,
+            EmailTemplate::BattleResult { .. } => false,
         }
-    };
+    }
+}
 
-    let client = SGClient::new(api_key.as_str());
-    let message = format!("Your MNSTR verification code is: {}", code);
+/// Wraps a rendered body in the branded layout every email shares.
+fn html_layout(title: &str, body: &str) -> String {
+    format!(
+        "<!DOCTYPE html>\
+         <html>\
+         <head><meta charset=\"utf-8\"><title>{title}</title></head>\
+         <body style=\"margin: 0; padding: 24px; background: #f4f4f7; font-family: sans-serif; color: #1f1f2e;\">\
+         <div style=\"max-width: 480px; margin: 0 auto; padding: 24px; background: #ffffff; border-radius: 8px;\">\
+         <h1 style=\"margin-top: 0; font-size: 20px;\">MNSTR</h1>\
+         {body}\
+         </div>\
+         </body>\
+         </html>",
+        title = escape_html(title),
+        body = body,
+    )
+}
+
+fn escape_html(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+pub async fn send_email(
+    state: &AppState,
+    template: &EmailTemplate,
+    display_name: &str,
+    email: &str,
+) -> Result<(), anyhow::Error> {
+    let rendered = template.render();
     let message = Mail::new()
-        .add_text(message.as_str())
-        .add_from(from_email.as_str())
-        .add_subject("MNSTR Verification Code")
+        .add_text(rendered.text.as_str())
+        .add_html(rendered.html.as_str())
+        .add_from(state.config.sendgrid_from_email.as_str())
+        .add_subject(rendered.subject.as_str())
         .add_to((email, display_name).into());
-    match client.send(message).await {
+    match state.sendgrid.send(message).await {
         Ok(_) => Ok(()),
         Err(e) => {
             println!(
-                "[send_email_verification_code] Failed to send email: {:?}",
-                e
+                "[send_email] Failed to send email to {}: {}",
+                redact(email),
+                redact_debug(&e)
             );
             return Err(anyhow!("Failed to send email"));
         }
     }
 }
+
+/// Emails `user` unless they opted out of `template`'s kind of email or have
+/// no address.
+///
+/// # Returns
+///
+/// Returns whether the email was sent.
+pub async fn send_user_email(
+    state: &AppState,
+    template: &EmailTemplate,
+    user: &User,
+) -> Result<bool, anyhow::Error> {
+    let email = match &user.email {
+        Some(email) => email,
+        None => return Ok(false),
+    };
+    if !user.receives_email(template) {
+        println!(
+            "[send_user_email] User {} opted out of email, not sending",
+            user.id
+        );
+        return Ok(false);
+    }
+    send_email(state, template, &user.display_name, email).await?;
+    Ok(true)
+}
+
+/// Verification codes are essential, so they are sent whatever the
+/// recipient's notification preferences.
+pub async fn send_email_verification_code(
+    state: &AppState,
+    display_name: &str,
+    email: &str,
+    code: &str,
+) -> Result<(), anyhow::Error> {
+    let template = EmailTemplate::VerificationCode {
+        code: code.to_string(),
+    };
+    send_email(state, &template, display_name, email).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verification_template_interpolates_code() {
+        let rendered = EmailTemplate::VerificationCode {
+            code: "482913".to_string(),
+        }
+        .render();
+
+        assert_eq!(rendered.subject, "MNSTR Verification Code");
+        assert_eq!(rendered.text, "Your MNSTR verification code is: 482913");
+        assert!(rendered.html.contains("482913"));
+        assert!(rendered.html.starts_with("<!DOCTYPE html>"));
+
+        let rendered = EmailTemplate::VerificationCode {
+            code: "<b>1</b>".to_string(),
+        }
+        .render();
+        assert!(rendered.html.contains("&lt;b&gt;1&lt;/b&gt;"));
+    }
+}
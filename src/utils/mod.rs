@@ -1,6 +1,11 @@
+pub mod admin;
+pub mod contact;
 pub mod passwords;
+pub mod redact;
 pub mod sessions;
 pub mod strings;
 pub mod time;
 pub mod token;
-pub mod emails;
\ No newline at end of file
+pub mod emails;
+pub mod multipliers;
+pub mod validation;
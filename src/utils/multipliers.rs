@@ -0,0 +1,116 @@
+//! Event multipliers for XP and coin awards.
+//!
+//! `XP_MULTIPLIER` and `COIN_MULTIPLIER` scale every award, e.g. `2` for a
+//! double XP weekend, and default to 1. They are read once, so an event starts
+//! and ends with a restart. Coin purchases aren't awards and are never scaled.
+
+use std::sync::OnceLock;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Multipliers {
+    pub xp: f64,
+    pub coins: f64,
+}
+
+impl Default for Multipliers {
+    fn default() -> Self {
+        Self {
+            xp: 1.0,
+            coins: 1.0,
+        }
+    }
+}
+
+impl Multipliers {
+    pub fn from_env() -> Self {
+        Self {
+            xp: parse_multiplier(std::env::var("XP_MULTIPLIER").ok()),
+            coins: parse_multiplier(std::env::var("COIN_MULTIPLIER").ok()),
+        }
+    }
+
+    /// The multipliers in effect, read from the environment on first use.
+    pub fn current() -> Self {
+        static CURRENT: OnceLock<Multipliers> = OnceLock::new();
+        *CURRENT.get_or_init(Multipliers::from_env)
+    }
+
+    pub fn xp(&self, xp: i32) -> i32 {
+        scale(xp, self.xp)
+    }
+
+    pub fn coins(&self, coins: i32) -> i32 {
+        scale(coins, self.coins)
+    }
+}
+
+/// Falls back to 1 for a missing, unparsable or negative multiplier.
+fn parse_multiplier(value: Option<String>) -> f64 {
+    match value.and_then(|value| value.trim().parse::<f64>().ok()) {
+        Some(multiplier) if multiplier.is_finite() && multiplier >= 0.0 => multiplier,
+        _ => 1.0,
+    }
+}
+
+fn scale(amount: i32, multiplier: f64) -> i32 {
+    (amount as f64 * multiplier).round() as i32
+}
+
+/// Scales an XP award with the current multiplier and logs it under `context`.
+pub fn award_xp(xp: i32, context: &str) -> i32 {
+    let multipliers = Multipliers::current();
+    let awarded = multipliers.xp(xp);
+    println!(
+        "[{}] Awarding {} xp ({} x{})",
+        context, awarded, xp, multipliers.xp
+    );
+    awarded
+}
+
+/// Scales a coin award with the current multiplier and logs it under `context`.
+pub fn award_coins(coins: i32, context: &str) -> i32 {
+    let multipliers = Multipliers::current();
+    let awarded = multipliers.coins(coins);
+    println!(
+        "[{}] Awarding {} coins ({} x{})",
+        context, awarded, coins, multipliers.coins
+    );
+    awarded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::user::User;
+
+    #[test]
+    fn test_double_multiplier_doubles_awards() {
+        let double = Multipliers {
+            xp: 2.0,
+            coins: 2.0,
+        };
+        assert_eq!(double.xp(40), 80);
+        assert_eq!(double.coins(12), 24);
+
+        let mut boosted = User::new(None, None, "password".to_string(), "Boosted".to_string());
+        let mut doubled = boosted.clone();
+        boosted.apply_xp(double.xp(15));
+        doubled.apply_xp(30);
+        assert_eq!(boosted.experience_level, doubled.experience_level);
+        assert_eq!(boosted.experience_points, doubled.experience_points);
+    }
+
+    #[test]
+    fn test_default_multiplier_keeps_awards() {
+        let unchanged = Multipliers::default();
+        for amount in [0, 5, 37, 1_000] {
+            assert_eq!(unchanged.xp(amount), amount);
+            assert_eq!(unchanged.coins(amount), amount);
+        }
+
+        assert_eq!(parse_multiplier(None), 1.0);
+        assert_eq!(parse_multiplier(Some(" 1.5 ".to_string())), 1.5);
+        assert_eq!(parse_multiplier(Some("double".to_string())), 1.0);
+        assert_eq!(parse_multiplier(Some("-2".to_string())), 1.0);
+    }
+}
@@ -0,0 +1,162 @@
+//! Masking of contact details in log output.
+//!
+//! Emails and phone numbers reach logs through error values, such as a unique
+//! violation echoing the duplicate email or a provider error echoing the
+//! recipient. Log lines that can carry them go through `redact` or
+//! `redact_debug` first. Setting `REDACT_CONTACT_INFO=false` turns masking off
+//! for local debugging.
+
+use std::{fmt::Debug, sync::OnceLock};
+
+/// Whether `REDACT_CONTACT_INFO` leaves masking on. Read once; on unless set
+/// to `0`, `false` or `no`.
+fn redaction_enabled() -> bool {
+    static ENABLED: OnceLock<bool> = OnceLock::new();
+    *ENABLED.get_or_init(|| match std::env::var("REDACT_CONTACT_INFO") {
+        Ok(value) => !matches!(value.trim().to_lowercase().as_str(), "0" | "false" | "no"),
+        Err(_) => true,
+    })
+}
+
+/// Keeps the first character of the local part and the domain.
+///
+/// # Examples
+///
+/// ```
+/// use crate::utils::redact::mask_email;
+///
+/// assert_eq!(mask_email("user@example.com"), "u***@example.com");
+/// ```
+pub fn mask_email(email: &str) -> String {
+    match email.split_once('@') {
+        Some((local, domain)) => match local.chars().next() {
+            Some(first) => format!("{}***@{}", first, domain),
+            None => format!("***@{}", domain),
+        },
+        None => "***".to_string(),
+    }
+}
+
+/// Keeps the last two digits.
+///
+/// # Examples
+///
+/// ```
+/// use crate::utils::redact::mask_phone;
+///
+/// assert_eq!(mask_phone("+15551234589"), "***-**89");
+/// ```
+pub fn mask_phone(phone: &str) -> String {
+    let digits = phone
+        .chars()
+        .filter(|c| c.is_ascii_digit())
+        .collect::<Vec<char>>();
+    if digits.len() < 2 {
+        return "***".to_string();
+    }
+    format!(
+        "***-**{}",
+        digits[digits.len() - 2..].iter().collect::<String>()
+    )
+}
+
+fn is_contact_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '%' | '+' | '-' | '@')
+}
+
+fn mask_word(word: &str) -> Option<String> {
+    // A sentence ending right after an address leaves its full stop attached
+    let trimmed = word.trim_end_matches('.');
+    if let Some((local, domain)) = trimmed.split_once('@') {
+        if !local.is_empty() && domain.contains('.') {
+            return Some(format!("{}{}", mask_email(trimmed), &word[trimmed.len()..]));
+        }
+        return None;
+    }
+    // Phone numbers are stored and sent in E.164, so only `+<digits>` is masked
+    if let Some(digits) = word.strip_prefix('+') {
+        if digits.len() >= 7 && digits.chars().all(|c| c.is_ascii_digit()) {
+            return Some(mask_phone(word));
+        }
+    }
+    None
+}
+
+/// Masks every email address and E.164 phone number found in `text`.
+///
+/// # Examples
+///
+/// ```
+/// use crate::utils::redact::mask_contacts;
+///
+/// assert_eq!(
+///     mask_contacts("Key (email)=(user@example.com) already exists."),
+///     "Key (email)=(u***@example.com) already exists."
+/// );
+/// ```
+pub fn mask_contacts(text: &str) -> String {
+    let mut masked = String::with_capacity(text.len());
+    let mut word = String::new();
+    for c in text.chars() {
+        if is_contact_char(c) {
+            word.push(c);
+            continue;
+        }
+        masked.push_str(&mask_word(&word).unwrap_or_else(|| word.clone()));
+        masked.push(c);
+        word.clear();
+    }
+    masked.push_str(&mask_word(&word).unwrap_or(word));
+    masked
+}
+
+/// `mask_contacts` unless `REDACT_CONTACT_INFO` turned masking off.
+pub fn redact(text: &str) -> String {
+    if !redaction_enabled() {
+        return text.to_string();
+    }
+    mask_contacts(text)
+}
+
+/// Formats `value` with `{:?}` and redacts the result.
+pub fn redact_debug<T: Debug + ?Sized>(value: &T) -> String {
+    redact(&format!("{:?}", value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mask_email() {
+        assert_eq!(mask_email("user@example.com"), "u***@example.com");
+        assert_eq!(mask_email("a@b.co"), "a***@b.co");
+        assert_eq!(mask_email("@example.com"), "***@example.com");
+        assert_eq!(mask_email("not-an-email"), "***");
+    }
+
+    #[test]
+    fn test_mask_phone() {
+        assert_eq!(mask_phone("+15551234589"), "***-**89");
+        assert_eq!(mask_phone("(555) 123-4567"), "***-**67");
+        assert_eq!(mask_phone("7"), "***");
+    }
+
+    #[test]
+    fn test_mask_contacts_in_error_text() {
+        assert_eq!(
+            mask_contacts(
+                "duplicate key value violates unique constraint: Key (email)=(user@example.com) already exists."
+            ),
+            "duplicate key value violates unique constraint: Key (email)=(u***@example.com) already exists."
+        );
+        assert_eq!(
+            mask_contacts("Failed to send to +15551234589 for user@example.com."),
+            "Failed to send to ***-**89 for u***@example.com."
+        );
+
+        // Ids, amounts and timestamps are left alone
+        let untouched = "user 0b6f0c4e-1f2a-4d6e-9a51-2f7f0b0c1d2e took 1760000000ms, +5 coins";
+        assert_eq!(mask_contacts(untouched), untouched);
+    }
+}
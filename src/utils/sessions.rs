@@ -8,10 +8,68 @@ pub trait SessionTrait<T> {
     async fn get_user(&mut self) -> Result<User, Error>;
 }
 
+/// Why a request has no usable session. Clients told `Expired` should log in
+/// again; `Unauthenticated` covers a missing, unknown or malformed token.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SessionError {
+    Expired,
+    Unauthenticated,
+}
+
+impl SessionError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            SessionError::Expired => "SESSION_EXPIRED",
+            SessionError::Unauthenticated => "UNAUTHENTICATED",
+        }
+    }
+
+    pub fn message(&self) -> &'static str {
+        match self {
+            SessionError::Expired => "Session expired",
+            SessionError::Unauthenticated => "Invalid session",
+        }
+    }
+
+    /// `Expired` when `error` came from an expired session, otherwise
+    /// `Unauthenticated`.
+    pub fn from_error(error: &Error) -> Self {
+        match error.downcast_ref::<SessionError>() {
+            Some(session_error) => *session_error,
+            None => SessionError::Unauthenticated,
+        }
+    }
+}
+
+impl std::fmt::Display for SessionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message())
+    }
+}
+
+impl std::error::Error for SessionError {}
+
+/// Refuses an expired session with `SessionError::Expired`, otherwise extends
+/// its expiry.
 pub async fn validate_session<T: SessionTrait<T>>(session: &mut T) -> Option<anyhow::Error> {
+    if session.expired() {
+        return Some(SessionError::Expired.into());
+    }
     session.update_expired().await
 }
 
+/// Finds and validates the session for `token`.
+pub async fn authenticate<T: SessionTrait<T>>(token: String) -> Result<T, SessionError> {
+    let mut session = match T::find_one_by_token(token).await {
+        Ok(session) => session,
+        Err(_) => return Err(SessionError::Unauthenticated),
+    };
+    if let Some(error) = validate_session(&mut session).await {
+        return Err(SessionError::from_error(&error));
+    }
+    Ok(session)
+}
+
 pub async fn get_user_from_token<T: SessionTrait<T> >(token: String) -> Result<User, Error> {
     let mut session = match T::find_one_by_token(token).await {
         Ok(session) => session,
@@ -24,4 +82,67 @@ pub async fn get_user_from_token<T: SessionTrait<T> >(token: String) -> Result<U
         Ok(user) => Ok(user),
         Err(e) => Err(e.into()),
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct FakeSession {
+        expired: bool,
+    }
+
+    impl SessionTrait<FakeSession> for FakeSession {
+        fn expired(&self) -> bool {
+            self.expired
+        }
+
+        async fn update_expired(&mut self) -> Option<anyhow::Error> {
+            None
+        }
+
+        async fn find_one_by_token(token: String) -> Result<FakeSession, Error> {
+            match token.as_str() {
+                "live-token" => Ok(FakeSession { expired: false }),
+                "expired-token" => Ok(FakeSession { expired: true }),
+                _ => Err(anyhow::anyhow!("Session not found")),
+            }
+        }
+
+        async fn get_user(&mut self) -> Result<User, Error> {
+            Err(anyhow::anyhow!("User not found"))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_expired_token_is_session_expired() {
+        let error = authenticate::<FakeSession>("expired-token".to_string())
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(error, SessionError::Expired);
+        assert_eq!(error.code(), "SESSION_EXPIRED");
+
+        assert!(
+            authenticate::<FakeSession>("live-token".to_string())
+                .await
+                .is_ok()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_garbage_token_is_unauthenticated() {
+        let error = authenticate::<FakeSession>("not a token".to_string())
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(error, SessionError::Unauthenticated);
+        assert_eq!(error.code(), "UNAUTHENTICATED");
+
+        let other = anyhow::anyhow!("Failed to update session");
+        assert_eq!(
+            SessionError::from_error(&other),
+            SessionError::Unauthenticated
+        );
+    }
+}
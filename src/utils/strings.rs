@@ -1,7 +1,8 @@
 /// Converts a camelCase or PascalCase string to snake_case.
 ///
 /// This function transforms strings from camelCase or PascalCase format to snake_case by:
-/// - Adding underscores before uppercase letters (except at the start of consecutive uppercase letters)
+/// - Adding underscores before uppercase letters that follow a lowercase letter
+/// - Keeping an acronym together and splitting its last letter off when it starts the next word
 /// - Converting all characters to lowercase
 ///
 /// # Examples
@@ -11,6 +12,7 @@
 ///
 /// assert_eq!(camel_to_snake_case("camelCase".to_string()), "camel_case");
 /// assert_eq!(camel_to_snake_case("ThisIsATest".to_string()), "this_is_a_test");
+/// assert_eq!(camel_to_snake_case("HTTPServer".to_string()), "http_server");
 /// assert_eq!(camel_to_snake_case("ABC".to_string()), "a_b_c");
 /// ```
 ///
@@ -18,7 +20,9 @@
 ///
 /// - For camelCase input: Adds underscore before uppercase letters
 /// - For PascalCase input: Converts first letter to lowercase and adds underscores before other uppercase letters
-/// - For consecutive uppercase letters (like in acronyms): Adds underscore between each letter
+/// - For consecutive uppercase letters followed by a word (like in acronyms): Keeps the acronym
+///   together and adds an underscore before the word, so `HTTPServer` becomes `http_server`
+/// - For consecutive uppercase letters at the end of the input: Adds underscore between each letter
 /// - For single-word lowercase input: Returns the same word in lowercase
 /// - For empty strings: Returns an empty string
 ///
@@ -30,26 +34,42 @@
 ///
 /// Returns a new String in snake_case format
 pub fn camel_to_snake_case(camel: String) -> String {
+    let chars = camel.chars().collect::<Vec<char>>();
     let mut snake = String::with_capacity(camel.len() + 4);
-    let mut chars = camel.chars().peekable();
 
-    while let Some(current) = chars.next() {
-        if let Some(&next) = chars.peek() {
-            if current.is_ascii_lowercase() && next.is_ascii_uppercase() {
-                snake.push(current);
-                snake.push('_');
+    for (i, &current) in chars.iter().enumerate() {
+        if i > 0 && current.is_ascii_uppercase() {
+            let previous = chars[i - 1];
+            let next = chars.get(i + 1);
+            let split = if previous.is_ascii_lowercase() {
+                true
+            } else if previous.is_ascii_uppercase() {
+                // Inside an uppercase run: the last letter before a lowercase one
+                // starts the next word, and a run that ends the input is split
+                // into single letters
+                next.is_some_and(|next| next.is_ascii_lowercase())
+                    || chars[i..].iter().all(|c| c.is_ascii_uppercase())
             } else {
-                snake.push(current.to_ascii_lowercase());
+                false
+            };
+            if split {
+                snake.push('_');
             }
-        } else {
-            // Handle the last character
-            snake.push(current.to_ascii_lowercase());
         }
+        snake.push(current.to_ascii_lowercase());
     }
 
     snake
 }
 
+/// Escapes `%`, `_` and `\` so `term` matches itself literally inside a
+/// `LIKE` pattern.
+pub fn escape_like(term: &str) -> String {
+    term.replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -62,7 +82,15 @@ mod tests {
             "this_is_a_test"
         );
         assert_eq!(camel_to_snake_case("ABC".to_string()), "a_b_c");
+        assert_eq!(camel_to_snake_case("HTTPServer".to_string()), "http_server");
+        assert_eq!(camel_to_snake_case("ItemEffect".to_string()), "item_effect");
         assert_eq!(camel_to_snake_case("simple".to_string()), "simple");
         assert_eq!(camel_to_snake_case("".to_string()), "");
     }
+
+    #[test]
+    fn test_escape_like() {
+        assert_eq!(escape_like("mnstr"), "mnstr");
+        assert_eq!(escape_like("100%_sure\\"), "100\\%\\_sure\\\\");
+    }
 }
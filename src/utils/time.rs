@@ -4,10 +4,34 @@
 //! and `OffsetDateTime` objects when working with serde serialization/deserialization.
 //! It's particularly useful when dealing with JSON or other data formats that need to
 //! represent timestamps.
+//!
+//! GraphQL exposes `OffsetDateTime` fields as juniper's `DateTime` scalar
+//! (the `time` integration), which writes RFC 3339 in UTC. `format_rfc3339`
+//! and `parse_rfc3339` follow the same rules so JSON and GraphQL responses
+//! carry identical strings.
 
 use serde::{self, Deserialize};
-use time::OffsetDateTime;
 use time::format_description::well_known::Rfc3339;
+use time::{OffsetDateTime, UtcOffset};
+
+/// Formats a timestamp as RFC 3339 in UTC.
+///
+/// # Example
+///
+/// ```
+/// use time::OffsetDateTime;
+///
+/// let epoch = OffsetDateTime::from_unix_timestamp(0).unwrap();
+/// assert_eq!(format_rfc3339(&epoch).unwrap(), "1970-01-01T00:00:00Z");
+/// ```
+pub fn format_rfc3339(date_time: &OffsetDateTime) -> Result<String, time::error::Format> {
+    date_time.to_offset(UtcOffset::UTC).format(&Rfc3339)
+}
+
+/// Parses an RFC 3339 timestamp with any offset.
+pub fn parse_rfc3339(value: &str) -> Result<OffsetDateTime, time::error::Parse> {
+    OffsetDateTime::parse(value, &Rfc3339)
+}
 
 /// Serializes an `Option<OffsetDateTime>` into a string using RFC 3339 format.
 ///
@@ -42,7 +66,7 @@ where
 {
     match date_time {
         Some(dt) => {
-            serializer.serialize_str(&dt.format(&Rfc3339).map_err(serde::ser::Error::custom)?)
+            serializer.serialize_str(&format_rfc3339(dt).map_err(serde::ser::Error::custom)?)
         }
         None => serializer.serialize_none(),
     }
@@ -80,9 +104,25 @@ where
 {
     let s: Option<String> = Option::deserialize(deserializer)?;
     match s {
-        Some(s) => Ok(Some(
-            OffsetDateTime::parse(&s, &Rfc3339).map_err(serde::de::Error::custom)?,
-        )),
+        Some(s) => Ok(Some(parse_rfc3339(&s).map_err(serde::de::Error::custom)?)),
         None => Ok(None),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rfc3339_is_written_in_utc() {
+        let epoch = OffsetDateTime::from_unix_timestamp(0).unwrap();
+        assert_eq!(format_rfc3339(&epoch).unwrap(), "1970-01-01T00:00:00Z");
+
+        let offset = epoch.to_offset(UtcOffset::from_hms(2, 0, 0).unwrap());
+        assert_eq!(format_rfc3339(&offset).unwrap(), "1970-01-01T00:00:00Z");
+
+        let parsed = parse_rfc3339("1970-01-01T02:00:00+02:00").unwrap();
+        assert_eq!(parsed, epoch);
+        assert!(parse_rfc3339("1970-01-01 00:00:00").is_err());
+    }
+}
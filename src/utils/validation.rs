@@ -0,0 +1,81 @@
+//! Validation of client-provided identifiers.
+//!
+//! QR codes and ids arrive from GraphQL arguments and websocket payloads and are
+//! checked here before they reach hashing or database lookups.
+
+use uuid::Uuid;
+
+/// Longest QR code payload accepted from a client.
+pub const MAX_QR_CODE_LENGTH: usize = 512;
+
+/// Checks a QR code is non-empty, at most `MAX_QR_CODE_LENGTH` bytes and made
+/// of printable ASCII.
+///
+/// # Examples
+///
+/// ```
+/// use crate::utils::validation::validate_qr_code;
+///
+/// assert!(validate_qr_code("mnstr:1234").is_ok());
+/// assert!(validate_qr_code("").is_err());
+/// ```
+///
+/// # Returns
+///
+/// Returns an error if the QR code is empty, too long or has other characters.
+pub fn validate_qr_code(qr_code: &str) -> Result<(), anyhow::Error> {
+    if qr_code.is_empty() || qr_code.len() > MAX_QR_CODE_LENGTH {
+        return Err(anyhow::anyhow!("Invalid QR code"));
+    }
+    if !qr_code.chars().all(|c| c.is_ascii_graphic() || c == ' ') {
+        return Err(anyhow::anyhow!("Invalid QR code"));
+    }
+    Ok(())
+}
+
+/// Checks an id is a hyphenated UUID, the format every table's ids use.
+///
+/// # Examples
+///
+/// ```
+/// use crate::utils::validation::validate_id;
+///
+/// assert!(validate_id("0b6f0c4e-1f2a-4d6e-9a51-2f7f0b0c1d2e").is_ok());
+/// assert!(validate_id("1 OR 1=1").is_err());
+/// ```
+///
+/// # Returns
+///
+/// Returns an error if the id isn't a UUID.
+pub fn validate_id(id: &str) -> Result<(), anyhow::Error> {
+    // `Uuid::parse_str` also takes simple, braced and urn forms; ids are only
+    // ever stored hyphenated
+    if id.len() != 36 || Uuid::parse_str(id).is_err() {
+        return Err(anyhow::anyhow!("Invalid id"));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_qr_code() {
+        assert!(validate_qr_code("mnstr:0b6f0c4e-1f2a-4d6e").is_ok());
+        assert!(validate_qr_code(&"a".repeat(MAX_QR_CODE_LENGTH)).is_ok());
+        assert!(validate_qr_code(&"a".repeat(MAX_QR_CODE_LENGTH + 1)).is_err());
+        assert!(validate_qr_code("").is_err());
+        assert!(validate_qr_code("mnstr\n1234").is_err());
+        assert!(validate_qr_code("mnstr\u{0}").is_err());
+    }
+
+    #[test]
+    fn test_validate_id() {
+        assert!(validate_id("0b6f0c4e-1f2a-4d6e-9a51-2f7f0b0c1d2e").is_ok());
+        assert!(validate_id("0b6f0c4e1f2a4d6e9a512f7f0b0c1d2e").is_err());
+        assert!(validate_id("0b6f0c4e-1f2a-4d6e-9a51-2f7f0b0c1d2z").is_err());
+        assert!(validate_id("' OR 1=1 --").is_err());
+        assert!(validate_id("").is_err());
+    }
+}
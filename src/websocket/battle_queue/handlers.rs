@@ -1,40 +1,59 @@
+use std::sync::Arc;
+
+use futures::stream::BoxStream;
 use futures_util::StreamExt as _;
-use rand::prelude::*;
 use redis::AsyncTypedCommands;
-use rocket_ws::{Config, Stream, WebSocket, result::Error};
+use rocket::{State, tokio::sync::Mutex};
+use rocket_ws::{Stream, WebSocket, result::Error};
 
 use crate::{
+    battle::{
+        helpers::{BattleRng, new_battle_seed},
+        turn_order::{first_turn_user_id, turn_order_rule},
+    },
+    database::connection::transaction,
     delete_resource_where_fields,
     models::{
         battle::Battle,
         battle_log::{BattleLog, BattleLogAction},
-        battle_status::{BattleStatus, BattleStatusState},
-        generated::mnstr_xp::XP_FOR_LEVEL,
-        mnstr::{Mnstr, MnstrOrderBy, MnstrOrderDirection},
+        battle_status::{BattleStatus, BattleStatusState, max_queue_duration},
+        block::Block,
+        mnstr::{FAINTED_MNSTR_ERROR, Mnstr},
         user::User,
     },
-    utils::token::RawToken,
+    state::AppState,
+    utils::{multipliers::Multipliers, token::RawToken, validation::validate_id},
     websocket::{
         battle_queue::models::{
-            BattleLogData, BattleQueue, BattleQueueAction, BattleQueueChannel, BattleQueueData,
-            BattleQueueDataAction, BattleQueueGameData, SortMnstrsInput,
+            BATTLE_SEQ_TTL_SECONDS, BattleChannelChange, BattleLogData, BattleOutcome, BattleQueue,
+            BattleQueueAction, BattleQueueChannel, BattleQueueData, BattleQueueDataAction,
+            BattleQueueGameData, BattleRewards, CombatMove, KnockoutResult, LOBBY_PUBSUB_CHANNEL,
+            SortMnstrsInput, battle_disconnects_key, battle_pubsub_channel, battle_seq_key,
+            disconnect_grace, knockout_result,
         },
+        battle_queue::practice::{
+            PRACTICE_IN_PROGRESS_ERROR, PRACTICE_OPPONENT_ID, PRACTICE_OPPONENT_NAME,
+            PRACTICE_REWARD_COOLDOWN_MINUTES, PracticeBattle,
+        },
+        config::{ws_config, ws_write_timeout},
         helpers::verify_session_token,
     },
 };
 
 #[get("/battle_queue/<token>")]
-pub async fn battle_queue(ws: WebSocket, token: RawToken) -> Stream!['static] {
-    let ws = ws.config(Config::default());
-    let session = match verify_session_token(token).await {
-        Ok(session) => Some(session),
-        Err(err) => {
-            println!("Invalid session: {:?}", err);
-            None
-        }
-    };
+pub async fn battle_queue(
+    ws: WebSocket,
+    token: RawToken,
+    state: &State<AppState>,
+) -> Stream!['static] {
+    let ws = ws.config(ws_config());
+    let redis = state.redis.clone();
+    let session = verify_session_token(token).await;
+    if let Err(err) = &session {
+        println!("Invalid session: {:?}", err);
+    }
     let mut user_name: Option<String> = None;
-    if let Some(session_ref) = session.as_ref() {
+    if let Ok(session_ref) = session.as_ref() {
         match User::find_one(session_ref.user_id.clone(), false).await {
             Ok(user) => {
                 user_name = Some(user.display_name);
@@ -47,21 +66,22 @@ pub async fn battle_queue(ws: WebSocket, token: RawToken) -> Stream!['static] {
 
     Stream! { ws => {
             // Check for valid session
-            if let None = session {
-                let battle_queue = build_error(
+            if let Err(error) = session {
+                let mut battle_queue = build_error(
                     None,
                     user_name,
                     BattleQueueChannel::Lobby,
                     BattleQueueAction::Error,
                     BattleQueueDataAction::Connect,
-                    "Invalid session".to_string(),
+                    error.message().to_string(),
                 );
+                battle_queue.data.code = Some(error.code().to_string());
                 yield serde_json::to_string(&battle_queue).unwrap().into();
                 return;
             }
 
             // Open Redis connection
-            let (client, mut connection) = match open_redis_with_connection().await {
+            let (client, mut connection) = match open_redis_with_connection(&redis).await {
                 Ok((client, connection)) => (client, connection),
                 Err(err) => {
                     println!("[redis] Error initializing Redis: {:?}", err);
@@ -81,8 +101,8 @@ pub async fn battle_queue(ws: WebSocket, token: RawToken) -> Stream!['static] {
             let session = session.unwrap();
             let session_user_id = session.user_id.clone();
 
-            // Subscribe to battle queue
-            let mut rx = subscribe_and_forward(&client).await;
+            // Subscribe to the lobby; battle channels are joined as games start
+            let (mut rx, mut subscription) = subscribe_and_forward(&client).await;
 
             // Insert battle status and notify lobby
             insert_initial_status_and_notify(
@@ -92,10 +112,13 @@ pub async fn battle_queue(ws: WebSocket, token: RawToken) -> Stream!['static] {
             ).await;
 
             // Ping connection: this prevents redis timeouts
-            spawn_redis_ping(connection.clone());
+            spawn_redis_ping(client.clone(), connection.clone());
 
             let user_name = user_name.clone();
 
+            // A practice battle is played on this connection alone
+            let mut practice: Option<PracticeBattle> = None;
+
             // React to incoming messages from the battle queue and clients
             let mut ws = ws;
             loop {
@@ -103,9 +126,15 @@ pub async fn battle_queue(ws: WebSocket, token: RawToken) -> Stream!['static] {
                     maybe_payload = rx.recv() => {
                         match maybe_payload {
                             Some(payload) => {
+                                if let Ok(queue) = serde_json::from_str::<BattleQueue>(&payload) {
+                                    if let Some(change) = queue.battle_channel_change(&session_user_id) {
+                                        subscription.apply(change).await;
+                                    }
+                                }
                                 yield payload.into();
                             },
-                            None => { /* channel closed */ }
+                            // The forwarder gave up on a client too slow to keep up
+                            None => break,
                         }
                     },
                     maybe_message = ws.next() => {
@@ -117,8 +146,21 @@ pub async fn battle_queue(ws: WebSocket, token: RawToken) -> Stream!['static] {
                                         continue;
                                     }
                                 }
-                            if let Some(payload) = handle_incoming_ws_message(message, &mut connection, &session_user_id, &user_name).await {
-                                yield payload.into();
+                            let queue = match build_battle_queue(message) {
+                                Ok(queue) => queue,
+                                Err(err) => {
+                                    println!("[battle_queue_handler] Error building battle queue: {:?}", err);
+                                    // Notify others and cleanup
+                                    on_player_left(&mut connection, &session_user_id, &user_name).await;
+                                    continue;
+                                }
+                            };
+                            let replies = match handle_practice_message(&queue, &mut practice, &session_user_id, &user_name).await {
+                                Some(replies) => replies,
+                                None => handle_incoming_ws_message(queue, &mut connection, &mut subscription, &session_user_id, &user_name).await,
+                            };
+                            for reply in replies {
+                                yield reply.into();
                             }
                         },
                             None => break,
@@ -126,44 +168,305 @@ pub async fn battle_queue(ws: WebSocket, token: RawToken) -> Stream!['static] {
                     }
                 }
             }
+
+            // Dropping out of a running battle starts the rejoin grace period
+            let battle_id = subscription.state.lock().await.battle_id.clone();
+            if let Some(battle_id) = battle_id {
+                on_battle_disconnect(&mut connection, battle_id, &session_user_id).await;
+            }
         }
     }
 }
 
 // Extracted: Open redis client and a multiplexed connection
-async fn open_redis_with_connection()
--> Result<(redis::Client, redis::aio::MultiplexedConnection), Error> {
-    let client = connect_to_redis().await?;
+async fn open_redis_with_connection(
+    client: &redis::Client,
+) -> Result<(redis::Client, redis::aio::MultiplexedConnection), Error> {
     let connection = client.get_multiplexed_async_connection().await.unwrap();
-    Ok((client, connection))
+    Ok((client.clone(), connection))
 }
 
+/// Pubsub payloads buffered per connection before it is considered stalled.
+pub const FORWARDER_CHANNEL_CAPACITY: usize = 256;
+
 // Extracted: Subscribe and forward pubsub messages into an internal channel
 async fn subscribe_and_forward(
     client: &redis::Client,
-) -> rocket::tokio::sync::mpsc::UnboundedReceiver<String> {
-    let mut pubsub = client.get_async_pubsub().await.unwrap();
-    pubsub.subscribe("battle_queue").await.unwrap();
-    let mut pubsub_stream = pubsub.into_on_message();
-    let (tx, rx) = rocket::tokio::sync::mpsc::unbounded_channel::<String>();
+) -> (
+    rocket::tokio::sync::mpsc::Receiver<String>,
+    BattleSubscription,
+) {
+    let pubsub = client.get_async_pubsub().await.unwrap();
+    let (mut sink, pubsub_stream) = pubsub.split();
+    sink.subscribe(LOBBY_PUBSUB_CHANNEL).await.unwrap();
+    let subscription = BattleSubscription {
+        state: Arc::new(Mutex::new(SubscriptionState {
+            sink,
+            battle_id: None,
+        })),
+    };
+    let (tx, rx) = rocket::tokio::sync::mpsc::channel::<String>(FORWARDER_CHANNEL_CAPACITY);
+
+    let client = client.clone();
+    let state = subscription.state.clone();
+    rocket::tokio::spawn(forward_payloads(
+        payloads(pubsub_stream),
+        tx,
+        ws_write_timeout(),
+        move || {
+            let client = client.clone();
+            let state = state.clone();
+            async move { resubscribe(&client, &state).await }
+        },
+    ));
+    (rx, subscription)
+}
+
+fn payloads(pubsub_stream: redis::aio::PubSubStream) -> BoxStream<'static, String> {
+    pubsub_stream
+        .filter_map(|message| async move { message.get_payload::<String>().ok() })
+        .boxed()
+}
+
+/// Forwards payloads into `tx` until its receiver is dropped. When the pubsub
+/// stream ends, e.g. because Redis restarted, a new one is requested from
+/// `resubscribe` every second until one is returned.
+///
+/// A client that falls a full channel behind and doesn't take a payload
+/// within `write_timeout` is disconnected rather than skipped ahead: dropping
+/// lobby or battle messages would leave it with a wrong view of the game,
+/// while a reconnect lets it rejoin with fresh state. The forwarder stops and
+/// drops `tx`, so the connection closes once the buffered payloads have been
+/// delivered.
+async fn forward_payloads<F, Fut>(
+    mut stream: BoxStream<'static, String>,
+    tx: rocket::tokio::sync::mpsc::Sender<String>,
+    write_timeout: std::time::Duration,
+    mut resubscribe: F,
+) where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Option<BoxStream<'static, String>>>,
+{
+    loop {
+        while let Some(payload) = stream.next().await {
+            match tx.send_timeout(payload, write_timeout).await {
+                Ok(_) => (),
+                Err(rocket::tokio::sync::mpsc::error::SendTimeoutError::Timeout(_)) => {
+                    println!("[redis] client fell behind, closing connection");
+                    return;
+                }
+                Err(rocket::tokio::sync::mpsc::error::SendTimeoutError::Closed(_)) => return,
+            }
+        }
+        println!("[redis] pubsub stream ended, resubscribing");
+        stream = loop {
+            if tx.is_closed() {
+                return;
+            }
+            match resubscribe().await {
+                Some(stream) => {
+                    println!("[redis] pubsub resubscribed successfully");
+                    break stream;
+                }
+                None => rocket::tokio::time::sleep(std::time::Duration::from_secs(1)).await,
+            }
+        };
+    }
+}
+
+/// Opens a new pubsub connection subscribed to the lobby and the battle the
+/// connection follows, and swaps its sink into the subscription.
+async fn resubscribe(
+    client: &redis::Client,
+    state: &Mutex<SubscriptionState>,
+) -> Option<BoxStream<'static, String>> {
+    let pubsub = match client.get_async_pubsub().await {
+        Ok(pubsub) => pubsub,
+        Err(err) => {
+            println!("[redis] pubsub reconnect failed: {:?}", err);
+            return None;
+        }
+    };
+    let (mut sink, pubsub_stream) = pubsub.split();
+
+    // Held while subscribing so a concurrent join can't slip between the two sinks
+    let mut state = state.lock().await;
+    let mut channels = vec![LOBBY_PUBSUB_CHANNEL.to_string()];
+    if let Some(battle_id) = &state.battle_id {
+        channels.push(battle_pubsub_channel(battle_id));
+    }
+    for channel in channels {
+        if let Err(err) = sink.subscribe(&channel).await {
+            println!(
+                "[redis] pubsub resubscribe to {} failed: {:?}",
+                channel, err
+            );
+            return None;
+        }
+    }
+    state.sink = sink;
+    Some(payloads(pubsub_stream))
+}
+
+/// The battle channel a connection follows in addition to the lobby.
+struct BattleSubscription {
+    state: Arc<Mutex<SubscriptionState>>,
+}
+
+/// Shared with the forwarding task, which replaces the sink on resubscribe.
+struct SubscriptionState {
+    sink: redis::aio::PubSubSink,
+    battle_id: Option<String>,
+}
+
+impl BattleSubscription {
+    async fn apply(&mut self, change: BattleChannelChange) {
+        match change {
+            BattleChannelChange::Join(battle_id) => self.join(&battle_id).await,
+            BattleChannelChange::Leave(battle_id) => {
+                let mut state = self.state.lock().await;
+                if state.battle_id.as_ref() == Some(&battle_id) {
+                    state.leave().await;
+                }
+            }
+        }
+    }
+
+    async fn join(&mut self, battle_id: &String) {
+        let mut state = self.state.lock().await;
+        if state.battle_id.as_ref() == Some(battle_id) {
+            return;
+        }
+        state.leave().await;
+        if let Err(err) = state.sink.subscribe(battle_pubsub_channel(battle_id)).await {
+            println!("[BattleSubscription::join] Error subscribing: {:?}", err);
+            return;
+        }
+        state.battle_id = Some(battle_id.clone());
+    }
+}
+
+impl SubscriptionState {
+    async fn leave(&mut self) {
+        if let Some(battle_id) = self.battle_id.take() {
+            if let Err(err) = self
+                .sink
+                .unsubscribe(battle_pubsub_channel(&battle_id))
+                .await
+            {
+                println!("[BattleSubscription::leave] Error unsubscribing: {:?}", err);
+            }
+        }
+    }
+}
+
+/// How often queued players are checked against the max queue duration.
+const IDLE_QUEUE_SWEEP_INTERVAL_SECONDS: u64 = 30;
+
+/// Periodically removes players left in the queue for longer than
+/// `max_queue_duration()` and tells the lobby they left.
+pub fn spawn_idle_queue_sweeper(client: redis::Client) {
     rocket::tokio::spawn(async move {
         loop {
-            let message = match pubsub_stream.next().await {
-                Some(m) => m,
-                None => break,
+            rocket::tokio::time::sleep(std::time::Duration::from_secs(
+                IDLE_QUEUE_SWEEP_INTERVAL_SECONDS,
+            ))
+            .await;
+
+            let statuses = match BattleStatus::find_all_by(vec![(
+                "status",
+                BattleStatusState::InQueue.to_string().into(),
+            )])
+            .await
+            {
+                Ok(statuses) => statuses,
+                Err(err) => {
+                    println!(
+                        "[idle_queue_sweeper] Error finding queued players: {:?}",
+                        err
+                    );
+                    continue;
+                }
             };
-            let payload: String = match message.get_payload() {
-                Ok(p) => p,
-                Err(_) => continue,
+            let connection = match client.get_multiplexed_async_connection().await {
+                Ok(connection) => connection,
+                Err(err) => {
+                    println!("[idle_queue_sweeper] Error connecting to Redis: {:?}", err);
+                    continue;
+                }
             };
-            let _ = tx.send(payload);
+
+            sweep_idle_queue(
+                statuses,
+                time::OffsetDateTime::now_utc(),
+                max_queue_duration(),
+                |status| async move {
+                    let params = vec![
+                        ("id", status.id.clone().into()),
+                        ("status", BattleStatusState::InQueue.to_string().into()),
+                    ];
+                    match delete_resource_where_fields!(BattleStatus, params).await {
+                        Ok(_) => true,
+                        Err(err) => {
+                            println!(
+                                "[idle_queue_sweeper] Error deleting battle status: {:?}",
+                                err
+                            );
+                            false
+                        }
+                    }
+                },
+                |queue| {
+                    let mut connection = connection.clone();
+                    async move { publish_queue(&mut connection, &queue).await }
+                },
+            )
+            .await;
         }
     });
-    rx
+}
+
+/// Removes every idle queued player with `remove` and publishes a `Left` frame
+/// with `notify` for each one removed. Returns how many were removed.
+async fn sweep_idle_queue<R, RFut, N, NFut>(
+    statuses: Vec<BattleStatus>,
+    now: time::OffsetDateTime,
+    max_duration: time::Duration,
+    mut remove: R,
+    mut notify: N,
+) -> usize
+where
+    R: FnMut(BattleStatus) -> RFut,
+    RFut: Future<Output = bool>,
+    N: FnMut(BattleQueue) -> NFut,
+    NFut: Future<Output = ()>,
+{
+    let mut removed = 0;
+    for status in statuses {
+        if !status.is_idle_in_queue(now, max_duration) {
+            continue;
+        }
+        let user_id = status.user_id.clone();
+        let display_name = status.display_name.clone();
+        if !remove(status).await {
+            continue;
+        }
+        notify(build_success(
+            Some(user_id),
+            Some(display_name),
+            BattleQueueChannel::Lobby,
+            BattleQueueAction::Left,
+            BattleQueueDataAction::Left,
+            "Removed from the battle queue after being idle".to_string(),
+        ))
+        .await;
+        removed += 1;
+    }
+    removed
 }
 
 // Extracted: Spawn background ping to keep connection alive with reconnection attempts
-fn spawn_redis_ping(mut connection: redis::aio::MultiplexedConnection) {
+fn spawn_redis_ping(client: redis::Client, mut connection: redis::aio::MultiplexedConnection) {
     rocket::tokio::spawn(async move {
         loop {
             match connection.ping().await {
@@ -172,15 +475,13 @@ fn spawn_redis_ping(mut connection: redis::aio::MultiplexedConnection) {
                 }
                 Err(err) => {
                     println!("[redis] ping failed: {:?}", err);
-                    if let Ok(client) = connect_to_redis().await {
-                        match client.get_multiplexed_async_connection().await {
-                            Ok(new_conn) => {
-                                println!("[redis] ping reconnected successfully");
-                                connection = new_conn;
-                            }
-                            Err(reconn_err) => {
-                                println!("[redis] ping reconnect failed: {:?}", reconn_err);
-                            }
+                    match client.get_multiplexed_async_connection().await {
+                        Ok(new_conn) => {
+                            println!("[redis] ping reconnected successfully");
+                            connection = new_conn;
+                        }
+                        Err(reconn_err) => {
+                            println!("[redis] ping reconnect failed: {:?}", reconn_err);
                         }
                     }
                     rocket::tokio::time::sleep(std::time::Duration::from_secs(1)).await;
@@ -248,28 +549,86 @@ fn build_battle_queue(message: Result<rocket_ws::Message, Error>) -> Result<Batt
         ));
     }
 
-    let queue: BattleQueue = match serde_json::from_str(&message) {
-        Ok(queue) => queue,
+    match parse_battle_queue(&message) {
+        Ok(queue) => Ok(queue),
         Err(err) => {
             println!(
                 "[build_battle_queue] Error building battle queue: {:?}",
                 err
             );
             println!("[build_battle_queue] Message: {:?}", message);
-            return Ok(build_error(
+            Ok(build_error(
                 None,
                 None,
                 BattleQueueChannel::Lobby,
                 BattleQueueAction::Error,
                 BattleQueueDataAction::Error,
                 "Invalid message".to_string(),
-            ));
+            ))
         }
-    };
+    }
+}
+
+fn parse_battle_queue(message: &str) -> Result<BattleQueue, serde_json::Error> {
+    let mut queue: BattleQueue = serde_json::from_str(message)?;
+
+    // The server decides routing; clients can't push battle traffic to the lobby
+    if queue.data.action.is_in_battle() {
+        queue.channel = BattleQueueChannel::Battle;
+    }
 
     Ok(queue)
 }
 
+/// What the sender of a command gets back: the ack and the replies when it was
+/// accepted, or only the replies when it was rejected.
+fn acknowledged(ack: &BattleQueue, handled: Result<Vec<String>, Vec<String>>) -> Vec<String> {
+    match handled {
+        Ok(replies) => {
+            let mut acknowledged = vec![serde_json::to_string(ack).unwrap()];
+            acknowledged.extend(replies);
+            acknowledged
+        }
+        Err(replies) => replies,
+    }
+}
+
+/// Whether a queue message a handler built and published was an error.
+fn published(queue: &BattleQueue) -> Result<Option<String>, Option<String>> {
+    match queue.action {
+        BattleQueueAction::Error => Err(None),
+        _ => Ok(None),
+    }
+}
+
+/// Echoes the command's data action and id so the sender can match the ack to
+/// the command it has in flight.
+fn build_ack(
+    queue: &BattleQueue,
+    session_user_id: &String,
+    user_name: &Option<String>,
+) -> BattleQueue {
+    let mut battle_queue_data = BattleQueueData::new(
+        queue.data.action.clone(),
+        Some(session_user_id.clone()),
+        user_name.clone(),
+        None,
+        None,
+        None,
+        None,
+        None,
+        None,
+        None,
+    );
+    battle_queue_data.id = queue.data.id.clone();
+    BattleQueue::new(
+        Some(session_user_id.clone()),
+        queue.channel.clone(),
+        BattleQueueAction::Ack,
+        battle_queue_data,
+    )
+}
+
 fn build_error(
     user_id: Option<String>,
     user_name: Option<String>,
@@ -318,14 +677,28 @@ fn build_success(
     battle_queue
 }
 
-async fn connect_to_redis() -> Result<redis::Client, Error> {
-    let config = std::env::var("REDIS_URL").unwrap();
-    let client = redis::Client::open(config).unwrap();
-    Ok(client)
-}
-
 // Message handling helpers
 async fn publish_queue(connection: &mut redis::aio::MultiplexedConnection, queue: &BattleQueue) {
+    let queue = sequence_queue(queue, |battle_id| {
+        let mut connection = connection.clone();
+        async move {
+            let key = battle_seq_key(&battle_id);
+            let seq = match connection.incr(&key, 1).await {
+                Ok(seq) => seq as i64,
+                Err(err) => {
+                    println!("[publish_queue] Error sequencing battle frame: {:?}", err);
+                    return None;
+                }
+            };
+            if seq == 1 {
+                if let Err(err) = connection.expire(&key, BATTLE_SEQ_TTL_SECONDS).await {
+                    println!("[publish_queue] Error expiring sequence: {:?}", err);
+                }
+            }
+            Some(seq)
+        }
+    })
+    .await;
     let payload = serde_json::to_string(&queue).unwrap();
     match queue.action {
         BattleQueueAction::Ping => {}
@@ -333,7 +706,24 @@ async fn publish_queue(connection: &mut redis::aio::MultiplexedConnection, queue
             // println!("[publish_queue] Queue: {:?}", payload);
         }
     }
-    connection.publish("battle_queue", payload).await.unwrap();
+    connection
+        .publish(queue.pubsub_channel(), payload)
+        .await
+        .unwrap();
+}
+
+/// Stamps a battle broadcast with the next number `next_seq` hands out for its
+/// battle. Lobby frames go out as they are.
+async fn sequence_queue<N, NFut>(queue: &BattleQueue, next_seq: N) -> BattleQueue
+where
+    N: FnOnce(String) -> NFut,
+    NFut: Future<Output = Option<i64>>,
+{
+    let mut queue = queue.clone();
+    if let (BattleQueueChannel::Battle, Some(battle_id)) = (&queue.channel, queue.battle_id()) {
+        queue.data.seq = next_seq(battle_id).await;
+    }
+    queue
 }
 
 async fn on_player_left(
@@ -367,364 +757,1061 @@ async fn on_player_left(
     publish_queue(connection, &battle_queue).await;
 }
 
-// Extracted handler for incoming websocket messages
-async fn handle_incoming_ws_message(
-    message: Result<rocket_ws::Message, Error>,
+/// What became of a player who dropped mid-battle once their grace period ran
+/// out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DisconnectResolution {
+    Resumed,
+    Forfeited,
+}
+
+/// Pauses the running battle `user_id` dropped out of and gives them
+/// `disconnect_grace()` to rejoin before their opponent is declared the winner.
+async fn on_battle_disconnect(
     connection: &mut redis::aio::MultiplexedConnection,
-    session_user_id: &String,
-    user_name: &Option<String>,
-) -> Option<String> {
-    // Return early if message is empty
-    if let Ok(msg) = &message {
-        if msg.is_empty() {
-            return None;
+    battle_id: String,
+    user_id: &String,
+) {
+    let battle = match Battle::find_one(battle_id.clone()).await {
+        Ok(battle) => battle,
+        Err(err) => {
+            println!("[on_battle_disconnect] Error finding battle: {:?}", err);
+            return;
         }
+    };
+    if battle.is_settled() {
+        return;
     }
+    let winner_id = match battle.forfeit_winner_id(user_id) {
+        Some(winner_id) => winner_id,
+        None => return,
+    };
 
-    match build_battle_queue(message) {
-        Ok(mut queue) => match queue.data.action {
-            BattleQueueDataAction::Connect => {
-                insert_initial_status_and_notify(connection, session_user_id, user_name).await;
-                None
-            }
-            BattleQueueDataAction::List => {
-                match handle_list_request(session_user_id, user_name).await {
-                    Ok(payload) => Some(payload),
-                    Err(_) => Some(
-                        serde_json::to_string(&build_error(
-                            Some(session_user_id.clone()),
-                            user_name.clone(),
-                            BattleQueueChannel::Lobby,
-                            BattleQueueAction::Error,
-                            BattleQueueDataAction::List,
-                            "Error getting list of players in the battle queue".to_string(),
-                        ))
-                        .unwrap(),
-                    ),
-                }
-            }
-            BattleQueueDataAction::SortMnstrs(sort_mnstrs_input) => {
-                match handle_sort_mnstrs_request(session_user_id, user_name, &sort_mnstrs_input)
-                    .await
-                {
-                    Ok(payload) => Some(payload),
-                    Err(_) => Some(
-                        serde_json::to_string(&build_error(
-                            Some(session_user_id.clone()),
-                            user_name.clone(),
-                            BattleQueueChannel::Lobby,
-                            BattleQueueAction::Error,
-                            BattleQueueDataAction::SortMnstrs(sort_mnstrs_input),
-                            "Error sorting mnstrs".to_string(),
-                        ))
-                        .unwrap(),
-                    ),
-                }
-            }
-            BattleQueueDataAction::Accept => {
-                if let Err(_) =
-                    handle_accept_challenge(&queue, session_user_id, user_name, connection).await
-                {
-                    let error_queue = build_error(
-                        Some(session_user_id.clone()),
-                        user_name.clone(),
-                        BattleQueueChannel::Lobby,
-                        BattleQueueAction::Error,
-                        BattleQueueDataAction::Accept,
-                        "Error accepting challenge".to_string(),
-                    );
-                    publish_queue(connection, &error_queue).await;
-                }
-                None
-            }
-            BattleQueueDataAction::MnstrChosen => {
-                let raw_game_data = queue.data.data.clone().unwrap();
-                let mut battle_game_data: BattleQueueGameData =
-                    serde_json::from_str(&raw_game_data.clone()).unwrap();
-                match update_battle_mnstrs(
-                    &battle_game_data.battle_id.clone().unwrap(),
-                    &battle_game_data.challenger_mnstr.clone(),
-                    &battle_game_data.opponent_mnstr.clone(),
+    let grace = disconnect_grace();
+    let key = battle_disconnects_key(&battle_id);
+    // Each disconnect gets its own token, so the timer of one the player has
+    // since rejoined from can't take a later one
+    let token = uuid::Uuid::new_v4().to_string();
+    if let Err(err) = connection.hset(&key, user_id, &token).await {
+        println!("[on_battle_disconnect] Error pausing battle: {:?}", err);
+        return;
+    }
+    // Outlives the timer, so a restart mid-grace can't leave the battle paused
+    if let Err(err) = connection.expire(&key, 2 * grace.as_secs() as i64).await {
+        println!("[on_battle_disconnect] Error expiring pause: {:?}", err);
+    }
+    publish_queue(
+        connection,
+        &battle_notice(
+            &battle_id,
+            None,
+            user_id,
+            BattleQueueAction::Paused,
+            BattleQueueDataAction::Paused,
+            format!(
+                "Player disconnected, waiting {} seconds for them to rejoin",
+                grace.as_secs()
+            ),
+        ),
+    )
+    .await;
+
+    let mut pending_connection = connection.clone();
+    let connection = connection.clone();
+    let user_id = user_id.clone();
+    rocket::tokio::spawn(async move {
+        let pending_battle_id = battle_id.clone();
+        let pending_user_id = user_id.clone();
+        let resolution = resolve_disconnect(
+            grace,
+            move || async move {
+                take_disconnect(
+                    &mut pending_connection,
+                    &pending_battle_id,
+                    &pending_user_id,
+                    Some(&token),
                 )
                 .await
-                {
-                    Ok(battle) => {
-                        battle_game_data.battle_id = Some(battle.id.clone());
-                        if let Some(challenger_mnstr_id) = battle.challenger_mnstr_id.clone() {
-                            let mut challenger_mnstr =
-                                match Mnstr::find_one(challenger_mnstr_id, false).await {
-                                    Ok(mnstr) => mnstr,
-                                    Err(_) => {
-                                        let error_queue = build_error(
-                                            Some(session_user_id.clone()),
-                                            user_name.clone(),
-                                            BattleQueueChannel::Lobby,
-                                            BattleQueueAction::Error,
-                                            BattleQueueDataAction::MnstrChosen,
-                                            "Error finding challenger mnstr".to_string(),
-                                        );
-                                        publish_queue(connection, &error_queue).await;
-                                        return None;
-                                    }
-                                };
-
-                            challenger_mnstr.current_attack = challenger_mnstr.max_attack;
-                            challenger_mnstr.current_defense = challenger_mnstr.max_defense;
-
-                            println!("[handle_incoming_ws_message] Updating challenger mnstr");
-                            if let Some(error) = challenger_mnstr.update().await {
-                                println!(
-                                    "[handle_incoming_ws_message] Error updating challenger mnstr: {:?}",
-                                    error
-                                );
-                                return None;
-                            }
-
-                            battle_game_data.challenger_mnstr = Some(challenger_mnstr);
-                            queue.data.user_id = Some(battle.challenger_id.clone());
-                        }
-                        if let Some(opponent_mnstr_id) = battle.opponent_mnstr_id.clone() {
-                            let mut opponent_mnstr =
-                                match Mnstr::find_one(opponent_mnstr_id, false).await {
-                                    Ok(mnstr) => mnstr,
-                                    Err(_) => {
-                                        let error_queue = build_error(
-                                            Some(session_user_id.clone()),
-                                            user_name.clone(),
-                                            BattleQueueChannel::Lobby,
-                                            BattleQueueAction::Error,
-                                            BattleQueueDataAction::MnstrChosen,
-                                            "Error finding opponent mnstr".to_string(),
-                                        );
-                                        publish_queue(connection, &error_queue).await;
-                                        return None;
-                                    }
-                                };
-
-                            opponent_mnstr.current_attack = opponent_mnstr.max_attack;
-                            opponent_mnstr.current_defense = opponent_mnstr.max_defense;
-
-                            println!("[handle_incoming_ws_message] Updating opponent mnstr");
-                            if let Some(error) = opponent_mnstr.update().await {
-                                println!(
-                                    "[handle_incoming_ws_message] Error updating opponent mnstr: {:?}",
-                                    error
-                                );
-                                return None;
-                            }
-
-                            battle_game_data.opponent_mnstr = Some(opponent_mnstr);
-                            queue.data.opponent_id = Some(battle.opponent_id.clone());
-                        }
-
-                        let coin_flip = {
-                            let mut rng = rand::rng();
-                            rng.random_range(0..2)
-                        };
-                        let turn_user_id;
-                        if coin_flip == 0 {
-                            turn_user_id = battle.challenger_id.clone();
-                        } else {
-                            turn_user_id = battle.opponent_id.clone();
-                        }
-                        battle_game_data.turn_user_id = Some(turn_user_id);
+            },
+            move || forfeit_disconnected(connection, battle_id, user_id, winner_id),
+        )
+        .await;
+        println!(
+            "[on_battle_disconnect] Disconnect resolved: {:?}",
+            resolution
+        );
+    });
+}
 
-                        queue.data.data = Some(serde_json::to_string(&battle_game_data).unwrap());
-                        if battle.challenger_mnstr_id.is_some()
-                            && battle.opponent_mnstr_id.is_some()
-                        {
-                            queue.data.action = BattleQueueDataAction::GameStarted;
-                            queue.action = BattleQueueAction::GameStarted;
-                        }
-                        println!("[handle_incoming_ws_message] Queue: {:?}", queue);
-                        publish_queue(connection, &queue).await;
-                        None
-                    }
-                    Err(_) => {
-                        let error_queue = build_error(
-                            Some(session_user_id.clone()),
-                            user_name.clone(),
-                            BattleQueueChannel::Lobby,
-                            BattleQueueAction::Error,
-                            BattleQueueDataAction::MnstrChosen,
-                            "Error choosing mnstr".to_string(),
-                        );
-                        publish_queue(connection, &error_queue).await;
-                        None
-                    }
-                }
-            }
-            BattleQueueDataAction::Rejoin => {
-                let raw_game_data = queue.data.data.clone().unwrap();
-                let mut battle_game_data: BattleQueueGameData =
-                    serde_json::from_str(&raw_game_data.clone()).unwrap();
-                println!(
-                    "[handle_rejoin_request] Battle game data: {:?}",
-                    battle_game_data
-                );
-                if let None = battle_game_data.battle_id {
-                    let error_queue = build_error(
-                        Some(session_user_id.clone()),
-                        user_name.clone(),
-                        BattleQueueChannel::Battle,
-                        BattleQueueAction::Error,
-                        BattleQueueDataAction::Rejoin,
-                        "Error rejoining battle".to_string(),
-                    );
-                    publish_queue(connection, &error_queue).await;
-                    return None;
-                }
-                let battle_id = battle_game_data.battle_id.clone().unwrap();
-                match handle_rejoin_request(&battle_id).await {
-                    Ok(battle) => {
-                        let params = vec![
-                            ("user_id", session_user_id.clone().into()),
-                            ("status", BattleStatusState::InQueue.to_string().into()),
-                        ];
-                        let error = match BattleStatus::find_one_by(params).await {
-                            Ok(mut status) => {
-                                status.delete().await;
-                                None
-                            }
-                            Err(_) => {
-                                println!(
-                                    "[handle_rejoin_request] Error deleting old battle status"
-                                );
-                                Some(anyhow::Error::msg("Error deleting old battle status"))
-                            }
-                        };
-                        if let Some(_) = error {
-                            publish_queue(
-                                connection,
-                                &build_error(
-                                    Some(session_user_id.clone()),
-                                    user_name.clone(),
-                                    BattleQueueChannel::Battle,
-                                    BattleQueueAction::Error,
-                                    BattleQueueDataAction::Rejoin,
-                                    "Error deleting old battle status".to_string(),
-                                ),
-                            )
-                            .await;
-                            return None;
-                        }
+/// Waits out `grace`, then ends the battle with `forfeit` unless
+/// `still_disconnected` reports that the player rejoined in the meantime.
+async fn resolve_disconnect<P, PFut, F, FFut>(
+    grace: std::time::Duration,
+    still_disconnected: P,
+    forfeit: F,
+) -> DisconnectResolution
+where
+    P: FnOnce() -> PFut,
+    PFut: Future<Output = bool>,
+    F: FnOnce() -> FFut,
+    FFut: Future<Output = ()>,
+{
+    rocket::tokio::time::sleep(grace).await;
+    if !still_disconnected().await {
+        return DisconnectResolution::Resumed;
+    }
+    forfeit().await;
+    DisconnectResolution::Forfeited
+}
 
-                        let challenger_mnstr = match Mnstr::find_one(
-                            battle.challenger_mnstr_id.clone().unwrap(),
-                            false,
-                        )
-                        .await
-                        {
-                            Ok(mnstr) => mnstr,
-                            Err(_) => {
-                                return None;
-                            }
-                        };
-                        battle_game_data.challenger_mnstr = Some(challenger_mnstr);
-                        queue.data.user_id = Some(battle.challenger_id.clone());
+/// Only takes the entry while it still holds `token`, comparing and deleting
+/// in one step.
+const TAKE_DISCONNECT_SCRIPT: &str = "if redis.call('HGET', KEYS[1], ARGV[1]) == ARGV[2] then \
+     return redis.call('HDEL', KEYS[1], ARGV[1]) end return 0";
 
-                        let opponent_mnstr =
-                            match Mnstr::find_one(battle.opponent_mnstr_id.clone().unwrap(), false)
-                                .await
-                            {
-                                Ok(mnstr) => mnstr,
-                                Err(_) => {
-                                    return None;
-                                }
-                            };
-                        battle_game_data.opponent_mnstr = Some(opponent_mnstr);
-                        queue.data.opponent_id = Some(battle.opponent_id.clone());
+/// Clears `user_id`'s pending disconnect, or with a `token` only the disconnect
+/// it was recorded for. Only one of a rejoin and the grace timer gets `true`,
+/// so a battle is either resumed or forfeited, never both.
+async fn take_disconnect(
+    connection: &mut redis::aio::MultiplexedConnection,
+    battle_id: &str,
+    user_id: &str,
+    token: Option<&str>,
+) -> bool {
+    let key = battle_disconnects_key(battle_id);
+    let removed = match token {
+        Some(token) => {
+            redis::Script::new(TAKE_DISCONNECT_SCRIPT)
+                .key(key)
+                .arg(user_id)
+                .arg(token)
+                .invoke_async::<i64>(connection)
+                .await
+        }
+        None => connection
+            .hdel(key, user_id)
+            .await
+            .map(|removed| removed as i64),
+    };
+    match removed {
+        Ok(removed) => removed > 0,
+        Err(err) => {
+            println!("[take_disconnect] Error clearing disconnect: {:?}", err);
+            false
+        }
+    }
+}
 
-                        queue.data.data = Some(serde_json::to_string(&battle_game_data).unwrap());
-                        queue.data.action = BattleQueueDataAction::Rejoined;
-                        queue.action = BattleQueueAction::Rejoined;
-                        publish_queue(connection, &queue).await;
-                        None
-                    }
-                    Err(_) => {
-                        let error_queue = build_error(
-                            Some(session_user_id.clone()),
-                            user_name.clone(),
-                            BattleQueueChannel::Battle,
-                            BattleQueueAction::Error,
-                            BattleQueueDataAction::Rejoin,
-                            "Error rejoining battle".to_string(),
-                        );
-                        publish_queue(connection, &error_queue).await;
-                        return None;
-                    }
-                }
-            }
-            BattleQueueDataAction::InGameAction => None,
-            BattleQueueDataAction::Escape => {
-                let game_data = queue.data.data.clone().unwrap();
-                let mut game_data: BattleQueueGameData =
-                    serde_json::from_str(&game_data.clone()).unwrap();
-
-                if let None = game_data.winner_id.clone() {
-                    let winner_id: String;
-                    let challenger_mnstr = game_data.challenger_mnstr.clone().unwrap();
-                    let opponent_mnstr = game_data.opponent_mnstr.clone().unwrap();
-
-                    if challenger_mnstr.user_id.clone() == session_user_id.clone() {
-                        winner_id = opponent_mnstr.user_id.clone();
-                    } else {
-                        winner_id = challenger_mnstr.user_id.clone();
-                    }
-                    game_data.winner_id = Some(winner_id);
-                    queue.data.data = Some(serde_json::to_string(&game_data).unwrap());
-                }
+/// Ends the battle in `winner_id`'s favour after `user_id` failed to rejoin
+/// in time.
+async fn forfeit_disconnected(
+    mut connection: redis::aio::MultiplexedConnection,
+    battle_id: String,
+    user_id: String,
+    winner_id: String,
+) {
+    let mut queue = battle_notice(
+        &battle_id,
+        Some(winner_id.clone()),
+        &winner_id,
+        BattleQueueAction::GameEnded,
+        BattleQueueDataAction::GameEnded,
+        "Opponent didn't rejoin in time".to_string(),
+    );
+    let settled = settle_once(
+        Battle::claim_winner(battle_id.clone(), winner_id.clone()),
+        // The player who dropped out leaves the battle, not the winner
+        handle_game_ended(&mut queue, &user_id, &None, BattleOutcome::Disconnected),
+    )
+    .await;
+    match settled {
+        Ok(Some(None)) => publish_queue(&mut connection, &queue).await,
+        Ok(Some(Some(error))) => publish_queue(&mut connection, &error).await,
+        Ok(None) => println!(
+            "[forfeit_disconnected] Battle {} was settled by another action",
+            battle_id
+        ),
+        Err(err) => println!("[forfeit_disconnected] Error settling battle: {:?}", err),
+    }
+}
 
-                if let Some(error) = handle_game_ended(&mut queue, session_user_id, user_name).await
-                {
-                    publish_queue(connection, &error).await;
-                    return None;
-                }
-                publish_queue(connection, &queue).await;
-                None
+/// A server notice on a battle's channel.
+fn battle_notice(
+    battle_id: &str,
+    winner_id: Option<String>,
+    user_id: &String,
+    action: BattleQueueAction,
+    data_action: BattleQueueDataAction,
+    message: String,
+) -> BattleQueue {
+    let mut queue = build_success(
+        Some(user_id.clone()),
+        None,
+        BattleQueueChannel::Battle,
+        action,
+        data_action,
+        message,
+    );
+    let game_data = BattleQueueGameData {
+        battle_id: Some(battle_id.to_string()),
+        challenger_mnstr: None,
+        challenger_mnstrs: None,
+        opponent_mnstr: None,
+        opponent_mnstrs: None,
+        mnstr: None,
+        winner_id,
+        winner_xp_awarded: None,
+        winner_coins_awarded: None,
+        loser_xp_awarded: None,
+        loser_coins_awarded: None,
+        turn_user_id: None,
+        battle_log_data: None,
+    };
+    queue.data.set_game_data(&game_data);
+    queue
+}
+
+/// Builds an error while a player of the battle is disconnected, so turns wait
+/// until they rejoin or forfeit.
+async fn ensure_battle_not_paused(
+    connection: &mut redis::aio::MultiplexedConnection,
+    queue: &BattleQueue,
+    session_user_id: &String,
+    user_name: &Option<String>,
+    data_action: BattleQueueDataAction,
+) -> Option<BattleQueue> {
+    let battle_id = queue.battle_id()?;
+    match connection.hlen(battle_disconnects_key(&battle_id)).await {
+        Ok(0) => None,
+        Ok(_) => Some(build_error(
+            Some(session_user_id.clone()),
+            user_name.clone(),
+            BattleQueueChannel::Battle,
+            BattleQueueAction::Error,
+            data_action,
+            "Battle paused until the opponent rejoins".to_string(),
+        )),
+        Err(err) => {
+            println!("[ensure_battle_not_paused] Error checking pause: {:?}", err);
+            None
+        }
+    }
+}
+
+/// Handles a command from the client. Once the command is accepted the sender
+/// gets an ack, followed by any reply meant only for them; a rejected command
+/// only gets its error.
+async fn handle_incoming_ws_message(
+    queue: BattleQueue,
+    connection: &mut redis::aio::MultiplexedConnection,
+    subscription: &mut BattleSubscription,
+    session_user_id: &String,
+    user_name: &Option<String>,
+) -> Vec<String> {
+    let ack = build_ack(&queue, session_user_id, user_name);
+    let handled =
+        dispatch_ws_message(queue, connection, subscription, session_user_id, user_name).await;
+    let handled = match handled {
+        Ok(reply) => Ok(reply.into_iter().collect()),
+        Err(reply) => Err(reply.into_iter().collect()),
+    };
+    acknowledged(&ack, handled)
+}
+
+/// `Ok` when the command was accepted and `Err` when it was rejected, each
+/// with the reply for the sender, if any.
+async fn dispatch_ws_message(
+    mut queue: BattleQueue,
+    connection: &mut redis::aio::MultiplexedConnection,
+    subscription: &mut BattleSubscription,
+    session_user_id: &String,
+    user_name: &Option<String>,
+) -> Result<Option<String>, Option<String>> {
+    if let Err(error) = validate_queue_ids(&queue) {
+        println!(
+            "[battle_queue_handler] Rejecting queue message: {:?}",
+            error
+        );
+        let error_queue = build_error(
+            Some(session_user_id.clone()),
+            user_name.clone(),
+            queue.channel.clone(),
+            BattleQueueAction::Error,
+            queue.data.action.clone(),
+            error.to_string(),
+        );
+        return Err(Some(serde_json::to_string(&error_queue).unwrap()));
+    }
+
+    match queue.data.action {
+        BattleQueueDataAction::Connect => {
+            insert_initial_status_and_notify(connection, session_user_id, user_name).await;
+            Ok(None)
+        }
+        BattleQueueDataAction::List => {
+            match handle_list_request(session_user_id, user_name).await {
+                Ok(payload) => Ok(Some(payload)),
+                Err(_) => Err(Some(
+                    serde_json::to_string(&build_error(
+                        Some(session_user_id.clone()),
+                        user_name.clone(),
+                        BattleQueueChannel::Lobby,
+                        BattleQueueAction::Error,
+                        BattleQueueDataAction::List,
+                        "Error getting list of players in the battle queue".to_string(),
+                    ))
+                    .unwrap(),
+                )),
             }
-            BattleQueueDataAction::Attack => {
-                if let Some(error) = handle_attack(&mut queue, session_user_id, user_name).await {
-                    publish_queue(connection, &error).await;
-                    return None;
+        }
+        BattleQueueDataAction::SortMnstrs(sort_mnstrs_input) => {
+            match handle_sort_mnstrs_request(session_user_id, user_name, &sort_mnstrs_input).await {
+                Ok(payload) => Ok(Some(payload)),
+                Err(_) => Err(Some(
+                    serde_json::to_string(&build_error(
+                        Some(session_user_id.clone()),
+                        user_name.clone(),
+                        BattleQueueChannel::Lobby,
+                        BattleQueueAction::Error,
+                        BattleQueueDataAction::SortMnstrs(sort_mnstrs_input),
+                        "Error sorting mnstrs".to_string(),
+                    ))
+                    .unwrap(),
+                )),
+            }
+        }
+        BattleQueueDataAction::Ready => {
+            let queue = handle_ready(session_user_id, user_name, true).await;
+            publish_queue(connection, &queue).await;
+            published(&queue)
+        }
+        BattleQueueDataAction::Unready => {
+            let queue = handle_ready(session_user_id, user_name, false).await;
+            publish_queue(connection, &queue).await;
+            published(&queue)
+        }
+        BattleQueueDataAction::Challenge => {
+            let blocked = match queue.data.opponent_id.clone() {
+                Some(opponent_id) => players_blocked(session_user_id, &opponent_id).await,
+                None => false,
+            };
+            if blocked {
+                let error_queue = build_error(
+                    Some(session_user_id.clone()),
+                    user_name.clone(),
+                    BattleQueueChannel::Lobby,
+                    BattleQueueAction::Error,
+                    BattleQueueDataAction::Challenge,
+                    "Player is not available".to_string(),
+                );
+                return Err(Some(serde_json::to_string(&error_queue).unwrap()));
+            }
+            if let Err(message) = record_challenge(&queue, session_user_id).await {
+                let error_queue = build_error(
+                    Some(session_user_id.clone()),
+                    user_name.clone(),
+                    BattleQueueChannel::Lobby,
+                    BattleQueueAction::Error,
+                    BattleQueueDataAction::Challenge,
+                    message.to_string(),
+                );
+                return Err(Some(serde_json::to_string(&error_queue).unwrap()));
+            }
+            publish_queue(connection, &queue).await;
+            Ok(None)
+        }
+        BattleQueueDataAction::CancelChallenge => {
+            let queue = handle_cancel_challenge(&queue, session_user_id, user_name).await;
+            publish_queue(connection, &queue).await;
+            published(&queue)
+        }
+        BattleQueueDataAction::Accept => {
+            if let Err(_) =
+                handle_accept_challenge(&queue, session_user_id, user_name, connection).await
+            {
+                let error_queue = build_error(
+                    Some(session_user_id.clone()),
+                    user_name.clone(),
+                    BattleQueueChannel::Lobby,
+                    BattleQueueAction::Error,
+                    BattleQueueDataAction::Accept,
+                    "Error accepting challenge".to_string(),
+                );
+                publish_queue(connection, &error_queue).await;
+                return Err(None);
+            }
+            Ok(None)
+        }
+        BattleQueueDataAction::MnstrChosen => {
+            let mut battle_game_data = match queue.data.game_data() {
+                Ok(game_data) => game_data,
+                Err(error) => {
+                    println!(
+                        "[handle_incoming_ws_message] Failed to read game data: {:?}",
+                        error
+                    );
+                    let error_queue = build_error(
+                        Some(session_user_id.clone()),
+                        user_name.clone(),
+                        BattleQueueChannel::Battle,
+                        BattleQueueAction::Error,
+                        BattleQueueDataAction::MnstrChosen,
+                        "Invalid game data".to_string(),
+                    );
+                    publish_queue(connection, &error_queue).await;
+                    return Err(None);
+                }
+            };
+            match update_battle_mnstrs(
+                &battle_game_data.battle_id.clone().unwrap(),
+                session_user_id,
+                &battle_game_data.challenger_mnstr.clone(),
+                &battle_game_data.opponent_mnstr.clone(),
+            )
+            .await
+            {
+                Ok(battle) => {
+                    battle_game_data.battle_id = Some(battle.id.clone());
+                    if let Some(challenger_mnstr_id) = battle.challenger_mnstr_id.clone() {
+                        let mut challenger_mnstr =
+                            match Mnstr::find_one(challenger_mnstr_id, false).await {
+                                Ok(mnstr) => mnstr,
+                                Err(_) => {
+                                    let error_queue = build_error(
+                                        Some(session_user_id.clone()),
+                                        user_name.clone(),
+                                        BattleQueueChannel::Lobby,
+                                        BattleQueueAction::Error,
+                                        BattleQueueDataAction::MnstrChosen,
+                                        "Error finding challenger mnstr".to_string(),
+                                    );
+                                    publish_queue(connection, &error_queue).await;
+                                    return Err(None);
+                                }
+                            };
+
+                        challenger_mnstr.current_attack = challenger_mnstr.max_attack;
+                        challenger_mnstr.current_defense = challenger_mnstr.max_defense;
+
+                        println!("[handle_incoming_ws_message] Updating challenger mnstr");
+                        if let Some(error) = challenger_mnstr.update().await {
+                            println!(
+                                "[handle_incoming_ws_message] Error updating challenger mnstr: {:?}",
+                                error
+                            );
+                            return Err(None);
+                        }
+
+                        battle_game_data.challenger_mnstr = Some(challenger_mnstr);
+                        queue.data.user_id = Some(battle.challenger_id.clone());
+                    }
+                    if let Some(opponent_mnstr_id) = battle.opponent_mnstr_id.clone() {
+                        let mut opponent_mnstr =
+                            match Mnstr::find_one(opponent_mnstr_id, false).await {
+                                Ok(mnstr) => mnstr,
+                                Err(_) => {
+                                    let error_queue = build_error(
+                                        Some(session_user_id.clone()),
+                                        user_name.clone(),
+                                        BattleQueueChannel::Lobby,
+                                        BattleQueueAction::Error,
+                                        BattleQueueDataAction::MnstrChosen,
+                                        "Error finding opponent mnstr".to_string(),
+                                    );
+                                    publish_queue(connection, &error_queue).await;
+                                    return Err(None);
+                                }
+                            };
+
+                        opponent_mnstr.current_attack = opponent_mnstr.max_attack;
+                        opponent_mnstr.current_defense = opponent_mnstr.max_defense;
+
+                        println!("[handle_incoming_ws_message] Updating opponent mnstr");
+                        if let Some(error) = opponent_mnstr.update().await {
+                            println!(
+                                "[handle_incoming_ws_message] Error updating opponent mnstr: {:?}",
+                                error
+                            );
+                            return Err(None);
+                        }
+
+                        battle_game_data.opponent_mnstr = Some(opponent_mnstr);
+                        queue.data.opponent_id = Some(battle.opponent_id.clone());
+                    }
+
+                    let mut rng = BattleRng::for_turn(battle.seed, 0);
+                    let turn_user_id = first_turn_user_id(
+                        turn_order_rule(),
+                        &battle.challenger_id,
+                        battle_game_data.challenger_mnstr.as_ref(),
+                        &battle.opponent_id,
+                        battle_game_data.opponent_mnstr.as_ref(),
+                        || rng.coin_flip(),
+                    );
+                    battle_game_data.turn_user_id = Some(turn_user_id);
+
+                    queue.data.set_game_data(&battle_game_data);
+                    if battle.mnstrs_chosen() {
+                        queue.data.action = BattleQueueDataAction::GameStarted;
+                        queue.action = BattleQueueAction::GameStarted;
+                    }
+                    println!("[handle_incoming_ws_message] Queue: {:?}", queue);
+                    publish_queue(connection, &queue).await;
+                    Ok(None)
+                }
+                Err(error) => {
+                    // A fainted pick is the player's to fix, so say which
+                    let message = match error.to_string().as_str() {
+                        FAINTED_MNSTR_ERROR => FAINTED_MNSTR_ERROR,
+                        _ => "Error choosing mnstr",
+                    };
+                    let error_queue = build_error(
+                        Some(session_user_id.clone()),
+                        user_name.clone(),
+                        BattleQueueChannel::Lobby,
+                        BattleQueueAction::Error,
+                        BattleQueueDataAction::MnstrChosen,
+                        message.to_string(),
+                    );
+                    publish_queue(connection, &error_queue).await;
+                    Err(None)
                 }
-                println!("[handle_attack] Publishing queue: {:?}", queue);
-                publish_queue(connection, &queue).await;
-                None
             }
-            BattleQueueDataAction::Defend => {
-                if let Some(error) = handle_defend(&mut queue, session_user_id, user_name).await {
-                    publish_queue(connection, &error).await;
-                    return None;
+        }
+        BattleQueueDataAction::Rejoin => {
+            let mut battle_game_data = match queue.data.game_data() {
+                Ok(game_data) => game_data,
+                Err(error) => {
+                    println!(
+                        "[handle_incoming_ws_message] Failed to read game data: {:?}",
+                        error
+                    );
+                    let error_queue = build_error(
+                        Some(session_user_id.clone()),
+                        user_name.clone(),
+                        BattleQueueChannel::Battle,
+                        BattleQueueAction::Error,
+                        BattleQueueDataAction::Rejoin,
+                        "Invalid game data".to_string(),
+                    );
+                    publish_queue(connection, &error_queue).await;
+                    return Err(None);
                 }
-                println!("[handle_defend] Publishing queue: {:?}", queue);
-                publish_queue(connection, &queue).await;
-                None
+            };
+            println!(
+                "[handle_rejoin_request] Battle game data: {:?}",
+                battle_game_data
+            );
+            if let None = battle_game_data.battle_id {
+                let error_queue = build_error(
+                    Some(session_user_id.clone()),
+                    user_name.clone(),
+                    BattleQueueChannel::Battle,
+                    BattleQueueAction::Error,
+                    BattleQueueDataAction::Rejoin,
+                    "Error rejoining battle".to_string(),
+                );
+                publish_queue(connection, &error_queue).await;
+                return Err(None);
             }
-            BattleQueueDataAction::Magic => {
-                if let Some(error) = handle_magic(&mut queue, session_user_id, user_name).await {
-                    publish_queue(connection, &error).await;
-                    return None;
+            let battle_id = battle_game_data.battle_id.clone().unwrap();
+            match handle_rejoin_request(&battle_id, session_user_id).await {
+                Ok(battle) => {
+                    let params = vec![
+                        ("user_id", session_user_id.clone().into()),
+                        ("status", BattleStatusState::InQueue.to_string().into()),
+                    ];
+                    let error = match BattleStatus::find_one_by(params).await {
+                        Ok(mut status) => {
+                            status.delete().await;
+                            None
+                        }
+                        Err(_) => {
+                            println!("[handle_rejoin_request] Error deleting old battle status");
+                            Some(anyhow::Error::msg("Error deleting old battle status"))
+                        }
+                    };
+                    if let Some(_) = error {
+                        publish_queue(
+                            connection,
+                            &build_error(
+                                Some(session_user_id.clone()),
+                                user_name.clone(),
+                                BattleQueueChannel::Battle,
+                                BattleQueueAction::Error,
+                                BattleQueueDataAction::Rejoin,
+                                "Error deleting old battle status".to_string(),
+                            ),
+                        )
+                        .await;
+                        return Err(None);
+                    }
+
+                    let challenger_mnstr =
+                        match Mnstr::find_one(battle.challenger_mnstr_id.clone().unwrap(), false)
+                            .await
+                        {
+                            Ok(mnstr) => mnstr,
+                            Err(_) => {
+                                return Err(None);
+                            }
+                        };
+                    battle_game_data.challenger_mnstr = Some(challenger_mnstr);
+                    queue.data.user_id = Some(battle.challenger_id.clone());
+
+                    let opponent_mnstr =
+                        match Mnstr::find_one(battle.opponent_mnstr_id.clone().unwrap(), false)
+                            .await
+                        {
+                            Ok(mnstr) => mnstr,
+                            Err(_) => {
+                                return Err(None);
+                            }
+                        };
+                    battle_game_data.opponent_mnstr = Some(opponent_mnstr);
+                    queue.data.opponent_id = Some(battle.opponent_id.clone());
+
+                    queue.data.set_game_data(&battle_game_data);
+                    queue.data.action = BattleQueueDataAction::Rejoined;
+                    queue.action = BattleQueueAction::Rejoined;
+                    subscription.join(&battle_id).await;
+                    publish_queue(connection, &queue).await;
+
+                    if take_disconnect(connection, &battle_id, session_user_id, None).await {
+                        publish_queue(
+                            connection,
+                            &battle_notice(
+                                &battle_id,
+                                None,
+                                session_user_id,
+                                BattleQueueAction::Resumed,
+                                BattleQueueDataAction::Resumed,
+                                "Player rejoined, battle resumed".to_string(),
+                            ),
+                        )
+                        .await;
+                    }
+                    Ok(None)
+                }
+                Err(_) => {
+                    let error_queue = build_error(
+                        Some(session_user_id.clone()),
+                        user_name.clone(),
+                        BattleQueueChannel::Battle,
+                        BattleQueueAction::Error,
+                        BattleQueueDataAction::Rejoin,
+                        "Error rejoining battle".to_string(),
+                    );
+                    publish_queue(connection, &error_queue).await;
+                    return Err(None);
                 }
-                println!("[handle_magic] Publishing queue: {:?}", queue);
-                publish_queue(connection, &queue).await;
-                None
             }
-            _ => {
-                publish_queue(connection, &queue).await;
-                None
+        }
+        BattleQueueDataAction::InGameAction => Ok(None),
+        BattleQueueDataAction::Escape => {
+            if let Some(error) = handle_escape(&mut queue, session_user_id, user_name).await {
+                publish_queue(connection, &error).await;
+                return Err(None);
             }
+
+            if let Some(error) = handle_game_ended(
+                &mut queue,
+                session_user_id,
+                user_name,
+                BattleOutcome::Escaped,
+            )
+            .await
+            {
+                publish_queue(connection, &error).await;
+                return Err(None);
+            }
+            publish_queue(connection, &queue).await;
+            Ok(None)
+        }
+        BattleQueueDataAction::Surrender => {
+            if let Some(error) = handle_surrender(&mut queue, session_user_id, user_name).await {
+                publish_queue(connection, &error).await;
+                return Err(None);
+            }
+
+            if let Some(error) = handle_game_ended(
+                &mut queue,
+                session_user_id,
+                user_name,
+                BattleOutcome::Surrendered,
+            )
+            .await
+            {
+                publish_queue(connection, &error).await;
+                return Err(None);
+            }
+            publish_queue(connection, &queue).await;
+            Ok(None)
+        }
+        BattleQueueDataAction::Attack => {
+            if let Some(error) = ensure_battle_not_paused(
+                connection,
+                &queue,
+                session_user_id,
+                user_name,
+                BattleQueueDataAction::Attack,
+            )
+            .await
+            {
+                publish_queue(connection, &error).await;
+                return Err(None);
+            }
+            if let Some(error) = handle_attack(&mut queue, session_user_id, user_name).await {
+                publish_queue(connection, &error).await;
+                return Err(None);
+            }
+            println!("[handle_attack] Publishing queue: {:?}", queue);
+            publish_queue(connection, &queue).await;
+            Ok(None)
+        }
+        BattleQueueDataAction::Defend => {
+            if let Some(error) = ensure_battle_not_paused(
+                connection,
+                &queue,
+                session_user_id,
+                user_name,
+                BattleQueueDataAction::Defend,
+            )
+            .await
+            {
+                publish_queue(connection, &error).await;
+                return Err(None);
+            }
+            if let Some(error) = handle_defend(&mut queue, session_user_id, user_name).await {
+                publish_queue(connection, &error).await;
+                return Err(None);
+            }
+            println!("[handle_defend] Publishing queue: {:?}", queue);
+            publish_queue(connection, &queue).await;
+            Ok(None)
+        }
+        BattleQueueDataAction::Magic => {
+            if let Some(error) = ensure_battle_not_paused(
+                connection,
+                &queue,
+                session_user_id,
+                user_name,
+                BattleQueueDataAction::Magic,
+            )
+            .await
+            {
+                publish_queue(connection, &error).await;
+                return Err(None);
+            }
+            if let Some(error) = handle_magic(&mut queue, session_user_id, user_name).await {
+                publish_queue(connection, &error).await;
+                return Err(None);
+            }
+            println!("[handle_magic] Publishing queue: {:?}", queue);
+            publish_queue(connection, &queue).await;
+            Ok(None)
+        }
+        // `build_battle_queue` couldn't read the message
+        BattleQueueDataAction::Error => {
+            publish_queue(connection, &queue).await;
+            Err(None)
+        }
+        _ => {
+            publish_queue(connection, &queue).await;
+            Ok(None)
+        }
+    }
+}
+
+/// Plays practice battles. Returns the messages for the player, with the ack
+/// first when the command was accepted, when `queue` starts or belongs to a
+/// practice battle, or `None` to leave it to `handle_incoming_ws_message`.
+async fn handle_practice_message(
+    queue: &BattleQueue,
+    practice: &mut Option<PracticeBattle>,
+    session_user_id: &String,
+    user_name: &Option<String>,
+) -> Option<Vec<String>> {
+    let handled = play_practice_message(queue, practice, session_user_id, user_name).await?;
+    let to_strings = |replies: Vec<BattleQueue>| {
+        replies
+            .iter()
+            .map(|reply| serde_json::to_string(reply).unwrap())
+            .collect::<Vec<String>>()
+    };
+    let handled = match handled {
+        Ok(replies) => Ok(to_strings(replies)),
+        Err(error) => Err(to_strings(vec![error])),
+    };
+    let ack = build_ack(queue, session_user_id, user_name);
+    Some(acknowledged(&ack, handled))
+}
+
+/// `Ok` with the replies when the practice command was accepted, or `Err` with
+/// the error when it was rejected.
+async fn play_practice_message(
+    queue: &BattleQueue,
+    practice: &mut Option<PracticeBattle>,
+    session_user_id: &String,
+    user_name: &Option<String>,
+) -> Option<Result<Vec<BattleQueue>, BattleQueue>> {
+    let practice_error = |data_action: BattleQueueDataAction, message: &str| {
+        build_error(
+            Some(session_user_id.clone()),
+            user_name.clone(),
+            BattleQueueChannel::Battle,
+            BattleQueueAction::Error,
+            data_action,
+            message.to_string(),
+        )
+    };
+
+    match queue.data.action {
+        BattleQueueDataAction::Practice => {
+            if practice.is_some() {
+                return Some(Err(practice_error(
+                    BattleQueueDataAction::Practice,
+                    PRACTICE_IN_PROGRESS_ERROR,
+                )));
+            }
+            let mnstr = match load_practice_mnstr(&queue.data.user_mnstr_id, session_user_id).await
+            {
+                Ok(mnstr) => mnstr,
+                Err(error) => {
+                    println!("[handle_practice_message] Can't practice: {:?}", error);
+                    return Some(Err(practice_error(
+                        BattleQueueDataAction::Practice,
+                        &error.to_string(),
+                    )));
+                }
+            };
+            let battle = PracticeBattle::new(&mnstr, new_battle_seed());
+            let started = practice_message(
+                &battle,
+                session_user_id,
+                user_name,
+                BattleQueueAction::GameStarted,
+                BattleQueueDataAction::GameStarted,
+                None,
+            );
+            *practice = Some(battle);
+            return Some(Ok(vec![started]));
+        }
+        // Leaving a practice battle ends it without rewards
+        BattleQueueDataAction::Escape | BattleQueueDataAction::Surrender => {
+            let battle = practice.take()?;
+            return Some(Ok(vec![practice_message(
+                &battle,
+                session_user_id,
+                user_name,
+                BattleQueueAction::GameEnded,
+                BattleQueueDataAction::GameEnded,
+                None,
+            )]));
+        }
+        _ => (),
+    }
+
+    let player_move = CombatMove::from_action(&queue.data.action)?;
+    let battle = practice.as_mut()?;
+
+    let battle_log_data = match battle.play_player_move(player_move) {
+        Ok(battle_log_data) => battle_log_data,
+        Err(error) => {
+            return Some(Err(practice_error(
+                queue.data.action.clone(),
+                &error.to_string(),
+            )));
+        }
+    };
+    let (action, data_action) = player_move.actions();
+    let mut replies = vec![practice_message(
+        battle,
+        session_user_id,
+        user_name,
+        action,
+        data_action,
+        Some(battle_log_data),
+    )];
+
+    if let Some((opponent_move, battle_log_data)) = battle.play_opponent_move() {
+        let (action, data_action) = opponent_move.actions();
+        let mut reply = practice_message(
+            battle,
+            session_user_id,
+            user_name,
+            action,
+            data_action,
+            Some(battle_log_data),
+        );
+        reply.user_id = Some(PRACTICE_OPPONENT_ID.to_string());
+        replies.push(reply);
+    }
+
+    if battle.result().is_some() {
+        let battle = practice.take()?;
+        replies.push(settle_practice(&battle, session_user_id, user_name).await);
+    }
+    Some(Ok(replies))
+}
+
+/// The mnstr `user_id` picked for practice, or their primary mnstr.
+async fn load_practice_mnstr(
+    mnstr_id: &Option<String>,
+    user_id: &String,
+) -> Result<Mnstr, anyhow::Error> {
+    let mnstr = match mnstr_id {
+        Some(mnstr_id) => {
+            validate_id(mnstr_id)?;
+            Mnstr::find_one(mnstr_id.clone(), false).await.ok()
+        }
+        None => match User::find_one(user_id.clone(), false).await {
+            Ok(user) => user.primary_mnstr().await.ok().flatten(),
+            Err(_) => None,
         },
-        Err(err) => {
+    };
+    let mnstr = match mnstr {
+        Some(mnstr) if mnstr.user_id == *user_id && mnstr.archived_at.is_none() => mnstr,
+        _ => return Err(anyhow::anyhow!("Choose a mnstr to practice with")),
+    };
+    if mnstr.is_fainted() {
+        return Err(anyhow::anyhow!(FAINTED_MNSTR_ERROR));
+    }
+    if Battle::is_mnstr_locked(mnstr.id.clone()).await? {
+        return Err(anyhow::anyhow!("Mnstr is in a battle"));
+    }
+    Ok(mnstr)
+}
+
+fn practice_message(
+    battle: &PracticeBattle,
+    session_user_id: &String,
+    user_name: &Option<String>,
+    action: BattleQueueAction,
+    data_action: BattleQueueDataAction,
+    battle_log_data: Option<BattleLogData>,
+) -> BattleQueue {
+    let mut data = BattleQueueData::new(
+        data_action,
+        Some(session_user_id.clone()),
+        user_name.clone(),
+        Some(PRACTICE_OPPONENT_ID.to_string()),
+        Some(PRACTICE_OPPONENT_NAME.to_string()),
+        Some(battle.player.id.clone()),
+        Some(battle.opponent.id.clone()),
+        None,
+        None,
+        None,
+    );
+    data.set_game_data(&battle.game_data(battle_log_data));
+    BattleQueue::new(
+        Some(session_user_id.clone()),
+        BattleQueueChannel::Battle,
+        action,
+        data,
+    )
+}
+
+/// Pays the player's practice XP to them and their mnstr and announces the end
+/// of the battle. The mnstr is reloaded so the practice copy's stats are never
+/// saved.
+async fn settle_practice(
+    battle: &PracticeBattle,
+    session_user_id: &String,
+    user_name: &Option<String>,
+) -> BattleQueue {
+    let mut ended = practice_message(
+        battle,
+        session_user_id,
+        user_name,
+        BattleQueueAction::GameEnded,
+        BattleQueueDataAction::GameEnded,
+        None,
+    );
+    let xp = match pay_practice(battle, session_user_id).await {
+        Ok(xp) => xp,
+        Err(error) => {
             println!(
-                "[battle_queue_handler] Error building battle queue: {:?}",
-                err
+                "[settle_practice] Failed to pay practice reward: {:?}",
+                error
             );
-            // Notify others and cleanup
-            on_player_left(connection, session_user_id, user_name).await;
-            None
+            0
+        }
+    };
+
+    let mut game_data = battle.game_data(None);
+    let awarded = Some(Multipliers::current().xp(xp));
+    match game_data.winner_id.as_deref() == Some(session_user_id.as_str()) {
+        true => game_data.winner_xp_awarded = awarded,
+        false => game_data.loser_xp_awarded = awarded,
+    }
+    ended.data.set_game_data(&game_data);
+    ended
+}
+
+/// Pays a won practice battle through the same awards as ranked battles,
+/// unless the player was paid for one within the cooldown.
+///
+/// # Returns
+///
+/// Returns the XP paid.
+async fn pay_practice(
+    battle: &PracticeBattle,
+    session_user_id: &String,
+) -> Result<i32, anyhow::Error> {
+    let xp = battle.player_xp().unwrap_or(0);
+    if xp <= 0 {
+        return Ok(0);
+    }
+    transaction(async {
+        let cooldown = time::Duration::minutes(PRACTICE_REWARD_COOLDOWN_MINUTES);
+        if !User::claim_practice_reward(session_user_id.clone(), cooldown).await? {
+            return Ok(0);
         }
+        let mut mnstr = Mnstr::find_one(battle.player.id.clone(), false).await?;
+        if let Some(error) = award_side(session_user_id, &mut mnstr, xp, 0).await {
+            return Err(anyhow::anyhow!(error));
+        }
+        Ok(xp)
+    })
+    .await
+}
+
+/// Checks the ids a client sent before any of them reach the database.
+fn validate_queue_ids(queue: &BattleQueue) -> Result<(), anyhow::Error> {
+    let data = &queue.data;
+    for id in [
+        &data.user_id,
+        &data.user_mnstr_id,
+        &data.opponent_id,
+        &data.opponent_mnstr_id,
+    ]
+    .into_iter()
+    .flatten()
+    {
+        validate_id(id)?;
+    }
+
+    // Game data is only present on battle messages; anything that doesn't parse
+    // is rejected by the handler that reads it
+    let game_data = match data.game_data() {
+        Ok(game_data) => game_data,
+        Err(_) => return Ok(()),
+    };
+    if let Some(battle_id) = &game_data.battle_id {
+        validate_id(battle_id)?;
     }
+    for mnstr in [
+        &game_data.challenger_mnstr,
+        &game_data.opponent_mnstr,
+        &game_data.mnstr,
+    ]
+    .into_iter()
+    .flatten()
+    {
+        validate_id(&mnstr.id)?;
+    }
+    Ok(())
 }
 
 async fn handle_list_request(
@@ -746,17 +1833,14 @@ async fn handle_list_request(
         }
     };
     print!("[handle_list_request] List: {:?}", list);
-    let list = list
-        .into_iter()
-        .filter(|item| item.user_id != *requester_user_id)
-        .collect::<Vec<_>>();
-
-    let list = list.into_iter().fold(Vec::new(), |mut acc, item| {
-        if !acc.iter().any(|x: &BattleStatus| x.user_id == item.user_id) {
-            acc.push(item);
+    let blocked_user_ids = match Block::blocked_user_ids(requester_user_id.clone()).await {
+        Ok(blocked_user_ids) => blocked_user_ids,
+        Err(err) => {
+            println!("[handle_list_request] Error finding blocks: {:?}", err);
+            return Err(err.into());
         }
-        acc
-    });
+    };
+    let list = listable_players(list, requester_user_id, &blocked_user_ids);
 
     let mut battle_queue = build_success(
         Some(requester_user_id.clone()),
@@ -770,6 +1854,26 @@ async fn handle_list_request(
     Ok(serde_json::to_string(&battle_queue).unwrap())
 }
 
+/// The other queued players, once each, leaving out anyone blocked either way.
+fn listable_players(
+    list: Vec<BattleStatus>,
+    requester_user_id: &String,
+    blocked_user_ids: &[String],
+) -> Vec<BattleStatus> {
+    let list = list
+        .into_iter()
+        .filter(|item| item.user_id != *requester_user_id)
+        .filter(|item| !blocked_user_ids.contains(&item.user_id))
+        .collect::<Vec<_>>();
+
+    list.into_iter().fold(Vec::new(), |mut acc, item| {
+        if !acc.iter().any(|x: &BattleStatus| x.user_id == item.user_id) {
+            acc.push(item);
+        }
+        acc
+    })
+}
+
 async fn handle_sort_mnstrs_request(
     requester_user_id: &String,
     user_name: &Option<String>,
@@ -780,7 +1884,7 @@ async fn handle_sort_mnstrs_request(
         requester_user_id, sort_mnstrs_input
     );
     let params = vec![("user_id", requester_user_id.clone().into())];
-    let mnstrs = match Mnstr::find_all_by(
+    let mnstrs = match Mnstr::find_all_unarchived_by(
         params,
         false,
         sort_mnstrs_input.sort_by,
@@ -809,6 +1913,188 @@ async fn handle_sort_mnstrs_request(
     Ok(serde_json::to_string(&battle_queue).unwrap())
 }
 
+async fn handle_ready(
+    session_user_id: &String,
+    user_name: &Option<String>,
+    ready: bool,
+) -> BattleQueue {
+    let (action, data_action) = match ready {
+        true => (BattleQueueAction::Ready, BattleQueueDataAction::Ready),
+        false => (BattleQueueAction::Unready, BattleQueueDataAction::Unready),
+    };
+
+    let params = vec![("user_id", session_user_id.clone().into())];
+    let mut status = match BattleStatus::find_one_by(params).await {
+        Ok(status) => status,
+        Err(err) => {
+            println!("[handle_ready] Error finding battle status: {:?}", err);
+            return build_error(
+                Some(session_user_id.clone()),
+                user_name.clone(),
+                BattleQueueChannel::Lobby,
+                BattleQueueAction::Error,
+                data_action,
+                "Error finding battle status".to_string(),
+            );
+        }
+    };
+    if !matches!(status.status, BattleStatusState::InQueue) {
+        return build_error(
+            Some(session_user_id.clone()),
+            user_name.clone(),
+            BattleQueueChannel::Lobby,
+            BattleQueueAction::Error,
+            data_action,
+            "Only queued players can change readiness".to_string(),
+        );
+    }
+
+    status.ready = ready;
+    if let Some(err) = status.update().await {
+        println!("[handle_ready] Error updating battle status: {:?}", err);
+        return build_error(
+            Some(session_user_id.clone()),
+            user_name.clone(),
+            BattleQueueChannel::Lobby,
+            BattleQueueAction::Error,
+            data_action,
+            "Error updating battle status".to_string(),
+        );
+    }
+
+    let message = match ready {
+        true => "Player is ready",
+        false => "Player is no longer ready",
+    };
+    build_success(
+        Some(session_user_id.clone()),
+        user_name.clone(),
+        BattleQueueChannel::Lobby,
+        action,
+        data_action,
+        message.to_string(),
+    )
+}
+
+/// Keeps the open challenge on the challenger's status so the challenged
+/// player can only preview and accept challenges that were actually sent.
+async fn record_challenge(
+    queue: &BattleQueue,
+    session_user_id: &String,
+) -> Result<(), &'static str> {
+    let opponent_id = match queue.data.opponent_id.clone() {
+        Some(opponent_id) => opponent_id,
+        None => return Err("Missing opponent id"),
+    };
+    let params = vec![("user_id", session_user_id.clone().into())];
+    let mut status = match BattleStatus::find_one_by(params).await {
+        Ok(status) => status,
+        Err(err) => {
+            println!("[record_challenge] Error finding battle status: {:?}", err);
+            return Err("Error finding battle status");
+        }
+    };
+    if !status.can_cancel_challenge() {
+        return Err("Already in a battle");
+    }
+    status.opponent_id = Some(opponent_id);
+    status.opponent_name = queue.data.opponent_name.clone();
+    if let Some(err) = status.update().await {
+        println!("[record_challenge] Error updating battle status: {:?}", err);
+        return Err("Error updating battle status");
+    }
+    Ok(())
+}
+
+async fn handle_cancel_challenge(
+    queue: &BattleQueue,
+    session_user_id: &String,
+    user_name: &Option<String>,
+) -> BattleQueue {
+    let cancel_error = |message: &str| {
+        build_error(
+            Some(session_user_id.clone()),
+            user_name.clone(),
+            BattleQueueChannel::Lobby,
+            BattleQueueAction::Error,
+            BattleQueueDataAction::CancelChallenge,
+            message.to_string(),
+        )
+    };
+
+    let opponent_id = match queue.data.opponent_id.clone() {
+        Some(opponent_id) => opponent_id,
+        None => return cancel_error("Missing opponent id"),
+    };
+
+    let params = vec![("user_id", session_user_id.clone().into())];
+    let mut status = match BattleStatus::find_one_by(params).await {
+        Ok(status) => status,
+        Err(err) => {
+            println!(
+                "[handle_cancel_challenge] Error finding battle status: {:?}",
+                err
+            );
+            return cancel_error("Error finding battle status");
+        }
+    };
+    if !status.can_cancel_challenge() {
+        return cancel_error("Challenge has already been accepted");
+    }
+
+    if status.opponent_id.is_some() {
+        status.opponent_id = None;
+        status.opponent_name = None;
+        if let Some(err) = status.update().await {
+            println!(
+                "[handle_cancel_challenge] Error updating battle status: {:?}",
+                err
+            );
+            return cancel_error("Error updating battle status");
+        }
+    }
+
+    let mut cancelled = build_success(
+        Some(session_user_id.clone()),
+        user_name.clone(),
+        BattleQueueChannel::Lobby,
+        BattleQueueAction::ChallengeCancelled,
+        BattleQueueDataAction::CancelChallenge,
+        "Challenge withdrawn".to_string(),
+    );
+    cancelled.data.opponent_id = Some(opponent_id);
+    cancelled.data.opponent_name = queue.data.opponent_name.clone();
+    cancelled
+}
+
+async fn players_blocked(user_id: &String, other_id: &String) -> bool {
+    match Block::is_blocked_between(user_id.clone(), other_id.clone()).await {
+        Ok(blocked) => blocked,
+        Err(err) => {
+            // Refuse the pairing rather than risk matching blocked players
+            println!("[players_blocked] Error finding blocks: {:?}", err);
+            true
+        }
+    }
+}
+
+async fn players_can_match(challenger_id: &String, opponent_id: &String) -> bool {
+    if players_blocked(challenger_id, opponent_id).await {
+        return false;
+    }
+    let params = vec![("user_id", challenger_id.clone().into())];
+    let challenger_status = match BattleStatus::find_one_by(params).await {
+        Ok(status) => status,
+        Err(_) => return false,
+    };
+    let params = vec![("user_id", opponent_id.clone().into())];
+    let opponent_status = match BattleStatus::find_one_by(params).await {
+        Ok(status) => status,
+        Err(_) => return false,
+    };
+    challenger_status.can_match(&opponent_status)
+}
+
 async fn handle_accept_challenge(
     queue: &BattleQueue,
     session_user_id: &String,
@@ -820,6 +2106,19 @@ async fn handle_accept_challenge(
     let opponent_id = queue.data.opponent_id.clone().unwrap();
     let challenger_id = queue.data.user_id.clone().unwrap();
 
+    if !players_can_match(&challenger_id, &opponent_id).await {
+        let error = build_error(
+            Some(session_user_id.clone()),
+            user_name.clone(),
+            BattleQueueChannel::Lobby,
+            BattleQueueAction::Error,
+            BattleQueueDataAction::Accept,
+            "Both players must be ready".to_string(),
+        );
+        publish_queue(connection, &error).await;
+        return Err(());
+    }
+
     let battle = match create_battle(&challenger_id, &opponent_id).await {
         Ok(battle) => battle,
         Err(_) => {
@@ -859,58 +2158,36 @@ async fn handle_accept_challenge(
         return Err(());
     }
 
-    let challenger_mnstrs = match load_mnstrs(&challenger_id.clone()).await {
-        Ok(mnstrs) => mnstrs,
-        Err(_) => {
-            publish_queue(
-                connection,
-                &build_error(
-                    Some(session_user_id.clone()),
-                    user_name.clone(),
-                    BattleQueueChannel::Lobby,
-                    BattleQueueAction::Error,
-                    BattleQueueDataAction::Challenge,
-                    "Error loading mnstrs".to_string(),
-                ),
-            )
-            .await;
-            return Err(());
-        }
-    };
-    if let Some(error) = error {
-        publish_queue(connection, &error).await;
-        return Err(());
-    }
-
-    let opponent_mnstrs = match load_mnstrs(&opponent_id.clone()).await {
-        Ok(mnstrs) => mnstrs,
-        Err(_) => {
-            publish_queue(
-                connection,
-                &build_error(
-                    Some(session_user_id.clone()),
-                    user_name.clone(),
-                    BattleQueueChannel::Lobby,
-                    BattleQueueAction::Error,
-                    BattleQueueDataAction::Challenge,
-                    "Error loading mnstrs".to_string(),
-                ),
-            )
-            .await;
-            return Err(());
-        }
-    };
+    let (challenger_mnstrs, opponent_mnstrs) =
+        match load_battlers_mnstrs(&challenger_id, &opponent_id).await {
+            Ok(mnstrs) => mnstrs,
+            Err(_) => {
+                publish_queue(
+                    connection,
+                    &build_error(
+                        Some(session_user_id.clone()),
+                        user_name.clone(),
+                        BattleQueueChannel::Lobby,
+                        BattleQueueAction::Error,
+                        BattleQueueDataAction::Challenge,
+                        "Error loading mnstrs".to_string(),
+                    ),
+                )
+                .await;
+                return Err(());
+            }
+        };
 
-    let coin_flip = {
-        let mut rng = rand::rng();
-        rng.random_range(0..2)
-    };
-    let turn_user_id;
-    if coin_flip == 0 {
-        turn_user_id = challenger_id.clone();
-    } else {
-        turn_user_id = opponent_id.clone();
-    }
+    // Mnstrs haven't been chosen yet; the turn is decided again once they are
+    let mut rng = BattleRng::for_turn(battle.seed, 0);
+    let turn_user_id = first_turn_user_id(
+        turn_order_rule(),
+        &challenger_id,
+        None,
+        &opponent_id,
+        None,
+        || rng.coin_flip(),
+    );
 
     let battle_queue_game_data_map = BattleQueueGameData {
         battle_id: Some(battle.id.clone()),
@@ -933,6 +2210,8 @@ async fn handle_accept_challenge(
     queue.data.data = Some(battle_queue_game_data);
     queue.data.action = BattleQueueDataAction::GameStarted;
     queue.action = BattleQueueAction::GameStarted;
+    // Neither player follows the battle channel yet, so hand off through the lobby
+    queue.channel = BattleQueueChannel::Lobby;
 
     publish_queue(connection, &queue).await;
     Ok(())
@@ -966,6 +2245,7 @@ async fn handle_accept_request(
         status.battle_id = Some(battle_id.clone());
     }
     status.status = BattleStatusState::InBattle;
+    status.ready = false;
 
     if let Some(error) = status.update().await {
         println!(
@@ -988,6 +2268,7 @@ async fn handle_accept_request(
         status.battle_id = Some(battle_id.clone());
     }
     status.status = BattleStatusState::InBattle;
+    status.ready = false;
 
     if let Some(error) = status.update().await {
         println!(
@@ -1022,6 +2303,7 @@ async fn create_battle(challenger_id: &String, opponent_id: &String) -> Result<B
 
 async fn update_battle_mnstrs(
     battle_id: &String,
+    session_user_id: &String,
     challenger_mnstr: &Option<Mnstr>,
     opponent_mnstr: &Option<Mnstr>,
 ) -> Result<Battle, anyhow::Error> {
@@ -1033,20 +2315,72 @@ async fn update_battle_mnstrs(
             println!("[update_battle_mnstrs] Failed to find battle: {:?}", error);
             return Err(error.into());
         }
-    };
-    if let Some(challenger_mnstr) = challenger_mnstr {
-        println!(
-            "[update_battle_mnstrs] Challenger mnstr: {:?}",
-            challenger_mnstr.id.clone()
-        );
-        battle.challenger_mnstr_id = Some(challenger_mnstr.id.clone());
-    }
-    if let Some(opponent_mnstr) = opponent_mnstr {
-        println!(
-            "[update_battle_mnstrs] Opponent mnstr: {:?}",
-            opponent_mnstr.id.clone()
-        );
-        battle.opponent_mnstr_id = Some(opponent_mnstr.id.clone());
+    };
+    // Only the sender's side is taken from the message, and the pick is checked
+    // against the stored mnstr, not the client's copy
+    let now = time::OffsetDateTime::now_utc();
+    let engagements = match Battle::find_mnstr_engagements(session_user_id.clone(), now).await {
+        Ok(engagements) => engagements,
+        Err(error) => {
+            println!(
+                "[update_battle_mnstrs] Failed to find engagements: {:?}",
+                error
+            );
+            return Err(error);
+        }
+    };
+    let chosen = match battle.challenger_id == *session_user_id {
+        true => challenger_mnstr,
+        false => opponent_mnstr,
+    };
+    if let Some(mnstr) = chosen {
+        println!(
+            "[update_battle_mnstrs] Chosen mnstr: {:?}",
+            mnstr.id.clone()
+        );
+        let stored = match Mnstr::find_one(mnstr.id.clone(), false).await {
+            Ok(stored) => stored,
+            Err(error) => {
+                println!("[update_battle_mnstrs] Failed to find mnstr: {:?}", error);
+                return Err(error);
+            }
+        };
+        if let Err(error) = battle.choose_mnstr_for(session_user_id, &stored, &engagements, now) {
+            println!("[update_battle_mnstrs] Rejected mnstr: {:?}", error);
+            return Err(error);
+        }
+    }
+    // Fall back to the player's primary mnstr when the client didn't pick one
+    if battle.needs_mnstr(session_user_id) {
+        let user = match User::find_one(session_user_id.clone(), false).await {
+            Ok(user) => user,
+            Err(error) => {
+                println!("[update_battle_mnstrs] Failed to find user: {:?}", error);
+                return Err(error.into());
+            }
+        };
+        match user.primary_mnstr().await {
+            Ok(Some(primary_mnstr)) => {
+                println!(
+                    "[update_battle_mnstrs] Primary mnstr: {:?}",
+                    primary_mnstr.id.clone()
+                );
+                // A primary that can't battle is left for the player to replace
+                if let Err(error) =
+                    battle.choose_mnstr_for(session_user_id, &primary_mnstr, &engagements, now)
+                {
+                    println!("[update_battle_mnstrs] Skipped primary mnstr: {:?}", error);
+                }
+            }
+            Ok(None) => (),
+            Err(error) => {
+                println!(
+                    "[update_battle_mnstrs] Failed to find primary mnstr: {:?}",
+                    error
+                );
+                return Err(error.into());
+            }
+        }
     }
     if let Some(error) = battle.update().await {
         println!("[update_battle] Failed to update battle: {:?}", error);
@@ -1055,19 +2389,22 @@ async fn update_battle_mnstrs(
     Ok(battle)
 }
 
-async fn load_mnstrs(user_id: &String) -> Result<Vec<Mnstr>, ()> {
-    let mnstrs = Mnstr::find_all_by(
-        vec![("user_id", user_id.clone().into())],
-        false,
-        Option::<MnstrOrderBy>::None,
-        Option::<MnstrOrderDirection>::None,
-    )
-    .await
-    .map_err(|_| ())?;
-    Ok(mnstrs)
+/// Loads both battlers' mnstrs in one query, split into the challenger's and
+/// the opponent's.
+async fn load_battlers_mnstrs(
+    challenger_id: &String,
+    opponent_id: &String,
+) -> Result<(Vec<Mnstr>, Vec<Mnstr>), ()> {
+    let mnstrs =
+        Mnstr::find_all_unarchived_by_user_ids(vec![challenger_id.clone(), opponent_id.clone()])
+            .await
+            .map_err(|_| ())?;
+    Ok(mnstrs
+        .into_iter()
+        .partition(|mnstr| &mnstr.user_id == challenger_id))
 }
 
-async fn handle_rejoin_request(battle_id: &String) -> Result<Battle, ()> {
+async fn handle_rejoin_request(battle_id: &String, session_user_id: &String) -> Result<Battle, ()> {
     let battle = match Battle::find_one(battle_id.clone()).await {
         Ok(battle) => battle,
         Err(error) => {
@@ -1075,6 +2412,13 @@ async fn handle_rejoin_request(battle_id: &String) -> Result<Battle, ()> {
             return Err(());
         }
     };
+    if !battle.can_rejoin(session_user_id) {
+        println!(
+            "[handle_rejoin_request] Battle {} can't be rejoined",
+            battle_id
+        );
+        return Err(());
+    }
     Ok(battle)
 }
 
@@ -1094,14 +2438,34 @@ async fn handle_left(session_user_id: &String) -> Option<String> {
     None
 }
 
+/// Dice for the next turn: every turn writes one battle log, so the log count
+/// is the index of the turn about to be played.
+async fn battle_rng(battle_id: &String) -> Result<BattleRng, anyhow::Error> {
+    let battle = Battle::find_one(battle_id.clone()).await?;
+    let turn = BattleLog::count_for_battle(battle_id.clone()).await?;
+    Ok(BattleRng::for_turn(battle.seed, turn + 1))
+}
+
 async fn handle_attack(
     queue: &mut BattleQueue,
     session_user_id: &String,
     user_name: &Option<String>,
 ) -> Option<BattleQueue> {
-    let game_data = queue.data.data.clone().unwrap();
-    let mut battle_game_data: BattleQueueGameData =
-        serde_json::from_str(&game_data.clone()).unwrap();
+    let mut battle_game_data = match queue.data.game_data() {
+        Ok(game_data) => game_data,
+        Err(error) => {
+            println!("[handle_attack] Failed to read game data: {:?}", error);
+            let error_queue = build_error(
+                Some(session_user_id.clone()),
+                user_name.clone(),
+                BattleQueueChannel::Battle,
+                BattleQueueAction::Error,
+                BattleQueueDataAction::Attack,
+                "Invalid game data".to_string(),
+            );
+            return Some(error_queue);
+        }
+    };
 
     let battle_id = battle_game_data.battle_id.clone().unwrap();
     let challenger = battle_game_data.challenger_mnstr.clone().unwrap();
@@ -1118,28 +2482,40 @@ async fn handle_attack(
         defender = opponent.clone();
     }
 
-    let mut battle_log_data = BattleLogData {
-        missed: None,
-        hit: None,
-        damage: None,
-        defense: None,
-    };
-
-    let battle_log_action;
+    // The other player's killing blow may have landed since this turn was checked
+    if let Some(error_queue) = ensure_battle_unsettled(
+        &battle_id,
+        session_user_id,
+        user_name,
+        BattleQueueDataAction::Attack,
+    )
+    .await
+    {
+        return Some(error_queue);
+    }
 
-    match crate::battle::physical::attack(&mut attacker, &mut defender) {
-        (true, damage) => {
-            battle_log_data.hit = Some(true);
-            battle_log_data.damage = Some(damage);
-            battle_log_action = BattleLogAction::Hit;
-            println!("[handle_attack] Hit! {:?}", damage);
-        }
-        (false, _) => {
-            battle_log_data.missed = Some(true);
-            battle_log_action = BattleLogAction::Missed;
-            println!("[handle_attack] Missed");
+    let mut rng = match battle_rng(&battle_id).await {
+        Ok(rng) => rng,
+        Err(error) => {
+            println!("[handle_attack] Failed to seed battle rng: {:?}", error);
+            let error_queue = build_error(
+                Some(session_user_id.clone()),
+                user_name.clone(),
+                BattleQueueChannel::Battle,
+                BattleQueueAction::Error,
+                BattleQueueDataAction::Attack,
+                "Error loading battle".to_string(),
+            );
+            return Some(error_queue);
         }
-    }
+    };
+
+    let (battle_log_action, battle_log_data) =
+        CombatMove::Attack.play(&mut attacker, &mut defender, &mut rng);
+    println!(
+        "[handle_attack] {:?}: {:?}",
+        battle_log_action, battle_log_data
+    );
 
     battle_game_data.battle_log_data = Some(battle_log_data.clone());
 
@@ -1166,11 +2542,6 @@ async fn handle_attack(
         return Some(error_queue);
     }
 
-    attacker.current_attack -= 1;
-    attacker.current_speed -= 1;
-    defender.current_defense -= 1;
-    defender.current_intelligence -= 1;
-
     println!("[handle_attack] Updating attacker");
     if let Some(error) = attacker.update().await {
         println!("[handle_attack] Failed to update attacker: {:?}", error);
@@ -1209,15 +2580,30 @@ async fn handle_attack(
     }
     battle_game_data.turn_user_id = Some(defender.user_id.clone());
 
-    if defender.current_health <= 0 {
-        println!("[handle_attack] Defender is dead!");
-        battle_game_data.winner_id = Some(attacker.user_id.clone());
-        queue.data.data = Some(serde_json::to_string(&battle_game_data).unwrap());
-        if let Some(error) = handle_game_ended(queue, session_user_id, user_name).await {
+    let knockout = knockout_result(
+        battle_game_data.challenger_mnstr.as_ref().unwrap(),
+        battle_game_data.opponent_mnstr.as_ref().unwrap(),
+    );
+    if let Some(result) = knockout {
+        println!("[handle_attack] Knockout: {:?}", result);
+        if let KnockoutResult::Winner(winner_id) = &result {
+            battle_game_data.winner_id = Some(winner_id.clone());
+        }
+        queue.data.set_game_data(&battle_game_data);
+        if let Some(error) = handle_knockout(
+            queue,
+            &battle_id,
+            result,
+            session_user_id,
+            user_name,
+            BattleQueueDataAction::Attack,
+        )
+        .await
+        {
             return Some(error);
         }
     } else {
-        queue.data.data = Some(serde_json::to_string(&battle_game_data).unwrap());
+        queue.data.set_game_data(&battle_game_data);
     }
     None
 }
@@ -1227,9 +2613,21 @@ async fn handle_defend(
     session_user_id: &String,
     user_name: &Option<String>,
 ) -> Option<BattleQueue> {
-    let game_data = queue.data.data.clone().unwrap();
-    let mut battle_game_data: BattleQueueGameData =
-        serde_json::from_str(&game_data.clone()).unwrap();
+    let mut battle_game_data = match queue.data.game_data() {
+        Ok(game_data) => game_data,
+        Err(error) => {
+            println!("[handle_defend] Failed to read game data: {:?}", error);
+            let error_queue = build_error(
+                Some(session_user_id.clone()),
+                user_name.clone(),
+                BattleQueueChannel::Battle,
+                BattleQueueAction::Error,
+                BattleQueueDataAction::Defend,
+                "Invalid game data".to_string(),
+            );
+            return Some(error_queue);
+        }
+    };
 
     let battle_id = battle_game_data.battle_id.clone().unwrap();
     let challenger = battle_game_data.challenger_mnstr.clone().unwrap();
@@ -1247,19 +2645,26 @@ async fn handle_defend(
         attacker = opponent.clone();
     }
 
-    let defense = crate::battle::defend::rest(&mut attacker);
-
-    let battle_log_data = BattleLogData {
-        missed: None,
-        hit: None,
-        damage: None,
-        defense: Some(defense),
+    let mut rng = match battle_rng(&battle_id).await {
+        Ok(rng) => rng,
+        Err(error) => {
+            println!("[handle_defend] Failed to seed battle rng: {:?}", error);
+            let error_queue = build_error(
+                Some(session_user_id.clone()),
+                user_name.clone(),
+                BattleQueueChannel::Battle,
+                BattleQueueAction::Error,
+                BattleQueueDataAction::Defend,
+                "Error loading battle".to_string(),
+            );
+            return Some(error_queue);
+        }
     };
 
-    let battle_log_action;
-
-    battle_log_action = BattleLogAction::Defended;
-    println!("[handle_defend] Defend! {:?}", defense);
+    // Defending only touches the defender, so the other side is a throwaway copy
+    let (battle_log_action, battle_log_data) =
+        CombatMove::Defend.play(&mut attacker, &mut defender.clone(), &mut rng);
+    println!("[handle_defend] Defend! {:?}", battle_log_data.defense);
 
     battle_game_data.battle_log_data = Some(battle_log_data.clone());
 
@@ -1309,7 +2714,7 @@ async fn handle_defend(
         battle_game_data.challenger_mnstr = Some(attacker.clone());
     }
     battle_game_data.turn_user_id = Some(defender.user_id.clone());
-    queue.data.data = Some(serde_json::to_string(&battle_game_data).unwrap());
+    queue.data.set_game_data(&battle_game_data);
 
     None
 }
@@ -1319,9 +2724,21 @@ async fn handle_magic(
     session_user_id: &String,
     user_name: &Option<String>,
 ) -> Option<BattleQueue> {
-    let game_data = queue.data.data.clone().unwrap();
-    let mut battle_game_data: BattleQueueGameData =
-        serde_json::from_str(&game_data.clone()).unwrap();
+    let mut battle_game_data = match queue.data.game_data() {
+        Ok(game_data) => game_data,
+        Err(error) => {
+            println!("[handle_magic] Failed to read game data: {:?}", error);
+            let error_queue = build_error(
+                Some(session_user_id.clone()),
+                user_name.clone(),
+                BattleQueueChannel::Battle,
+                BattleQueueAction::Error,
+                BattleQueueDataAction::Magic,
+                "Invalid game data".to_string(),
+            );
+            return Some(error_queue);
+        }
+    };
 
     let battle_id = battle_game_data.battle_id.clone().unwrap();
     let challenger = battle_game_data.challenger_mnstr.clone().unwrap();
@@ -1338,28 +2755,40 @@ async fn handle_magic(
         defender = opponent.clone();
     }
 
-    let mut battle_log_data = BattleLogData {
-        missed: None,
-        hit: None,
-        damage: None,
-        defense: None,
-    };
-
-    let battle_log_action;
+    // The other player's killing blow may have landed since this turn was checked
+    if let Some(error_queue) = ensure_battle_unsettled(
+        &battle_id,
+        session_user_id,
+        user_name,
+        BattleQueueDataAction::Magic,
+    )
+    .await
+    {
+        return Some(error_queue);
+    }
 
-    match crate::battle::magic::attack(&mut attacker, &mut defender) {
-        (true, damage) => {
-            battle_log_data.hit = Some(true);
-            battle_log_data.damage = Some(damage);
-            battle_log_action = BattleLogAction::Hit;
-            println!("[handle_magic] Hit! {:?}", damage);
-        }
-        (false, _) => {
-            battle_log_data.missed = Some(true);
-            battle_log_action = BattleLogAction::Missed;
-            println!("[handle_magic] Missed");
+    let mut rng = match battle_rng(&battle_id).await {
+        Ok(rng) => rng,
+        Err(error) => {
+            println!("[handle_magic] Failed to seed battle rng: {:?}", error);
+            let error_queue = build_error(
+                Some(session_user_id.clone()),
+                user_name.clone(),
+                BattleQueueChannel::Battle,
+                BattleQueueAction::Error,
+                BattleQueueDataAction::Magic,
+                "Error loading battle".to_string(),
+            );
+            return Some(error_queue);
         }
-    }
+    };
+
+    let (battle_log_action, battle_log_data) =
+        CombatMove::Magic.play(&mut attacker, &mut defender, &mut rng);
+    println!(
+        "[handle_magic] {:?}: {:?}",
+        battle_log_action, battle_log_data
+    );
 
     battle_game_data.battle_log_data = Some(battle_log_data.clone());
 
@@ -1424,23 +2853,228 @@ async fn handle_magic(
     }
     battle_game_data.turn_user_id = Some(defender.user_id.clone());
 
-    if defender.current_health <= 0 {
-        println!("[handle_attack] Defender is dead!");
-        battle_game_data.winner_id = Some(attacker.user_id.clone());
-        queue.data.data = Some(serde_json::to_string(&battle_game_data).unwrap());
-        if let Some(error) = handle_game_ended(queue, session_user_id, user_name).await {
+    let knockout = knockout_result(
+        battle_game_data.challenger_mnstr.as_ref().unwrap(),
+        battle_game_data.opponent_mnstr.as_ref().unwrap(),
+    );
+    if let Some(result) = knockout {
+        println!("[handle_attack] Knockout: {:?}", result);
+        if let KnockoutResult::Winner(winner_id) = &result {
+            battle_game_data.winner_id = Some(winner_id.clone());
+        }
+        queue.data.set_game_data(&battle_game_data);
+        if let Some(error) = handle_knockout(
+            queue,
+            &battle_id,
+            result,
+            session_user_id,
+            user_name,
+            BattleQueueDataAction::Magic,
+        )
+        .await
+        {
             return Some(error);
         }
     } else {
-        queue.data.data = Some(serde_json::to_string(&battle_game_data).unwrap());
+        queue.data.set_game_data(&battle_game_data);
+    }
+    None
+}
+
+// Escape outcomes come from the battle row alone; any winner in the payload is ignored
+async fn handle_escape(
+    queue: &mut BattleQueue,
+    session_user_id: &String,
+    user_name: &Option<String>,
+) -> Option<BattleQueue> {
+    let escape_error = |message: &str| {
+        build_error(
+            Some(session_user_id.clone()),
+            user_name.clone(),
+            BattleQueueChannel::Battle,
+            BattleQueueAction::Error,
+            BattleQueueDataAction::Escape,
+            message.to_string(),
+        )
+    };
+
+    let mut game_data = match queue.data.game_data() {
+        Ok(game_data) => game_data,
+        Err(_) => return Some(escape_error("Invalid game data")),
+    };
+    let battle_id = match game_data.battle_id.clone() {
+        Some(battle_id) => battle_id,
+        None => return Some(escape_error("Missing battle id")),
+    };
+    let battle = match Battle::find_one(battle_id).await {
+        Ok(battle) => battle,
+        Err(_) => return Some(escape_error("Error finding battle")),
+    };
+
+    if !apply_forfeit_outcome(&mut game_data, &battle, session_user_id) {
+        return Some(escape_error("Not a participant in this battle"));
+    }
+    queue.data.set_game_data(&game_data);
+    None
+}
+
+// Surrendering settles the battle against the sender, like an escape, but is logged
+// and rewarded separately
+async fn handle_surrender(
+    queue: &mut BattleQueue,
+    session_user_id: &String,
+    user_name: &Option<String>,
+) -> Option<BattleQueue> {
+    let surrender_error = |message: &str| {
+        build_error(
+            Some(session_user_id.clone()),
+            user_name.clone(),
+            BattleQueueChannel::Battle,
+            BattleQueueAction::Error,
+            BattleQueueDataAction::Surrender,
+            message.to_string(),
+        )
+    };
+
+    let mut game_data = match queue.data.game_data() {
+        Ok(game_data) => game_data,
+        Err(_) => return Some(surrender_error("Invalid game data")),
+    };
+    let battle_id = match game_data.battle_id.clone() {
+        Some(battle_id) => battle_id,
+        None => return Some(surrender_error("Missing battle id")),
+    };
+    let battle = match Battle::find_one(battle_id).await {
+        Ok(battle) => battle,
+        Err(_) => return Some(surrender_error("Error finding battle")),
+    };
+
+    if !apply_forfeit_outcome(&mut game_data, &battle, session_user_id) {
+        return Some(surrender_error("Not a participant in this battle"));
+    }
+
+    let mnstr_id = match battle.challenger_id == *session_user_id {
+        true => battle.challenger_mnstr_id.clone(),
+        false => battle.opponent_mnstr_id.clone(),
+    };
+    let mut battle_log = BattleLog::new(
+        battle.id.clone(),
+        session_user_id.clone(),
+        mnstr_id.unwrap_or_default(),
+        BattleLogAction::Surrendered,
+        "".to_string(),
+    );
+    if let Some(error) = battle_log.create().await {
+        println!("[handle_surrender] Failed to log surrender: {:?}", error);
     }
+
+    queue.data.set_game_data(&game_data);
     None
 }
 
+fn apply_forfeit_outcome(
+    game_data: &mut BattleQueueGameData,
+    battle: &Battle,
+    forfeiting_user_id: &str,
+) -> bool {
+    match battle.forfeit_winner_id(forfeiting_user_id) {
+        Some(winner_id) => {
+            game_data.winner_id = Some(winner_id);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Builds an error when the battle has a winner already or can't be found, so
+/// a late action doesn't touch a battle that has been settled.
+async fn ensure_battle_unsettled(
+    battle_id: &String,
+    session_user_id: &String,
+    user_name: &Option<String>,
+    data_action: BattleQueueDataAction,
+) -> Option<BattleQueue> {
+    match Battle::find_one(battle_id.clone()).await {
+        Ok(battle) if !battle.is_settled() => None,
+        Ok(_) | Err(_) => {
+            println!(
+                "[ensure_battle_unsettled] Battle {} is already settled",
+                battle_id
+            );
+            Some(build_error(
+                Some(session_user_id.clone()),
+                user_name.clone(),
+                BattleQueueChannel::Battle,
+                BattleQueueAction::Error,
+                data_action,
+                "Battle already settled".to_string(),
+            ))
+        }
+    }
+}
+
+/// Ends the game after a knockout, or as a draw when both mnstrs went down,
+/// unless a racing knockout claimed the battle first.
+async fn handle_knockout(
+    queue: &mut BattleQueue,
+    battle_id: &String,
+    result: KnockoutResult,
+    session_user_id: &String,
+    user_name: &Option<String>,
+    data_action: BattleQueueDataAction,
+) -> Option<BattleQueue> {
+    let settled = match result {
+        KnockoutResult::Winner(winner_id) => {
+            settle_once(
+                Battle::claim_winner(battle_id.clone(), winner_id),
+                handle_game_ended(queue, session_user_id, user_name, BattleOutcome::Knockout),
+            )
+            .await
+        }
+        KnockoutResult::Draw => {
+            settle_once(
+                Battle::claim_draw(battle_id.clone()),
+                handle_draw(queue, session_user_id, user_name),
+            )
+            .await
+        }
+    };
+    match settled {
+        Ok(Some(error)) => error,
+        Ok(None) | Err(_) => {
+            println!(
+                "[handle_knockout] Battle {} was settled by another action",
+                battle_id
+            );
+            Some(build_error(
+                Some(session_user_id.clone()),
+                user_name.clone(),
+                BattleQueueChannel::Battle,
+                BattleQueueAction::Error,
+                data_action,
+                "Battle already settled".to_string(),
+            ))
+        }
+    }
+}
+
+/// Runs `settle` only when `claim` reports this caller won the race to record
+/// the winner. Returns `Ok(None)` when someone else already had.
+async fn settle_once<T>(
+    claim: impl Future<Output = Result<bool, anyhow::Error>>,
+    settle: impl Future<Output = T>,
+) -> Result<Option<T>, anyhow::Error> {
+    if !claim.await? {
+        return Ok(None);
+    }
+    Ok(Some(settle.await))
+}
+
 async fn handle_game_ended(
     queue: &mut BattleQueue,
     session_user_id: &String,
     user_name: &Option<String>,
+    outcome: BattleOutcome,
 ) -> Option<BattleQueue> {
     println!("[handle_game_ended] Ending game");
 
@@ -1457,9 +3091,21 @@ async fn handle_game_ended(
         return Some(error_queue);
     }
 
-    let raw_game_data = queue.data.data.clone().unwrap();
-    let battle_game_data: BattleQueueGameData =
-        serde_json::from_str(&raw_game_data.clone()).unwrap();
+    let battle_game_data = match queue.data.game_data() {
+        Ok(game_data) => game_data,
+        Err(error) => {
+            println!("[handle_game_ended] Failed to read game data: {:?}", error);
+            let error_queue = build_error(
+                Some(session_user_id.clone()),
+                user_name.clone(),
+                BattleQueueChannel::Battle,
+                BattleQueueAction::Error,
+                queue.data.action.clone(),
+                "Invalid game data".to_string(),
+            );
+            return Some(error_queue);
+        }
+    };
 
     println!("[handle_game_ended] Finding battle");
     let mut battle = match Battle::find_one(battle_game_data.battle_id.clone().unwrap()).await {
@@ -1538,53 +3184,19 @@ async fn handle_game_ended(
                 loser_mnstr_id = opponent_mnstr.id.clone();
             } else {
                 winner_user_id = opponent_mnstr.user_id.clone();
-                winner_mnstr_id = opponent_mnstr.id.clone();
-                loser_user_id = challenger_mnstr.user_id.clone();
-                loser_mnstr_id = challenger_mnstr.id.clone();
-            }
-        }
-    } else {
-        let error_queue = build_error(
-            Some(session_user_id.clone()),
-            user_name.clone(),
-            BattleQueueChannel::Battle,
-            BattleQueueAction::Error,
-            queue.data.action.clone(),
-            "Error finding winner".to_string(),
-        );
-        return Some(error_queue);
-    }
-
-    battle.winner_id = Some(winner_user_id.clone());
-    battle.winner_mnstr_id = Some(winner_mnstr_id.clone());
-
-    println!("[handle_game_ended] Updating battle");
-    if let Some(error) = battle.update().await {
-        println!(
-            "[handle_escape_request] Failed to update battle: {:?}",
-            error
-        );
-        let error_queue = build_error(
-            Some(session_user_id.clone()),
-            user_name.clone(),
-            BattleQueueChannel::Battle,
-            BattleQueueAction::Error,
-            BattleQueueDataAction::Escape,
-            "Error updating battle".to_string(),
-        );
-        return Some(error_queue);
-    }
-
-    println!("[handle_game_ended] Deleting battle");
-    if let Some(error) = battle.delete().await {
-        println!("[handle_game_ended] Failed to delete battle: {:?}", error);
+                winner_mnstr_id = opponent_mnstr.id.clone();
+                loser_user_id = challenger_mnstr.user_id.clone();
+                loser_mnstr_id = challenger_mnstr.id.clone();
+            }
+        }
+    } else {
         let error_queue = build_error(
             Some(session_user_id.clone()),
             user_name.clone(),
             BattleQueueChannel::Battle,
             BattleQueueAction::Error,
-            BattleQueueDataAction::Escape,
-            "Error deleting battle".to_string(),
+            queue.data.action.clone(),
+            "Error finding winner".to_string(),
         );
         return Some(error_queue);
     }
@@ -1654,11 +3266,55 @@ async fn handle_game_ended(
     };
 
     println!("[handle_game_ended] Updating winner");
-    let xp_to_next_level = XP_FOR_LEVEL[loser_mnstr.current_level as usize + 1];
-    let winner_xp_awarded = (xp_to_next_level as f64 / 4.0).floor() as i32;
-    let loser_xp_awarded = (xp_to_next_level as f64 / 8.0).floor() as i32;
-    let winner_coins_awarded = loser_mnstr.coins();
-    let loser_coins_awarded = 5;
+    let xp_to_next_level = loser_mnstr.experience_to_next_level();
+    let rewards = outcome.rewards(xp_to_next_level, loser_mnstr.coins());
+    // The awards below apply the event multipliers; the battle records what they credit
+    let multipliers = Multipliers::current();
+    let winner_xp_awarded = rewards.winner_xp;
+    let loser_xp_awarded = rewards.loser_xp;
+    let winner_coins_awarded = rewards.winner_coins;
+    let loser_coins_awarded = rewards.loser_coins;
+
+    battle.winner_id = Some(winner_user_id.clone());
+    battle.winner_mnstr_id = Some(winner_mnstr_id.clone());
+    // Persist the settlement so a player who has already disconnected can
+    // still look up the result afterwards
+    battle.outcome = Some(outcome.to_string());
+    battle.winner_xp_awarded = Some(multipliers.xp(winner_xp_awarded));
+    battle.winner_coins_awarded = Some(multipliers.coins(winner_coins_awarded));
+    battle.loser_xp_awarded = Some(multipliers.xp(loser_xp_awarded));
+    battle.loser_coins_awarded = Some(multipliers.coins(loser_coins_awarded));
+
+    println!("[handle_game_ended] Updating battle");
+    if let Some(error) = battle.update().await {
+        println!(
+            "[handle_escape_request] Failed to update battle: {:?}",
+            error
+        );
+        let error_queue = build_error(
+            Some(session_user_id.clone()),
+            user_name.clone(),
+            BattleQueueChannel::Battle,
+            BattleQueueAction::Error,
+            BattleQueueDataAction::Escape,
+            "Error updating battle".to_string(),
+        );
+        return Some(error_queue);
+    }
+
+    println!("[handle_game_ended] Deleting battle");
+    if let Some(error) = battle.delete().await {
+        println!("[handle_game_ended] Failed to delete battle: {:?}", error);
+        let error_queue = build_error(
+            Some(session_user_id.clone()),
+            user_name.clone(),
+            BattleQueueChannel::Battle,
+            BattleQueueAction::Error,
+            BattleQueueDataAction::Escape,
+            "Error deleting battle".to_string(),
+        );
+        return Some(error_queue);
+    }
 
     println!("[handle_game_ended] Updating winner xp");
     if let Some(error) = winner.update_xp(winner_xp_awarded).await {
@@ -1819,19 +3475,738 @@ async fn handle_game_ended(
         challenger_mnstrs: None,
         opponent_mnstrs: None,
         mnstr: None,
-        winner_xp_awarded: Some(winner_xp_awarded),
-        winner_coins_awarded: Some(winner_coins_awarded),
-        loser_coins_awarded: Some(loser_coins_awarded),
-        loser_xp_awarded: Some(loser_xp_awarded),
+        winner_xp_awarded: battle.winner_xp_awarded,
+        winner_coins_awarded: battle.winner_coins_awarded,
+        loser_coins_awarded: battle.loser_coins_awarded,
+        loser_xp_awarded: battle.loser_xp_awarded,
         turn_user_id: None,
         battle_log_data: None,
     };
 
     println!("[handle_game_ended] Updating battle queue");
-    queue.data.data = Some(serde_json::to_string(&battle_game_data).unwrap());
+    queue.data.set_game_data(&battle_game_data);
+    queue.data.user_id = Some(battle.challenger_id.clone());
+    queue.data.opponent_id = Some(battle.opponent_id.clone());
+    queue.data.action = BattleQueueDataAction::GameEnded;
+    queue.action = BattleQueueAction::GameEnded;
+    None
+}
+
+/// Records a draw on `battle` and returns each side's rewards, challenger
+/// first. Each side earns its share of the XP the other side's mnstr needs for
+/// its next level. There are no winner columns to fill in a draw, so the
+/// challenger's awards go in the winner columns and the opponent's in the loser
+/// columns.
+fn record_draw(
+    battle: &mut Battle,
+    challenger_mnstr: &Mnstr,
+    opponent_mnstr: &Mnstr,
+    multipliers: Multipliers,
+) -> (BattleRewards, BattleRewards) {
+    let challenger_rewards = BattleOutcome::Draw.rewards(
+        opponent_mnstr.experience_to_next_level(),
+        opponent_mnstr.coins(),
+    );
+    let opponent_rewards = BattleOutcome::Draw.rewards(
+        challenger_mnstr.experience_to_next_level(),
+        challenger_mnstr.coins(),
+    );
+
+    battle.winner_id = None;
+    battle.winner_mnstr_id = None;
+    battle.outcome = Some(BattleOutcome::Draw.to_string());
+    battle.winner_xp_awarded = Some(multipliers.xp(challenger_rewards.loser_xp));
+    battle.winner_coins_awarded = Some(multipliers.coins(challenger_rewards.loser_coins));
+    battle.loser_xp_awarded = Some(multipliers.xp(opponent_rewards.loser_xp));
+    battle.loser_coins_awarded = Some(multipliers.coins(opponent_rewards.loser_coins));
+    (challenger_rewards, opponent_rewards)
+}
+
+/// Pays one side of a settled battle: `xp` to the user and their mnstr, and
+/// `coins` to the user.
+async fn award_side(user_id: &String, mnstr: &mut Mnstr, xp: i32, coins: i32) -> Option<String> {
+    let mut user = match User::find_one(user_id.clone(), false).await {
+        Ok(user) => user,
+        Err(e) => {
+            println!("[award_side] Failed to find user: {:?}", e);
+            return Some("Error finding user".to_string());
+        }
+    };
+    if let Some(error) = user.update_xp(xp).await {
+        println!("[award_side] Failed to update user xp: {:?}", error);
+        return Some("Error updating user xp".to_string());
+    }
+    if coins != 0 {
+        if let Some(error) = user.add_coins(coins).await {
+            println!("[award_side] Failed to update user coins: {:?}", error);
+            return Some("Error updating user coins".to_string());
+        }
+    }
+    if let Some(error) = mnstr.update_xp(xp).await {
+        println!("[award_side] Failed to update mnstr xp: {:?}", error);
+        return Some("Error updating mnstr xp".to_string());
+    }
+    None
+}
+
+/// Pays one side of a draw and restores its mnstr's stats.
+async fn award_draw_side(
+    user_id: &String,
+    mnstr: &mut Mnstr,
+    rewards: &BattleRewards,
+) -> Option<String> {
+    if let Some(error) = award_side(user_id, mnstr, rewards.loser_xp, rewards.loser_coins).await {
+        return Some(error);
+    }
+
+    mnstr.current_defense = mnstr.max_defense;
+    mnstr.current_attack = mnstr.max_attack;
+    mnstr.current_intelligence = mnstr.max_intelligence;
+    mnstr.current_speed = mnstr.max_speed;
+    mnstr.current_magic = mnstr.max_magic;
+    mnstr.current_health = mnstr.max_health;
+    if let Some(error) = mnstr.update().await {
+        println!("[award_draw_side] Failed to update mnstr: {:?}", error);
+        return Some("Error updating mnstr".to_string());
+    }
+    None
+}
+
+/// Ends the game as a draw: no winner, both sides paid `DRAW_REWARDS` and the
+/// battle archived.
+async fn handle_draw(
+    queue: &mut BattleQueue,
+    session_user_id: &String,
+    user_name: &Option<String>,
+) -> Option<BattleQueue> {
+    println!("[handle_draw] Ending game in a draw");
+    let draw_error = |message: String| {
+        build_error(
+            Some(session_user_id.clone()),
+            user_name.clone(),
+            BattleQueueChannel::Battle,
+            BattleQueueAction::Error,
+            queue.data.action.clone(),
+            message,
+        )
+    };
+
+    if let Some(error) = handle_left(session_user_id).await {
+        return Some(draw_error(error));
+    }
+
+    let battle_game_data = match queue.data.game_data() {
+        Ok(game_data) => game_data,
+        Err(_) => return Some(draw_error("Invalid game data".to_string())),
+    };
+
+    println!("[handle_draw] Finding battle");
+    let mut battle = match Battle::find_one(battle_game_data.battle_id.clone().unwrap()).await {
+        Ok(battle) => battle,
+        Err(_) => return Some(draw_error("Error finding battle".to_string())),
+    };
+
+    println!("[handle_draw] Finding mnstrs");
+    let mut challenger_mnstr =
+        match Mnstr::find_one(battle.challenger_mnstr_id.clone().unwrap(), false).await {
+            Ok(mnstr) => mnstr,
+            Err(_) => return Some(draw_error("Error finding challenger mnstr".to_string())),
+        };
+    let mut opponent_mnstr =
+        match Mnstr::find_one(battle.opponent_mnstr_id.clone().unwrap(), false).await {
+            Ok(mnstr) => mnstr,
+            Err(_) => return Some(draw_error("Error finding opponent mnstr".to_string())),
+        };
+
+    let (challenger_rewards, opponent_rewards) = record_draw(
+        &mut battle,
+        &challenger_mnstr,
+        &opponent_mnstr,
+        Multipliers::current(),
+    );
+
+    println!("[handle_draw] Updating battle");
+    if let Some(error) = battle.update().await {
+        println!("[handle_draw] Failed to update battle: {:?}", error);
+        return Some(draw_error("Error updating battle".to_string()));
+    }
+
+    println!("[handle_draw] Deleting battle");
+    if let Some(error) = battle.delete().await {
+        println!("[handle_draw] Failed to delete battle: {:?}", error);
+        return Some(draw_error("Error deleting battle".to_string()));
+    }
+
+    println!("[handle_draw] Rewarding challenger");
+    if let Some(error) = award_draw_side(
+        &battle.challenger_id,
+        &mut challenger_mnstr,
+        &challenger_rewards,
+    )
+    .await
+    {
+        return Some(draw_error(error));
+    }
+
+    println!("[handle_draw] Rewarding opponent");
+    if let Some(error) =
+        award_draw_side(&battle.opponent_id, &mut opponent_mnstr, &opponent_rewards).await
+    {
+        return Some(draw_error(error));
+    }
+
+    let battle_game_data = BattleQueueGameData {
+        winner_id: None,
+        opponent_mnstr: Some(opponent_mnstr),
+        challenger_mnstr: Some(challenger_mnstr),
+        battle_id: Some(battle.id.clone()),
+        challenger_mnstrs: None,
+        opponent_mnstrs: None,
+        mnstr: None,
+        winner_xp_awarded: battle.winner_xp_awarded,
+        winner_coins_awarded: battle.winner_coins_awarded,
+        loser_coins_awarded: battle.loser_coins_awarded,
+        loser_xp_awarded: battle.loser_xp_awarded,
+        turn_user_id: None,
+        battle_log_data: None,
+    };
+
+    println!("[handle_draw] Updating battle queue");
+    queue.data.set_game_data(&battle_game_data);
     queue.data.user_id = Some(battle.challenger_id.clone());
     queue.data.opponent_id = Some(battle.opponent_id.clone());
     queue.data.action = BattleQueueDataAction::GameEnded;
     queue.action = BattleQueueAction::GameEnded;
     None
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::generated::mnstr_xp::XP_FOR_LEVEL;
+
+    #[test]
+    fn test_blocked_players_cannot_be_matched_or_challenged() {
+        let status = |user_id: &str| {
+            BattleStatus::new(
+                user_id.to_string(),
+                user_id.to_string(),
+                None,
+                None,
+                None,
+                BattleStatusState::InQueue,
+            )
+        };
+        let block = Block::new("blocker".to_string(), "blocked".to_string());
+        let list = vec![status("blocker"), status("blocked"), status("other")];
+
+        let listed = listable_players(
+            list.clone(),
+            &"blocker".to_string(),
+            &["blocked".to_string()],
+        );
+        let listed = listed
+            .iter()
+            .map(|item| item.user_id.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(listed, vec!["other"]);
+
+        // The blocked player can't find the blocker either
+        let listed = listable_players(list, &"blocked".to_string(), &["blocker".to_string()]);
+        let listed = listed
+            .iter()
+            .map(|item| item.user_id.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(listed, vec!["other"]);
+
+        // What `players_blocked` checks before a challenge or accept goes through
+        assert!(block.separates("blocker", "blocked"));
+        assert!(block.separates("blocked", "blocker"));
+        assert!(!block.separates("blocker", "other"));
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_knockouts_settle_once() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        // Stands in for the battle row's winner_id and the conditional UPDATE
+        let winner_id: Arc<Mutex<Option<String>>> = Arc::new(Mutex::new(None));
+        let settlements = Arc::new(AtomicUsize::new(0));
+
+        let knockout = |attacker: &'static str| {
+            let winner_id = winner_id.clone();
+            let settlements = settlements.clone();
+            rocket::tokio::spawn(async move {
+                let claim = async {
+                    let mut winner_id = winner_id.lock().await;
+                    if winner_id.is_some() {
+                        return Ok::<bool, anyhow::Error>(false);
+                    }
+                    *winner_id = Some(attacker.to_string());
+                    Ok(true)
+                };
+                let settle = async {
+                    settlements.fetch_add(1, Ordering::SeqCst);
+                };
+                settle_once(claim, settle).await.unwrap().is_some()
+            })
+        };
+
+        let (challenger, opponent) = futures::join!(knockout("challenger"), knockout("opponent"));
+        let settled = [challenger.unwrap(), opponent.unwrap()];
+
+        assert_eq!(settled.iter().filter(|settled| **settled).count(), 1);
+        assert_eq!(settlements.load(Ordering::SeqCst), 1);
+        assert!(winner_id.lock().await.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_dropped_pubsub_stream_is_resubscribed() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let resubscribes = Arc::new(AtomicUsize::new(0));
+        let (tx, mut rx) = rocket::tokio::sync::mpsc::channel::<String>(FORWARDER_CHANNEL_CAPACITY);
+
+        // The first stream ends after one message, as it would when Redis restarts
+        let dropped = futures::stream::iter(vec!["before".to_string()]).boxed();
+        let counter = resubscribes.clone();
+        let write_timeout = std::time::Duration::from_secs(1);
+        rocket::tokio::spawn(forward_payloads(dropped, tx, write_timeout, move || {
+            let attempt = counter.fetch_add(1, Ordering::SeqCst);
+            async move {
+                match attempt {
+                    // The first reconnect attempt fails and is retried
+                    0 => None,
+                    1 => Some(futures::stream::iter(vec!["after".to_string()]).boxed()),
+                    _ => Some(futures::stream::pending().boxed()),
+                }
+            }
+        }));
+
+        assert_eq!(rx.recv().await.as_deref(), Some("before"));
+        assert_eq!(rx.recv().await.as_deref(), Some("after"));
+        assert!(resubscribes.load(Ordering::SeqCst) >= 2);
+    }
+
+    #[tokio::test]
+    async fn test_idle_queued_player_is_removed_and_notified() {
+        let now = time::OffsetDateTime::now_utc();
+        let max_duration = time::Duration::minutes(10);
+        let queued = |user_id: &str, queued_for: time::Duration| {
+            let mut status = BattleStatus::new(
+                user_id.to_string(),
+                format!("Player {}", user_id),
+                None,
+                None,
+                None,
+                BattleStatusState::InQueue,
+            );
+            status.id = user_id.to_string();
+            status.updated_at = Some(now - queued_for);
+            status
+        };
+        let statuses = vec![
+            queued("idle", max_duration + time::Duration::minutes(1)),
+            queued("fresh", time::Duration::minutes(1)),
+        ];
+
+        let removed = Arc::new(Mutex::new(Vec::<String>::new()));
+        let notified = Arc::new(Mutex::new(Vec::<BattleQueue>::new()));
+        let count = sweep_idle_queue(
+            statuses,
+            now,
+            max_duration,
+            |status| {
+                let removed = removed.clone();
+                async move {
+                    removed.lock().await.push(status.id);
+                    true
+                }
+            },
+            |queue| {
+                let notified = notified.clone();
+                async move { notified.lock().await.push(queue) }
+            },
+        )
+        .await;
+
+        assert_eq!(count, 1);
+        assert_eq!(*removed.lock().await, vec!["idle".to_string()]);
+        let notified = notified.lock().await;
+        assert_eq!(notified.len(), 1);
+        assert!(matches!(notified[0].action, BattleQueueAction::Left));
+        assert!(matches!(notified[0].channel, BattleQueueChannel::Lobby));
+        assert_eq!(notified[0].data.user_id.as_deref(), Some("idle"));
+    }
+
+    #[tokio::test]
+    async fn test_stalled_consumer_is_disconnected_at_capacity() {
+        let capacity = 4;
+        let (tx, mut rx) = rocket::tokio::sync::mpsc::channel::<String>(capacity);
+        let flood = futures::stream::iter((0..1000).map(|i| i.to_string())).boxed();
+
+        // Nothing reads while the forwarder runs, like a client that stopped reading
+        let write_timeout = std::time::Duration::from_millis(10);
+        let forwarder =
+            rocket::tokio::spawn(forward_payloads(flood, tx, write_timeout, || async {
+                Some(futures::stream::pending().boxed())
+            }));
+        rocket::tokio::time::timeout(std::time::Duration::from_secs(5), forwarder)
+            .await
+            .expect("forwarder stops on overflow")
+            .unwrap();
+
+        let mut buffered = Vec::new();
+        while let Some(payload) = rx.recv().await {
+            buffered.push(payload);
+        }
+        assert_eq!(buffered, vec!["0", "1", "2", "3"]);
+    }
+
+    #[test]
+    fn test_malformed_ids_are_rejected() {
+        let queue = |opponent_id: &str, game_data: serde_json::Value| {
+            BattleQueue::new(
+                None,
+                BattleQueueChannel::Battle,
+                BattleQueueAction::MnstrChosen,
+                BattleQueueData::new(
+                    BattleQueueDataAction::MnstrChosen,
+                    None,
+                    None,
+                    Some(opponent_id.to_string()),
+                    None,
+                    None,
+                    None,
+                    Some(game_data.to_string()),
+                    None,
+                    None,
+                ),
+            )
+        };
+        let id = "0b6f0c4e-1f2a-4d6e-9a51-2f7f0b0c1d2e";
+
+        assert!(validate_queue_ids(&queue(id, serde_json::json!({ "battleId": id }))).is_ok());
+        assert!(
+            validate_queue_ids(&queue(id, serde_json::json!({ "battleId": "1 OR 1=1" }))).is_err()
+        );
+        assert!(
+            validate_queue_ids(&queue(
+                &"a".repeat(4096),
+                serde_json::json!({ "battleId": id })
+            ))
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_handled_actions_are_acked_to_the_sender() {
+        let user_id = "0b6f0c4e-1f2a-4d6e-9a51-2f7f0b0c1d2e".to_string();
+        let user_name = Some("Player".to_string());
+        let command = |data_action: BattleQueueDataAction| {
+            let queue = BattleQueue::new(
+                Some(user_id.clone()),
+                BattleQueueChannel::Lobby,
+                BattleQueueAction::InGameAction,
+                BattleQueueData::new(
+                    data_action,
+                    Some(user_id.clone()),
+                    None,
+                    None,
+                    None,
+                    None,
+                    None,
+                    None,
+                    None,
+                    None,
+                ),
+            );
+            let id = queue.data.id.clone();
+            let message = rocket_ws::Message::Text(serde_json::to_string(&queue).unwrap());
+            (Ok(message), id)
+        };
+
+        for data_action in [
+            BattleQueueDataAction::Connect,
+            BattleQueueDataAction::List,
+            BattleQueueDataAction::SortMnstrs(SortMnstrsInput::new(None, None)),
+            BattleQueueDataAction::Ready,
+            BattleQueueDataAction::Unready,
+            BattleQueueDataAction::Challenge,
+            BattleQueueDataAction::CancelChallenge,
+            BattleQueueDataAction::Accept,
+            BattleQueueDataAction::MnstrChosen,
+            BattleQueueDataAction::Rejoin,
+            BattleQueueDataAction::Attack,
+            BattleQueueDataAction::Defend,
+            BattleQueueDataAction::Magic,
+            BattleQueueDataAction::Escape,
+            BattleQueueDataAction::Surrender,
+            BattleQueueDataAction::Practice,
+            BattleQueueDataAction::Ping,
+        ] {
+            let (message, id) = command(data_action.clone());
+            let queue = build_battle_queue(message).unwrap();
+            let ack = build_ack(&queue, &user_id, &user_name);
+            assert!(matches!(ack.action, BattleQueueAction::Ack));
+            assert_eq!(ack.user_id.as_deref(), Some(user_id.as_str()));
+            assert_eq!(ack.data.id, id);
+            assert_eq!(
+                serde_json::to_value(&ack.data.action).unwrap(),
+                serde_json::to_value(&data_action).unwrap()
+            );
+            // Battle commands are acked on the channel they are routed to
+            assert_eq!(
+                matches!(ack.channel, BattleQueueChannel::Battle),
+                data_action.is_in_battle()
+            );
+
+            // The ack comes once the handler accepted the command, before
+            // its reply
+            let replies = acknowledged(&ack, Ok(vec!["reply".to_string()]));
+            assert_eq!(replies.len(), 2);
+            let acked: BattleQueue = serde_json::from_str(&replies[0]).unwrap();
+            assert!(matches!(acked.action, BattleQueueAction::Ack));
+            assert_eq!(acked.data.id, id);
+            assert_eq!(replies[1], "reply");
+        }
+
+        // Commands the handler rejects get their error, not an ack
+        let (message, _) = command(BattleQueueDataAction::Challenge);
+        let queue = build_battle_queue(message).unwrap();
+        let ack = build_ack(&queue, &user_id, &user_name);
+        assert_eq!(
+            acknowledged(&ack, Err(vec!["error".to_string()])),
+            vec!["error".to_string()]
+        );
+        assert!(acknowledged(&ack, Err(vec![])).is_empty());
+
+        let mut error = queue.clone();
+        error.action = BattleQueueAction::Error;
+        assert!(published(&error).is_err());
+        assert!(published(&queue).is_ok());
+
+        // Messages the server can't read are rejected before any handler runs
+        for text in ["not json", ""] {
+            let queue = build_battle_queue(Ok(rocket_ws::Message::Text(text.to_string())));
+            assert!(matches!(
+                queue.unwrap().data.action,
+                BattleQueueDataAction::Error
+            ));
+        }
+        let (bad_ids, _) = command(BattleQueueDataAction::Challenge);
+        let bad_ids = match bad_ids {
+            Ok(rocket_ws::Message::Text(text)) => {
+                rocket_ws::Message::Text(text.replace(&user_id, "1 OR 1=1"))
+            }
+            _ => unreachable!(),
+        };
+        let queue = build_battle_queue(Ok(bad_ids)).unwrap();
+        assert!(validate_queue_ids(&queue).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_battle_broadcasts_are_sequenced_per_battle() {
+        let counters = std::sync::Mutex::new(std::collections::HashMap::<String, i64>::new());
+        let next_seq = |battle_id: String| {
+            let mut counters = counters.lock().unwrap();
+            let seq = counters.entry(battle_id).or_insert(0);
+            *seq += 1;
+            std::future::ready(Some(*seq))
+        };
+        let broadcast = |battle_id: &str| {
+            battle_notice(
+                battle_id,
+                None,
+                &"player".to_string(),
+                BattleQueueAction::Paused,
+                BattleQueueDataAction::Paused,
+                "Player disconnected".to_string(),
+            )
+        };
+
+        let mut last = 0;
+        for _ in 0..5 {
+            let sequenced = sequence_queue(&broadcast("battle"), &next_seq).await;
+            let seq = sequenced.data.seq.unwrap();
+            assert!(seq > last);
+            last = seq;
+
+            // Another battle's broadcasts don't advance this one's
+            let other = sequence_queue(&broadcast("other"), &next_seq).await;
+            assert_eq!(other.data.seq, Some(seq));
+        }
+
+        let lobby = build_success(
+            None,
+            None,
+            BattleQueueChannel::Lobby,
+            BattleQueueAction::Joined,
+            BattleQueueDataAction::Connect,
+            "Joined".to_string(),
+        );
+        assert_eq!(sequence_queue(&lobby, &next_seq).await.data.seq, None);
+    }
+
+    #[test]
+    fn test_escaping_player_always_loses() {
+        let battle = Battle::new(
+            "challenger".to_string(),
+            "Challenger".to_string(),
+            "opponent".to_string(),
+            "Opponent".to_string(),
+        );
+
+        for claimed_winner in [None, Some("challenger"), Some("opponent")] {
+            let mut game_data: BattleQueueGameData = serde_json::from_value(serde_json::json!({
+                "battleId": "battle",
+                "winnerId": claimed_winner,
+            }))
+            .unwrap();
+
+            assert!(apply_forfeit_outcome(&mut game_data, &battle, "challenger"));
+            assert_eq!(game_data.winner_id.as_deref(), Some("opponent"));
+
+            assert!(apply_forfeit_outcome(&mut game_data, &battle, "opponent"));
+            assert_eq!(game_data.winner_id.as_deref(), Some("challenger"));
+        }
+
+        let mut game_data: BattleQueueGameData =
+            serde_json::from_value(serde_json::json!({ "winnerId": "intruder" })).unwrap();
+        assert!(!apply_forfeit_outcome(&mut game_data, &battle, "intruder"));
+    }
+
+    #[test]
+    fn test_surrender_settles_with_opponent_as_winner() {
+        let battle = Battle::new(
+            "challenger".to_string(),
+            "Challenger".to_string(),
+            "opponent".to_string(),
+            "Opponent".to_string(),
+        );
+        let mut game_data: BattleQueueGameData =
+            serde_json::from_value(serde_json::json!({ "winnerId": "challenger" })).unwrap();
+        assert!(apply_forfeit_outcome(&mut game_data, &battle, "challenger"));
+        assert_eq!(game_data.winner_id.as_deref(), Some("opponent"));
+
+        let surrendered = BattleOutcome::Surrendered.rewards(160, 12);
+        let escaped = BattleOutcome::Escaped.rewards(160, 12);
+        assert_eq!(surrendered.winner_xp, 40);
+        assert_eq!(surrendered.loser_xp, 30);
+        assert_eq!(surrendered.winner_coins, 12);
+        assert_eq!(surrendered.loser_coins, 8);
+        assert!(surrendered.loser_xp > escaped.loser_xp);
+        assert!(surrendered.loser_coins > escaped.loser_coins);
+    }
+
+    /// Stands in for the Redis hash of pending disconnects and the battle row,
+    /// with the timer and a rejoin racing to clear the same entry.
+    async fn disconnect_during_battle(rejoin_after: Option<u64>) -> (DisconnectResolution, Battle) {
+        let battle = Arc::new(Mutex::new(Battle::new(
+            "challenger".to_string(),
+            "Challenger".to_string(),
+            "opponent".to_string(),
+            "Opponent".to_string(),
+        )));
+        let pending = Arc::new(Mutex::new(vec!["challenger".to_string()]));
+        let take = |pending: Arc<Mutex<Vec<String>>>| async move {
+            let mut pending = pending.lock().await;
+            let before = pending.len();
+            pending.retain(|user_id| user_id != "challenger");
+            pending.len() < before
+        };
+
+        if let Some(millis) = rejoin_after {
+            let pending = pending.clone();
+            rocket::tokio::spawn(async move {
+                rocket::tokio::time::sleep(std::time::Duration::from_millis(millis)).await;
+                assert!(take(pending).await);
+            });
+        }
+
+        let resolution = resolve_disconnect(
+            std::time::Duration::from_millis(50),
+            || take(pending.clone()),
+            || {
+                let battle = battle.clone();
+                async move {
+                    let mut battle = battle.lock().await;
+                    battle.winner_id = battle.forfeit_winner_id("challenger");
+                }
+            },
+        )
+        .await;
+        let battle = battle.lock().await.clone();
+        (resolution, battle)
+    }
+
+    #[tokio::test]
+    async fn test_rejoining_within_grace_resumes_battle() {
+        let (resolution, battle) = disconnect_during_battle(Some(5)).await;
+
+        assert_eq!(resolution, DisconnectResolution::Resumed);
+        assert!(!battle.is_settled());
+    }
+
+    #[tokio::test]
+    async fn test_grace_timeout_awards_opponent() {
+        let (resolution, battle) = disconnect_during_battle(None).await;
+
+        assert_eq!(resolution, DisconnectResolution::Forfeited);
+        assert_eq!(battle.winner_id.as_deref(), Some("opponent"));
+        assert_eq!(
+            BattleOutcome::Disconnected.schedule(),
+            BattleOutcome::Escaped.schedule()
+        );
+    }
+
+    #[test]
+    fn test_simultaneous_knockout_is_recorded_as_draw() {
+        let mut battle = Battle::new(
+            "challenger".to_string(),
+            "Challenger".to_string(),
+            "opponent".to_string(),
+            "Opponent".to_string(),
+        );
+        let mut challenger_mnstr = Mnstr::new("challenger".to_string(), None, None, "qr-c".into());
+        let mut opponent_mnstr = Mnstr::new("opponent".to_string(), None, None, "qr-o".into());
+        challenger_mnstr.current_level = 2;
+        opponent_mnstr.current_health = 0;
+        assert_eq!(
+            knockout_result(&challenger_mnstr, &opponent_mnstr),
+            Some(KnockoutResult::Winner("challenger".to_string()))
+        );
+
+        challenger_mnstr.current_health = 0;
+        assert_eq!(
+            knockout_result(&challenger_mnstr, &opponent_mnstr),
+            Some(KnockoutResult::Draw)
+        );
+
+        let (challenger_rewards, opponent_rewards) = record_draw(
+            &mut battle,
+            &challenger_mnstr,
+            &opponent_mnstr,
+            Multipliers::default(),
+        );
+        assert!(battle.is_draw());
+        assert!(battle.is_settled());
+        assert_eq!(battle.winner_id, None);
+        assert_eq!(battle.outcome.as_deref(), Some("draw"));
+        // Each side gets an eighth of the XP the other side's mnstr needs
+        assert_eq!(challenger_rewards.loser_xp, XP_FOR_LEVEL[1] / 8);
+        assert_eq!(opponent_rewards.loser_xp, XP_FOR_LEVEL[3] / 8);
+        assert_eq!(challenger_rewards.winner_coins, 0);
+        assert_eq!(battle.winner_xp_awarded, Some(XP_FOR_LEVEL[1] / 8));
+        assert_eq!(battle.loser_xp_awarded, Some(XP_FOR_LEVEL[3] / 8));
+        assert_eq!(battle.winner_coins_awarded, Some(5));
+        assert_eq!(battle.loser_coins_awarded, Some(5));
+
+        battle.archived_at = Some(time::OffsetDateTime::now_utc());
+        let challenger_result = battle.result_for("challenger").unwrap();
+        let opponent_result = battle.result_for("opponent").unwrap();
+        assert!(!challenger_result.won && !opponent_result.won);
+        assert_eq!(challenger_result.xp_awarded, Some(XP_FOR_LEVEL[1] / 8));
+        assert_eq!(opponent_result.xp_awarded, Some(XP_FOR_LEVEL[3] / 8));
+    }
+}
@@ -1,10 +1,15 @@
+use juniper::GraphQLObject;
 use rocket::serde;
 use serde::{Deserialize, Serialize};
 use time::OffsetDateTime;
 use uuid::Uuid;
 
 use crate::{
-    models::mnstr::{Mnstr, MnstrOrderBy, MnstrOrderDirection},
+    battle::helpers::BattleRng,
+    models::{
+        battle_log::BattleLogAction,
+        mnstr::{Mnstr, MnstrOrderBy, MnstrOrderDirection},
+    },
     utils::time::{deserialize_offset_date_time, serialize_offset_date_time},
 };
 
@@ -24,6 +29,41 @@ impl std::fmt::Display for BattleQueueChannel {
     }
 }
 
+/// Redis channel shared by every connection in the battle queue.
+pub const LOBBY_PUBSUB_CHANNEL: &str = "lobby";
+
+/// Redis channel carrying a single battle's traffic.
+pub fn battle_pubsub_channel(battle_id: &str) -> String {
+    format!("battle:{}", battle_id)
+}
+
+/// Redis hash of a battle's players who dropped and may still rejoin, keyed by
+/// user id. The battle is paused while it has any entries.
+pub fn battle_disconnects_key(battle_id: &str) -> String {
+    format!("battle_disconnects:{}", battle_id)
+}
+
+/// Redis counter behind a battle's broadcast sequence numbers.
+pub fn battle_seq_key(battle_id: &str) -> String {
+    format!("battle:{}:seq", battle_id)
+}
+
+/// How long a battle's sequence counter is kept after its first broadcast.
+pub const BATTLE_SEQ_TTL_SECONDS: i64 = 24 * 60 * 60;
+
+/// Used when `DISCONNECT_GRACE_SECONDS` isn't set.
+pub const DEFAULT_DISCONNECT_GRACE_SECONDS: u64 = 60;
+
+/// How long a player who dropped mid-battle has to rejoin before their
+/// opponent is declared the winner.
+pub fn disconnect_grace() -> std::time::Duration {
+    let seconds = std::env::var("DISCONNECT_GRACE_SECONDS")
+        .ok()
+        .and_then(|seconds| seconds.parse().ok())
+        .unwrap_or(DEFAULT_DISCONNECT_GRACE_SECONDS);
+    std::time::Duration::from_secs(seconds)
+}
+
 impl From<String> for BattleQueueChannel {
     fn from(value: String) -> Self {
         match value.as_str() {
@@ -49,6 +89,7 @@ pub enum BattleQueueAction {
     Watching,
     List,
     Challenge,
+    ChallengeCancelled,
     Accept,
     Reject,
     Ping,
@@ -62,6 +103,12 @@ pub enum BattleQueueAction {
     Defend,
     Magic,
     Escape,
+    Surrender,
+    Paused,
+    Resumed,
+    /// Sent back to the client that sent a command once the server has taken
+    /// it, with the command's data action and id.
+    Ack,
 }
 
 impl std::fmt::Display for BattleQueueAction {
@@ -79,6 +126,7 @@ impl std::fmt::Display for BattleQueueAction {
             BattleQueueAction::Watching => write!(f, "watching"),
             BattleQueueAction::List => write!(f, "list"),
             BattleQueueAction::Challenge => write!(f, "challenge"),
+            BattleQueueAction::ChallengeCancelled => write!(f, "challengeCancelled"),
             BattleQueueAction::Accept => write!(f, "accept"),
             BattleQueueAction::Reject => write!(f, "reject"),
             BattleQueueAction::Ping => write!(f, "ping"),
@@ -92,6 +140,10 @@ impl std::fmt::Display for BattleQueueAction {
             BattleQueueAction::Defend => write!(f, "defend"),
             BattleQueueAction::Magic => write!(f, "magic"),
             BattleQueueAction::Escape => write!(f, "escape"),
+            BattleQueueAction::Surrender => write!(f, "surrender"),
+            BattleQueueAction::Paused => write!(f, "paused"),
+            BattleQueueAction::Resumed => write!(f, "resumed"),
+            BattleQueueAction::Ack => write!(f, "ack"),
         }
     }
 }
@@ -111,6 +163,7 @@ impl From<String> for BattleQueueAction {
             "watching" => BattleQueueAction::Watching,
             "list" => BattleQueueAction::List,
             "challenge" => BattleQueueAction::Challenge,
+            "challengeCancelled" => BattleQueueAction::ChallengeCancelled,
             "accept" => BattleQueueAction::Accept,
             "reject" => BattleQueueAction::Reject,
             "ping" => BattleQueueAction::Ping,
@@ -123,6 +176,10 @@ impl From<String> for BattleQueueAction {
             "defend" => BattleQueueAction::Defend,
             "magic" => BattleQueueAction::Magic,
             "escape" => BattleQueueAction::Escape,
+            "surrender" => BattleQueueAction::Surrender,
+            "paused" => BattleQueueAction::Paused,
+            "resumed" => BattleQueueAction::Resumed,
+            "ack" => BattleQueueAction::Ack,
             _ => BattleQueueAction::Joined,
         }
     }
@@ -175,6 +232,47 @@ impl BattleQueue {
             archived_at: None,
         }
     }
+
+    /// The battle this message belongs to, read from its game data.
+    pub fn battle_id(&self) -> Option<String> {
+        self.data.game_data().ok()?.battle_id
+    }
+
+    /// Battle messages go to their battle's channel; everything else, including
+    /// the hand-off that tells both players a battle exists, goes to the lobby.
+    pub fn pubsub_channel(&self) -> String {
+        match (&self.channel, self.battle_id()) {
+            (BattleQueueChannel::Battle, Some(battle_id)) => battle_pubsub_channel(&battle_id),
+            _ => LOBBY_PUBSUB_CHANNEL.to_string(),
+        }
+    }
+
+    pub fn involves(&self, user_id: &str) -> bool {
+        self.data.user_id.as_deref() == Some(user_id)
+            || self.data.opponent_id.as_deref() == Some(user_id)
+    }
+
+    /// How a connection for `user_id` should change its battle subscription
+    /// after receiving this message.
+    pub fn battle_channel_change(&self, user_id: &str) -> Option<BattleChannelChange> {
+        if !self.involves(user_id) {
+            return None;
+        }
+        let battle_id = self.battle_id()?;
+        match self.action {
+            BattleQueueAction::GameStarted | BattleQueueAction::Rejoined => {
+                Some(BattleChannelChange::Join(battle_id))
+            }
+            BattleQueueAction::GameEnded => Some(BattleChannelChange::Leave(battle_id)),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BattleChannelChange {
+    Join(String),
+    Leave(String),
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -209,6 +307,7 @@ pub enum BattleQueueDataAction {
     List,
     Error,
     Challenge,
+    CancelChallenge,
     Accept,
     Reject,
     GameStarted,
@@ -221,9 +320,31 @@ pub enum BattleQueueDataAction {
     Defend,
     Magic,
     Escape,
+    Surrender,
+    Paused,
+    Resumed,
+    /// Starts a practice battle against the server.
+    Practice,
     SortMnstrs(SortMnstrsInput),
 }
 
+impl BattleQueueDataAction {
+    /// Actions that only concern the players of a single battle.
+    pub fn is_in_battle(&self) -> bool {
+        matches!(
+            self,
+            BattleQueueDataAction::MnstrChosen
+                | BattleQueueDataAction::InGameAction
+                | BattleQueueDataAction::Rejoin
+                | BattleQueueDataAction::Attack
+                | BattleQueueDataAction::Defend
+                | BattleQueueDataAction::Magic
+                | BattleQueueDataAction::Escape
+                | BattleQueueDataAction::Surrender
+        )
+    }
+}
+
 impl From<String> for BattleQueueDataAction {
     fn from(value: String) -> Self {
         match value.as_str() {
@@ -236,6 +357,7 @@ impl From<String> for BattleQueueDataAction {
             "list" => BattleQueueDataAction::List,
             "error" => BattleQueueDataAction::Error,
             "challenge" => BattleQueueDataAction::Challenge,
+            "cancelChallenge" => BattleQueueDataAction::CancelChallenge,
             "accept" => BattleQueueDataAction::Accept,
             "reject" => BattleQueueDataAction::Reject,
             "ping" => BattleQueueDataAction::Ping,
@@ -249,6 +371,10 @@ impl From<String> for BattleQueueDataAction {
             "defend" => BattleQueueDataAction::Defend,
             "magic" => BattleQueueDataAction::Magic,
             "escape" => BattleQueueDataAction::Escape,
+            "surrender" => BattleQueueDataAction::Surrender,
+            "paused" => BattleQueueDataAction::Paused,
+            "resumed" => BattleQueueDataAction::Resumed,
+            "practice" => BattleQueueDataAction::Practice,
             _ => BattleQueueDataAction::Connect,
         }
     }
@@ -267,7 +393,13 @@ pub struct BattleQueueData {
     pub opponent_mnstr_id: Option<String>,
     pub data: Option<String>,
     pub error: Option<String>,
+    /// Machine-readable reason for `error`, such as `SESSION_EXPIRED`.
+    pub code: Option<String>,
     pub message: Option<String>,
+    /// Orders a battle's broadcasts, counting up from 1 per battle. Clients
+    /// drop frames with a number they have already seen. Unset on lobby
+    /// frames.
+    pub seq: Option<i64>,
 }
 
 impl BattleQueueData {
@@ -294,7 +426,36 @@ impl BattleQueueData {
             opponent_mnstr_id,
             data,
             error,
+            code: None,
             message,
+            seq: None,
+        }
+    }
+
+    /// The game data packed into `data`.
+    ///
+    /// # Returns
+    ///
+    /// Returns an error when there is no game data or it doesn't parse.
+    pub fn game_data(&self) -> Result<BattleQueueGameData, anyhow::Error> {
+        let raw_game_data = match &self.data {
+            Some(raw_game_data) => raw_game_data,
+            None => return Err(anyhow::anyhow!("Missing game data")),
+        };
+        match serde_json::from_str::<BattleQueueGameData>(raw_game_data) {
+            Ok(game_data) => Ok(game_data),
+            Err(e) => Err(anyhow::anyhow!("Invalid game data: {}", e)),
+        }
+    }
+
+    /// Packs `game_data` into `data`, replacing whatever was there.
+    pub fn set_game_data(&mut self, game_data: &BattleQueueGameData) {
+        match serde_json::to_string(game_data) {
+            Ok(raw_game_data) => self.data = Some(raw_game_data),
+            Err(e) => println!(
+                "[BattleQueueData::set_game_data] Failed to serialize game data: {:?}",
+                e
+            ),
         }
     }
 }
@@ -312,13 +473,15 @@ impl From<String> for BattleQueueData {
             opponent_mnstr_id: None,
             data: None,
             error: Some("Invalid data".to_string()),
+            code: None,
             message: None,
+            seq: None,
         });
         data
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, GraphQLObject)]
 #[serde(rename_all = "camelCase")]
 pub struct BattleQueueGameData {
     pub battle_id: Option<String>,
@@ -336,11 +499,315 @@ pub struct BattleQueueGameData {
     pub battle_log_data: Option<BattleLogData>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, GraphQLObject)]
 #[serde(rename_all = "camelCase")]
 pub struct BattleLogData {
     pub missed: Option<bool>,
     pub hit: Option<bool>,
     pub damage: Option<i32>,
     pub defense: Option<i32>,
+    pub crit: Option<bool>,
+}
+
+/// A move a mnstr can make on its turn, played the same way in ranked and
+/// practice battles.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CombatMove {
+    Attack,
+    Magic,
+    Defend,
+}
+
+impl CombatMove {
+    pub fn from_action(action: &BattleQueueDataAction) -> Option<Self> {
+        match action {
+            BattleQueueDataAction::Attack => Some(CombatMove::Attack),
+            BattleQueueDataAction::Magic => Some(CombatMove::Magic),
+            BattleQueueDataAction::Defend => Some(CombatMove::Defend),
+            _ => None,
+        }
+    }
+
+    /// The message and data actions announcing the move.
+    pub fn actions(&self) -> (BattleQueueAction, BattleQueueDataAction) {
+        match self {
+            CombatMove::Attack => (BattleQueueAction::Attack, BattleQueueDataAction::Attack),
+            CombatMove::Magic => (BattleQueueAction::Magic, BattleQueueDataAction::Magic),
+            CombatMove::Defend => (BattleQueueAction::Defend, BattleQueueDataAction::Defend),
+        }
+    }
+
+    /// Plays the move for `actor` against `target` and returns what to log.
+    /// Defending only touches `actor`.
+    pub fn play(
+        &self,
+        actor: &mut Mnstr,
+        target: &mut Mnstr,
+        rng: &mut BattleRng,
+    ) -> (BattleLogAction, BattleLogData) {
+        let mut battle_log_data = BattleLogData {
+            missed: None,
+            hit: None,
+            damage: None,
+            defense: None,
+            crit: None,
+        };
+        let hit = match self {
+            CombatMove::Attack => crate::battle::physical::attack(actor, target, rng),
+            CombatMove::Magic => crate::battle::magic::attack(actor, target, rng),
+            CombatMove::Defend => {
+                battle_log_data.defense = Some(crate::battle::defend::rest(actor, rng));
+                return (BattleLogAction::Defended, battle_log_data);
+            }
+        };
+        if let CombatMove::Attack = self {
+            // Stats bottom out at zero, which `stat_modifier` treats as no bonus
+            actor.current_attack = (actor.current_attack - 1).max(0);
+            actor.current_speed = (actor.current_speed - 1).max(0);
+            target.current_defense = (target.current_defense - 1).max(0);
+            target.current_intelligence = (target.current_intelligence - 1).max(0);
+        }
+        match hit {
+            (true, damage) => {
+                battle_log_data.hit = Some(true);
+                battle_log_data.damage = Some(damage.amount);
+                battle_log_data.crit = Some(damage.crit);
+                (BattleLogAction::Hit, battle_log_data)
+            }
+            (false, _) => {
+                battle_log_data.missed = Some(true);
+                (BattleLogAction::Missed, battle_log_data)
+            }
+        }
+    }
+}
+
+/// How a battle ended, which decides the reward schedule applied.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BattleOutcome {
+    Knockout,
+    Escaped,
+    Surrendered,
+    Disconnected,
+    Draw,
+}
+
+impl std::fmt::Display for BattleOutcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BattleOutcome::Knockout => write!(f, "knockout"),
+            BattleOutcome::Escaped => write!(f, "escaped"),
+            BattleOutcome::Surrendered => write!(f, "surrendered"),
+            BattleOutcome::Disconnected => write!(f, "disconnected"),
+            BattleOutcome::Draw => write!(f, "draw"),
+        }
+    }
+}
+
+/// Rewards as fractions of the XP the loser's mnstr needs for its next level.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RewardSchedule {
+    pub winner_xp_share: f64,
+    pub loser_xp_share: f64,
+    pub loser_coins: i32,
+}
+
+pub const KNOCKOUT_REWARDS: RewardSchedule = RewardSchedule {
+    winner_xp_share: 0.25,
+    loser_xp_share: 0.125,
+    loser_coins: 5,
+};
+
+pub const ESCAPE_REWARDS: RewardSchedule = KNOCKOUT_REWARDS;
+
+/// Failing to rejoin within the grace period counts as escaping.
+pub const DISCONNECT_REWARDS: RewardSchedule = ESCAPE_REWARDS;
+
+/// Surrendering is penalized less than disconnecting mid-battle.
+pub const SURRENDER_REWARDS: RewardSchedule = RewardSchedule {
+    winner_xp_share: 0.25,
+    loser_xp_share: 0.1875,
+    loser_coins: 8,
+};
+
+/// Neither side wins a draw, so both are paid the loser's share. Between them
+/// they earn the XP a knockout winner alone would have.
+pub const DRAW_REWARDS: RewardSchedule = RewardSchedule {
+    winner_xp_share: 0.0,
+    loser_xp_share: 0.125,
+    loser_coins: 5,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BattleRewards {
+    pub winner_xp: i32,
+    pub loser_xp: i32,
+    pub winner_coins: i32,
+    pub loser_coins: i32,
+}
+
+impl BattleOutcome {
+    pub fn schedule(&self) -> RewardSchedule {
+        match self {
+            BattleOutcome::Knockout => KNOCKOUT_REWARDS,
+            BattleOutcome::Escaped => ESCAPE_REWARDS,
+            BattleOutcome::Surrendered => SURRENDER_REWARDS,
+            BattleOutcome::Disconnected => DISCONNECT_REWARDS,
+            BattleOutcome::Draw => DRAW_REWARDS,
+        }
+    }
+
+    pub fn rewards(&self, xp_to_next_level: i32, loser_mnstr_coins: i32) -> BattleRewards {
+        let schedule = self.schedule();
+        BattleRewards {
+            winner_xp: (xp_to_next_level as f64 * schedule.winner_xp_share).floor() as i32,
+            loser_xp: (xp_to_next_level as f64 * schedule.loser_xp_share).floor() as i32,
+            // Nobody takes the other side's coins in a draw
+            winner_coins: match self {
+                BattleOutcome::Draw => 0,
+                _ => loser_mnstr_coins,
+            },
+            loser_coins: schedule.loser_coins,
+        }
+    }
+}
+
+/// Who a knockout goes to once at least one mnstr is down.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KnockoutResult {
+    Winner(String),
+    Draw,
+}
+
+/// Decides a knockout from both mnstrs' health after an action. When both are
+/// down the one left with more health wins and equal health is a draw, so the
+/// result never depends on who acted.
+pub fn knockout_result(challenger: &Mnstr, opponent: &Mnstr) -> Option<KnockoutResult> {
+    match (challenger.current_health <= 0, opponent.current_health <= 0) {
+        (false, false) => None,
+        (true, false) => Some(KnockoutResult::Winner(opponent.user_id.clone())),
+        (false, true) => Some(KnockoutResult::Winner(challenger.user_id.clone())),
+        (true, true) => Some(
+            match challenger.current_health.cmp(&opponent.current_health) {
+                std::cmp::Ordering::Greater => KnockoutResult::Winner(challenger.user_id.clone()),
+                std::cmp::Ordering::Less => KnockoutResult::Winner(opponent.user_id.clone()),
+                std::cmp::Ordering::Equal => KnockoutResult::Draw,
+            },
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn battle_message(battle_id: &str, user_id: &str, action: BattleQueueAction) -> BattleQueue {
+        let data = BattleQueueData::new(
+            BattleQueueDataAction::Attack,
+            Some(user_id.to_string()),
+            None,
+            Some("opponent".to_string()),
+            None,
+            None,
+            None,
+            Some(serde_json::json!({ "battleId": battle_id }).to_string()),
+            None,
+            None,
+        );
+        BattleQueue::new(
+            Some(user_id.to_string()),
+            BattleQueueChannel::Battle,
+            action,
+            data,
+        )
+    }
+
+    #[test]
+    fn test_battle_messages_stay_on_their_battle_channel() {
+        // A connection follows the lobby plus the battle it was handed off to
+        let handoff = battle_message("battle-b", "player-b", BattleQueueAction::GameStarted);
+        let mut subscribed = vec![LOBBY_PUBSUB_CHANNEL.to_string()];
+        match handoff.battle_channel_change("player-b") {
+            Some(BattleChannelChange::Join(battle_id)) => {
+                subscribed.push(battle_pubsub_channel(&battle_id))
+            }
+            change => panic!("unexpected change: {:?}", change),
+        }
+
+        let other_battle = battle_message("battle-a", "player-a", BattleQueueAction::Attack);
+        assert_eq!(other_battle.pubsub_channel(), "battle:battle-a");
+        assert!(!subscribed.contains(&other_battle.pubsub_channel()));
+        assert_eq!(other_battle.battle_channel_change("player-b"), None);
+
+        let own_battle = battle_message("battle-b", "player-b", BattleQueueAction::Attack);
+        assert!(subscribed.contains(&own_battle.pubsub_channel()));
+
+        let mut lobby = own_battle.clone();
+        lobby.channel = BattleQueueChannel::Lobby;
+        assert_eq!(lobby.pubsub_channel(), LOBBY_PUBSUB_CHANNEL);
+
+        let ended = battle_message("battle-b", "player-b", BattleQueueAction::GameEnded);
+        assert_eq!(
+            ended.battle_channel_change("opponent"),
+            Some(BattleChannelChange::Leave("battle-b".to_string()))
+        );
+    }
+    fn game_data() -> BattleQueueGameData {
+        BattleQueueGameData {
+            battle_id: Some("battle".to_string()),
+            challenger_mnstr: Some(Mnstr::new(
+                "challenger".to_string(),
+                None,
+                None,
+                "qr-challenger".to_string(),
+            )),
+            challenger_mnstrs: None,
+            opponent_mnstr: None,
+            opponent_mnstrs: None,
+            mnstr: None,
+            winner_id: None,
+            winner_xp_awarded: Some(12),
+            winner_coins_awarded: None,
+            loser_xp_awarded: None,
+            loser_coins_awarded: None,
+            turn_user_id: Some("challenger".to_string()),
+            battle_log_data: None,
+        }
+    }
+
+    #[test]
+    fn test_game_data_round_trips() {
+        let mut message = battle_message("battle", "challenger", BattleQueueAction::Attack);
+        let mut data = game_data();
+        message.data.set_game_data(&data);
+
+        let read = message.data.game_data().unwrap();
+        assert_eq!(read.battle_id, data.battle_id);
+        assert_eq!(read.turn_user_id, data.turn_user_id);
+        assert_eq!(read.winner_xp_awarded, Some(12));
+        assert_eq!(
+            read.challenger_mnstr.map(|mnstr| mnstr.mnstr_qr_code),
+            Some("qr-challenger".to_string())
+        );
+        assert_eq!(message.battle_id(), Some("battle".to_string()));
+
+        // Setting again replaces the previous game data
+        data.battle_id = Some("rematch".to_string());
+        message.data.set_game_data(&data);
+        assert_eq!(
+            message.data.game_data().unwrap().battle_id,
+            Some("rematch".to_string())
+        );
+    }
+
+    #[test]
+    fn test_missing_or_invalid_game_data_is_an_error() {
+        let mut message = battle_message("battle", "challenger", BattleQueueAction::Attack);
+        message.data.data = Some("{\"battleId\": ".to_string());
+        assert!(message.data.game_data().is_err());
+        assert_eq!(message.battle_id(), None);
+
+        message.data.data = None;
+        assert!(message.data.game_data().is_err());
+    }
 }
@@ -0,0 +1,256 @@
+//! Practice battles against a mnstr the server plays.
+//!
+//! Nothing about a practice battle is stored. The player's mnstr fights as a
+//! copy at full stats against an opponent generated to match it, and the
+//! battle lives on the player's connection until one side is knocked out.
+//! Both sides play their moves with `CombatMove`, like ranked battles. A won
+//! practice battle pays a share of the knockout XP and no coins, at most once
+//! per `PRACTICE_REWARD_COOLDOWN_MINUTES`; a lost one pays nothing.
+
+use uuid::Uuid;
+
+use crate::{
+    battle::helpers::BattleRng,
+    models::mnstr::Mnstr,
+    websocket::battle_queue::models::{
+        BattleLogData, BattleOutcome, BattleQueueGameData, CombatMove, KnockoutResult,
+        knockout_result,
+    },
+};
+
+/// The nil UUID, which no user has, stands in for the server's side.
+pub const PRACTICE_OPPONENT_ID: &str = "00000000-0000-0000-0000-000000000000";
+
+pub const PRACTICE_OPPONENT_NAME: &str = "Practice Bot";
+
+/// The generated opponent's stats as a percentage of the player's mnstr's.
+pub const PRACTICE_OPPONENT_STAT_PERCENT: i32 = 90;
+
+/// The share of the XP a ranked knockout pays that a practice battle pays.
+pub const PRACTICE_XP_SHARE: f64 = 0.25;
+
+/// How long after a paid practice win the next one pays nothing.
+pub const PRACTICE_REWARD_COOLDOWN_MINUTES: i64 = 30;
+
+pub const PRACTICE_IN_PROGRESS_ERROR: &str = "Already in a practice battle";
+
+/// An opponent for `player` at the same level, with each stat
+/// `PRACTICE_OPPONENT_STAT_PERCENT` of the player's maximum.
+pub fn practice_opponent(player: &Mnstr) -> Mnstr {
+    let mut opponent = Mnstr::new(
+        PRACTICE_OPPONENT_ID.to_string(),
+        Some(PRACTICE_OPPONENT_NAME.to_string()),
+        None,
+        "practice".to_string(),
+    );
+    opponent.id = Uuid::new_v4().to_string();
+    opponent.current_level = player.current_level;
+
+    let scale = |stat: i32| (stat * PRACTICE_OPPONENT_STAT_PERCENT / 100).max(1);
+    opponent.max_health = scale(player.max_health);
+    opponent.max_attack = scale(player.max_attack);
+    opponent.max_defense = scale(player.max_defense);
+    opponent.max_speed = scale(player.max_speed);
+    opponent.max_intelligence = scale(player.max_intelligence);
+    opponent.max_magic = scale(player.max_magic);
+    opponent.show_at_rest();
+    opponent.update_experience_to_next_level();
+    opponent
+}
+
+/// The server's move: rest once its defense is worn below a quarter, otherwise
+/// attack with whichever of magic and attack is stronger right now.
+pub fn opponent_move(opponent: &Mnstr) -> CombatMove {
+    if opponent.current_defense < opponent.max_defense / 4 {
+        CombatMove::Defend
+    } else if opponent.current_magic > opponent.current_attack {
+        CombatMove::Magic
+    } else {
+        CombatMove::Attack
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PracticeBattle {
+    pub id: String,
+    pub seed: i64,
+    pub turn: i64,
+    pub player: Mnstr,
+    pub opponent: Mnstr,
+}
+
+impl PracticeBattle {
+    pub fn new(player: &Mnstr, seed: i64) -> Self {
+        let mut player = player.clone();
+        player.show_at_rest();
+        let opponent = practice_opponent(&player);
+        Self {
+            id: Uuid::new_v4().to_string(),
+            seed,
+            turn: 0,
+            player,
+            opponent,
+        }
+    }
+
+    fn next_rng(&mut self) -> BattleRng {
+        self.turn += 1;
+        BattleRng::for_turn(self.seed, self.turn)
+    }
+
+    /// Plays the player's move.
+    ///
+    /// # Returns
+    ///
+    /// Returns an error if the battle is already over.
+    pub fn play_player_move(
+        &mut self,
+        player_move: CombatMove,
+    ) -> Result<BattleLogData, anyhow::Error> {
+        if self.result().is_some() {
+            return Err(anyhow::anyhow!("Battle already settled"));
+        }
+        let mut rng = self.next_rng();
+        let (_, battle_log_data) = player_move.play(&mut self.player, &mut self.opponent, &mut rng);
+        Ok(battle_log_data)
+    }
+
+    /// Plays the server's reply, or nothing once the battle is over.
+    pub fn play_opponent_move(&mut self) -> Option<(CombatMove, BattleLogData)> {
+        if self.result().is_some() {
+            return None;
+        }
+        let reply = opponent_move(&self.opponent);
+        let mut rng = self.next_rng();
+        let (_, battle_log_data) = reply.play(&mut self.opponent, &mut self.player, &mut rng);
+        Some((reply, battle_log_data))
+    }
+
+    /// `None` while both mnstrs stand.
+    pub fn result(&self) -> Option<KnockoutResult> {
+        knockout_result(&self.player, &self.opponent)
+    }
+
+    /// The XP the player earns once the battle is over: `PRACTICE_XP_SHARE` of
+    /// what the same knockout would pay in a ranked battle for a win, and
+    /// nothing for a loss or a draw.
+    pub fn player_xp(&self) -> Option<i32> {
+        match self.result()? {
+            KnockoutResult::Winner(winner_id) if winner_id == self.player.user_id => {
+                let rewards =
+                    BattleOutcome::Knockout.rewards(self.opponent.experience_to_next_level(), 0);
+                Some((rewards.winner_xp as f64 * PRACTICE_XP_SHARE).floor() as i32)
+            }
+            KnockoutResult::Winner(_) | KnockoutResult::Draw => Some(0),
+        }
+    }
+
+    /// The battle as clients see it, with the player as the challenger.
+    pub fn game_data(&self, battle_log_data: Option<BattleLogData>) -> BattleQueueGameData {
+        let winner_id = match self.result() {
+            Some(KnockoutResult::Winner(winner_id)) => Some(winner_id),
+            _ => None,
+        };
+        BattleQueueGameData {
+            battle_id: Some(self.id.clone()),
+            challenger_mnstr: Some(self.player.clone()),
+            challenger_mnstrs: None,
+            opponent_mnstr: Some(self.opponent.clone()),
+            opponent_mnstrs: None,
+            mnstr: None,
+            winner_id,
+            winner_xp_awarded: None,
+            winner_coins_awarded: None,
+            loser_xp_awarded: None,
+            loser_coins_awarded: None,
+            turn_user_id: Some(self.player.user_id.clone()),
+            battle_log_data,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn player_mnstr() -> Mnstr {
+        let mut mnstr = Mnstr::new("player".to_string(), None, None, "qr".to_string());
+        mnstr.id = Uuid::new_v4().to_string();
+        mnstr.current_level = 4;
+        mnstr.max_health = 40;
+        mnstr.max_attack = 30;
+        mnstr.current_health = 1;
+        mnstr
+    }
+
+    #[test]
+    fn test_opponent_is_scaled_to_the_player() {
+        let player = player_mnstr();
+        let opponent = practice_opponent(&player);
+        assert_eq!(opponent.user_id, PRACTICE_OPPONENT_ID);
+        assert_eq!(opponent.current_level, player.current_level);
+        assert_eq!(opponent.max_health, 36);
+        assert_eq!(opponent.max_attack, 27);
+        assert_eq!(opponent.current_health, opponent.max_health);
+    }
+
+    #[test]
+    fn test_opponent_rests_when_its_defense_is_worn_down() {
+        let mut opponent = practice_opponent(&player_mnstr());
+        assert_ne!(opponent_move(&opponent), CombatMove::Defend);
+
+        opponent.current_defense = 0;
+        assert_eq!(opponent_move(&opponent), CombatMove::Defend);
+    }
+
+    #[test]
+    fn test_lost_practice_battle_pays_nothing() {
+        let mut battle = PracticeBattle::new(&player_mnstr(), 7);
+        assert!(battle.player_xp().is_none());
+
+        battle.player.current_health = 0;
+        assert_eq!(battle.player_xp(), Some(0));
+
+        battle.player.current_health = battle.player.max_health;
+        battle.opponent.current_health = 0;
+        assert!(battle.player_xp().unwrap() > 0);
+    }
+
+    #[test]
+    fn test_practice_battle_resolves_with_the_server_taking_turns() {
+        let player = player_mnstr();
+        let mut battle = PracticeBattle::new(&player, 7_301_044_221);
+        // The player's mnstr fights at full stats, whatever it was left with
+        assert_eq!(battle.player.current_health, battle.player.max_health);
+
+        let mut replies = 0;
+        for _ in 0..1_000 {
+            battle.play_player_move(CombatMove::Attack).unwrap();
+            if battle.play_opponent_move().is_some() {
+                replies += 1;
+            }
+            if battle.result().is_some() {
+                break;
+            }
+        }
+
+        assert!(battle.result().is_some());
+        assert!(replies > 0);
+        assert!(battle.play_player_move(CombatMove::Attack).is_err());
+        assert!(battle.play_opponent_move().is_none());
+
+        let ranked = BattleOutcome::Knockout.rewards(battle.opponent.experience_to_next_level(), 0);
+        let xp = battle.player_xp().unwrap();
+        assert!(xp < ranked.winner_xp);
+        match battle.result().unwrap() {
+            KnockoutResult::Winner(winner_id) if winner_id == battle.player.user_id => {
+                assert!(xp > 0)
+            }
+            _ => assert_eq!(xp, 0),
+        }
+
+        let game_data = battle.game_data(None);
+        assert_eq!(game_data.battle_id, Some(battle.id.clone()));
+        assert_eq!(game_data.winner_coins_awarded, None);
+    }
+}
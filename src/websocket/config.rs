@@ -0,0 +1,90 @@
+//! Limits for the WebSocket connections the server accepts.
+//!
+//! Frame and message sizes are enforced by the WebSocket layer, which fails
+//! the read and closes the connection once a client sends more than allowed.
+//! The write timeout bounds how long a connection may go without taking
+//! outgoing payloads before it is closed.
+
+use std::time::Duration;
+
+use rocket_ws::Config;
+
+/// Used when `WS_MAX_FRAME_SIZE` isn't set.
+pub const DEFAULT_WS_MAX_FRAME_SIZE: usize = 64 << 10;
+
+/// Used when `WS_MAX_MESSAGE_SIZE` isn't set.
+pub const DEFAULT_WS_MAX_MESSAGE_SIZE: usize = 256 << 10;
+
+/// Used when `WS_MAX_WRITE_BUFFER_SIZE` isn't set.
+pub const DEFAULT_WS_MAX_WRITE_BUFFER_SIZE: usize = 1 << 20;
+
+/// Used when `WS_WRITE_TIMEOUT_SECONDS` isn't set.
+pub const DEFAULT_WS_WRITE_TIMEOUT_SECONDS: u64 = 10;
+
+fn env_usize(name: &str, default: usize) -> usize {
+    std::env::var(name)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
+
+/// The WebSocket limits, read from `WS_MAX_FRAME_SIZE`, `WS_MAX_MESSAGE_SIZE`
+/// and `WS_MAX_WRITE_BUFFER_SIZE` in bytes.
+pub fn ws_config() -> Config {
+    let max_frame_size = env_usize("WS_MAX_FRAME_SIZE", DEFAULT_WS_MAX_FRAME_SIZE).max(1);
+    let max_message_size = env_usize("WS_MAX_MESSAGE_SIZE", DEFAULT_WS_MAX_MESSAGE_SIZE);
+
+    let mut config = Config::default();
+    config.max_frame_size = Some(max_frame_size);
+    // A message is at least one frame
+    config.max_message_size = Some(max_message_size.max(max_frame_size));
+    // The write buffer has to fit at least one full buffered write
+    config.max_write_buffer_size =
+        env_usize("WS_MAX_WRITE_BUFFER_SIZE", DEFAULT_WS_MAX_WRITE_BUFFER_SIZE)
+            .max(config.write_buffer_size + 1);
+    config
+}
+
+/// How long a connection that stopped taking payloads is given before it is
+/// closed, from `WS_WRITE_TIMEOUT_SECONDS`.
+pub fn ws_write_timeout() -> Duration {
+    let seconds = std::env::var("WS_WRITE_TIMEOUT_SECONDS")
+        .ok()
+        .and_then(|seconds| seconds.parse().ok())
+        .unwrap_or(DEFAULT_WS_WRITE_TIMEOUT_SECONDS);
+    Duration::from_secs(seconds)
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::{SinkExt, StreamExt};
+    use rocket_ws::{Message, result::Error};
+    use tokio_tungstenite::{WebSocketStream, tungstenite::protocol::Role};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_oversize_frame_is_rejected() {
+        let config = ws_config();
+        let max_frame_size = config.max_frame_size.unwrap();
+        let (client, server) = tokio::io::duplex(4 * max_frame_size);
+        let mut server = WebSocketStream::from_raw_socket(server, Role::Server, Some(config)).await;
+        let mut client = WebSocketStream::from_raw_socket(client, Role::Client, None).await;
+
+        client
+            .send(Message::Text("a".repeat(max_frame_size)))
+            .await
+            .unwrap();
+        let received = server.next().await.unwrap().unwrap();
+        assert_eq!(received.len(), max_frame_size);
+
+        client
+            .send(Message::Text("a".repeat(max_frame_size + 1)))
+            .await
+            .unwrap();
+        assert!(matches!(
+            server.next().await.unwrap(),
+            Err(Error::Capacity(_))
+        ));
+    }
+}
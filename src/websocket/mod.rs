@@ -1,8 +1,27 @@
 use rocket::Route;
 
 pub mod battle_queue;
+pub mod config;
 pub mod helpers;
 
 pub fn routes() -> Vec<Route> {
     routes![battle_queue::handlers::battle_queue]
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_single_battle_queue_route() {
+        let battle_queue_routes = routes()
+            .into_iter()
+            .filter(|route| route.uri.to_string().starts_with("/battle_queue"))
+            .collect::<Vec<_>>();
+        assert_eq!(battle_queue_routes.len(), 1);
+        assert_eq!(
+            battle_queue_routes[0].uri.to_string(),
+            "/battle_queue/<token>"
+        );
+    }
+}